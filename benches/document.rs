@@ -0,0 +1,17 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use twinstar::bench::document;
+use twinstar::Document;
+
+fn documents(c: &mut Criterion) {
+    let small = document(10);
+    let large = document(1000);
+    let large_text = large.to_string();
+
+    c.bench_function("document/build 1000 sections", |b| b.iter(|| document(1000)));
+    c.bench_function("document/render 10 sections", |b| b.iter(|| small.to_string()));
+    c.bench_function("document/render 1000 sections", |b| b.iter(|| large.to_string()));
+    c.bench_function("document/parse 1000 sections", |b| b.iter(|| Document::parse(&large_text)));
+}
+
+criterion_group!(benches, documents);
+criterion_main!(benches);
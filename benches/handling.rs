@@ -0,0 +1,39 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use twinstar::bench::{InMemoryServer, document, route_paths};
+use twinstar::{Server, Request, Response, GEMINI_PORT};
+
+fn handling(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let page = document(100).to_string();
+
+    let server = InMemoryServer::new(Server::bind(("localhost", GEMINI_PORT))
+        .add_route("/", move |_: Request| {
+            let page = page.clone();
+            Box::pin(async move { Ok(Response::success_gemini(page)) }) as _
+        })
+        .add_route("/plain", |_: Request| Box::pin(async { Ok(Response::success_plain("hi")) }) as _)
+        .add_route("/greeting", |_: Request| Box::pin(async { Ok(Response::success_gemini(String::from("# Hello\n"))) }) as _)
+        .add_middleware(|request: Request, next: twinstar::middleware::Next| next.run(request)));
+
+    let paths = route_paths(10_000).collect::<Vec<_>>();
+
+    c.bench_function("handling/small response", |b| b.iter(|| {
+        runtime.block_on(server.handle(b"gemini://localhost/plain\r\n")).unwrap()
+    }));
+    c.bench_function("handling/short string response", |b| b.iter(|| {
+        runtime.block_on(server.handle(b"gemini://localhost/greeting\r\n")).unwrap()
+    }));
+    c.bench_function("handling/100 section page", |b| b.iter(|| {
+        runtime.block_on(server.handle(b"gemini://localhost/\r\n")).unwrap()
+    }));
+
+    let mut next = 0;
+    c.bench_function("handling/fallback route", |b| b.iter(|| {
+        next = (next + 7919) % paths.len();
+        let request = format!("gemini://localhost{}\r\n", paths[next]);
+        runtime.block_on(server.handle(request.as_bytes())).unwrap()
+    }));
+}
+
+criterion_group!(benches, handling);
+criterion_main!(benches);
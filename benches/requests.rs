@@ -0,0 +1,14 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use twinstar::bench::parse_request;
+
+fn requests(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let short = b"gemini://localhost/\r\n";
+    let long = format!("gemini://example.org/{}?{}\r\n", "section/".repeat(60), "q%20x".repeat(100));
+
+    c.bench_function("requests/parse short", |b| b.iter(|| runtime.block_on(parse_request(short)).unwrap()));
+    c.bench_function("requests/parse long", |b| b.iter(|| runtime.block_on(parse_request(long.as_bytes())).unwrap()));
+}
+
+criterion_group!(benches, requests);
+criterion_main!(benches);
@@ -0,0 +1,23 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use twinstar::bench::{routing_table, route_paths};
+
+fn routing(c: &mut Criterion) {
+    let routes = routing_table(10_000);
+    let paths = route_paths(10_000)
+        .map(|path| path.trim_start_matches('/').split('/').map(str::to_owned).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    c.bench_function("routing/build 10k routes", |b| b.iter(|| routing_table(10_000)));
+
+    let mut next = 0;
+    c.bench_function("routing/match among 10k routes", |b| b.iter(|| {
+        next = (next + 7919) % paths.len();
+        routes.match_path(&paths[next]).map(|(_, index)| *index)
+    }));
+
+    let missing = ["section999", "page0", "nothing"];
+    c.bench_function("routing/miss among 10k routes", |b| b.iter(|| routes.match_path(&missing).is_some()));
+}
+
+criterion_group!(benches, routing);
+criterion_main!(benches);
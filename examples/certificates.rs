@@ -3,25 +3,24 @@ use futures_core::future::BoxFuture;
 use futures_util::FutureExt;
 use log::LevelFilter;
 use tokio::sync::RwLock;
-use twinstar::{Certificate, GEMINI_PORT, Request, Response, Server};
+use twinstar::{GEMINI_PORT, Request, Response, Server};
 use std::collections::HashMap;
 use std::sync::Arc;
 
-// Workaround for Certificates not being hashable
-type CertBytes = Vec<u8>;
-
 #[tokio::main]
 async fn main() -> Result<()> {
     env_logger::builder()
         .filter_module("twinstar", LevelFilter::Debug)
         .init();
 
-    let users = Arc::<RwLock::<HashMap<CertBytes, String>>>::default();
+    let users = Arc::<RwLock::<HashMap<String, String>>>::default();
 
     Server::bind(("0.0.0.0", GEMINI_PORT))
         .add_route("/", move|req| handle_request(users.clone(), req))
         .serve()
-        .await
+        .await?;
+
+    Ok(())
 }
 
 /// An ultra-simple demonstration of simple authentication.
@@ -31,12 +30,12 @@ async fn main() -> Result<()> {
 /// selecting a username.  They'll then get a message confirming their account creation.
 /// Any time this user visits the site in the future, they'll get a personalized welcome
 /// message.
-fn handle_request(users: Arc<RwLock<HashMap<CertBytes, String>>>, request: Request) -> BoxFuture<'static, Result<Response>> {
+fn handle_request(users: Arc<RwLock<HashMap<String, String>>>, request: Request) -> BoxFuture<'static, Result<Response>> {
     async move {
-        if let Some(Certificate(cert_bytes)) = request.certificate() {
+        if let Some(fingerprint) = request.certificate_fingerprint() {
             // The user provided a certificate
             let users_read = users.read().await;
-            if let Some(user) = users_read.get(cert_bytes) {
+            if let Some(user) = users_read.get(&fingerprint) {
                 // The user has already registered
                 Ok(
                     Response::success_gemini(
@@ -50,7 +49,7 @@ fn handle_request(users: Arc<RwLock<HashMap<CertBytes, String>>>, request: Reque
                     // The user provided some input (a username request)
                     let username = query_part.as_str();
                     let mut users_write = users.write().await;
-                    users_write.insert(cert_bytes.clone(), username.to_owned());
+                    users_write.insert(fingerprint, username.to_owned());
                     Ok(
                         Response::success_gemini(
                             format!(
@@ -0,0 +1,48 @@
+//! A small hosting daemon for many users' capsules
+//!
+//! Run it with either a directory of tenants, laid out as `<name>/public_gemini`, or a
+//! tenant config file, see the docs of `twinstar::multi_tenant`:
+//!
+//! ```text
+//! cargo run --example multi_tenant -- /srv/gemini/users
+//! cargo run --example multi_tenant -- tenants.conf
+//! ```
+//!
+//! The TLS certificate and key are taken from `cert/`.  A summary of each tenant's
+//! resource usage is written to `usage.gmi` every five minutes.
+
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::*;
+use log::LevelFilter;
+use twinstar::{Server, GEMINI_PORT};
+use twinstar::multi_tenant::MultiTenant;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::builder()
+        .filter_module("twinstar", LevelFilter::Info)
+        .init();
+
+    let source = std::env::args().nth(1)
+        .context("Usage: multi_tenant <tenant directory or config file>")?;
+    let tenants = if Path::new(&source).is_dir() {
+        MultiTenant::from_directory(&source)?
+    } else {
+        MultiTenant::from_config_file(&source)?
+    };
+
+    tokio::spawn(tenants.metrics().write_summaries("usage.gmi", Duration::from_secs(300)));
+
+    for tenant in tenants.tenants() {
+        log::info!("Serving ~{} from {}", tenant.name(), tenant.root().display());
+    }
+
+    Server::bind(("localhost", GEMINI_PORT))
+        .add_route("/", tenants.into_handler())
+        .serve()
+        .await?;
+
+    Ok(())
+}
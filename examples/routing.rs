@@ -1,3 +1,6 @@
+// Still shows the deprecated Response::document(), like it always has
+#![allow(deprecated)]
+
 use anyhow::*;
 use futures_core::future::BoxFuture;
 use futures_util::FutureExt;
@@ -15,7 +18,9 @@ async fn main() -> Result<()> {
         .add_route("/route", handle_short)
         .add_route("/route/long", handle_long)
         .serve()
-        .await
+        .await?;
+
+    Ok(())
 }
 
 fn handle_base(req: Request) -> BoxFuture<'static, Result<Response>> {
@@ -13,7 +13,9 @@ async fn main() -> Result<()> {
     Server::bind(("localhost", GEMINI_PORT))
         .add_route("/", handle_request)
         .serve()
-        .await
+        .await?;
+
+    Ok(())
 }
 
 fn handle_request(request: Request) -> BoxFuture<'static, Result<Response>> {
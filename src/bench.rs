@@ -0,0 +1,165 @@
+//! Helpers for benchmarking twinstar and capsules built on it
+//!
+//! This module is only available with the `bench` feature.  It provides inputs for the
+//! hot paths of the server, and a way to run a [`Builder`] entirely in memory, without
+//! TLS or sockets.  The benchmarks in the `benches/` directory measure them using
+//! [criterion](https://docs.rs/criterion), and can be run using
+//!
+//! ```text
+//! cargo bench --features bench
+//! ```
+//!
+//! Capsules can use the same helpers to measure their own handlers:
+//!
+//! ```no_run
+//! use criterion::{criterion_group, criterion_main, Criterion};
+//! use twinstar::{Server, Request, Response, GEMINI_PORT};
+//! use twinstar::bench::InMemoryServer;
+//!
+//! fn hello(c: &mut Criterion) {
+//!     let server = InMemoryServer::new(Server::bind(("localhost", GEMINI_PORT))
+//!         .add_route("/", |_: Request| Box::pin(async { Ok(Response::success_plain("hi")) }) as _));
+//!     let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+//!
+//!     c.bench_function("hello", |b| b.iter(|| runtime.block_on(server.handle(b"gemini://localhost/\r\n"))));
+//! }
+//!
+//! criterion_group!(benches, hello);
+//! criterion_main!(benches);
+//! ```
+
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+use anyhow::Result;
+use tokio::io::BufReader;
+
+use crate::middleware::{Middleware, Next};
+use crate::routing::RoutingNode;
+use crate::types::{Document, Request};
+use crate::types::document::HeadingLevel;
+use crate::meta_defaults::MetaDefaults;
+use crate::{Builder, Handler};
+
+/// A routing table with `count` routes, like a large capsule with many sections
+///
+/// Routes are spread over three levels, e.g. `/section12/page3/part4`, and each route
+/// maps to its index.
+pub fn routing_table(count: usize) -> RoutingNode<usize> {
+    let mut routes = RoutingNode::default();
+
+    for (index, path) in route_paths(count).enumerate() {
+        let path = uriparse::Path::try_from(path.as_str())
+            .expect("twinstar BUG")
+            .into_owned();
+        routes.add_route_by_path(path, index).expect("twinstar BUG");
+    }
+
+    routes.shrink();
+    routes
+}
+
+/// The paths of the routes in [`routing_table()`]
+pub fn route_paths(count: usize) -> impl Iterator<Item = String> {
+    (0..count).map(|index| format!("/section{}/page{}/part{}", index / 100, index / 10 % 10, index % 10))
+}
+
+/// A document with `sections` sections, each with a heading, text and links
+pub fn document(sections: usize) -> Document {
+    let mut document = Document::new();
+
+    for section in 0..sections {
+        document
+            .add_heading(HeadingLevel::H2, format!("Section {}", section))
+            .add_text("Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor.")
+            .add_link(format!("/section{}/", section).as_str(), "Read more")
+            .add_link("gemini://example.org/", "Elsewhere")
+            .add_blank_line();
+    }
+
+    document
+}
+
+/// Parse a raw request, as the server does after the TLS handshake
+pub async fn parse_request(raw: &[u8]) -> Result<Request> {
+    crate::protocol::read_request(&mut BufReader::new(raw)).await
+}
+
+/// A server answering raw requests in memory, without TLS or sockets
+///
+/// This runs the same steps as the real server, except for the connection handling:
+/// the request is parsed, passed through the middleware and routed, and the response is
+/// serialized.  See the [module documentation](self) for an example.
+pub struct InMemoryServer {
+    middleware: Arc<[Arc<dyn Middleware>]>,
+    routes: Arc<RoutingNode<Handler>>,
+    fallback: Option<Handler>,
+    meta_defaults: Arc<MetaDefaults>,
+}
+
+impl InMemoryServer {
+    /// Take the routes and middleware of a builder
+    ///
+    /// The address and TLS settings of the builder are ignored.
+    pub fn new<A>(mut builder: Builder<A>) -> Self {
+        builder.routes.shrink();
+
+        Self {
+            middleware: builder.middleware.into(),
+            routes: Arc::new(builder.routes),
+            fallback: builder.fallback,
+            meta_defaults: Arc::new(builder.meta_defaults),
+        }
+    }
+
+    /// Answer a raw request, e.g. `b"gemini://localhost/\r\n"`, returning the raw response
+    pub async fn handle(&self, raw_request: &[u8]) -> Result<Vec<u8>> {
+        let request = parse_request(raw_request).await?;
+        let mut response = Next::new(self.middleware.clone(), self.routes.clone())
+            .with_fallback(self.fallback.clone())
+            .run(request)
+            .await?;
+        self.meta_defaults.apply(&mut response);
+
+        let mut raw_response = Vec::new();
+        let body = response.take_body();
+        crate::protocol::send_response_header(response.header(), false, &mut raw_response).await?;
+        crate::protocol::maybe_send_response_body(body, &mut raw_response).await?;
+
+        Ok(raw_response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Response;
+    use crate::{Server, HandlerResponse};
+
+    #[test]
+    fn builds_inputs() {
+        let routes = routing_table(1000);
+        assert_eq!(routes.match_path(["section9", "page9", "part9"]).map(|(_, index)| *index), Some(999));
+        assert_eq!(route_paths(1000).count(), 1000);
+        assert_eq!(document(3).to_string().matches("## Section").count(), 3);
+    }
+
+    #[tokio::test]
+    async fn handles_requests_in_memory() {
+        let server = InMemoryServer::new(Server::bind(("localhost", 0))
+            .set_default_lang("en")
+            .add_route("/", |_: Request| Box::pin(async { Ok(Response::success_gemini("hi")) }) as HandlerResponse));
+
+        let response = server.handle(b"gemini://localhost/\r\n").await.unwrap();
+        assert_eq!(response, b"20 text/gemini; lang=en\r\nhi");
+        assert!(server.handle(b"gemini://localhost/").await.is_err());
+
+        let server = InMemoryServer::new(Server::bind(("localhost", 0))
+            .add_route("/blog", |_: Request| Box::pin(async { Ok(Response::success_plain("blog")) }) as HandlerResponse)
+            .set_fallback(|request: Request| Box::pin(async move {
+                Ok(Response::success_plain(request.path_segments().join("/")))
+            }) as HandlerResponse));
+        assert_eq!(server.handle(b"gemini://localhost/blog/post\r\n").await.unwrap(), b"20 text/plain\r\nblog");
+        assert_eq!(server.handle(b"gemini://localhost/missing/page\r\n").await.unwrap(), b"20 text/plain\r\nmissing/page");
+    }
+}
@@ -0,0 +1,383 @@
+//! A minimal server without an async runtime
+//!
+//! The blocking [`Server`] serves one connection at a time on the calling thread,
+//! using std sockets and rustls directly.  This suits tiny deployments, like a capsule
+//! on a router or a retrocomputing host, where a multi-threaded async runtime is more
+//! than the job needs.  Handlers are plain functions returning a [`Response`], or
+//! anything else implementing [`IntoResponse`], and share the [types](crate::types),
+//! [`Document`](crate::Document) and [routing](crate::routing) with the async
+//! [`Server`](crate::Server).
+//!
+//! Since connections are served one after the other, a slow client holds up everyone
+//! else until the [timeout](Builder::set_timeout()) runs out.  None of the async
+//! server's extras, like middleware, rate limiting or access logs, are available.
+//!
+//! This requires the `blocking` feature.  Twinstar still depends on tokio, but the
+//! blocking server never starts a runtime.
+//!
+//! ```no_run
+//! use twinstar::{Request, GEMINI_PORT, blocking::Server};
+//!
+//! fn main() -> anyhow::Result<()> {
+//!     Server::bind(("localhost", GEMINI_PORT))
+//!         .add_route("/", |_: Request| Ok("# Hello from a router!"))
+//!         .serve()?;
+//!     Ok(())
+//! }
+//! ```
+
+use std::convert::TryFrom;
+use std::future::Future;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll, Wake};
+use std::thread::{self, Thread};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use tokio::io::AsyncReadExt;
+
+use crate::protocol::{self, MalformedRequest};
+use crate::routing::{self, RoutingNode};
+use crate::types::{Body, IntoResponse, PeerCertificate, Request, Response, ResponseHeader};
+use crate::{tls, tls_config, Error, PemSource, TlsVersion, REQUEST_URI_MAX_LEN};
+
+/// A handler of the blocking server
+pub type Handler = Box<dyn Fn(Request) -> Result<Response> + Send + Sync>;
+
+/// A Gemini server answering one connection at a time
+///
+/// See the [module documentation](self).
+pub struct Server {
+    listener: TcpListener,
+    tls_config: Arc<ServerConfig>,
+    routes: RoutingNode<Handler>,
+    timeout: Duration,
+}
+
+impl Server {
+    /// Start building a server listening on `addr`
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> Builder<A> {
+        Builder::bind(addr)
+    }
+
+    /// The address the server is listening on
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Serve connections until the listener fails
+    ///
+    /// Failing to accept a connection, or to serve one, is logged and doesn't stop the
+    /// server.
+    pub fn serve(self) -> Result<(), Error> {
+        loop {
+            let (stream, peer_addr) = match self.listener.accept() {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    warn!("Failed to accept connection: {}", err);
+                    continue;
+                },
+            };
+
+            if let Err(err) = self.serve_client(stream, peer_addr) {
+                warn!("Failed to serve {}: {:?}", peer_addr, err);
+            }
+        }
+    }
+
+    fn serve_client(&self, stream: TcpStream, peer_addr: SocketAddr) -> Result<()> {
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        let local_addr = stream.local_addr().ok();
+
+        let mut stream = StreamOwned::new(ServerConnection::new(self.tls_config.clone())?, stream);
+
+        let mut request = match read_request(&mut stream) {
+            Ok(request) => request,
+            Err(err) => {
+                if let Some(malformed) = err.downcast_ref::<MalformedRequest>() {
+                    debug!("Answering malformed request from {}: {}", peer_addr, malformed);
+                    let _ = write_response(malformed.response(), &mut stream);
+                }
+
+                return Err(err.context("Failed to receive request"));
+            },
+        };
+
+        debug!("Client requested: {}", request.uri());
+
+        // Identify the client certificate from the tls stream.  This is the first
+        // certificate in the certificate chain.
+        let client_cert = tls::peer_certificates(&stream.conn)
+            .into_iter()
+            .next()
+            .map(PeerCertificate::from_der);
+
+        request.set_cert(client_cert);
+        request.set_remote_addr(Some(peer_addr));
+        request.set_local_addr(local_addr);
+        request.set_tls_info(tls::tls_info(&stream.conn));
+
+        let response = match self.routes.match_request(&request) {
+            Some((trailing, handler)) => {
+                request.set_trailing(trailing);
+                call_handler(handler, request)
+            },
+            None => Response::not_found(),
+        };
+
+        write_response(response, &mut stream)
+            .context("Failed to send response")
+    }
+}
+
+/// Builds a blocking [`Server`]
+pub struct Builder<A> {
+    addr: A,
+    cert: PemSource,
+    key: PemSource,
+    timeout: Duration,
+    min_tls_version: TlsVersion,
+    routes: RoutingNode<Handler>,
+    route_origins: Vec<(String, String)>,
+}
+
+impl<A: ToSocketAddrs> Builder<A> {
+    fn bind(addr: A) -> Self {
+        Self {
+            addr,
+            cert: PemSource::File("cert/cert.pem".into()),
+            key: PemSource::File("cert/key.pem".into()),
+            timeout: Duration::from_secs(1),
+            min_tls_version: TlsVersion::Tls12,
+            routes: RoutingNode::default(),
+            route_origins: Vec::new(),
+        }
+    }
+
+    /// Look for `cert.pem` and `key.pem` in `dir`, instead of `cert/`
+    pub fn set_tls_dir(self, dir: impl Into<PathBuf>) -> Self {
+        let dir = dir.into();
+        self.set_cert(dir.join("cert.pem"))
+            .set_key(dir.join("key.pem"))
+    }
+
+    /// Set the path to the TLS certificate, `cert/cert.pem` by default
+    pub fn set_cert(mut self, cert_path: impl Into<PathBuf>) -> Self {
+        self.cert = PemSource::File(cert_path.into());
+        self
+    }
+
+    /// Use a PEM encoded TLS certificate chain held in memory
+    pub fn set_cert_bytes(mut self, cert_pem: &[u8]) -> Self {
+        self.cert = PemSource::Memory(cert_pem.to_vec());
+        self
+    }
+
+    /// Set the path to the private key, `cert/key.pem` by default
+    pub fn set_key(mut self, key_path: impl Into<PathBuf>) -> Self {
+        self.key = PemSource::File(key_path.into());
+        self
+    }
+
+    /// Use a PEM encoded private key held in memory
+    pub fn set_key_bytes(mut self, key_pem: &[u8]) -> Self {
+        self.key = PemSource::Memory(key_pem.to_vec());
+        self
+    }
+
+    /// Set how long reading the request and writing the response may block
+    ///
+    /// Unlike with the async server, this applies to every single read and write, and
+    /// not to the connection as a whole.  The default is 1 second.
+    pub fn set_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set the oldest version of TLS clients may use, see
+    /// [`crate::Builder::set_min_tls_version()`]
+    pub fn set_min_tls_version(mut self, version: TlsVersion) -> Self {
+        self.min_tls_version = version;
+        self
+    }
+
+    /// Add a handler for a route
+    ///
+    /// Handlers may return anything implementing [`IntoResponse`].  Routes work like
+    /// with the async server, see [`crate::Builder::add_route()`].  Entering a relative
+    /// or malformed path will result in a panic, and adding the same route twice makes
+    /// [`build()`](Self::build()) fail.
+    #[track_caller]
+    pub fn add_route<H, R>(mut self, path: &'static str, handler: H) -> Self
+    where
+        H: Fn(Request) -> Result<R> + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        let origin = panic::Location::caller().to_string();
+        let route = uriparse::path::Path::try_from(path).expect("Malformed path route received");
+        self.route_origins.push((routing::route_path(&route), origin));
+
+        // Conflicts are reported all at once when the server is built
+        let handler = move |request| handler(request).map(IntoResponse::into_response);
+        let _ = self.routes.add_route_by_path(route, Box::new(handler));
+        self
+    }
+
+    /// Load the certificate and bind the listener
+    pub fn build(self) -> Result<Server, Error> {
+        routing::check_conflicts(self.route_origins.iter().map(|(path, origin)| (path, origin.clone())))
+            .map_err(Error::Routing)?;
+
+        let config = tls_config(&[(&self.cert, &self.key)], self.min_tls_version, tls::allow_any_client())
+            .context("Failed to create TLS config")
+            .map_err(Error::Tls)?;
+        let listener = TcpListener::bind(self.addr)
+            .map_err(Error::io("Failed to create socket"))?;
+
+        Ok(Server {
+            listener,
+            tls_config: Arc::new(config),
+            routes: self.routes,
+            timeout: self.timeout,
+        })
+    }
+
+    /// Build the server and serve connections until the listener fails
+    pub fn serve(self) -> Result<(), Error> {
+        self.build()?.serve()
+    }
+}
+
+fn read_request(stream: &mut impl Read) -> Result<Request> {
+    let limit = REQUEST_URI_MAX_LEN + "\r\n".len();
+    let mut stream = BufReader::new(stream.take(limit as u64));
+    let mut uri = Vec::new();
+
+    stream.read_until(b'\n', &mut uri)?;
+
+    protocol::parse_request(uri)
+}
+
+/// Call `handler`, answering errors and panics with `50 PERMANENT FAILURE`
+fn call_handler(handler: &Handler, request: Request) -> Response {
+    let server_error = || Response::new(ResponseHeader::server_error_lossy(""));
+
+    match panic::catch_unwind(AssertUnwindSafe(|| handler(request))) {
+        Ok(Ok(response)) => response,
+        Ok(Err(err)) => {
+            error!("Handler failed: {:?}", err);
+            server_error()
+        },
+        Err(_) => {
+            error!("Handler panicked");
+            server_error()
+        },
+    }
+}
+
+fn write_response(mut response: Response, stream: &mut StreamOwned<ServerConnection, TcpStream>) -> Result<()> {
+    stream.write_all(protocol::header_line(response.header()).as_bytes())?;
+
+    if let Some(body) = response.take_body() {
+        write_body(body, stream)?;
+    }
+
+    stream.conn.send_close_notify();
+    stream.flush()?;
+
+    Ok(())
+}
+
+fn write_body(body: Body, stream: &mut impl Write) -> Result<()> {
+    match body {
+        Body::Bytes(bytes) => stream.write_all(&bytes)?,
+        Body::Inline(bytes) => stream.write_all(&bytes)?,
+        // Readers are polled without a runtime, which works for in-memory readers and
+        // anything else not relying on tokio's reactor or timers
+        Body::Reader(mut reader) => {
+            let mut buf = [0; 8 * 1024];
+            loop {
+                let read = block_on(reader.read(&mut buf))?;
+                if read == 0 {
+                    break;
+                }
+                stream.write_all(&buf[..read])?;
+            }
+        },
+        #[cfg(feature="mmap")]
+        Body::Mmap(mapped) => stream.write_all(&mapped)?,
+    }
+
+    Ok(())
+}
+
+/// Wakes the thread blocked in [`block_on()`]
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Poll `future` to completion on the current thread
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = Box::pin(future);
+    let waker = Arc::new(ThreadWaker(thread::current())).into();
+    let mut cx = TaskContext::from_waker(&waker);
+
+    loop {
+        match Pin::as_mut(&mut future).poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature="client")]
+    #[tokio::test]
+    async fn serves_requests() {
+        let generated = crate::testing::TestCertificate::new("localhost").generate().unwrap();
+        let server = Server::bind(("localhost", 0))
+            .set_cert_bytes(generated.certificate_pem().as_bytes())
+            .set_key_bytes(generated.key_pem().as_bytes())
+            .add_route("/", |request: Request| {
+                let body = format!("Hello {}", request.trailing_segments().join("/"));
+                Ok(Response::success_plain(body))
+            })
+            .add_route("/reader", |_: Request| Ok(Response::success_plain(Body::Reader(Box::new(&b"streamed"[..])))))
+            .add_route("/panic", |_: Request| -> Result<Response> { panic!("oops") })
+            .build()
+            .unwrap();
+
+        let port = server.local_addr().unwrap().port();
+        thread::spawn(move || server.serve());
+
+        let client = crate::client::Client::new();
+        let url = |path: &str| format!("gemini://localhost:{}{}", port, path);
+        assert_eq!(client.request(&url("/a/b")).await.unwrap().body_string().await.unwrap(), "Hello a/b");
+        assert_eq!(client.request(&url("/reader")).await.unwrap().body_string().await.unwrap(), "streamed");
+        let panicked = client.request(&url("/panic")).await.unwrap();
+        assert_eq!(panicked.header().status, crate::types::Status::PERMANENT_FAILURE);
+    }
+
+    #[test]
+    fn reads_requests() {
+        let request = read_request(&mut &b"gemini://localhost/a\r\nrest"[..]).unwrap();
+        assert_eq!(request.uri().to_string(), "gemini://localhost/a");
+
+        let err = read_request(&mut &b"gemini://localhost/\n"[..]).err().unwrap();
+        assert!(err.downcast_ref::<MalformedRequest>().is_some());
+    }
+}
@@ -0,0 +1,86 @@
+//! Timeouts for sending response bodies, chosen by MIME type
+//!
+//! See [`Builder::set_body_timeout()`](crate::Builder::set_body_timeout()).
+
+use std::time::Duration;
+
+/// The pattern matching every MIME type
+pub(crate) const ANY_MIME: &str = "*/*";
+
+/// How long clients have to receive the bodies of successful responses, by MIME pattern
+///
+/// Patterns are either a full MIME type like `application/zip`, a top-level type like
+/// `image/*`, or `*/*`.  The most specific pattern matching a response wins.  A pattern
+/// mapped to `None` leaves its responses to the general timeout.
+#[derive(Debug, Clone)]
+pub(crate) struct BodyTimeouts {
+    rules: Vec<(String, Option<Duration>)>,
+}
+
+impl BodyTimeouts {
+    /// Set the timeout for `pattern`, replacing any previous one
+    pub fn set(&mut self, pattern: &str, timeout: Option<Duration>) {
+        let pattern = pattern.trim().to_ascii_lowercase();
+
+        match self.rules.iter_mut().find(|(existing, _)| *existing == pattern) {
+            Some((_, existing)) => *existing = timeout,
+            None => self.rules.push((pattern, timeout)),
+        }
+    }
+
+    /// The timeout set for exactly `pattern`, if any
+    pub fn get(&self, pattern: &str) -> Option<Option<Duration>> {
+        self.rules.iter()
+            .find(|(existing, _)| existing == pattern)
+            .map(|(_, timeout)| *timeout)
+    }
+
+    /// How long a body of type `meta` may take to send, or `None` if it is sent within
+    /// the general timeout
+    pub fn body_timeout(&self, meta: &str) -> Option<Duration> {
+        let essence = meta.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+        let top_level = essence.split('/').next().unwrap_or_default();
+
+        self.get(&essence)
+            .or_else(|| self.get(&format!("{}/*", top_level)))
+            .or_else(|| self.get(ANY_MIME))
+            .flatten()
+    }
+}
+
+impl Default for BodyTimeouts {
+    /// Gemtext and plain text within the general timeout, and 30 seconds for anything
+    /// else, which clients might prompt the user about first
+    fn default() -> Self {
+        let mut timeouts = Self { rules: Vec::new() };
+        timeouts.set("text/gemini", None);
+        timeouts.set("text/plain", None);
+        timeouts.set(ANY_MIME, Some(Duration::from_secs(30)));
+        timeouts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_most_specific_pattern() {
+        let secs = |secs| Some(Duration::from_secs(secs));
+        let mut timeouts = BodyTimeouts::default();
+        assert_eq!(timeouts.body_timeout("text/gemini; lang=en"), None);
+        assert_eq!(timeouts.body_timeout("image/png"), secs(30));
+
+        timeouts.set("text/*", secs(5));
+        timeouts.set("image/*", secs(60));
+        timeouts.set("Application/Zip", secs(300));
+        assert_eq!(timeouts.body_timeout("text/gemini"), None);
+        assert_eq!(timeouts.body_timeout("text/html"), secs(5));
+        assert_eq!(timeouts.body_timeout("image/png"), secs(60));
+        assert_eq!(timeouts.body_timeout("application/zip"), secs(300));
+        assert_eq!(timeouts.body_timeout("application/pdf"), secs(30));
+
+        timeouts.set(ANY_MIME, None);
+        assert_eq!(timeouts.body_timeout("application/pdf"), None);
+    }
+}
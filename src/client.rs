@@ -0,0 +1,705 @@
+//! Making requests to other Gemini servers
+//!
+//! A [`Client`] sends a request and returns a [`ClientResponse`], with the body still to
+//! be read from the connection.  The body can be read as it arrives, or all at once as
+//! text or as a parsed gemtext [`Document`].  This is meant for tools built on top of twinstar, like
+//! proxies, crawlers or scripts posting to a capsule, rather than for browsing.
+//!
+//! Capsules identify users by the client certificate they present.  A client can
+//! present an [`Identity`] with every request, or only with requests to certain hosts:
+//!
+//! ```no_run
+//! # use twinstar::client::{Client, Identity};
+//! # async fn run() -> anyhow::Result<()> {
+//! let admin = Identity::from_pem_files("admin.crt", "admin.key")?;
+//! let client = Client::new().set_host_identity("example.org", admin);
+//!
+//! let response = client.request("gemini://example.org/admin/stats").await?;
+//! for (uri, label) in response.body_document().await?.links() {
+//!     println!("{} {}", uri, label.unwrap_or_default());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! By default, servers are trusted regardless of the certificate they present, since
+//! Gemini servers mostly use self-signed certificates.  For trust on first use, a client
+//! can remember the certificate each server presented in a [`KvStore`] using
+//! [`KnownHosts`], and refuse to talk to servers whose certificate changed:
+//!
+//! ```no_run
+//! # use twinstar::client::{Client, KnownHosts};
+//! # use twinstar::storage::MemoryStore;
+//! # async fn run() -> anyhow::Result<()> {
+//! let client = Client::new().set_known_hosts(KnownHosts::new(MemoryStore::new()));
+//! let response = client.request("gemini://example.org/").await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Handlers proxying or aggregating other capsules can pass their request on to
+//! [`Client::for_request()`], so a slow upstream server can't keep them from answering
+//! before the request's [`Deadline`] passes:
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use twinstar::{Server, Request, Response, GEMINI_PORT, client::Client, middleware::Budget};
+//! # async fn run() -> anyhow::Result<()> {
+//! let client = Client::new();
+//!
+//! Server::bind(("localhost", GEMINI_PORT))
+//!     .add_middleware(Budget::new(Duration::from_secs(3)))
+//!     .add_route("/mirror", move |request: Request| {
+//!         let client = client.for_request(&request);
+//!         Box::pin(async move {
+//!             let upstream = client.request("gemini://example.org/").await?;
+//!             Ok(Response::success_gemini(upstream.body_string().await?))
+//!         }) as _
+//!     })
+//!     .serve()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Result, Context, anyhow, bail, ensure};
+use rustls::{Certificate, ClientConfig, PrivateKey};
+use mime::Mime;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader as AsyncBufReader};
+use tokio::net::{lookup_host, TcpStream};
+use tokio::time::timeout;
+use tokio_rustls::TlsConnector;
+
+use crate::storage::KvStore;
+use crate::BoxedHandler;
+use crate::types::{Body, Document, Meta, PeerCertificate, Request, Response, ResponseHeader, Status, URIReference};
+use crate::util::Deadline;
+use crate::protocol::parse_header;
+use crate::{tls, GEMINI_PORT, REQUEST_URI_MAX_LEN};
+
+/// How long a client waits for a response by default
+pub const DEFAULT_CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The longest response header a client accepts, including the CRLF
+const MAX_HEADER_LEN: usize = "00 ".len() + Meta::MAX_LEN + "\r\n".len();
+
+/// A client certificate along with its private key
+#[derive(Debug, Clone)]
+pub struct Identity {
+    cert_chain: Vec<Certificate>,
+    key: PrivateKey,
+}
+
+impl Identity {
+    #[cfg(any(test, feature="testing"))]
+    pub(crate) fn new(cert_chain: Vec<Certificate>, key: PrivateKey) -> Self {
+        Self { cert_chain, key }
+    }
+
+    /// Use a PEM encoded certificate chain and private key
+    ///
+    /// The key may be in PKCS#8 or PKCS#1 format.  Both may also be given in the same
+    /// buffer.
+    pub fn from_pem(cert_pem: &[u8], key_pem: &[u8]) -> Result<Self> {
+        let cert_chain = tls::parse_certs(cert_pem)
+            .context("Failed to parse client certificate")?;
+        ensure!(!cert_chain.is_empty(), "No client certificate found");
+
+        let key = tls::parse_private_key(key_pem)
+            .context("Failed to parse client key")?;
+
+        Ok(Self { cert_chain, key })
+    }
+
+    /// Load a PEM encoded certificate chain and private key from files
+    pub fn from_pem_files(cert_path: impl AsRef<Path>, key_path: impl AsRef<Path>) -> Result<Self> {
+        let cert_path = cert_path.as_ref();
+        let key_path = key_path.as_ref();
+        let cert_pem = std::fs::read(cert_path)
+            .with_context(|| format!("Failed to read `{}`", cert_path.display()))?;
+        let key_pem = std::fs::read(key_path)
+            .with_context(|| format!("Failed to read `{}`", key_path.display()))?;
+
+        Self::from_pem(&cert_pem, &key_pem)
+    }
+
+    /// Load an identity stored under `key` in a [`KvStore`]
+    ///
+    /// The value must contain the PEM encoded certificate chain followed by the PEM
+    /// encoded private key.  Returns `None` if there is no such entry.
+    pub async fn from_store(store: &dyn KvStore, key: &str) -> Result<Option<Self>> {
+        let pem = match store.get(key).await? {
+            Some(pem) => pem,
+            None => return Ok(None),
+        };
+
+        Self::from_pem(&pem, &pem)
+            .with_context(|| format!("Invalid identity stored under `{}`", key))
+            .map(Some)
+    }
+
+    /// The certificate presented to servers, DER encoded
+    pub fn certificate(&self) -> &[u8] {
+        &self.cert_chain[0].0
+    }
+}
+
+/// The certificates servers presented to a client, for trust on first use
+///
+/// The first time a client connects to a host and port, the SHA-256 fingerprint of the
+/// server's certificate is stored under `known_hosts/<host>:<port>`.  Later connections
+/// fail before the request is sent if the server presents a different certificate.
+/// See [`Client::set_known_hosts()`].
+#[derive(Clone)]
+pub struct KnownHosts {
+    store: Arc<dyn KvStore>,
+}
+
+impl KnownHosts {
+    /// Remember certificates in `store`
+    pub fn new(store: impl KvStore + 'static) -> Self {
+        Self {
+            store: Arc::new(store),
+        }
+    }
+
+    /// Check `certificate`, DER encoded, against the one first seen for `host` and
+    /// `port`, remembering it if there is none yet
+    pub async fn check(&self, host: &str, port: u16, certificate: &[u8]) -> Result<()> {
+        let key = known_host_key(host, port);
+        let fingerprint = PeerCertificate::from_der(certificate.to_vec()).fingerprint();
+
+        match self.store.get(&key).await? {
+            Some(known) if known == fingerprint.as_bytes() => Ok(()),
+            Some(known) => bail!(
+                "The certificate of {}:{} changed from {} to {}",
+                host, port, String::from_utf8_lossy(&known), fingerprint,
+            ),
+            None => self.store.put(&key, fingerprint.into_bytes(), None).await,
+        }
+    }
+
+    /// Forget the certificate of `host` and `port`, e.g. after it was replaced on purpose
+    pub async fn forget(&self, host: &str, port: u16) -> Result<()> {
+        self.store.remove(&known_host_key(host, port)).await.map(drop)
+    }
+}
+
+impl fmt::Debug for KnownHosts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KnownHosts").finish_non_exhaustive()
+    }
+}
+
+fn known_host_key(host: &str, port: u16) -> String {
+    format!("known_hosts/{}:{}", host.to_ascii_lowercase(), port)
+}
+
+/// A client for making Gemini requests
+///
+/// See the [module documentation](self) for an example.
+#[derive(Debug, Clone)]
+pub struct Client {
+    timeout: Duration,
+    identity: Option<Identity>,
+    host_identities: HashMap<String, Identity>,
+    deadline: Option<Deadline>,
+    known_hosts: Option<KnownHosts>,
+    proxy_loopback: bool,
+}
+
+impl Client {
+    /// Create a client without any identities
+    pub fn new() -> Self {
+        Self {
+            timeout: DEFAULT_CLIENT_TIMEOUT,
+            identity: None,
+            host_identities: HashMap::new(),
+            deadline: None,
+            known_hosts: None,
+            proxy_loopback: false,
+        }
+    }
+
+    /// Set how long to wait for the connection and the response header
+    ///
+    /// Reading the body is not subject to this timeout.  The default is
+    /// [`DEFAULT_CLIENT_TIMEOUT`].
+    pub fn set_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Give up waiting for the connection and the response header once `deadline`
+    /// passes, even if the [timeout](Self::set_timeout()) hasn't run out yet
+    pub fn set_deadline(mut self, deadline: Deadline) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Only trust the certificate each server presented first, see [`KnownHosts`]
+    pub fn set_known_hosts(mut self, known_hosts: KnownHosts) -> Self {
+        self.known_hosts = Some(known_hosts);
+        self
+    }
+
+    /// A copy of this client for requests made while handling `request`
+    ///
+    /// The copy gives up once the [`Deadline`] of `request` passes, if it has one.
+    /// See the [module documentation](self) for an example.
+    pub fn for_request(&self, request: &Request) -> Self {
+        let mut client = self.clone();
+        if let Some(deadline) = Deadline::of(request) {
+            client.deadline = Some(deadline);
+        }
+        client
+    }
+
+    /// How long to wait for the connection and the response header from now on
+    fn remaining_timeout(&self) -> Duration {
+        match self.deadline {
+            Some(deadline) => self.timeout.min(deadline.remaining()),
+            None => self.timeout,
+        }
+    }
+
+    /// Relay [forwarded](Self::forward()) requests to loopback and unspecified addresses
+    ///
+    /// By default, requests for hosts resolving to such addresses are refused, since
+    /// they likely lead back to the proxy itself, which would relay them again and
+    /// again.  Requests for the address they were received on are refused either way.
+    pub fn set_proxy_loopback(mut self, allowed: bool) -> Self {
+        self.proxy_loopback = allowed;
+        self
+    }
+
+    /// Present `identity` to every host without an identity of its own
+    pub fn set_identity(mut self, identity: Identity) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    /// Present `identity` to `host`, instead of the default identity
+    pub fn set_host_identity(mut self, host: impl Into<String>, identity: Identity) -> Self {
+        self.host_identities.insert(host.into().to_ascii_lowercase(), identity);
+        self
+    }
+
+    /// The identity presented to `host`, if any
+    pub fn identity_for(&self, host: &str) -> Option<&Identity> {
+        self.host_identities.get(&host.to_ascii_lowercase())
+            .or(self.identity.as_ref())
+    }
+
+    /// Request `url`, presenting the identity configured for its host
+    pub async fn request(&self, url: &str) -> Result<ClientResponse> {
+        let uri = parse_url(url)?;
+        let host = host(&uri)?;
+        let identity = self.identity_for(&host);
+
+        self.request_uri(&uri, identity, None).await
+    }
+
+    /// Request `url`, presenting `identity` instead of the configured identities
+    pub async fn request_with_identity(&self, url: &str, identity: Option<&Identity>) -> Result<ClientResponse> {
+        let uri = parse_url(url)?;
+
+        self.request_uri(&uri, identity, None).await
+    }
+
+    /// Request `uri`, without connecting to any address `guard` refuses
+    async fn request_uri(
+        &self,
+        uri: &URIReference<'_>,
+        identity: Option<&Identity>,
+        guard: Option<&ProxyGuard>,
+    ) -> Result<ClientResponse> {
+        let host = host(uri)?;
+        let port = uri.port().unwrap_or(GEMINI_PORT);
+        let connector = TlsConnector::from(Arc::new(tls_config(identity)?));
+
+        // Hosts which are neither DNS names nor IP addresses get a placeholder name.  This
+        // is fine since the server certificate isn't verified against the name anyway.
+        let name = rustls::ServerName::try_from(host.as_str())
+            .or_else(|_| rustls::ServerName::try_from("localhost"))
+            .expect("twinstar BUG");
+
+        let connect = async {
+            let addrs = lookup_host((host.as_str(), port)).await
+                .with_context(|| format!("Failed to resolve {}", host))?
+                .collect::<Vec<_>>();
+
+            // The addresses are checked and connected to as resolved once, so a second
+            // lookup can't resolve to a different address
+            if let Some(refused) = guard.and_then(|guard| addrs.iter().find(|addr| guard.refuses(**addr))) {
+                return Err(RefusedTarget(*refused).into());
+            }
+
+            let stream = TcpStream::connect(addrs.as_slice()).await
+                .with_context(|| format!("Failed to connect to {}:{}", host, port))?;
+            let stream = connector.connect(name, stream).await
+                .with_context(|| format!("Failed to establish TLS session with {}", host))?;
+
+            if let Some(known_hosts) = &self.known_hosts {
+                let certificate = stream.get_ref().1.peer_certificates()
+                    .and_then(|certificates| certificates.first())
+                    .ok_or_else(|| anyhow!("{} presented no certificate", host))?;
+                known_hosts.check(&host, port, &certificate.0).await?;
+            }
+            let mut stream = AsyncBufReader::new(stream);
+
+            stream.write_all(format!("{}\r\n", uri).as_bytes()).await
+                .context("Failed to send request")?;
+            stream.flush().await
+                .context("Failed to send request")?;
+
+            let mut line = Vec::new();
+            (&mut stream).take(MAX_HEADER_LEN as u64).read_until(b'\n', &mut line).await
+                .context("Failed to receive response header")?;
+            let header = parse_header(&line)?;
+
+            Ok::<_, anyhow::Error>((header, stream))
+        };
+
+        let (header, stream) = timeout(self.remaining_timeout(), connect).await
+            .with_context(|| format!("Timed out waiting for {}", host))??;
+
+        let body = if header.status.is_success() {
+            Some(Box::new(stream) as BodyReader)
+        } else {
+            None
+        };
+
+        Ok(ClientResponse { header, body })
+    }
+}
+
+impl Client {
+    /// Relay `request` to the server it is for, and answer with its response
+    ///
+    /// The response is passed on as it is, with a successful response's body relayed
+    /// as it arrives.  Requests which can't be relayed, like requests for other schemes,
+    /// are answered with `53 PROXY REQUEST REFUSED`, and failures to reach the server
+    /// with `43 PROXY ERROR`.  The client certificate of `request` can't be relayed, so
+    /// the configured identities are presented instead.
+    ///
+    /// To keep a proxy from relaying requests to itself, requests for the address
+    /// `request` was received on are refused, and so are requests for loopback addresses
+    /// unless [allowed](Self::set_proxy_loopback()).  Why a request failed is only
+    /// logged, and not told to the client, as it may reveal hosts behind the proxy.
+    pub async fn forward(&self, request: &Request) -> Response {
+        let url = request.uri().to_string();
+        let uri = match parse_url(&url) {
+            Ok(uri) => uri,
+            Err(_) => return Response::new(ResponseHeader {
+                status: Status::PROXY_REQUEST_REFUSED,
+                meta: Meta::new_lossy("Only gemini requests can be proxied"),
+            }),
+        };
+        let identity = host(&uri).ok().and_then(|host| self.identity_for(&host));
+
+        let guard = ProxyGuard {
+            own_addr: request.local_addr(),
+            allow_loopback: self.proxy_loopback,
+        };
+
+        match self.for_request(request).request_uri(&uri, identity, Some(&guard)).await {
+            Ok(ClientResponse { header, body: Some(body) }) => Response::new(header).with_body(Body::Reader(body)),
+            Ok(ClientResponse { header, body: None }) => Response::new(header),
+            Err(err) if err.is::<RefusedTarget>() => {
+                debug!("Refusing to relay request for {}: {:#}", url, err);
+                Response::new(ResponseHeader {
+                    status: Status::PROXY_REQUEST_REFUSED,
+                    meta: Meta::new_lossy("Refusing to relay the request back to this server"),
+                })
+            },
+            Err(err) => {
+                debug!("Failed to relay request for {}: {:#}", url, err);
+                Response::new(ResponseHeader {
+                    status: Status::PROXY_ERROR,
+                    meta: Meta::new_lossy("Failed to reach the server"),
+                })
+            },
+        }
+    }
+
+    /// A handler [forwarding](Self::forward()) every request with this client
+    ///
+    /// See [`Builder::set_proxy_handler()`](crate::Builder::set_proxy_handler()).
+    pub fn into_proxy_handler(self) -> BoxedHandler {
+        Box::new(move |request| {
+            let client = self.clone();
+            Box::pin(async move { Ok(client.forward(&request).await) })
+        })
+    }
+}
+
+/// The addresses a forwarded request must not be relayed to, since they likely lead
+/// back to the proxy
+struct ProxyGuard {
+    own_addr: Option<SocketAddr>,
+    allow_loopback: bool,
+}
+
+impl ProxyGuard {
+    fn refuses(&self, addr: SocketAddr) -> bool {
+        let ip = canonical_ip(addr.ip());
+        let is_own = matches!(self.own_addr, Some(own) if canonical_ip(own.ip()) == ip && own.port() == addr.port());
+
+        is_own || (!self.allow_loopback && (ip.is_loopback() || ip.is_unspecified()))
+    }
+}
+
+/// The IPv4 address an IPv4-mapped IPv6 address stands for, or the address as is
+///
+/// Servers listening on both IPv4 and IPv6 see IPv4 clients with mapped addresses.
+fn canonical_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(ip) => ip.to_ipv4_mapped().map_or(IpAddr::V6(ip), IpAddr::V4),
+        ip => ip,
+    }
+}
+
+/// A forwarded request was for an address refused by a [`ProxyGuard`]
+#[derive(Debug)]
+struct RefusedTarget(SocketAddr);
+
+impl fmt::Display for RefusedTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} likely leads back to this server", self.0)
+    }
+}
+
+impl std::error::Error for RefusedTarget {}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The body of a response, read from the connection as it arrives
+pub type BodyReader = Box<dyn AsyncRead + Send + Sync + Unpin>;
+
+/// A response received by a [`Client`]
+///
+/// Only successful (`2x`) responses have a body.  The body is read from the
+/// connection, so it is consumed by reading it.
+pub struct ClientResponse {
+    header: ResponseHeader,
+    body: Option<BodyReader>,
+}
+
+impl ClientResponse {
+    /// The header of the response
+    pub const fn header(&self) -> &ResponseHeader {
+        &self.header
+    }
+
+    /// The status of the response
+    pub const fn status(&self) -> Status {
+        self.header.status
+    }
+
+    /// The meta of the response, e.g. the MIME of successful responses
+    pub fn meta(&self) -> &str {
+        self.header.meta.as_str()
+    }
+
+    /// The MIME of a successful response
+    pub fn mime(&self) -> Result<Mime> {
+        ensure!(self.status().is_success(), "Response has no body, its status is {}", self.status().code());
+
+        self.header.meta.to_mime()
+    }
+
+    /// Read the body as it arrives, if the response has one
+    pub fn body_reader(self) -> Option<BodyReader> {
+        self.body
+    }
+
+    /// Read the whole body
+    ///
+    /// Fails if the response isn't successful.
+    pub async fn body_bytes(self) -> Result<Vec<u8>> {
+        let status = self.status();
+        let mut body = self.body
+            .with_context(|| format!("Response has no body, its status is {}", status.code()))?;
+
+        let mut bytes = Vec::new();
+        body.read_to_end(&mut bytes).await
+            .context("Failed to receive response body")?;
+
+        Ok(bytes)
+    }
+
+    /// Read the whole body as text
+    ///
+    /// The body is decoded according to the `charset` parameter of the MIME, which
+    /// defaults to UTF-8.  Other charsets require the `charset` feature.
+    pub async fn body_string(self) -> Result<String> {
+        let mime = self.mime()?;
+        let bytes = self.body_bytes().await?;
+
+        decode(bytes, &mime)
+    }
+
+    /// Read the whole body as a gemtext document
+    ///
+    /// Fails if the response isn't a successful `text/gemini` response.
+    pub async fn body_document(self) -> Result<Document> {
+        let mime = self.mime()?;
+        ensure!(mime.essence_str() == crate::GEMINI_MIME_STR, "Response is `{}`, not gemtext", mime);
+
+        let text = self.body_string().await?;
+
+        Ok(Document::parse(&text))
+    }
+}
+
+/// Decode a text body according to the charset of its MIME
+fn decode(bytes: Vec<u8>, mime: &Mime) -> Result<String> {
+    let charset = mime.get_param(mime::CHARSET)
+        .map(|charset| charset.as_str().to_ascii_lowercase());
+
+    match charset.as_deref() {
+        None | Some("utf-8") | Some("utf8") | Some("us-ascii") => {
+            String::from_utf8(bytes).context("Response body is not valid UTF-8")
+        },
+        Some(charset) => decode_charset(bytes, charset),
+    }
+}
+
+#[cfg(feature="charset")]
+fn decode_charset(bytes: Vec<u8>, charset: &str) -> Result<String> {
+    let encoding = encoding_rs::Encoding::for_label(charset.as_bytes())
+        .with_context(|| format!("Unknown charset `{}`", charset))?;
+
+    Ok(encoding.decode(&bytes).0.into_owned())
+}
+
+#[cfg(not(feature="charset"))]
+fn decode_charset(_bytes: Vec<u8>, charset: &str) -> Result<String> {
+    bail!("Decoding `{}` requires the `charset` feature", charset)
+}
+
+fn parse_url(url: &str) -> Result<URIReference<'static>> {
+    ensure!(url.len() <= REQUEST_URI_MAX_LEN, "URL is longer than {} bytes", REQUEST_URI_MAX_LEN);
+
+    let uri = URIReference::try_from(url)
+        .with_context(|| format!("Invalid URL `{}`", url))?
+        .into_owned();
+
+    match uri.scheme() {
+        Some(scheme) if scheme.as_str().eq_ignore_ascii_case("gemini") => Ok(uri),
+        _ => bail!("Not a gemini URL: `{}`", url),
+    }
+}
+
+fn host(uri: &URIReference<'_>) -> Result<String> {
+    let host = uri.host()
+        .with_context(|| format!("URL `{}` has no host", uri))?
+        .to_string();
+
+    // IPv6 addresses are written in brackets
+    Ok(host.trim_start_matches('[').trim_end_matches(']').to_owned())
+}
+
+pub(crate) fn tls_config(identity: Option<&Identity>) -> Result<ClientConfig> {
+    tls::client_config(identity.map(|identity| (identity.cert_chain.clone(), identity.key.clone())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(id: u8) -> Identity {
+        Identity {
+            cert_chain: vec![Certificate(vec![id])],
+            key: PrivateKey(vec![id]),
+        }
+    }
+
+    #[test]
+    fn picks_identities_by_host() {
+        let client = Client::new()
+            .set_identity(identity(1))
+            .set_host_identity("Example.org", identity(2));
+
+        assert_eq!(client.identity_for("example.org").unwrap().certificate(), [2]);
+        assert_eq!(client.identity_for("example.com").unwrap().certificate(), [1]);
+        assert!(Client::new().identity_for("example.org").is_none());
+
+        assert!(Identity::from_pem(b"not a certificate", b"not a key").is_err());
+    }
+
+    #[tokio::test]
+    async fn trusts_certificates_on_first_use() {
+        let known_hosts = KnownHosts::new(crate::storage::MemoryStore::new());
+
+        known_hosts.check("Example.org", 1965, b"first").await.unwrap();
+        known_hosts.check("example.org", 1965, b"first").await.unwrap();
+        assert!(known_hosts.check("example.org", 1965, b"second").await.is_err());
+        known_hosts.check("example.org", 1966, b"second").await.unwrap();
+
+        known_hosts.forget("example.org", 1965).await.unwrap();
+        known_hosts.check("example.org", 1965, b"second").await.unwrap();
+    }
+
+    #[test]
+    fn limits_timeout_to_deadline() {
+        let client = Client::new().set_timeout(Duration::from_secs(10));
+        assert_eq!(client.remaining_timeout(), Duration::from_secs(10));
+
+        let mut request = Request::from_uri(URIReference::try_from("gemini://localhost/").unwrap().into_owned()).unwrap();
+        assert_eq!(client.for_request(&request).remaining_timeout(), Duration::from_secs(10));
+
+        request.extensions_mut().insert(Deadline::after(Duration::from_secs(2)));
+        let remaining = client.for_request(&request).remaining_timeout();
+        assert!(remaining <= Duration::from_secs(2) && remaining > Duration::from_secs(1));
+
+        let expired = client.set_deadline(Deadline::after(Duration::from_secs(0)));
+        assert_eq!(expired.remaining_timeout(), Duration::ZERO);
+    }
+
+    fn response(header: &[u8], body: &'static [u8]) -> ClientResponse {
+        let header = parse_header(header).unwrap();
+        let body = if header.status.is_success() { Some(Box::new(body) as BodyReader) } else { None };
+
+        ClientResponse { header, body }
+    }
+
+    #[tokio::test]
+    async fn reads_bodies() {
+        let document = response(b"20 text/gemini\r\n", b"# Hi\n=> /next Next\n").body_document().await.unwrap();
+        assert_eq!(document.links().next().unwrap().1, Some("Next"));
+
+        assert!(response(b"20 text/plain\r\n", b"# Hi").body_document().await.is_err());
+        assert_eq!(response(b"20 text/plain\r\n", b"Hi").body_string().await.unwrap(), "Hi");
+        assert!(response(b"20 text/plain\r\n", b"\xff").body_string().await.is_err());
+        assert!(response(b"51 Not found\r\n", b"").body_bytes().await.is_err());
+
+        let not_found = response(b"51 Not found\r\n", b"");
+        assert_eq!(not_found.status(), Status::NOT_FOUND);
+        assert_eq!(not_found.meta(), "Not found");
+        assert!(not_found.body_reader().is_none());
+    }
+
+    #[test]
+    fn parses_urls() {
+        let uri = parse_url("gemini://[::1]:1966/path?query").unwrap();
+        assert_eq!(host(&uri).unwrap(), "::1");
+        assert_eq!(uri.port(), Some(1966));
+
+        assert!(parse_url("https://example.org/").is_err());
+        assert!(parse_url("/relative").is_err());
+    }
+}
@@ -0,0 +1,146 @@
+//! Deciding which client certificates are accepted during the TLS handshake
+//!
+//! Gemini clients usually create self-signed certificates, so by default the server
+//! accepts any certificate, and leaves it to handlers to decide what a certificate is
+//! good for.  Servers which only serve known clients can instead set a
+//! [`ClientCertPolicy`] using
+//! [`Builder::set_client_cert_verifier()`](crate::Builder::set_client_cert_verifier()).
+//! Certificates rejected by the policy fail the TLS handshake, so their requests never
+//! reach the server.
+//!
+//! [`SignedBy`] only accepts certificates issued by one of a set of certificate
+//! authorities, e.g. an internal CA:
+//!
+//! ```no_run
+//! # use twinstar::{Server, GEMINI_PORT, client_cert::SignedBy};
+//! # async fn run() -> anyhow::Result<()> {
+//! Server::bind(("localhost", GEMINI_PORT))
+//!     .set_client_cert_verifier(SignedBy::from_pem_file("internal-ca.pem")?)
+//!     .serve()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Clients without a certificate are always let through, so they can be asked for one
+//! with `60 CLIENT CERTIFICATE REQUIRED`.  When a policy is set, clients also have to
+//! prove that they hold the key of their certificate, which the default skips to stay
+//! compatible with unusual self-signed certificates.
+
+use std::convert::TryFrom;
+use std::path::Path;
+use std::time::SystemTime;
+
+use anyhow::{Result, Context, anyhow, ensure};
+
+use crate::tls;
+use crate::types::PeerCertificate;
+
+/// Decides whether a certificate chain presented by a client is accepted
+///
+/// This is implemented for all functions and closures taking the chain and returning a
+/// `Result`, so simple policies don't need a dedicated type.
+pub trait ClientCertPolicy: Send + Sync + 'static {
+    /// Accept or reject a chain, which starts with the client's own certificate
+    ///
+    /// The error is logged, and the TLS handshake fails.
+    fn verify(&self, chain: &[PeerCertificate]) -> Result<()>;
+}
+
+impl<F> ClientCertPolicy for F
+where
+    F: Fn(&[PeerCertificate]) -> Result<()> + Send + Sync + 'static,
+{
+    fn verify(&self, chain: &[PeerCertificate]) -> Result<()> {
+        (self)(chain)
+    }
+}
+
+/// Signature algorithms accepted in client certificate chains
+static SIGNATURE_ALGORITHMS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::ECDSA_P256_SHA384,
+    &webpki::ECDSA_P384_SHA256,
+    &webpki::ECDSA_P384_SHA384,
+    &webpki::ED25519,
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA512,
+    &webpki::RSA_PKCS1_3072_8192_SHA384,
+];
+
+/// Only accepts certificates issued by one of a set of certificate authorities
+///
+/// Certificates also have to be valid at the time of the request.  See the
+/// [module documentation](self) for an example.
+#[derive(Debug, Clone)]
+pub struct SignedBy {
+    authorities: Vec<PeerCertificate>,
+}
+
+impl SignedBy {
+    /// Accept certificates issued by any of `authorities`
+    pub fn new(authorities: Vec<PeerCertificate>) -> Self {
+        Self { authorities }
+    }
+
+    /// Accept certificates issued by any of the PEM encoded `authorities`
+    pub fn from_pem(authorities: &[u8]) -> Result<Self> {
+        let authorities = tls::parse_certs(authorities)?;
+        ensure!(!authorities.is_empty(), "No certificate authority found");
+
+        Ok(Self::new(authorities.into_iter().map(|cert| PeerCertificate::from_der(cert.0)).collect()))
+    }
+
+    /// Accept certificates issued by any of the authorities in a PEM file
+    pub fn from_pem_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let pem = std::fs::read(path)
+            .with_context(|| format!("Failed to read `{}`", path.display()))?;
+
+        Self::from_pem(&pem)
+            .with_context(|| format!("Failed to load certificate authorities from `{}`", path.display()))
+    }
+}
+
+impl ClientCertPolicy for SignedBy {
+    fn verify(&self, chain: &[PeerCertificate]) -> Result<()> {
+        let (certificate, intermediates) = chain.split_first()
+            .ok_or_else(|| anyhow!("No client certificate"))?;
+        let intermediates = intermediates.iter().map(PeerCertificate::as_der).collect::<Vec<_>>();
+        let anchors = self.authorities.iter()
+            .filter_map(|authority| webpki::TrustAnchor::try_from_cert_der(authority.as_der()).ok())
+            .collect::<Vec<_>>();
+        let now = webpki::Time::try_from(SystemTime::now())
+            .map_err(|_| anyhow!("The system time is before 1970"))?;
+
+        webpki::EndEntityCert::try_from(certificate.as_der())
+            .and_then(|certificate| certificate.verify_for_usage(
+                SIGNATURE_ALGORITHMS,
+                &anchors,
+                &intermediates,
+                now,
+                webpki::KeyUsage::client_auth(),
+                &[],
+            ))
+            .map_err(|err| anyhow!("Client certificate rejected: {}", err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestCertificate;
+
+    #[test]
+    fn verifies_issuers() {
+        let alice = TestCertificate::new("alice").generate().unwrap();
+        let mallory = TestCertificate::new("mallory").generate().unwrap();
+
+        let policy = SignedBy::from_pem(alice.certificate_pem().as_bytes()).unwrap();
+        assert!(policy.verify(&[alice.certificate().clone()]).is_ok());
+        assert!(policy.verify(&[mallory.certificate().clone()]).is_err());
+        assert!(policy.verify(&[]).is_err());
+        assert!(SignedBy::from_pem(b"").is_err());
+    }
+}
@@ -0,0 +1,220 @@
+//! A summary of how a server is configured
+//!
+//! See [`Server::describe()`](crate::Server::describe()).  This module requires the
+//! `description` feature.
+
+use std::fmt::{self, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::util::{json_list, json_string};
+
+/// The cargo features twinstar was compiled with
+const FEATURES: &[(&str, bool)] = &[
+    ("std", cfg!(feature="std")),
+    ("serve_dir", cfg!(feature="serve_dir")),
+    ("routing", cfg!(feature="routing")),
+    ("handler", cfg!(feature="handler")),
+    ("rate_limit", cfg!(feature="rate_limit")),
+    ("maintenance", cfg!(feature="maintenance")),
+    ("drain", cfg!(feature="drain")),
+    ("description", cfg!(feature="description")),
+    ("failures", cfg!(feature="failures")),
+    ("titan", cfg!(feature="titan")),
+    ("client", cfg!(feature="client")),
+    ("document_parse", cfg!(feature="document_parse")),
+    ("auth", cfg!(feature="auth")),
+    ("metrics", cfg!(feature="metrics")),
+    ("route_debug", cfg!(feature="route_debug")),
+    ("blocking", cfg!(feature="blocking")),
+    ("charset", cfg!(feature="charset")),
+    ("file_store", cfg!(feature="file_store")),
+    ("sled_store", cfg!(feature="sled_store")),
+    ("storage", cfg!(feature="storage")),
+    ("logging", cfg!(feature="logging")),
+    ("events", cfg!(feature="events")),
+    ("tarpit", cfg!(feature="tarpit")),
+    ("middleware", cfg!(feature="middleware")),
+    ("load_shedding", cfg!(feature="load_shedding")),
+    ("fingerprint", cfg!(feature="fingerprint")),
+    ("trusted_proxies", cfg!(feature="trusted_proxies")),
+    ("multi_tenant", cfg!(feature="multi_tenant")),
+    ("windows-service", cfg!(feature="windows-service")),
+    ("geoip", cfg!(feature="geoip")),
+    ("mmap", cfg!(feature="mmap")),
+    ("bench", cfg!(feature="bench")),
+    ("uuid", cfg!(feature="uuid")),
+    ("chrono", cfg!(feature="chrono")),
+    ("testing", cfg!(feature="testing")),
+    ("x509", cfg!(feature="x509")),
+    ("generate_cert", cfg!(feature="generate_cert")),
+    ("unicode_normalization", cfg!(feature="unicode_normalization")),
+];
+
+/// A machine-readable summary of a server's configuration
+///
+/// This is meant for verifying a deployment without reading its code.  The
+/// [`Display`](fmt::Display) implementation produces a short human-readable report,
+/// which is logged when the server starts, while [`to_json()`](Self::to_json())
+/// produces a JSON object for tooling.
+///
+/// Twinstar doesn't support virtual hosts, so every route is served for every hostname
+/// the server is reached under, unless it is limited to some hostnames with
+/// [`Builder::set_hostnames()`](crate::Builder::set_hostnames()).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ServerDescription {
+    /// The version of twinstar the server is running
+    pub version: &'static str,
+    /// The addresses the server is accepting connections on
+    pub listen_addrs: Vec<SocketAddr>,
+    /// The paths of all registered routes, sorted
+    pub routes: Vec<String>,
+    /// How TLS is set up
+    pub tls: TlsDescription,
+    /// How long clients have to send their request and receive simple responses
+    pub timeout: Duration,
+    /// How long clients have to receive complex response bodies, if limited
+    pub complex_body_timeout: Option<Duration>,
+    /// Whether load shedding is enabled
+    pub load_shedding: bool,
+    /// Whether access and error records are logged to a sink
+    pub access_log: bool,
+    /// The optional cargo features twinstar was compiled with
+    pub features: Vec<&'static str>,
+}
+
+/// The TLS part of a [`ServerDescription`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct TlsDescription {
+    /// Where the certificate was loaded from, `<memory>` if it was given as bytes, or
+    /// `<custom>` with a custom TLS config
+    pub cert_path: PathBuf,
+    /// Where the private key was loaded from, `<memory>` if it was given as bytes, or
+    /// `<custom>` with a custom TLS config
+    pub key_path: PathBuf,
+    /// The TLS versions the server accepts, e.g. `TLSv1_3`
+    pub versions: Vec<String>,
+    /// Whether clients may present a certificate
+    pub client_certificates: bool,
+}
+
+impl ServerDescription {
+    pub(crate) fn enabled_features() -> Vec<&'static str> {
+        FEATURES.iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| *name)
+            .collect()
+    }
+
+    /// Render the description as a JSON object
+    ///
+    /// Durations are given in milliseconds, with `null` meaning unlimited.
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+
+        json.push('{');
+        let _ = write!(json, "\"version\":{},", json_string(self.version));
+        let _ = write!(json, "\"listen_addrs\":{},", json_list(self.listen_addrs.iter().map(ToString::to_string)));
+        let _ = write!(json, "\"routes\":{},", json_list(self.routes.iter().cloned()));
+        let _ = write!(json, "\"route_count\":{},", self.routes.len());
+        let _ = write!(
+            json,
+            "\"tls\":{{\"cert_path\":{},\"key_path\":{},\"versions\":{},\"client_certificates\":{}}},",
+            json_string(&self.tls.cert_path.to_string_lossy()),
+            json_string(&self.tls.key_path.to_string_lossy()),
+            json_list(self.tls.versions.iter().cloned()),
+            self.tls.client_certificates,
+        );
+        let _ = write!(json, "\"timeout_ms\":{},", self.timeout.as_millis());
+        let _ = match self.complex_body_timeout {
+            Some(timeout) => write!(json, "\"complex_body_timeout_ms\":{},", timeout.as_millis()),
+            None => write!(json, "\"complex_body_timeout_ms\":null,"),
+        };
+        let _ = write!(json, "\"load_shedding\":{},", self.load_shedding);
+        let _ = write!(json, "\"access_log\":{},", self.access_log);
+        let _ = write!(json, "\"features\":{}", json_list(self.features.iter().map(|f| f.to_string())));
+        json.push('}');
+
+        json
+    }
+}
+
+impl fmt::Display for ServerDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let addrs = self.listen_addrs.iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        let enabled = |enabled| if enabled { "enabled" } else { "disabled" };
+
+        writeln!(f, "twinstar {}", self.version)?;
+        writeln!(f, "listening on: {}", addrs.join(", "))?;
+        writeln!(f, "routes ({}): {}", self.routes.len(), self.routes.join(", "))?;
+        writeln!(
+            f,
+            "tls: {}, certificate `{}`, key `{}`, client certificates {}",
+            self.tls.versions.join(", "),
+            self.tls.cert_path.display(),
+            self.tls.key_path.display(),
+            if self.tls.client_certificates { "accepted" } else { "refused" },
+        )?;
+        match self.complex_body_timeout {
+            Some(complex) => writeln!(f, "timeouts: {:?}, complex bodies {:?}", self.timeout, complex)?,
+            None => writeln!(f, "timeouts: {:?}, complex bodies unlimited", self.timeout)?,
+        }
+        writeln!(f, "load shedding: {}", enabled(self.load_shedding))?;
+        writeln!(f, "access log: {}", enabled(self.access_log))?;
+        write!(f, "features: {}", self.features.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_json() {
+        let description = ServerDescription {
+            version: "0.4.0",
+            listen_addrs: vec!["127.0.0.1:1965".parse().unwrap()],
+            routes: vec!["/".to_owned(), "/say \"hi\"".to_owned()],
+            tls: TlsDescription {
+                cert_path: "cert/cert.pem".into(),
+                key_path: "cert/key.pem".into(),
+                versions: vec!["TLSv1_3".to_owned()],
+                client_certificates: true,
+            },
+            timeout: Duration::from_secs(1),
+            complex_body_timeout: None,
+            load_shedding: false,
+            access_log: true,
+            features: vec!["serve_dir"],
+        };
+
+        assert_eq!(description.to_json(), concat!(
+            r#"{"version":"0.4.0","listen_addrs":["127.0.0.1:1965"],"routes":["/","/say \"hi\""],"#,
+            r#""route_count":2,"tls":{"cert_path":"cert/cert.pem","key_path":"cert/key.pem","#,
+            r#""versions":["TLSv1_3"],"client_certificates":true},"timeout_ms":1000,"#,
+            r#""complex_body_timeout_ms":null,"load_shedding":false,"access_log":true,"#,
+            r#""features":["serve_dir"]}"#,
+        ));
+    }
+
+    #[test]
+    fn lists_every_feature() {
+        let manifest = include_str!("../Cargo.toml");
+        let features = manifest
+            .split("\n[features]\n").nth(1).unwrap()
+            .split("\n[").next().unwrap()
+            .lines()
+            .filter_map(|line| line.split_once(" = "))
+            .map(|(name, _)| name)
+            .filter(|&name| name != "default");
+
+        for feature in features {
+            assert!(FEATURES.iter().any(|(name, _)| *name == feature), "`{}` is missing from FEATURES", feature);
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! Finishing the connections in flight when a server shuts down
+//!
+//! Once the shutdown future passed to [`Builder::serve_until()`](crate::Builder::serve_until())
+//! completes, the server stops accepting connections, and drains the ones it already
+//! accepted before returning.  How long a connection may take to finish depends on the
+//! [`DrainPolicy`] of the route it requested, so drains during deploys are fast, without
+//! cutting off transfers which are about to finish:
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use twinstar::{Server, Shutdown, GEMINI_PORT, drain::DrainPolicy};
+//! # async fn run() -> anyhow::Result<()> {
+//! let shutdown = Shutdown::new();
+//!
+//! Server::bind(("localhost", GEMINI_PORT))
+//!     .set_drain_policy("/", DrainPolicy::Finish)
+//!     .set_drain_policy("/chat", DrainPolicy::Cancel)
+//!     .set_drain_policy("/files", DrainPolicy::FinishWithin(Duration::from_secs(10)))
+//!     .serve_until(shutdown.wait())
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Connections which haven't sent their request yet are given the time of the regular
+//! [timeout](crate::Builder::set_timeout()) to do so, and are then drained according
+//! to the route they requested.
+//!
+//! This module requires the `drain` feature.  Without it, connections in flight are
+//! always finished.
+
+use std::convert::TryFrom;
+use std::future::{self, Future};
+use std::sync::Mutex;
+use std::task::Poll;
+use std::time::{Duration, Instant};
+
+use uriparse::path::Path;
+
+use crate::routing::RoutingNode;
+use crate::types::Request;
+
+/// How long connections to routes without a drain policy may take to finish
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What happens to a connection still in flight when the server shuts down
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DrainPolicy {
+    /// Close the connection right away, e.g. for streams which never end
+    Cancel,
+    /// Let the connection finish, but close it if it takes longer than this, e.g. for
+    /// large static files
+    FinishWithin(Duration),
+    /// Let the connection finish however long it takes, e.g. for short dynamic pages
+    Finish,
+}
+
+impl Default for DrainPolicy {
+    /// Finishing within [`DEFAULT_DRAIN_TIMEOUT`]
+    fn default() -> Self {
+        Self::FinishWithin(DEFAULT_DRAIN_TIMEOUT)
+    }
+}
+
+/// The drain policies of routes, matched the same way as handlers
+#[derive(Default)]
+pub(crate) struct DrainPolicies {
+    routes: RoutingNode<DrainPolicy>,
+}
+
+impl DrainPolicies {
+    /// Set the policy of `route_prefix`, replacing any previous one
+    ///
+    /// Panics if `route_prefix` isn't a valid path, like [`RoutingNode::add_route()`].
+    pub fn set(&mut self, route_prefix: &str, policy: DrainPolicy) {
+        let path = Path::try_from(route_prefix)
+            .expect("Malformed path route received")
+            .into_owned();
+
+        self.routes.remove_route_by_path(path.clone());
+        self.routes.add_route_by_path(path, policy)
+            .expect("twinstar BUG");
+    }
+
+    /// The policy of the route matching `request`
+    pub fn policy_for(&self, request: &Request) -> DrainPolicy {
+        self.routes.match_request(request)
+            .map(|(_, policy)| *policy)
+            .unwrap_or_default()
+    }
+}
+
+/// Run `connection` to completion, unless `draining` completes and the current
+/// `policy` of the connection says to close it first
+///
+/// The policy can change while the connection runs, e.g. once its request has been
+/// read.  Returns `None` if the connection was closed.
+pub(crate) async fn drain_with<F: Future>(
+    connection: F,
+    draining: impl Future<Output = ()>,
+    policy: &Mutex<DrainPolicy>,
+) -> Option<F::Output> {
+    let mut connection = Box::pin(connection);
+    let mut draining = Box::pin(draining);
+    let mut drain_started = None;
+    let mut timer = None;
+
+    future::poll_fn(|cx| {
+        if let Poll::Ready(output) = connection.as_mut().poll(cx) {
+            return Poll::Ready(Some(output));
+        }
+
+        let drain_started = match drain_started {
+            Some(started) => started,
+            None => match draining.as_mut().poll(cx) {
+                Poll::Ready(()) => *drain_started.get_or_insert_with(Instant::now),
+                Poll::Pending => return Poll::Pending,
+            },
+        };
+
+        match *policy.lock().expect("twinstar BUG") {
+            DrainPolicy::Cancel => Poll::Ready(None),
+            DrainPolicy::Finish => Poll::Pending,
+            DrainPolicy::FinishWithin(limit) => {
+                let deadline = tokio::time::Instant::from_std(drain_started + limit);
+                let timer = timer.get_or_insert_with(|| Box::pin(tokio::time::sleep_until(deadline)));
+                timer.as_mut().poll(cx).map(|()| None)
+            },
+        }
+    }).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn drains_by_policy() {
+        let slow = || tokio::time::sleep(Duration::from_millis(50));
+        let drain = |connection, policy| async move {
+            drain_with(connection, async {}, &Mutex::new(policy)).await
+        };
+
+        assert_eq!(drain(slow(), DrainPolicy::Cancel).await, None);
+        assert_eq!(drain(slow(), DrainPolicy::Finish).await, Some(()));
+        assert_eq!(drain(slow(), DrainPolicy::FinishWithin(Duration::from_secs(5))).await, Some(()));
+        assert_eq!(drain(slow(), DrainPolicy::FinishWithin(Duration::from_millis(5))).await, None);
+
+        // Without draining, connections always finish
+        let finished = drain_with(slow(), future::pending(), &Mutex::new(DrainPolicy::Cancel)).await;
+        assert_eq!(finished, Some(()));
+    }
+}
@@ -0,0 +1,143 @@
+use std::fmt;
+use std::io;
+
+/// An error returned by the server
+///
+/// Unlike an [`anyhow::Error`], this can be matched on to find out what went wrong,
+/// e.g. to retry binding the socket, but to give up on a broken certificate.  Most
+/// variants carry an [`anyhow::Error`] with the details, including the context of what
+/// the server was doing at the time.
+///
+/// This converts into an [`anyhow::Error`] using `?`, so applications using anyhow
+/// can keep doing so.  Handlers keep returning [`anyhow::Result`].
+///
+/// ```no_run
+/// # use twinstar::{Server, Error, GEMINI_PORT};
+/// # async fn run() -> anyhow::Result<()> {
+/// match Server::bind(("localhost", GEMINI_PORT)).serve().await {
+///     Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::AddrInUse => {
+///         eprintln!("Another server is already running");
+///     },
+///     result => result?,
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Loading the certificate or key, or setting up TLS failed
+    Tls(anyhow::Error),
+    /// Binding or accepting on the socket failed
+    Io(io::Error),
+    /// Something given to the server couldn't be parsed, e.g. a route or meta
+    Parse(anyhow::Error),
+    /// The routes are invalid, e.g. because the same route was added twice
+    Routing(anyhow::Error),
+    /// A handler or middleware failed
+    Handler(anyhow::Error),
+    /// Any other error
+    Other(anyhow::Error),
+}
+
+impl Error {
+    /// An I/O error, with `context` describing what failed
+    ///
+    /// The kind of `error` is kept, so it can still be matched on, and `error` itself
+    /// becomes the source.
+    pub(crate) fn io(context: &str) -> impl FnOnce(io::Error) -> Self + '_ {
+        move |error| Self::Io(io::Error::new(error.kind(), IoContext {
+            context: context.to_owned(),
+            source: error,
+        }))
+    }
+}
+
+/// The context added by [`Error::io`], with the original error as its source
+#[derive(Debug)]
+struct IoContext {
+    context: String,
+    source: io::Error,
+}
+
+impl fmt::Display for IoContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.context)
+    }
+}
+
+impl std::error::Error for IoContext {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => error.fmt(f),
+            Self::Tls(error)
+            | Self::Parse(error)
+            | Self::Routing(error)
+            | Self::Handler(error)
+            | Self::Other(error) => error.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(error) => error.source(),
+            Self::Tls(error)
+            | Self::Parse(error)
+            | Self::Routing(error)
+            | Self::Handler(error)
+            | Self::Other(error) => error.source(),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+/// Recovers a wrapped `Error`, or treats the error as [`Error::Other`]
+impl From<anyhow::Error> for Error {
+    fn from(error: anyhow::Error) -> Self {
+        match error.downcast::<Self>() {
+            Ok(error) => error,
+            Err(error) => Self::Other(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn converts_from_and_to_anyhow() {
+        let error = Error::io("Failed to create socket")(io::Error::new(io::ErrorKind::AddrInUse, "in use"));
+        assert!(matches!(&error, Error::Io(e) if e.kind() == io::ErrorKind::AddrInUse));
+        assert_eq!(error.to_string(), "Failed to create socket");
+        let source = std::error::Error::source(&error).unwrap();
+        assert_eq!(source.to_string(), "in use");
+        assert!(matches!(source.downcast_ref::<io::Error>(), Some(e) if e.kind() == io::ErrorKind::AddrInUse));
+
+        let chain = anyhow::Error::new(Error::io("Failed to bind")(io::ErrorKind::AddrInUse.into()));
+        assert_eq!(format!("{:#}", chain), "Failed to bind: address in use");
+
+        let error = anyhow::Error::new(error);
+        assert!(matches!(Error::from(error), Error::Io(_)));
+
+        let error = Err::<(), _>(io::Error::other("root cause")).context("Failed to load key").unwrap_err();
+        let error = Error::Tls(error);
+        assert_eq!(error.to_string(), "Failed to load key");
+        assert_eq!(std::error::Error::source(&error).unwrap().to_string(), "root cause");
+        assert!(matches!(Error::from(anyhow::anyhow!("oops")), Error::Other(_)));
+    }
+}
@@ -0,0 +1,457 @@
+//! Notifying other systems about what happens on the server
+//!
+//! The server publishes an [`Event`] on its [`EventBus`] whenever something noteworthy
+//! happens, like a request being answered.  Events are delivered to [bridges](EventBridge), each running on a thread of its own, so a slow
+//! or unreachable endpoint never holds up request handling.  Instead, events are
+//! dropped when a bridge falls too far behind.
+//!
+//! Two bridges are included: [`FileBridge`] appends events to a file as JSON lines,
+//! and [`HttpBridge`] POSTs them as JSON to an HTTP endpoint, e.g. of an alerting
+//! system.
+//!
+//! ```no_run
+//! # use twinstar::{Server, GEMINI_PORT, events::{FileBridge, HttpBridge}};
+//! # async fn run() -> anyhow::Result<()> {
+//! let builder = Server::bind(("localhost", GEMINI_PORT));
+//!
+//! builder.events().add_bridge(FileBridge::new("events.jsonl"));
+//! builder.events().add_bridge(HttpBridge::new("http://localhost:9000/hooks/gemini")?);
+//!
+//! builder.serve().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::time::Duration;
+use std::convert::TryFrom;
+
+use anyhow::{Result, Context, anyhow, bail, ensure};
+use uriparse::URI;
+
+use crate::logging::AccessRecord;
+use crate::util::{json_string, rfc3339};
+
+/// The number of events buffered for each bridge before events are dropped
+pub const DEFAULT_EVENT_BUFFER: usize = 256;
+
+/// How long the [`HttpBridge`] waits for the endpoint
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Something which happened on the server
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum Event {
+    /// A request was answered, published by the server after every request
+    RequestCompleted(AccessRecord),
+}
+
+impl Event {
+    /// A short name for the kind of event, e.g. `request_completed`
+    pub const fn kind(&self) -> &'static str {
+        match self {
+            Self::RequestCompleted(_) => "request_completed",
+        }
+    }
+
+    /// Render the event as a JSON object
+    ///
+    /// The object always has an `event` field containing the [`kind()`](Self::kind())
+    /// of the event and a `time` field containing an RFC 3339 timestamp, followed by
+    /// fields specific to the kind of event.
+    pub fn to_json(&self) -> String {
+        let fields = match self {
+            Self::RequestCompleted(record) => format!(
+                "\"time\":{},\"peer_addr\":{},\"uri\":{},\"status\":{},\"meta\":{},\"body_bytes\":{},\"duration_ms\":{}{}{}",
+                json_string(&rfc3339(record.time)),
+                json_string(&record.peer_addr.to_string()),
+                json_string(&record.uri),
+                record.status.code(),
+                json_string(&record.meta),
+                record.body_bytes,
+                record.duration.as_millis(),
+                geo_fields(record),
+                record.tls_fingerprint.as_deref()
+                    .map(|hash| format!(",\"tls_fingerprint\":{}", json_string(hash)))
+                    .unwrap_or_default(),
+            ),
+        };
+
+        format!("{{\"event\":{},{}}}", json_string(self.kind()), fields)
+    }
+}
+
+/// The JSON fields for what is known about where a client connects from
+#[cfg(feature="geoip")]
+fn geo_fields(record: &AccessRecord) -> String {
+    let geo = match &record.geo {
+        Some(geo) => geo,
+        None => return String::new(),
+    };
+
+    format!(
+        ",\"country\":{},\"asn\":{}",
+        geo.country.as_deref().map(json_string).unwrap_or_else(|| "null".to_owned()),
+        geo.asn.map(|asn| asn.to_string()).unwrap_or_else(|| "null".to_owned()),
+    )
+}
+
+#[cfg(not(feature="geoip"))]
+fn geo_fields(_record: &AccessRecord) -> String {
+    String::new()
+}
+
+/// A destination events are delivered to
+///
+/// Bridges run on a dedicated thread, so they are free to block.
+pub trait EventBridge: Send + 'static {
+    /// Deliver a single event
+    fn deliver(&mut self, event: &Event) -> Result<()>;
+}
+
+/// The channel events are published on
+///
+/// This is a cheap handle which can be cloned and used from anywhere, for example to
+/// publish events from handlers.  Publishing never blocks, and does little work when
+/// there are no bridges.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    inner: Arc<BusInner>,
+}
+
+#[derive(Default)]
+struct BusInner {
+    subscribers: Mutex<Vec<SyncSender<Arc<Event>>>>,
+    dropped: AtomicU64,
+}
+
+impl EventBus {
+    /// Create a bus without any bridges
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Publish an event to all bridges and subscriptions
+    ///
+    /// Bridges which have fallen more than their buffer behind miss the event.
+    pub fn publish(&self, event: Event) {
+        let mut subscribers = self.inner.subscribers.lock().expect("twinstar BUG");
+
+        if subscribers.is_empty() {
+            return;
+        }
+
+        let event = Arc::new(event);
+
+        subscribers.retain(|subscriber| match subscriber.try_send(event.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                self.inner.dropped.fetch_add(1, Ordering::Relaxed);
+                true
+            },
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+
+    /// Whether anything is listening for events
+    pub fn has_subscribers(&self) -> bool {
+        !self.inner.subscribers.lock().expect("twinstar BUG").is_empty()
+    }
+
+    /// Receive events published from now on, buffering up to `capacity` of them
+    ///
+    /// The subscription ends when the returned receiver is dropped.
+    pub fn subscribe(&self, capacity: usize) -> Receiver<Arc<Event>> {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        self.inner.subscribers.lock().expect("twinstar BUG").push(sender);
+        receiver
+    }
+
+    /// Deliver all events published from now on to `bridge`
+    ///
+    /// The bridge runs on a thread of its own, with a buffer of
+    /// [`DEFAULT_EVENT_BUFFER`] events.
+    pub fn add_bridge(&self, mut bridge: impl EventBridge) {
+        let events = self.subscribe(DEFAULT_EVENT_BUFFER);
+
+        let spawned = std::thread::Builder::new()
+            .name("twinstar-events".to_owned())
+            .spawn(move || {
+                for event in events {
+                    if let Err(err) = bridge.deliver(&event) {
+                        warn!("Failed to deliver {} event: {:?}", event.kind(), err);
+                    }
+                }
+            });
+
+        if let Err(err) = spawned {
+            error!("Failed to spawn event bridge thread, events will be dropped: {:?}", err);
+        }
+    }
+
+    /// The number of times an event was dropped because a subscriber fell behind
+    pub fn dropped(&self) -> u64 {
+        self.inner.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// An [`EventBridge`] appending events to a file, one JSON object per line
+pub struct FileBridge {
+    path: PathBuf,
+}
+
+impl FileBridge {
+    /// Append events to the file at `path`, which is created if it doesn't exist
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+        }
+    }
+}
+
+impl EventBridge for FileBridge {
+    fn deliver(&mut self, event: &Event) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open `{}`", self.path.display()))?;
+
+        writeln!(file, "{}", event.to_json())
+            .with_context(|| format!("Failed to write to `{}`", self.path.display()))
+    }
+}
+
+/// An [`EventBridge`] POSTing events as JSON to an HTTP endpoint
+///
+/// Every event is sent in a request of its own, with a `Content-Type` of
+/// `application/json`.  Deliveries fail if the endpoint doesn't answer with a `2xx`
+/// status within 10 seconds.  Only plain `http` endpoints are supported, so to reach
+/// endpoints on other hosts, the bridge should talk to a local relay.
+pub struct HttpBridge {
+    host: String,
+    port: u16,
+    /// The value of the `Host` header, e.g. `[::1]:9000`
+    host_header: String,
+    path: String,
+    headers: Vec<(String, String)>,
+}
+
+impl HttpBridge {
+    /// POST events to `url`, e.g. `http://localhost:9000/hooks/gemini`
+    pub fn new(url: &str) -> Result<Self> {
+        let uri = URI::try_from(url)
+            .with_context(|| format!("Invalid endpoint URL `{}`", url))?;
+
+        ensure!(uri.scheme().as_str() == "http", "Only http endpoints are supported, got `{}`", url);
+
+        let host = uri.host()
+            .ok_or_else(|| anyhow!("Endpoint URL `{}` has no host", url))?
+            .to_string();
+        let host = host.trim_start_matches('[').trim_end_matches(']').to_owned();
+        let mut path = uri.path().to_string();
+
+        if path.is_empty() {
+            path.push('/');
+        }
+
+        if let Some(query) = uri.query() {
+            path.push('?');
+            path.push_str(query.as_str());
+        }
+
+        let port = uri.port().unwrap_or(80);
+        let mut host_header = match host.contains(':') {
+            true => format!("[{}]", host),
+            false => host.clone(),
+        };
+
+        if port != 80 {
+            host_header.push_str(&format!(":{}", port));
+        }
+
+        Ok(Self {
+            host,
+            port,
+            host_header,
+            path,
+            headers: Vec::new(),
+        })
+    }
+
+    /// Send an additional header with every request, e.g. for authentication
+    ///
+    /// Fails if the name or value contains a line break, which would end the header.
+    pub fn add_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Result<Self> {
+        let name = name.into();
+        let value = value.into();
+
+        ensure!(
+            !name.contains(&['\r', '\n'][..]) && !value.contains(&['\r', '\n'][..]),
+            "Header `{}` contains a line break", name.escape_debug(),
+        );
+
+        self.headers.push((name, value));
+        Ok(self)
+    }
+}
+
+impl EventBridge for HttpBridge {
+    fn deliver(&mut self, event: &Event) -> Result<()> {
+        let addr = (self.host.as_str(), self.port).to_socket_addrs()
+            .with_context(|| format!("Failed to resolve `{}`", self.host))?
+            .next()
+            .ok_or_else(|| anyhow!("`{}` did not resolve to any address", self.host))?;
+
+        let mut stream = TcpStream::connect_timeout(&addr, HTTP_TIMEOUT)
+            .with_context(|| format!("Failed to connect to `{}`", addr))?;
+        stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+        stream.set_write_timeout(Some(HTTP_TIMEOUT))?;
+
+        let body = event.to_json();
+        let mut request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+            self.path, self.host_header, body.len(),
+        );
+
+        for (name, value) in &self.headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+
+        request.push_str("\r\n");
+        request.push_str(&body);
+
+        stream.write_all(request.as_bytes())
+            .context("Failed to send event")?;
+
+        let mut status_line = String::new();
+        BufReader::new(stream).read_line(&mut status_line)
+            .context("Failed to read response")?;
+
+        let status = status_line.split_whitespace().nth(1).unwrap_or("");
+        if !status.starts_with('2') {
+            bail!("Endpoint answered with `{}`", status_line.trim_end());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::time::UNIX_EPOCH;
+    use super::*;
+
+    fn request(path: &str) -> Event {
+        Event::RequestCompleted(AccessRecord {
+            time: UNIX_EPOCH,
+            peer_addr: "192.0.2.1:4242".parse().unwrap(),
+            uri: format!("gemini://localhost{}", path),
+            status: crate::types::Status::SUCCESS,
+            meta: "text/gemini".to_owned(),
+            body_bytes: 5,
+            duration: Duration::from_millis(2),
+            handler_duration: None,
+            #[cfg(feature="geoip")]
+            geo: None,
+            tls_fingerprint: None,
+        })
+    }
+
+    #[test]
+    fn renders_json() {
+        assert_eq!(
+            request("/\"quoted\"").to_json(),
+            r#"{"event":"request_completed","time":"1970-01-01T00:00:00Z","peer_addr":"192.0.2.1:4242","uri":"gemini://localhost/\"quoted\"","status":20,"meta":"text/gemini","body_bytes":5,"duration_ms":2}"#,
+        );
+    }
+
+    #[cfg(feature="geoip")]
+    #[test]
+    fn renders_geo_fields() {
+        let mut event = request("/");
+        let Event::RequestCompleted(record) = &mut event;
+        record.geo = Some(crate::geoip::GeoInfo {
+            country: Some("NZ".to_owned()),
+            asn: None,
+            as_org: None,
+        });
+        record.tls_fingerprint = Some("0123abcd".to_owned());
+
+        assert!(event.to_json().ends_with(r#""duration_ms":2,"country":"NZ","asn":null,"tls_fingerprint":"0123abcd"}"#));
+    }
+
+    #[test]
+    fn delivers_and_drops() {
+        let bus = EventBus::new();
+        assert!(!bus.has_subscribers());
+        bus.publish(request("/nobody-listens"));
+
+        let events = bus.subscribe(1);
+        bus.publish(request("/alice"));
+        bus.publish(request("/bob"));
+
+        assert_eq!(events.try_recv().unwrap().to_json(), request("/alice").to_json());
+        assert!(events.try_recv().is_err());
+        assert_eq!(bus.dropped(), 1);
+
+        drop(events);
+        bus.publish(request("/carol"));
+        assert!(!bus.has_subscribers());
+    }
+
+    #[test]
+    fn posts_to_http_endpoints() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+
+            while !String::from_utf8_lossy(&request).ends_with('}') {
+                let read = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..read]);
+            }
+
+            stream.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let mut bridge = HttpBridge::new(&format!("http://127.0.0.1:{}/hook?token=1", port)).unwrap()
+            .add_header("X-Source", "twinstar").unwrap();
+        bridge.deliver(&request("/alice")).unwrap();
+
+        let sent = server.join().unwrap();
+        assert!(sent.starts_with(&format!("POST /hook?token=1 HTTP/1.1\r\nHost: 127.0.0.1:{}\r\n", port)));
+        assert!(sent.contains("\r\nX-Source: twinstar\r\n"));
+        assert!(sent.ends_with(&request("/alice").to_json()));
+    }
+
+    #[test]
+    fn builds_host_headers() {
+        let host_header = |url| HttpBridge::new(url).unwrap().host_header;
+
+        assert_eq!(host_header("http://localhost/hook"), "localhost");
+        assert_eq!(host_header("http://localhost:9000/hook"), "localhost:9000");
+        assert_eq!(host_header("http://[::1]/hook"), "[::1]");
+        assert_eq!(host_header("http://[::1]:9000/hook"), "[::1]:9000");
+    }
+
+    #[test]
+    fn rejects_line_breaks_in_headers() {
+        let bridge = || HttpBridge::new("http://localhost/hook").unwrap();
+
+        assert!(bridge().add_header("X-Token", "secret\r\nX-Injected: 1").is_err());
+        assert!(bridge().add_header("X-Token\n", "secret").is_err());
+    }
+}
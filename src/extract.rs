@@ -0,0 +1,261 @@
+//! Typed parameters from the path of a request
+//!
+//! Handlers often use the segments following their route as parameters, like the id
+//! in `/users/42`.  Instead of parsing [`Request::trailing_segments()`] by hand, a
+//! handler can be wrapped using [`with_path()`], which parses the segments into a
+//! [`Path`] before calling it:
+//!
+//! ```no_run
+//! # use twinstar::{Server, Request, Response, GEMINI_PORT, extract::{Path, with_path}};
+//! # async fn run() -> anyhow::Result<()> {
+//! Server::bind(("localhost", GEMINI_PORT))
+//!     // Serves e.g. /users/42/alice
+//!     .add_route("/users", with_path(|Path((id, name)): Path<(u32, String)>, _: Request| {
+//!         Box::pin(async move {
+//!             Ok(Response::success_gemini(format!("# User {}: {}", id, name)))
+//!         }) as _
+//!     }))
+//!     .serve()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Requests with segments which fail to parse are answered with `59 BAD REQUEST`,
+//! naming the offending segment, and requests with the wrong number of segments are
+//! answered with `51 NOT FOUND`.  Neither reaches the handler.
+//!
+//! Any type can be used as a parameter by implementing [`FromSegment`].  Besides the
+//! standard library types, implementations for [`uuid::Uuid`](https://docs.rs/uuid) and
+//! the date and time types of [chrono](https://docs.rs/chrono) are available using the
+//! `uuid` and `chrono` features.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::ops;
+use std::str::FromStr;
+
+use anyhow::{Result, Context};
+use percent_encoding::percent_decode_str;
+
+use crate::types::{Request, Response};
+use crate::HandlerResponse;
+
+/// A value which can be parsed from a single, percent decoded path segment
+pub trait FromSegment: Sized {
+    /// Parse the value, describing what is wrong with `segment` on failure
+    fn from_segment(segment: &str) -> Result<Self>;
+}
+
+fn parse<T>(segment: &str) -> Result<T>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    segment.parse::<T>()
+        .with_context(|| format!("Invalid path segment `{}`", segment))
+}
+
+macro_rules! from_str_segment {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            impl FromSegment for $ty {
+                fn from_segment(segment: &str) -> Result<Self> {
+                    parse(segment)
+                }
+            }
+        )*
+    };
+}
+
+from_str_segment!(
+    u8, u16, u32, u64, u128, usize,
+    i8, i16, i32, i64, i128, isize,
+    f32, f64, bool, char,
+    IpAddr, Ipv4Addr, Ipv6Addr,
+);
+
+#[cfg(feature="uuid")]
+from_str_segment!(uuid::Uuid);
+
+#[cfg(feature="chrono")]
+from_str_segment!(
+    chrono::NaiveDate, chrono::NaiveTime, chrono::NaiveDateTime,
+    chrono::DateTime<chrono::FixedOffset>, chrono::DateTime<chrono::Utc>,
+);
+
+impl FromSegment for String {
+    fn from_segment(segment: &str) -> Result<Self> {
+        Ok(segment.to_owned())
+    }
+}
+
+/// Values which can be parsed from a list of path segments
+///
+/// This is implemented for every [`FromSegment`], taking exactly one segment, for
+/// tuples of up to six of them, taking one segment each, and for [`Vec`]s, taking all
+/// segments.
+pub trait FromSegments: Sized {
+    /// Whether the value is made up of `count` segments
+    fn accepts(count: usize) -> bool;
+
+    /// Parse the value from percent decoded segments
+    ///
+    /// This is only called if [`accepts()`](Self::accepts()) returned `true` for the
+    /// number of segments.
+    fn from_segments(segments: &[String]) -> Result<Self>;
+}
+
+impl<T: FromSegment> FromSegments for T {
+    fn accepts(count: usize) -> bool {
+        count == 1
+    }
+
+    fn from_segments(segments: &[String]) -> Result<Self> {
+        T::from_segment(&segments[0])
+    }
+}
+
+impl<T: FromSegment> FromSegments for Vec<T> {
+    fn accepts(_count: usize) -> bool {
+        true
+    }
+
+    fn from_segments(segments: &[String]) -> Result<Self> {
+        segments.iter()
+            .map(|segment| T::from_segment(segment))
+            .collect()
+    }
+}
+
+impl FromSegments for () {
+    fn accepts(count: usize) -> bool {
+        count == 0
+    }
+
+    fn from_segments(_segments: &[String]) -> Result<Self> {
+        Ok(())
+    }
+}
+
+macro_rules! tuple_segments {
+    ($len:expr; $($ty:ident $index:tt),*) => {
+        impl<$($ty: FromSegment),*> FromSegments for ($($ty,)*) {
+            fn accepts(count: usize) -> bool {
+                count == $len
+            }
+
+            fn from_segments(segments: &[String]) -> Result<Self> {
+                Ok(($($ty::from_segment(&segments[$index])?,)*))
+            }
+        }
+    };
+}
+
+tuple_segments!(1; A 0);
+tuple_segments!(2; A 0, B 1);
+tuple_segments!(3; A 0, B 1, C 2);
+tuple_segments!(4; A 0, B 1, C 2, D 3);
+tuple_segments!(5; A 0, B 1, C 2, D 3, E 4);
+tuple_segments!(6; A 0, B 1, C 2, D 3, E 4, F 5);
+
+/// Parameters parsed from the segments following a route
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Path<T>(pub T);
+
+impl<T: FromSegments> Path<T> {
+    /// Parse the trailing segments of a routed request
+    ///
+    /// On failure, the response the request should be answered with is returned.
+    pub fn from_request(request: &Request) -> Result<Self, Response> {
+        let segments = request.trailing_segments().iter()
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| percent_decode_str(segment).decode_utf8_lossy().into_owned())
+            .collect::<Vec<_>>();
+
+        if !T::accepts(segments.len()) {
+            return Err(Response::not_found());
+        }
+
+        T::from_segments(&segments)
+            .map(Path)
+            .map_err(|err| Response::bad_request_lossy(err.to_string()))
+    }
+}
+
+impl<T> Path<T> {
+    /// Unwrap the parsed parameters
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> ops::Deref for Path<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+/// Turn a handler taking [`Path`] parameters into one which can be routed to
+///
+/// See the [module documentation](self) for an example.
+pub fn with_path<T, F>(handler: F) -> impl Fn(Request) -> HandlerResponse + Send + Sync + 'static
+where
+    T: FromSegments,
+    F: Fn(Path<T>, Request) -> HandlerResponse + Send + Sync + 'static,
+{
+    move |request: Request| match Path::from_request(&request) {
+        Ok(path) => handler(path, request),
+        Err(response) => Box::pin(async move { Ok(response) }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use super::*;
+    use crate::types::Status;
+    use crate::uri::URIReference;
+
+    fn request(trailing: &[&str]) -> Request {
+        let uri = URIReference::try_from("gemini://localhost/").unwrap().into_owned();
+        let mut request = Request::from_uri(uri).unwrap();
+        request.set_trailing(trailing.iter().map(|segment| segment.to_string()).collect());
+        request
+    }
+
+    fn status<T: FromSegments>(trailing: &[&str]) -> Status {
+        match Path::<T>::from_request(&request(trailing)) {
+            Ok(_) => Status::SUCCESS,
+            Err(response) => response.header().status,
+        }
+    }
+
+    #[test]
+    fn parses_segments() {
+        let Path((id, name)) = Path::<(u32, String)>::from_request(&request(&["42", "J%C3%BCrgen"])).ok().unwrap();
+        assert_eq!((id, name.as_str()), (42, "Jürgen"));
+
+        let Path(ids) = Path::<Vec<u8>>::from_request(&request(&["1", "2", "3"])).ok().unwrap();
+        assert_eq!(ids, [1, 2, 3]);
+
+        let Path(ip) = Path::<IpAddr>::from_request(&request(&["::1"])).ok().unwrap();
+        assert_eq!(ip, IpAddr::from(Ipv6Addr::LOCALHOST));
+
+        assert_eq!(status::<()>(&[""]), Status::SUCCESS);
+    }
+
+    #[test]
+    fn rejects_bad_segments() {
+        assert_eq!(status::<(u32, String)>(&["forty-two", "alice"]), Status::BAD_REQUEST);
+        assert_eq!(status::<(u32, String)>(&["42"]), Status::NOT_FOUND);
+        assert_eq!(status::<u8>(&["42", "43"]), Status::NOT_FOUND);
+        assert_eq!(status::<Vec<u8>>(&["1", "256"]), Status::BAD_REQUEST);
+
+        let response = Path::<u8>::from_request(&request(&["256"])).err().unwrap();
+        assert_eq!(response.header().meta.as_str(), "Invalid path segment `256`");
+    }
+}
@@ -0,0 +1,251 @@
+//! Counting requests which couldn't be served, by cause
+//!
+//! Every connection which fails is counted under one [`FailureKind`], so failures can
+//! be aggregated and alerted on, instead of digging through the error log.  With the
+//! `metrics` feature, the counters can be read through a `FailureStats` handle, which
+//! is available from `Builder::failure_stats()` for metrics exporters, and from
+//! `Server::failure_stats()`.  With the `logging` feature, the kind of failure is also
+//! included in `ErrorRecord`s.
+//!
+//! Failed TLS handshakes are additionally counted by [`HandshakeFailureCause`], telling
+//! port scanners apart from outdated clients, and the ClientHello of the client is
+//! logged at debug level.
+//!
+//! ```no_run
+//! # #[cfg(feature="metrics")] {
+//! # use twinstar::{Server, GEMINI_PORT, failures::FailureKind};
+//! # async fn run() -> anyhow::Result<()> {
+//! let builder = Server::bind(("localhost", GEMINI_PORT));
+//! let failures = builder.failure_stats();
+//!
+//! std::thread::spawn(move || loop {
+//!     std::thread::sleep(std::time::Duration::from_secs(60));
+//!     for (kind, count) in failures.counts() {
+//!         println!("twinstar_failures_total{{kind=\"{}\"}} {}", kind, count);
+//!     }
+//! });
+//!
+//! builder.serve().await?;
+//! # Ok(())
+//! # }
+//! # }
+//! ```
+
+use std::fmt;
+#[cfg(feature="metrics")]
+use std::sync::Arc;
+#[cfg(feature="metrics")]
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Why a request couldn't be served
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum FailureKind {
+    /// A trusted proxy sent a missing or malformed PROXY protocol header
+    ProxyHeader,
+    /// The TLS handshake with the client failed
+    TlsHandshake,
+    /// The client sent something which isn't a valid request
+    MalformedRequest,
+    /// The client was too slow sending its request or receiving the response
+    Timeout,
+    /// The handler returned an error, and the client was sent `50 PERMANENT FAILURE`
+    HandlerError,
+    /// The handler panicked, and the client was sent `50 PERMANENT FAILURE`
+    HandlerPanic,
+    /// The handler sent a response breaking the specification, and the client was sent
+    /// `42 CGI ERROR` instead (only checked in [strict mode](crate::Builder::set_strict()))
+    InvalidResponse,
+    /// Writing the response to the client failed
+    ResponseWrite,
+}
+
+impl FailureKind {
+    /// Every kind of failure, in the order they can happen while serving a request
+    pub const ALL: [Self; 8] = [
+        Self::ProxyHeader,
+        Self::TlsHandshake,
+        Self::MalformedRequest,
+        Self::Timeout,
+        Self::HandlerError,
+        Self::HandlerPanic,
+        Self::InvalidResponse,
+        Self::ResponseWrite,
+    ];
+
+    /// A short name for the kind, in snake case, e.g. `tls_handshake`
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::ProxyHeader => "proxy_header",
+            Self::TlsHandshake => "tls_handshake",
+            Self::MalformedRequest => "malformed_request",
+            Self::Timeout => "timeout",
+            Self::HandlerError => "handler_error",
+            Self::HandlerPanic => "handler_panic",
+            Self::InvalidResponse => "invalid_response",
+            Self::ResponseWrite => "response_write",
+        }
+    }
+
+    #[cfg(feature="metrics")]
+    const fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+impl fmt::Display for FailureKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// What a client failing the TLS handshake offered, as far as it tells why it failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum HandshakeFailureCause {
+    /// The client didn't start with a ClientHello, like port scanners, and clients
+    /// speaking another protocol, e.g. plain HTTP
+    NotTls,
+    /// The client only offered TLS versions older than the
+    /// [minimum](crate::Builder::set_min_tls_version()), like ancient clients
+    OutdatedTls,
+    /// The client offered an accepted TLS version, but the handshake failed anyway, e.g.
+    /// because there was no common cipher suite, or the client certificate was rejected
+    Other,
+}
+
+impl HandshakeFailureCause {
+    /// Every cause of failed handshakes
+    pub const ALL: [Self; 3] = [Self::NotTls, Self::OutdatedTls, Self::Other];
+
+    /// A short name for the cause, in snake case, e.g. `outdated_tls`
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::NotTls => "not_tls",
+            Self::OutdatedTls => "outdated_tls",
+            Self::Other => "other",
+        }
+    }
+
+    /// The cause of a failed handshake, given the newest TLS version the client offered
+    /// and the oldest version the server accepts, as in the ClientHello
+    #[cfg(feature="fingerprint")]
+    pub(crate) fn classify(max_offered: Option<u16>, min_accepted: u16) -> Self {
+        match max_offered {
+            None => Self::NotTls,
+            Some(version) if version < min_accepted => Self::OutdatedTls,
+            Some(_) => Self::Other,
+        }
+    }
+
+    #[cfg(feature="metrics")]
+    const fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+impl fmt::Display for HandshakeFailureCause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Counters of failed requests, by [`FailureKind`], and of failed TLS handshakes, by
+/// [`HandshakeFailureCause`]
+///
+/// This is a cheap handle which can be cloned and read from anywhere.  This requires
+/// the `metrics` feature.
+#[cfg(feature="metrics")]
+#[derive(Debug, Clone, Default)]
+pub struct FailureStats {
+    counters: Arc<[AtomicU64; 8]>,
+    handshake_counters: Arc<[AtomicU64; 3]>,
+}
+
+#[cfg(feature="metrics")]
+impl FailureStats {
+    /// Create a new set of counters, all at zero
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of failures of one kind
+    pub fn get(&self, kind: FailureKind) -> u64 {
+        self.counters[kind.index()].load(Ordering::Relaxed)
+    }
+
+    /// The number of failures of every kind, in the order of [`FailureKind::ALL`]
+    pub fn counts(&self) -> Vec<(FailureKind, u64)> {
+        FailureKind::ALL.iter()
+            .map(|kind| (*kind, self.get(*kind)))
+            .collect()
+    }
+
+    /// The number of failures of all kinds
+    pub fn total(&self) -> u64 {
+        FailureKind::ALL.iter().map(|kind| self.get(*kind)).sum()
+    }
+
+    /// The number of failed TLS handshakes with one cause
+    ///
+    /// These are also counted as [`FailureKind::TlsHandshake`].
+    pub fn handshake_failures(&self, cause: HandshakeFailureCause) -> u64 {
+        self.handshake_counters[cause.index()].load(Ordering::Relaxed)
+    }
+
+    /// The number of failed TLS handshakes with every cause, in the order of
+    /// [`HandshakeFailureCause::ALL`]
+    pub fn handshake_failure_counts(&self) -> Vec<(HandshakeFailureCause, u64)> {
+        HandshakeFailureCause::ALL.iter()
+            .map(|cause| (*cause, self.handshake_failures(*cause)))
+            .collect()
+    }
+
+    pub(crate) fn record(&self, kind: FailureKind) {
+        self.counters[kind.index()].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_handshake_failure(&self, cause: HandshakeFailureCause) {
+        self.handshake_counters[cause.index()].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(all(test, feature="fingerprint"))]
+mod tests {
+    use super::*;
+
+    #[cfg(feature="metrics")]
+    #[test]
+    fn counts_by_kind() {
+        let stats = FailureStats::new();
+        let handle = stats.clone();
+
+        stats.record(FailureKind::Timeout);
+        stats.record(FailureKind::Timeout);
+        stats.record(FailureKind::HandlerPanic);
+
+        assert_eq!(handle.get(FailureKind::Timeout), 2);
+        assert_eq!(handle.get(FailureKind::TlsHandshake), 0);
+        assert_eq!(handle.total(), 3);
+        assert_eq!(handle.counts()[5], (FailureKind::HandlerPanic, 1));
+        assert_eq!(FailureKind::ALL.iter().map(FailureKind::index).collect::<Vec<_>>(), (0..8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn classifies_handshake_failures() {
+        let classify = HandshakeFailureCause::classify;
+        assert_eq!(classify(None, 0x0303), HandshakeFailureCause::NotTls);
+        assert_eq!(classify(Some(0x0301), 0x0303), HandshakeFailureCause::OutdatedTls);
+        assert_eq!(classify(Some(0x0303), 0x0304), HandshakeFailureCause::OutdatedTls);
+        assert_eq!(classify(Some(0x0304), 0x0303), HandshakeFailureCause::Other);
+
+        #[cfg(feature="metrics")]
+        {
+            let stats = FailureStats::new();
+            stats.record_handshake_failure(HandshakeFailureCause::NotTls);
+            assert_eq!(stats.handshake_failures(HandshakeFailureCause::NotTls), 1);
+            assert_eq!(stats.handshake_failure_counts()[2], (HandshakeFailureCause::Other, 0));
+            assert_eq!(stats.total(), 0);
+        }
+    }
+}
@@ -0,0 +1,80 @@
+//! Serving the icon of a capsule at `/favicon.txt`
+//!
+//! See [`Builder::set_favicon()`](crate::Builder::set_favicon()).
+
+use std::collections::HashMap;
+
+use crate::types::{Request, Response};
+
+/// The path clients request the icon of a capsule from
+pub(crate) const FAVICON_PATH: &str = "/favicon.txt";
+
+/// The icons of a capsule, by hostname
+///
+/// Following the community convention, an icon is a single emoji, sent as plain text.
+/// Hosts without an icon of their own get the default icon, if any.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Favicons {
+    default: Option<String>,
+    hosts: HashMap<String, String>,
+}
+
+impl Favicons {
+    pub fn set_default(&mut self, icon: String) {
+        self.default = Some(icon);
+    }
+
+    pub fn set_host(&mut self, host: &str, icon: String) {
+        self.hosts.insert(host.to_ascii_lowercase(), icon);
+    }
+
+    /// Whether there is any icon to serve
+    pub fn is_empty(&self) -> bool {
+        self.default.is_none() && self.hosts.is_empty()
+    }
+
+    /// The icon of the host `request` was sent to
+    pub fn icon_for(&self, request: &Request) -> Option<&str> {
+        request.uri().host()
+            .and_then(|host| self.hosts.get(&host.to_string().to_ascii_lowercase()))
+            .or(self.default.as_ref())
+            .map(String::as_str)
+    }
+
+    /// Answer a request for [`FAVICON_PATH`]
+    pub fn respond(&self, request: &Request) -> Response {
+        match self.icon_for(request) {
+            Some(icon) => Response::success(&mime::TEXT_PLAIN_UTF_8, icon.to_owned()),
+            None => Response::not_found(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use super::*;
+    use crate::types::{Status, URIReference};
+
+    fn request(uri: &str) -> Request {
+        Request::from_uri(URIReference::try_from(uri).unwrap().into_owned()).unwrap()
+    }
+
+    #[test]
+    fn picks_icons_by_host() {
+        let mut favicons = Favicons::default();
+        assert!(favicons.is_empty());
+
+        favicons.set_host("Blog.example.org", "📝".to_owned());
+        let response = favicons.respond(&request("gemini://example.org/favicon.txt"));
+        assert_eq!(response.header().status, Status::NOT_FOUND);
+
+        favicons.set_default('🚀'.into());
+        assert_eq!(favicons.icon_for(&request("gemini://example.org/favicon.txt")), Some("🚀"));
+        assert_eq!(favicons.icon_for(&request("gemini://blog.example.org/favicon.txt")), Some("📝"));
+
+        let mut response = favicons.respond(&request("gemini://blog.example.org/favicon.txt"));
+        assert_eq!(response.header().meta.as_str(), "text/plain; charset=utf-8");
+        assert_eq!(response.take_body().unwrap().as_bytes(), Some("📝".as_bytes()));
+    }
+}
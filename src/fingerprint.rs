@@ -0,0 +1,430 @@
+//! Recognizing client software by its TLS handshake
+//!
+//! Scanners and crawlers misbehaving on a capsule often rotate their addresses, but
+//! keep using the same TLS library with the same settings.  The first message a client
+//! sends, the ClientHello, reveals those settings: which TLS version, cipher suites,
+//! extensions and curves it offers, and in which order.  Condensing them into a
+//! [`TlsFingerprint`] gives a value which stays the same across addresses, and can be
+//! used to identify and throttle such software.
+//!
+//! Fingerprinting is enabled using
+//! [`Builder::set_tls_fingerprinting()`](crate::Builder::set_tls_fingerprinting()).  The
+//! fingerprint is then attached to every request, where middleware can look at it
+//! using [`TlsFingerprint::of()`], and included in the access log.
+//!
+//! The fingerprint is built the same way as a [JA3](https://github.com/salesforce/ja3)
+//! fingerprint.  The [`ja3`](TlsFingerprint::ja3) string can be compared to JA3
+//! databases after hashing it with MD5.  The [`hash`](TlsFingerprint::hash) used in logs
+//! is based on SHA-256 instead.
+//!
+//! Independent of fingerprinting, the ClientHello of a client failing the handshake is
+//! logged at debug level, with the TLS versions, cipher suites and server name it
+//! offered.
+
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::types::Request;
+use crate::util::hex;
+
+/// The most bytes of a connection kept for fingerprinting
+///
+/// This is the size of the largest possible TLS record and its header.
+const MAX_RECORD_LEN: usize = 5 + (1 << 14);
+
+/// Characteristics of the TLS library a client uses
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TlsFingerprint {
+    /// The JA3 string of the ClientHello, e.g. `771,4865-4866,0-11-10,29-23,0`
+    ///
+    /// This is made up of the TLS version, cipher suites, extensions, elliptic curves
+    /// and elliptic curve point formats offered by the client, in the order the client
+    /// sent them.  GREASE values are left out.
+    pub ja3: String,
+    /// A short hash of the JA3 string, as 32 hex digits
+    pub hash: String,
+}
+
+impl TlsFingerprint {
+    /// The fingerprint of the client sending a request, if fingerprinting is enabled
+    pub fn of(request: &Request) -> Option<&Self> {
+        request.extensions().get::<Self>()
+    }
+
+    /// Fingerprint a TLS record containing a ClientHello
+    ///
+    /// Returns `None` if `record` doesn't start with a well-formed ClientHello.
+    pub fn from_client_hello(record: &[u8]) -> Option<Self> {
+        let ja3 = ja3(record)?;
+        let digest = ring::digest::digest(&ring::digest::SHA256, ja3.as_bytes());
+        let hash = hex(&digest.as_ref()[..16]);
+
+        Some(Self { ja3, hash })
+    }
+}
+
+/// The TLS versions, cipher suites and server name a ClientHello offers
+///
+/// GREASE values are left out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct OfferedParameters {
+    /// From the `supported_versions` extension, or the legacy version without it
+    pub versions: Vec<u16>,
+    pub cipher_suites: Vec<u16>,
+    pub server_name: Option<String>,
+}
+
+impl OfferedParameters {
+    /// Read the parameters of a TLS record containing a ClientHello
+    pub fn from_client_hello(record: &[u8]) -> Option<Self> {
+        let mut hello = client_hello(record)?;
+
+        let mut versions = vec![hello.u16()?];
+        hello.take(32)?;
+        hello.vec8()?;
+        let cipher_suites = without_grease(hello.vec16()?.u16s());
+        hello.vec8()?;
+
+        let mut server_name = None;
+
+        if let Some(mut list) = hello.vec16() {
+            while let Some(kind) = list.u16() {
+                let mut data = list.vec16()?;
+
+                match kind {
+                    0 => {
+                        let mut names = data.vec16()?;
+                        if names.u8()? == 0 {
+                            server_name = Some(String::from_utf8_lossy(names.vec16()?.0).into_owned());
+                        }
+                    },
+                    43 => versions = without_grease(data.vec8()?.u16s()),
+                    _ => {},
+                }
+            }
+        }
+
+        Some(Self { versions, cipher_suites, server_name })
+    }
+
+    /// The newest TLS version offered
+    pub fn max_version(&self) -> Option<u16> {
+        self.versions.iter().copied().max()
+    }
+}
+
+impl fmt::Display for OfferedParameters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let versions = self.versions.iter()
+            .map(|version| match version {
+                0x0300 => "SSLv3".to_owned(),
+                0x0301 => "TLSv1.0".to_owned(),
+                0x0302 => "TLSv1.1".to_owned(),
+                0x0303 => "TLSv1.2".to_owned(),
+                0x0304 => "TLSv1.3".to_owned(),
+                _ => format!("{:#06x}", version),
+            })
+            .collect::<Vec<_>>();
+        let cipher_suites = self.cipher_suites.iter()
+            .map(|suite| format!("{:#06x}", suite))
+            .collect::<Vec<_>>();
+
+        write!(f, "versions [{}], cipher suites [{}], ", versions.join(", "), cipher_suites.join(", "))?;
+        match &self.server_name {
+            Some(name) => write!(f, "server name {:?}", name),
+            None => f.write_str("no server name"),
+        }
+    }
+}
+
+/// A stream keeping the first TLS record read from it, which is the ClientHello
+///
+/// The record is kept until the handshake is done, which is at most
+/// [`MAX_RECORD_LEN`] bytes per connection.
+pub(crate) struct ClientHelloRecorder<S> {
+    stream: S,
+    record: Option<Vec<u8>>,
+}
+
+impl<S> ClientHelloRecorder<S> {
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            record: Some(Vec::new()),
+        }
+    }
+
+    /// Fingerprint the recorded ClientHello, and stop recording
+    pub fn take_fingerprint(&mut self) -> Option<TlsFingerprint> {
+        self.record.take()
+            .and_then(|record| TlsFingerprint::from_client_hello(&record))
+    }
+
+    /// Read what the recorded ClientHello offered, and stop recording
+    pub fn take_offered(&mut self) -> Option<OfferedParameters> {
+        self.record.take()
+            .and_then(|record| OfferedParameters::from_client_hello(&record))
+    }
+
+    /// Stop recording
+    pub fn discard(&mut self) {
+        self.record = None;
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ClientHelloRecorder<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let result = Pin::new(&mut self.stream).poll_read(cx, buf);
+
+        if let Some(record) = &mut self.record {
+            let read = &buf.filled()[filled..];
+            let wanted = record_len(record).unwrap_or(MAX_RECORD_LEN).saturating_sub(record.len());
+            record.extend_from_slice(&read[..read.len().min(wanted)]);
+        }
+
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for ClientHelloRecorder<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+/// The length of the record starting `bytes`, including its header, once known
+fn record_len(bytes: &[u8]) -> Option<usize> {
+    let header = bytes.get(..5)?;
+    Some(5 + usize::from(u16::from_be_bytes([header[3], header[4]])))
+}
+
+/// Reads big endian integers and length prefixed slices from a message
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u24(&mut self) -> Option<usize> {
+        self.take(3).map(|bytes| usize::from(bytes[0]) << 16 | usize::from(bytes[1]) << 8 | usize::from(bytes[2]))
+    }
+
+    fn vec8(&mut self) -> Option<Reader<'a>> {
+        let len = self.u8()?;
+        self.take(len.into()).map(Reader)
+    }
+
+    fn vec16(&mut self) -> Option<Reader<'a>> {
+        let len = self.u16()?;
+        self.take(len.into()).map(Reader)
+    }
+
+    fn u16s(mut self) -> Vec<u16> {
+        let mut values = Vec::new();
+        while let Some(value) = self.u16() {
+            values.push(value);
+        }
+        values
+    }
+}
+
+/// Whether a value is one of the reserved GREASE values clients send at random
+fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
+fn without_grease(values: Vec<u16>) -> Vec<u16> {
+    values.into_iter().filter(|value| !is_grease(*value)).collect()
+}
+
+fn join(values: impl IntoIterator<Item = u16>) -> String {
+    values.into_iter()
+        .filter(|value| !is_grease(*value))
+        .map(|value| value.to_string())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// The body of the ClientHello contained in a TLS record
+fn client_hello(record: &[u8]) -> Option<Reader<'_>> {
+    let mut record = Reader(record);
+
+    // A handshake record containing a ClientHello
+    if record.u8()? != 22 {
+        return None;
+    }
+    record.take(2)?;
+    let mut handshake = record.vec16()?;
+    if handshake.u8()? != 1 {
+        return None;
+    }
+    let len = handshake.u24()?;
+    handshake.take(len).map(Reader)
+}
+
+/// Build the JA3 string of the ClientHello contained in a TLS record
+fn ja3(record: &[u8]) -> Option<String> {
+    let mut hello = client_hello(record)?;
+
+    let version = hello.u16()?;
+    hello.take(32)?;
+    hello.vec8()?;
+    let ciphers = hello.vec16()?.u16s();
+    hello.vec8()?;
+
+    let mut extensions = Vec::new();
+    let mut curves = Vec::new();
+    let mut point_formats = Vec::new();
+
+    if let Some(mut list) = hello.vec16() {
+        while let Some(kind) = list.u16() {
+            let mut data = list.vec16()?;
+            extensions.push(kind);
+
+            match kind {
+                10 => curves = data.vec16()?.u16s(),
+                11 => point_formats = data.vec8()?.0.iter().map(|format| u16::from(*format)).collect(),
+                _ => {},
+            }
+        }
+    }
+
+    Some(format!(
+        "{},{},{},{},{}",
+        version,
+        join(ciphers),
+        join(extensions),
+        join(curves),
+        join(point_formats),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a ClientHello record with the given cipher suites and extensions
+    fn client_hello(ciphers: &[u16], extensions: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        let mut hello = vec![0x03, 0x03];
+        hello.extend_from_slice(&[0; 32]);
+        hello.push(0);
+        hello.extend_from_slice(&((ciphers.len() * 2) as u16).to_be_bytes());
+        for cipher in ciphers {
+            hello.extend_from_slice(&cipher.to_be_bytes());
+        }
+        hello.extend_from_slice(&[1, 0]);
+
+        let mut list = Vec::new();
+        for (kind, data) in extensions {
+            list.extend_from_slice(&kind.to_be_bytes());
+            list.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            list.extend_from_slice(data);
+        }
+        hello.extend_from_slice(&(list.len() as u16).to_be_bytes());
+        hello.extend_from_slice(&list);
+
+        let mut handshake = vec![1, 0];
+        handshake.extend_from_slice(&(hello.len() as u16).to_be_bytes());
+        handshake.extend_from_slice(&hello);
+
+        let mut record = vec![22, 3, 1];
+        record.extend_from_slice(&(handshake.len() as u16).to_be_bytes());
+        record.extend_from_slice(&handshake);
+        record
+    }
+
+    #[test]
+    fn builds_ja3_strings() {
+        let record = client_hello(
+            &[0x2a2a, 4865, 4866, 49195],
+            &[
+                (0x3a3a, vec![]),
+                (0, vec![0, 0]),
+                (10, vec![0, 6, 0x4a, 0x4a, 0, 29, 0, 23]),
+                (11, vec![1, 0]),
+            ],
+        );
+
+        let fingerprint = TlsFingerprint::from_client_hello(&record).unwrap();
+        assert_eq!(fingerprint.ja3, "771,4865-4866-49195,0-10-11,29-23,0");
+        assert_eq!(fingerprint.hash.len(), 32);
+
+        // GREASE values don't change the fingerprint
+        let without_grease = client_hello(&[4865, 4866, 49195], &[(0, vec![0, 0]), (10, vec![0, 4, 0, 29, 0, 23]), (11, vec![1, 0])]);
+        assert_eq!(TlsFingerprint::from_client_hello(&without_grease), Some(fingerprint));
+
+        assert_eq!(TlsFingerprint::from_client_hello(&record[..20]), None);
+        assert_eq!(TlsFingerprint::from_client_hello(b"GET / HTTP/1.1\r\n"), None);
+    }
+
+    #[tokio::test]
+    async fn records_the_first_record() {
+        use tokio::io::AsyncReadExt;
+
+        let mut stream = client_hello(&[4865], &[]);
+        stream.extend_from_slice(b"application data");
+
+        let mut recorder = ClientHelloRecorder::new(&stream[..]);
+        let mut read = Vec::new();
+        recorder.read_to_end(&mut read).await.unwrap();
+
+        assert_eq!(read, stream);
+        assert_eq!(recorder.take_fingerprint().unwrap().ja3, "771,4865,,,");
+        assert_eq!(recorder.take_fingerprint(), None);
+    }
+
+    #[test]
+    fn reads_offered_parameters() {
+        let mut server_name = vec![0, 14, 0, 0, 11];
+        server_name.extend_from_slice(b"example.org");
+        let record = client_hello(
+            &[0x2a2a, 4865, 49195],
+            &[(0, server_name), (43, vec![6, 0x1a, 0x1a, 3, 4, 3, 3])],
+        );
+
+        let offered = OfferedParameters::from_client_hello(&record).unwrap();
+        assert_eq!(offered.versions, [0x0304, 0x0303]);
+        assert_eq!(offered.cipher_suites, [4865, 49195]);
+        assert_eq!(offered.server_name.as_deref(), Some("example.org"));
+        assert_eq!(offered.max_version(), Some(0x0304));
+        assert_eq!(
+            offered.to_string(),
+            "versions [TLSv1.3, TLSv1.2], cipher suites [0x1301, 0xc02b], server name \"example.org\"",
+        );
+
+        let legacy = OfferedParameters::from_client_hello(&client_hello(&[0x002f], &[])).unwrap();
+        assert_eq!(legacy.max_version(), Some(0x0303));
+        assert_eq!(legacy.server_name, None);
+        assert_eq!(OfferedParameters::from_client_hello(b"GET / HTTP/1.1\r\n"), None);
+    }
+}
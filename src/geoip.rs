@@ -0,0 +1,115 @@
+//! Looking up where clients connect from
+//!
+//! A server can be given [MaxMind](https://www.maxmind.com) databases using
+//! [`Builder::set_geoip()`](crate::Builder::set_geoip()).  The country and autonomous
+//! system of every client is then looked up before the request is handled, attached to
+//! the request as a [`GeoInfo`], and included in the access log.
+//!
+//! ```no_run
+//! # async fn run() -> anyhow::Result<()> {
+//! # use twinstar::{Server, Request, Response, GEMINI_PORT, geoip::{GeoIp, GeoInfo}};
+//! Server::bind(("localhost", GEMINI_PORT))
+//!     .set_geoip(GeoIp::new().set_country_database("GeoLite2-Country.mmdb")?)
+//!     .add_route("/", |request: Request| Box::pin(async move {
+//!         let country = GeoInfo::of(&request).and_then(|geo| geo.country.as_deref());
+//!         Ok(Response::success_gemini(format!("Hello from {}!", country.unwrap_or("somewhere"))))
+//!     }) as _)
+//!     .serve()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! This module requires the `geoip` feature.
+
+use crate::types::Request;
+
+/// Where a client connects from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GeoInfo {
+    /// The ISO 3166-1 code of the country, like `DE`
+    pub country: Option<String>,
+    /// The number of the autonomous system
+    pub asn: Option<u32>,
+    /// The organization operating the autonomous system
+    pub as_org: Option<String>,
+}
+
+impl GeoInfo {
+    /// The information looked up for a request, if a database was configured
+    pub fn of(request: &Request) -> Option<&Self> {
+        request.extensions().get::<Self>()
+    }
+
+    /// Whether nothing is known about the client
+    pub fn is_empty(&self) -> bool {
+        self.country.is_none() && self.asn.is_none() && self.as_org.is_none()
+    }
+}
+
+pub use self::lookup::GeoIp;
+
+mod lookup {
+    use std::net::IpAddr;
+    use std::path::Path;
+
+    use anyhow::{Result, Context};
+    use maxminddb::{Reader, geoip2};
+
+    use super::GeoInfo;
+
+    /// The databases client addresses are looked up in
+    ///
+    /// Both GeoIP2 and the free GeoLite2 databases are supported.  A country database,
+    /// an ASN database, or both can be used.  The databases are read into memory once.
+    #[derive(Default)]
+    pub struct GeoIp {
+        country: Option<Reader<Vec<u8>>>,
+        asn: Option<Reader<Vec<u8>>>,
+    }
+
+    impl GeoIp {
+        /// Look up nothing, until databases are added
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Look up countries in the database at `path`, e.g. `GeoLite2-Country.mmdb`
+        pub fn set_country_database(mut self, path: impl AsRef<Path>) -> Result<Self> {
+            self.country = Some(open(path.as_ref())?);
+            Ok(self)
+        }
+
+        /// Look up autonomous systems in the database at `path`, e.g. `GeoLite2-ASN.mmdb`
+        pub fn set_asn_database(mut self, path: impl AsRef<Path>) -> Result<Self> {
+            self.asn = Some(open(path.as_ref())?);
+            Ok(self)
+        }
+
+        /// Look up everything known about `ip`
+        pub fn lookup(&self, ip: IpAddr) -> GeoInfo {
+            let mut info = GeoInfo::default();
+
+            if let Some(country) = &self.country {
+                info.country = country.lookup::<geoip2::Country>(ip).ok()
+                    .and_then(|country| country.country)
+                    .and_then(|country| country.iso_code)
+                    .map(str::to_owned);
+            }
+
+            if let Some(asn) = &self.asn {
+                if let Ok(asn) = asn.lookup::<geoip2::Asn>(ip) {
+                    info.asn = asn.autonomous_system_number;
+                    info.as_org = asn.autonomous_system_organization.map(str::to_owned);
+                }
+            }
+
+            info
+        }
+    }
+
+    fn open(path: &Path) -> Result<Reader<Vec<u8>>> {
+        Reader::open_readfile(path)
+            .with_context(|| format!("Failed to open GeoIP database `{}`", path.display()))
+    }
+}
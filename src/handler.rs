@@ -0,0 +1,234 @@
+//! Combinators for tweaking handlers
+//!
+//! Small changes around a single handler, like adding a footer to one page or checking
+//! a password on one route, don't warrant a piece of [middleware](crate::middleware)
+//! running for every request.  [`HandlerExt`] adds combinators to every handler
+//! instead, which wrap it in another handler:
+//!
+//! ```no_run
+//! # #[cfg(feature="serve_dir")] {
+//! # use twinstar::{Server, Request, Response, Status, GEMINI_PORT};
+//! # use twinstar::handler::HandlerExt;
+//! # use twinstar::util::ServeDir;
+//! # async fn run() -> anyhow::Result<()> {
+//! let private = ServeDir::new("private").into_handler()
+//!     .before(|request: &mut Request| {
+//!         match request.certificate() {
+//!             None => Some(Response::client_certificate_required()),
+//!             Some(_) => None,
+//!         }
+//!     });
+//!
+//! let public = ServeDir::new("public").into_handler()
+//!     .or_else(ServeDir::new("archive").into_handler())
+//!     .map_response(|response: Response| {
+//!         if response.header().status == Status::NOT_FOUND {
+//!             Response::redirect_temporary_lossy("/")
+//!         } else {
+//!             response
+//!         }
+//!     });
+//!
+//! Server::bind(("localhost", GEMINI_PORT))
+//!     .add_route("/private", private)
+//!     .add_route("/", public)
+//!     .serve()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! # }
+//! ```
+//!
+//! Handlers can also be written as plain `async fn`s, and turned into handlers using
+//! [`from_fn()`].
+//!
+//! This module requires the `handler` feature.
+
+use std::future::Future;
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use crate::types::{IntoResponse, Request, Response, Status};
+use crate::HandlerResponse;
+
+pub use crate::BoxedHandler;
+
+/// Turn an async function into a handler
+///
+/// This saves boxing the returned future by hand.  The function may return anything
+/// implementing [`IntoResponse`], like a [`Document`](crate::Document):
+///
+/// ```no_run
+/// # use twinstar::{Server, Request, Response, GEMINI_PORT};
+/// # use twinstar::handler::from_fn;
+/// async fn hello(_request: Request) -> anyhow::Result<Response> {
+///     Ok(Response::success_plain("Hello!"))
+/// }
+///
+/// # async fn run() -> anyhow::Result<()> {
+/// Server::bind(("localhost", GEMINI_PORT))
+///     .add_route("/", from_fn(hello))
+///     .serve()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn from_fn<F, Fut, R>(f: F) -> BoxedHandler
+where
+    F: Fn(Request) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<R>> + Send + 'static,
+    R: IntoResponse,
+{
+    Box::new(move |request| {
+        let response = f(request);
+        Box::pin(async move { response.await.map(IntoResponse::into_response) })
+    })
+}
+
+/// Combinators available on every handler
+///
+/// This is implemented for all functions and closures taking a [`Request`] and
+/// returning a boxed future.  See the [module documentation](self) for an example.
+///
+/// The trait is sealed, so combinators can be added without breaking anyone.
+pub trait HandlerExt: sealed::Sealed + Fn(Request) -> HandlerResponse + Send + Sync + Sized + 'static {
+    /// Transform every response this handler produces
+    ///
+    /// Errors of the handler are passed on unchanged.
+    fn map_response<F>(self, f: F) -> BoxedHandler
+    where
+        F: Fn(Response) -> Response + Send + Sync + 'static,
+    {
+        let f = Arc::new(f);
+
+        Box::new(move |request| {
+            let response = (self)(request);
+            let f = f.clone();
+
+            Box::pin(async move { response.await.map(|response| f(response)) })
+        })
+    }
+
+    /// Let `other` handle requests this handler answers with `51 NOT FOUND`
+    ///
+    /// This is useful for layering e.g. a directory of static files over generated
+    /// pages.  Since this handler consumes the request, `other` receives a copy of it
+    /// without any [extensions](crate::types::Extensions) attached by middleware.
+    fn or_else<H>(self, other: H) -> BoxedHandler
+    where
+        H: Fn(Request) -> HandlerResponse + Send + Sync + 'static,
+    {
+        let other = Arc::new(other);
+
+        Box::new(move |request| {
+            let fallback = request.clone_without_extensions();
+            let response = (self)(request);
+            let other = other.clone();
+
+            Box::pin(async move {
+                let response = response.await?;
+
+                if response.header().status != Status::NOT_FOUND {
+                    return Ok(response);
+                }
+
+                (other)(fallback).await
+            })
+        })
+    }
+
+    /// Run `f` on every request before this handler sees it
+    ///
+    /// `f` may change the request, or answer it itself by returning a response, in
+    /// which case this handler isn't called at all.
+    fn before<F>(self, f: F) -> BoxedHandler
+    where
+        F: Fn(&mut Request) -> Option<Response> + Send + Sync + 'static,
+    {
+        Box::new(move |mut request| {
+            match f(&mut request) {
+                Some(response) => Box::pin(async { Ok(response) }),
+                None => (self)(request),
+            }
+        })
+    }
+}
+
+impl<H> HandlerExt for H
+where
+    H: Fn(Request) -> HandlerResponse + Send + Sync + 'static,
+{}
+
+mod sealed {
+    use crate::types::Request;
+    use crate::HandlerResponse;
+
+    pub trait Sealed {}
+
+    impl<H> Sealed for H
+    where
+        H: Fn(Request) -> HandlerResponse + Send + Sync + 'static,
+    {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use super::*;
+    use crate::uri::URIReference;
+
+    fn request(uri: &str) -> Request {
+        let uri = URIReference::try_from(uri).unwrap().into_owned();
+        let mut request = Request::from_uri(uri).unwrap();
+        request.set_trailing(Vec::new());
+        request
+    }
+
+    fn echo(request: Request) -> HandlerResponse {
+        Box::pin(async move { Ok(Response::success_plain(request.uri().path().to_string())) })
+    }
+
+    fn missing(_: Request) -> HandlerResponse {
+        Box::pin(async { Ok(Response::not_found()) })
+    }
+
+    #[tokio::test]
+    async fn combines_handlers() {
+        let handler = missing
+            .or_else(echo)
+            .map_response(|mut response: Response| {
+                response.header_mut().status = Status::CERTIFICATE_NOT_VALID;
+                response
+            });
+        let response = handler(request("gemini://localhost/a")).await.unwrap();
+        assert_eq!(response.header().status, Status::CERTIFICATE_NOT_VALID);
+
+        let handler = echo.or_else(missing);
+        let response = handler(request("gemini://localhost/a")).await.unwrap();
+        assert_eq!(response.header().status, Status::SUCCESS);
+
+        let handler = echo.before(|request: &mut Request| {
+            match request.path_segments().first().map(String::as_str) {
+                Some("secret") => Some(Response::not_found()),
+                _ => None,
+            }
+        });
+        let response = handler(request("gemini://localhost/secret")).await.unwrap();
+        assert_eq!(response.header().status, Status::NOT_FOUND);
+        let response = handler(request("gemini://localhost/public")).await.unwrap();
+        assert_eq!(response.header().status, Status::SUCCESS);
+    }
+
+    #[tokio::test]
+    async fn wraps_async_functions() {
+        async fn greet(request: Request) -> Result<Response> {
+            Ok(Response::success_plain(format!("hello {}", request.uri().path())))
+        }
+
+        let handler = from_fn(greet).or_else(missing);
+        let mut response = handler(request("gemini://localhost/you")).await.unwrap();
+        assert_eq!(response.header().status, Status::SUCCESS);
+        assert_eq!(response.take_body().unwrap().as_bytes(), Some(&b"hello /you"[..]));
+    }
+}
@@ -0,0 +1,136 @@
+//! Refusing requests for hosts the server doesn't serve
+//!
+//! See [`Builder::set_hostnames()`](crate::Builder::set_hostnames()) and
+//! [`Builder::set_proxy_handler()`](crate::Builder::set_proxy_handler()).
+
+use crate::GEMINI_PORT;
+use crate::types::{Request, Response, ResponseHeader, Status, Meta};
+
+/// A hostname the server answers for, optionally on a specific port
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Hostname {
+    host: String,
+    port: Option<u16>,
+}
+
+impl Hostname {
+    /// Parse `example.org`, `example.org:1965` or `[::1]:1965`
+    ///
+    /// Panics if the port isn't a number.
+    fn parse(hostname: &str) -> Self {
+        let port_colon = if hostname.starts_with('[') {
+            hostname.find(']').and_then(|end| hostname[end..].find(':').map(|colon| end + colon))
+        } else if hostname.matches(':').count() == 1 {
+            hostname.find(':')
+        } else {
+            None
+        };
+
+        let (host, port) = match port_colon {
+            Some(colon) => {
+                let port = hostname[colon + 1..].parse()
+                    .expect("Malformed hostname received");
+                (&hostname[..colon], Some(port))
+            },
+            None => (hostname, None),
+        };
+
+        Self {
+            host: normalize(host),
+            port,
+        }
+    }
+}
+
+/// The schemes of requests the server answers itself
+#[cfg(not(feature="titan"))]
+const SCHEMES: &[&str] = &["gemini"];
+#[cfg(feature="titan")]
+const SCHEMES: &[&str] = &["gemini", crate::titan::SCHEME];
+
+/// Lowercase a host and strip the trailing dot of fully qualified names
+fn normalize(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// The hostnames the server answers for
+///
+/// Requests for any other host, or with a scheme other than `gemini` (or `titan`, with
+/// the `titan` feature), are proxy requests, which are refused with
+/// `53 PROXY REQUEST REFUSED`, unless the server has a proxy handler.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Hostnames {
+    names: Vec<Hostname>,
+}
+
+impl Hostnames {
+    pub fn add(&mut self, hostname: &str) {
+        self.names.push(Hostname::parse(hostname));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Whether `request`, received on `local_port`, is for one of the hostnames
+    ///
+    /// Hostnames without a port match requests for the port the request was received
+    /// on, which is [`GEMINI_PORT`] if the request doesn't name one.
+    pub fn matches(&self, request: &Request, local_port: Option<u16>) -> bool {
+        let uri = request.uri();
+        if uri.scheme().is_some_and(|scheme| !SCHEMES.contains(&scheme.as_str())) {
+            return false;
+        }
+
+        let host = match uri.host() {
+            Some(host) => normalize(&host.to_string()),
+            None => return false,
+        };
+        let port = uri.port().unwrap_or(GEMINI_PORT);
+
+        self.names.iter().any(|name| {
+            name.host == host && match name.port {
+                Some(expected) => expected == port,
+                None => local_port.is_none_or(|local_port| local_port == port),
+            }
+        })
+    }
+
+    /// The response refusing a request for another host
+    pub fn refusal() -> Response {
+        Response::new(ResponseHeader {
+            status: Status::PROXY_REQUEST_REFUSED,
+            meta: Meta::new_lossy("Proxy request refused"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use super::*;
+    use crate::types::URIReference;
+
+    fn request(uri: &str) -> Request {
+        Request::from_uri(URIReference::try_from(uri).unwrap().into_owned()).unwrap()
+    }
+
+    #[test]
+    fn matches_hostnames() {
+        let mut hostnames = Hostnames::default();
+        hostnames.add("Example.org");
+        hostnames.add("example.org:1966");
+        hostnames.add("[::1]:1965");
+
+        assert!(hostnames.matches(&request("gemini://example.org/"), Some(1965)));
+        assert!(hostnames.matches(&request("gemini://EXAMPLE.org.:1965/"), Some(1965)));
+        assert!(hostnames.matches(&request("gemini://example.org:1966/"), Some(1965)));
+        assert!(hostnames.matches(&request("gemini://[::1]/"), Some(1967)));
+        assert!(!hostnames.matches(&request("gemini://example.org:1967/"), Some(1965)));
+        assert!(!hostnames.matches(&request("gemini://example.com/"), Some(1965)));
+        assert!(!hostnames.matches(&request("https://example.org/"), Some(1965)));
+        assert_eq!(hostnames.matches(&request("titan://example.org/a;size=0"), Some(1965)), cfg!(feature="titan"));
+
+        assert_eq!(Hostnames::refusal().header().status, Status::PROXY_REQUEST_REFUSED);
+    }
+}
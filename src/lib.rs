@@ -1,144 +1,958 @@
+#![cfg_attr(not(any(test, feature="std")), no_std)]
+
+extern crate alloc;
+#[cfg(feature="std")]
 #[macro_use] extern crate log;
 
+#[cfg(feature="std")]
 use std::{
+    any::Any,
     panic::AssertUnwindSafe,
     convert::TryFrom,
-    io::BufReader,
     sync::Arc,
     path::PathBuf,
-    time::Duration,
+    time::{Duration, Instant, SystemTime},
+    net::SocketAddr,
+    future::{self, Future},
+    task::Poll,
 };
+#[cfg(feature="std")]
 use futures_core::future::BoxFuture;
+#[cfg(feature="std")]
 use tokio::{
-    prelude::*,
-    io::{self, BufStream},
+    io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufStream},
     net::{TcpStream, ToSocketAddrs},
     time::timeout,
 };
+#[cfg(feature="std")]
 use tokio::net::TcpListener;
-use rustls::ClientCertVerifier;
-use rustls::internal::msgs::handshake::DigitallySignedStruct;
-use tokio_rustls::{rustls, TlsAcceptor};
+#[cfg(feature="std")]
+use tokio_rustls::TlsAcceptor;
+#[cfg(feature="std")]
 use rustls::*;
-use anyhow::{Result, Context, anyhow, bail, ensure};
+#[cfg(feature="std")]
+use anyhow::{Result, Context};
+#[cfg(feature="std")]
 use lazy_static::lazy_static;
+#[cfg(feature="std")]
 use crate::util::opt_timeout;
+#[cfg(feature="std")]
 use routing::RoutingNode;
+#[cfg(feature="routing")]
+use routing::RouteReport;
+#[cfg(feature="load_shedding")]
+use load_shedding::{AtConnectionLimit, ConnectionLimit, LoadShedder, LoadShedding};
+#[cfg(feature="rate_limit")]
+use rate_limit::RateLimiter;
+#[cfg(feature="description")]
+use description::{ServerDescription, TlsDescription};
+#[cfg(feature="maintenance")]
+use maintenance::Maintenance;
+#[cfg(feature="std")]
+use meta_defaults::MetaDefaults;
+#[cfg(feature="std")]
+use body_timeouts::{BodyTimeouts, ANY_MIME};
+#[cfg(feature="std")]
+use favicon::{Favicons, FAVICON_PATH};
+#[cfg(feature="std")]
+use hostnames::Hostnames;
+#[cfg(feature="middleware")]
+use middleware::{Middleware, Next};
+#[cfg(feature="auth")]
+use client_cert::ClientCertPolicy;
+#[cfg(feature="std")]
+use protocol::{send_response_header, maybe_send_response_body};
+#[cfg(feature="events")]
+use events::{Event, EventBus};
+#[cfg(feature="trusted_proxies")]
+use trusted_proxies::TrustedProxies;
+#[cfg(feature="failures")]
+use failures::FailureKind;
+#[cfg(feature="fingerprint")]
+use failures::HandshakeFailureCause;
+#[cfg(feature="metrics")]
+use failures::FailureStats;
+#[cfg(feature="drain")]
+use drain::{DrainPolicies, DrainPolicy};
+#[cfg(feature="drain")]
+use std::sync::Mutex;
+#[cfg(feature="fingerprint")]
+use fingerprint::{ClientHelloRecorder, OfferedParameters, TlsFingerprint};
+#[cfg(feature="geoip")]
+use geoip::{GeoInfo, GeoIp};
+#[cfg(feature="logging")]
+use logging::{
+    AccessRecord, ErrorRecord, LogMetrics, LogRecord, LogSink, NonBlockingSink, DEFAULT_LOG_BUFFER,
+};
+#[cfg(feature="std")]
+use redaction::QueryRedaction;
+#[cfg(feature="titan")]
+use titan::DEFAULT_MAX_UPLOAD_SIZE;
 
 pub mod types;
+#[cfg(feature="std")]
 pub mod util;
+#[cfg(feature="std")]
 pub mod routing;
-
+#[cfg(feature="std")]
+pub mod extract;
+#[cfg(feature="load_shedding")]
+pub mod load_shedding;
+#[cfg(feature="rate_limit")]
+pub mod rate_limit;
+#[cfg(feature="logging")]
+pub mod logging;
+#[cfg(feature="std")]
+#[cfg_attr(not(feature="logging"), allow(dead_code))]
+mod redaction;
+#[cfg(feature="storage")]
+pub mod storage;
+#[cfg(feature="description")]
+pub mod description;
+#[cfg(feature="maintenance")]
+mod maintenance;
+#[cfg(feature="std")]
+mod meta_defaults;
+#[cfg(feature="std")]
+mod body_timeouts;
+#[cfg(feature="std")]
+mod favicon;
+#[cfg(feature="std")]
+mod hostnames;
+#[cfg(feature="middleware")]
+pub mod middleware;
+#[cfg(feature="handler")]
+pub mod handler;
+#[cfg(feature="std")]
+pub mod protocol;
+#[cfg(feature="std")]
+pub mod prelude;
+#[cfg(feature="events")]
+pub mod events;
+#[cfg(feature="trusted_proxies")]
+pub mod trusted_proxies;
+#[cfg(feature="geoip")]
+pub mod geoip;
+#[cfg(feature="failures")]
+pub mod failures;
+#[cfg(feature="drain")]
+pub mod drain;
+#[cfg(feature="titan")]
+pub mod titan;
+#[cfg(feature="fingerprint")]
+pub mod fingerprint;
+#[cfg(feature="tarpit")]
+pub mod tarpit;
+#[cfg(feature="blocking")]
+pub mod blocking;
+#[cfg(feature="client")]
+pub mod client;
+#[cfg(feature="auth")]
+pub mod client_cert;
+#[cfg(feature="client")]
+pub mod tools;
+#[cfg(feature="multi_tenant")]
+pub mod multi_tenant;
+#[cfg(any(all(test, feature="std"), feature="testing"))]
+pub mod testing;
+#[cfg(feature="x509")]
+pub mod x509;
+#[cfg(feature="bench")]
+pub mod bench;
+#[cfg(feature="std")]
+mod shutdown;
+#[cfg(feature="std")]
+mod error;
+#[cfg(feature="std")]
+mod tls;
+#[cfg(all(windows, feature="windows-service"))]
+pub mod windows;
+
+#[cfg(feature="std")]
 pub use mime;
+#[cfg(feature="std")]
+pub use tokio_rustls::rustls;
+#[cfg(feature="std")]
 pub use uriparse as uri;
 pub use types::*;
+#[cfg(feature="std")]
+pub use shutdown::Shutdown;
+#[cfg(feature="std")]
+pub use error::Error;
+#[cfg(feature="std")]
+pub use tls::TlsVersion;
 
 pub const REQUEST_URI_MAX_LEN: usize = 1024;
 pub const GEMINI_PORT: u16 = 1965;
 
+/// How much a client may send after its request before the connection is closed anyway
+#[cfg(feature="std")]
+const MAX_LINGER_BYTES: u64 = REQUEST_URI_MAX_LEN as u64;
+/// How long to wait for a client to close its side after a `close_notify`
+#[cfg(feature="std")]
+const MAX_LINGER: Duration = Duration::from_secs(1);
+
+#[cfg(feature="std")]
 type Handler = Arc<dyn Fn(Request) -> HandlerResponse + Send + Sync>;
+#[cfg(feature="logging")]
+type RequestCallback = Arc<dyn Fn(&AccessRecord) + Send + Sync>;
+#[cfg(feature="std")]
 pub (crate) type HandlerResponse = BoxFuture<'static, Result<Response>>;
 
+/// A handler behind a pointer, as returned by the handlers built by twinstar
+///
+/// Like any other handler, it can be passed to [`Builder::add_route()`], or combined
+/// further.
+#[cfg(feature="std")]
+pub type BoxedHandler = Box<dyn Fn(Request) -> HandlerResponse + Send + Sync>;
+
+#[cfg(feature="std")]
 #[derive(Clone)]
 pub struct Server {
     tls_acceptor: TlsAcceptor,
-    listener: Arc<TcpListener>,
+    listeners: Arc<[TcpListener]>,
     routes: Arc<RoutingNode<Handler>>,
+    fallback: Option<Handler>,
+    #[cfg(feature="middleware")]
+    middleware: Arc<[Arc<dyn Middleware>]>,
     timeout: Duration,
-    complex_timeout: Option<Duration>,
+    body_timeouts: Arc<BodyTimeouts>,
+    #[cfg(feature="load_shedding")]
+    load_shedder: Option<Arc<LoadShedder>>,
+    #[cfg(feature="load_shedding")]
+    connection_limit: Option<Arc<ConnectionLimit>>,
+    #[cfg(feature="rate_limit")]
+    rate_limiter: Option<RateLimiter>,
+    #[cfg(feature="logging")]
+    log_sink: Option<Arc<NonBlockingSink>>,
+    query_redaction: Arc<QueryRedaction>,
+    #[cfg(feature="logging")]
+    on_request_complete: Arc<[RequestCallback]>,
+    #[cfg(feature="description")]
+    description: Arc<ServerDescription>,
+    #[cfg(feature="maintenance")]
+    maintenance: Arc<Maintenance>,
+    #[cfg(feature="events")]
+    events: EventBus,
+    #[cfg(feature="trusted_proxies")]
+    trusted_proxies: Option<Arc<TrustedProxies>>,
+    #[cfg(feature="geoip")]
+    geoip: Option<Arc<GeoIp>>,
+    meta_defaults: Arc<MetaDefaults>,
+    #[cfg(feature="metrics")]
+    failures: FailureStats,
+    strict: bool,
+    #[cfg(feature="fingerprint")]
+    min_tls_version: TlsVersion,
+    #[cfg(feature="fingerprint")]
+    tls_fingerprinting: bool,
+    close_notify: bool,
+    #[cfg(feature="x509")]
+    validate_client_cert_expiry: bool,
+    header_flush: HeaderFlush,
+    #[cfg(feature="drain")]
+    drain_policies: Arc<DrainPolicies>,
+    #[cfg(feature="titan")]
+    max_upload_size: usize,
+    #[cfg(feature="titan")]
+    upload_timeout: Duration,
+    #[cfg(feature="route_debug")]
+    route_labels: Option<Arc<RoutingNode<String>>>,
+    hostnames: Option<Arc<Hostnames>>,
+    proxy_handler: Option<Handler>,
+    path_normalizer: Option<util::PathNormalizer>,
+    state: Arc<Extensions>,
+}
+
+/// Why a connection couldn't be served
+#[cfg(feature="std")]
+struct Failure {
+    #[cfg(feature="failures")]
+    kind: FailureKind,
+    error: anyhow::Error,
 }
 
+/// Turn an error into a [`Failure`] of the given [`FailureKind`]
+///
+/// The kind is only kept with the `failures` feature.
+#[cfg(feature="std")]
+macro_rules! failure {
+    ($kind:ident) => {
+        |error: anyhow::Error| Failure {
+            #[cfg(feature="failures")]
+            kind: FailureKind::$kind,
+            error,
+        }
+    };
+}
+
+/// What's needed to write an access record once a response has been sent
+#[cfg(feature="std")]
+#[cfg_attr(not(feature="logging"), allow(dead_code))]
+struct PendingAccessRecord {
+    time: SystemTime,
+    start: Instant,
+    peer_addr: SocketAddr,
+    uri: String,
+    handler_duration: Option<Duration>,
+    #[cfg(feature="geoip")]
+    geo: Option<GeoInfo>,
+    /// The hash of the client's TLS fingerprint
+    tls_fingerprint: Option<String>,
+}
+
+#[cfg(feature="std")]
 impl Server {
+    /// Start building a server listening on every address `addr` resolves to
+    ///
+    /// A host name like `localhost` is served on all of its IPv4 and IPv6 addresses,
+    /// and a slice of addresses on each of them, e.g. for dual-stack servers:
+    ///
+    /// ```no_run
+    /// # use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+    /// # use twinstar::{Server, GEMINI_PORT};
+    /// # async fn run() -> anyhow::Result<()> {
+    /// let addrs = [
+    ///     SocketAddr::from((Ipv4Addr::UNSPECIFIED, GEMINI_PORT)),
+    ///     SocketAddr::from((Ipv6Addr::UNSPECIFIED, GEMINI_PORT)),
+    /// ];
+    ///
+    /// Server::bind(&addrs[..])
+    ///     .serve()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// Addresses which can't be bound, e.g. IPv6 addresses on hosts without IPv6, are
+    /// skipped with a warning, as long as at least one address can be bound.  With port
+    /// 0, every address gets a port of its own, which are listed by
+    /// [`local_addrs()`](Self::local_addrs()).
     pub fn bind<A: ToSocketAddrs>(addr: A) -> Builder<A> {
         Builder::bind(addr)
     }
 
-    async fn serve(self) -> Result<()> {
+    /// The addresses this server accepts connections on
+    ///
+    /// For listeners bound to port 0, this includes the port picked by the operating
+    /// system.
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.listeners.iter().filter_map(|listener| listener.local_addr().ok()).collect()
+    }
+
+    /// Summarize how this server is configured
+    ///
+    /// The same summary is logged at the `info` level when the server starts serving.
+    /// This requires the `description` feature.
+    #[cfg(feature="description")]
+    pub fn describe(&self) -> ServerDescription {
+        (*self.description).clone()
+    }
+
+    /// Put every route below `route_prefix` under maintenance
+    ///
+    /// Until the maintenance is lifted using [`clear_maintenance()`](Self::clear_maintenance()),
+    /// requests to these routes are answered with `41 SERVER UNAVAILABLE` and `message`
+    /// as the meta, instead of being passed to their handlers.  Routes are matched the
+    /// same way as for handlers, so `/blog` also covers `/blog/2020/hello.gmi`.
+    ///
+    /// This takes effect immediately, also for servers which are already serving, since
+    /// all clones of a [`Server`] share their maintenance state.  This requires the
+    /// `maintenance` feature.
+    ///
+    /// ```no_run
+    /// # use twinstar::{Server, GEMINI_PORT};
+    /// # async fn run() -> anyhow::Result<()> {
+    /// let server = Server::bind(("localhost", GEMINI_PORT)).build().await?;
+    /// let handle = server.clone();
+    /// tokio::spawn(server.serve());
+    ///
+    /// handle.set_maintenance("/blog", "Moving the blog, back in an hour")?;
+    /// // ...
+    /// handle.clear_maintenance("/blog")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature="maintenance")]
+    pub fn set_maintenance(&self, route_prefix: &str, message: &str) -> Result<(), Error> {
+        self.maintenance.set_unavailable(route_prefix, message).map_err(Error::Parse)
+    }
+
+    /// Put every route below `route_prefix` under maintenance, answering with `document`
+    ///
+    /// Like [`set_maintenance()`](Self::set_maintenance()), but requests are answered
+    /// with a successful response containing `document`, which can explain the
+    /// maintenance in more detail than a meta can.
+    #[cfg(feature="maintenance")]
+    pub fn set_maintenance_page(&self, route_prefix: &str, document: &Document) -> Result<(), Error> {
+        self.maintenance.set_page(route_prefix, document).map_err(Error::Parse)
+    }
+
+    /// Lift the maintenance of `route_prefix`, restoring its regular handlers
+    ///
+    /// Returns whether the route was under maintenance.  Maintenance of routes nested
+    /// below `route_prefix` is kept.
+    #[cfg(feature="maintenance")]
+    pub fn clear_maintenance(&self, route_prefix: &str) -> Result<bool, Error> {
+        self.maintenance.clear(route_prefix).map_err(Error::Parse)
+    }
+
+    /// The routes currently under maintenance
+    #[cfg(feature="maintenance")]
+    pub fn maintenance_routes(&self) -> Vec<String> {
+        self.maintenance.routes()
+    }
+
+    /// The number of requests which couldn't be served, by cause
+    ///
+    /// See the [`failures`] module for details.  This requires the `metrics` feature.
+    #[cfg(feature="metrics")]
+    pub fn failure_stats(&self) -> &FailureStats {
+        &self.failures
+    }
+
+    /// The bus this server publishes [`Event`]s on
+    ///
+    /// This requires the `events` feature.
+    #[cfg(feature="events")]
+    pub const fn events(&self) -> &EventBus {
+        &self.events
+    }
+
+    /// Start serving requests
+    pub async fn serve(self) -> Result<(), Error> {
+        self.serve_until(future::pending()).await
+    }
+
+    /// Start serving requests until `shutdown` completes
+    ///
+    /// See [`Builder::serve_until()`] for details.  Connections accepted by other
+    /// clones of this server are not drained.
+    pub async fn serve_until(self, shutdown: impl Future<Output = ()>) -> Result<(), Error> {
+        let mut shutdown = Box::pin(shutdown);
+
+        #[cfg(feature="description")]
+        for line in self.description.to_string().lines() {
+            info!("{}", line);
+        }
+
+        // Where polling starts, so busy listeners can't starve the others
+        let mut next_listener = 0;
+
+        // Every connection holds a sender, so receiving ends once all of them are closed
+        let draining = Shutdown::new();
+        let (connections, mut drained) = tokio::sync::mpsc::channel::<()>(1);
+
+        // When connections are limited by not accepting them, a permit for the next one
+        #[cfg(feature="load_shedding")]
+        let stop_accepting = self.connection_limit.as_ref()
+            .filter(|limit| limit.at_limit() == AtConnectionLimit::StopAccepting);
+        #[cfg(feature="load_shedding")]
+        let mut next_permit = None;
+        #[cfg(feature="load_shedding")]
+        let mut admitting = None;
+
         loop {
-            let (stream, _addr) = self.listener.accept().await
-                .context("Failed to accept client")?;
+            let accepted = future::poll_fn(|cx| {
+                if shutdown.as_mut().poll(cx).is_ready() {
+                    return Poll::Ready(None);
+                }
+
+                #[cfg(feature="load_shedding")]
+                if let (Some(limit), None) = (stop_accepting, &next_permit) {
+                    let admit = admitting.get_or_insert_with(|| Box::pin(limit.admit()));
+                    next_permit = Some(futures_core::ready!(admit.as_mut().poll(cx)));
+                    admitting = None;
+                }
+
+                for offset in 0..self.listeners.len() {
+                    let index = (next_listener + offset) % self.listeners.len();
+                    if let Poll::Ready(accepted) = self.listeners[index].poll_accept(cx) {
+                        next_listener = index + 1;
+                        return Poll::Ready(Some(accepted));
+                    }
+                }
+
+                Poll::Pending
+            });
+
+            let (stream, addr) = match accepted.await {
+                Some(accepted) => accepted.map_err(Error::io("Failed to accept client"))?,
+                None => {
+                    info!("Shutting down, no longer accepting connections");
+                    draining.trigger();
+                    drop(connections);
+                    drained.recv().await;
+                    info!("All connections drained");
+                    return Ok(());
+                },
+            };
+            #[cfg(feature="load_shedding")]
+            let permit = match (&self.connection_limit, next_permit.take()) {
+                (_, Some(permit)) => Some(permit),
+                (Some(limit), None) => limit.try_admit(),
+                (None, None) => None,
+            };
+            #[cfg(feature="load_shedding")]
+            let admitted = self.connection_limit.is_none() || permit.is_some();
+            #[cfg(not(feature="load_shedding"))]
+            let (permit, admitted) = (None::<()>, true);
             let this = self.clone();
+            let connection = connections.clone();
+            #[cfg(feature="drain")]
+            let draining = draining.wait();
 
             tokio::spawn(async move {
-                if let Err(err) = this.serve_client(stream).await {
-                    error!("{:?}", err);
+                let _connection = (connection, permit);
+                #[cfg(feature="drain")]
+                let served = {
+                    let policy = Mutex::new(DrainPolicy::Finish);
+                    let served = this.serve_client(stream, addr, &policy, admitted);
+                    match drain::drain_with(served, draining, &policy).await {
+                        Some(served) => served,
+                        None => {
+                            debug!("Closed connection from {} while draining", addr);
+                            return;
+                        },
+                    }
+                };
+                #[cfg(not(feature="drain"))]
+                let served = this.serve_client(stream, addr, admitted).await;
+
+                if let Err(failure) = served {
+                    this.report_failure(failure, addr);
                 }
             });
         }
     }
 
-    async fn serve_client(self, stream: TcpStream) -> Result<()> {
+    /// Log a connection which couldn't be served, and count it with the `metrics` feature
+    #[cfg_attr(not(feature="logging"), allow(unused_variables))]
+    fn report_failure(&self, failure: Failure, peer_addr: SocketAddr) {
+        #[cfg(feature="failures")]
+        {
+            error!("{}: {:?}", failure.kind, failure.error);
+            self.record_failure(failure.kind);
+        }
+        #[cfg(not(feature="failures"))]
+        error!("{:?}", failure.error);
+
+        #[cfg(feature="logging")]
+        self.log(LogRecord::Error(ErrorRecord {
+            time: SystemTime::now(),
+            peer_addr: Some(peer_addr),
+            message: format!("{:#}", failure.error),
+            #[cfg(feature="failures")]
+            kind: Some(failure.kind),
+        }));
+    }
+
+    /// Count a failed request, if the `metrics` feature is enabled
+    #[cfg(feature="failures")]
+    #[cfg_attr(not(feature="metrics"), allow(unused_variables))]
+    fn record_failure(&self, kind: FailureKind) {
+        #[cfg(feature="metrics")]
+        self.failures.record(kind);
+    }
+
+    /// Log and count what a client failing the TLS handshake offered
+    #[cfg(feature="fingerprint")]
+    fn diagnose_handshake<S>(&self, mut stream: ClientHelloRecorder<S>, peer_addr: SocketAddr) {
+        let offered = stream.take_offered();
+        let max_offered = offered.as_ref().and_then(OfferedParameters::max_version);
+        let cause = HandshakeFailureCause::classify(max_offered, self.min_tls_version.wire_version());
+        #[cfg(feature="metrics")]
+        self.failures.record_handshake_failure(cause);
+
+        match offered {
+            Some(offered) => debug!("TLS handshake with {} failed ({}), client offered {}", peer_addr, cause, offered),
+            None => debug!("TLS handshake with {} failed ({}), client sent no ClientHello", peer_addr, cause),
+        }
+    }
+
+    /// Log a failed TLS handshake, without recording what the client offered
+    #[cfg(not(feature="fingerprint"))]
+    fn diagnose_handshake<S>(&self, _stream: S, peer_addr: SocketAddr) {
+        debug!("TLS handshake with {} failed", peer_addr);
+    }
+
+    #[cfg_attr(not(feature="load_shedding"), allow(unused_variables))]
+    async fn serve_client(
+        &self,
+        #[cfg_attr(not(feature="trusted_proxies"), allow(unused_mut))]
+        mut stream: TcpStream,
+        peer_addr: SocketAddr,
+        #[cfg(feature="drain")]
+        drain_policy: &Mutex<DrainPolicy>,
+        admitted: bool,
+    ) -> Result<(), Failure> {
+        let local_addr = stream.local_addr().ok();
+
         let fut_accept_request = async {
-            let stream = self.tls_acceptor.accept(stream).await
-                .context("Failed to establish TLS session")?;
+            #[cfg(feature="trusted_proxies")]
+            let peer_addr = match &self.trusted_proxies {
+                Some(proxies) if proxies.is_trusted(peer_addr.ip()) => {
+                    TrustedProxies::read_header(&mut stream, peer_addr).await
+                        .map_err(failure!(ProxyHeader))?
+                },
+                _ => peer_addr,
+            };
+
+            #[cfg(feature="fingerprint")]
+            let stream = ClientHelloRecorder::new(stream);
+            #[cfg_attr(not(feature="fingerprint"), allow(unused_mut))]
+            let mut stream = match self.tls_acceptor.accept(stream).into_fallible().await {
+                Ok(stream) => stream,
+                Err((error, stream)) => {
+                    self.diagnose_handshake(stream, peer_addr);
+                    return Err(failure!(TlsHandshake)(
+                        anyhow::Error::from(error).context("Failed to establish TLS session")
+                    ));
+                },
+            };
+            #[cfg(feature="fingerprint")]
+            let tls_fingerprint = {
+                let recorder = &mut stream.get_mut().0;
+                if self.tls_fingerprinting {
+                    recorder.take_fingerprint()
+                } else {
+                    recorder.discard();
+                    None
+                }
+            };
             let mut stream = BufStream::new(stream);
 
-            let request = receive_request(&mut stream).await
-                .context("Failed to receive request")?;
-
-            Result::<_, anyhow::Error>::Ok((request, stream))
+            #[cfg_attr(not(feature="fingerprint"), allow(unused_mut))]
+            let mut request = match protocol::read_request(&mut stream).await {
+                Ok(request) => request,
+                Err(err) => {
+                    if let Some(malformed) = err.downcast_ref::<protocol::MalformedRequest>() {
+                        // The request failed either way, so a failure to answer doesn't matter
+                        if protocol::write_response(malformed.response(), &mut stream).await.is_ok() {
+                            self.close_connection(&mut stream).await;
+                        }
+                    }
+
+                    return Err(failure!(MalformedRequest)(err.context("Failed to receive request")));
+                },
+            };
+
+            #[cfg(feature="fingerprint")]
+            if let Some(fingerprint) = tls_fingerprint {
+                request.extensions_mut().insert(fingerprint);
+            }
+
+            Ok((request, stream, peer_addr))
         };
 
         // Use a timeout for interacting with the client
         let fut_accept_request = timeout(self.timeout, fut_accept_request);
-        let (mut request, mut stream) = fut_accept_request.await
-            .context("Client timed out while waiting for response")??;
+        let (mut request, mut stream, peer_addr) = fut_accept_request.await
+            .context("Client timed out while waiting for response")
+            .map_err(failure!(Timeout))??;
+
+        let mut access = PendingAccessRecord {
+            time: SystemTime::now(),
+            start: Instant::now(),
+            peer_addr,
+            uri: self.query_redaction.redact(&request),
+            handler_duration: None,
+            #[cfg(feature="geoip")]
+            geo: self.geoip.as_ref().map(|geoip| geoip.lookup(peer_addr.ip())),
+            #[cfg(feature="fingerprint")]
+            tls_fingerprint: TlsFingerprint::of(&request).map(|fingerprint| fingerprint.hash.clone()),
+            #[cfg(not(feature="fingerprint"))]
+            tls_fingerprint: None,
+        };
+
+        debug!("Client requested: {}", access.uri);
 
-        debug!("Client requested: {}", request.uri());
+        #[cfg(feature="geoip")]
+        if let Some(geo) = &access.geo {
+            request.extensions_mut().insert(geo.clone());
+        }
+
+        #[cfg(feature="titan")]
+        if titan::is_titan(&request) {
+            let refused = titan::receive_upload(&mut request, &mut stream, self.max_upload_size, self.upload_timeout).await
+                .map_err(|err| {
+                    let timed_out = err.chain().any(|cause| cause.is::<tokio::time::error::Elapsed>());
+                    if timed_out { failure!(Timeout)(err) } else { failure!(MalformedRequest)(err) }
+                })?;
+            if let Some(response) = refused {
+                debug!("Refusing upload for {}", access.uri);
+                return self.finish_request(response, &mut stream, access).await;
+            }
+        }
 
         // Identify the client certificate from the tls stream.  This is the first
         // certificate in the certificate chain.
-        let client_cert = stream.get_ref()
-            .get_ref()
-            .1
-            .get_peer_certificates()
-            .and_then(|mut v| if v.is_empty() {None} else {Some(v.remove(0))});
+        let connection = stream.get_ref().get_ref().1;
+        let client_cert = tls::peer_certificates(connection)
+            .into_iter()
+            .next()
+            .map(PeerCertificate::from_der);
+        let tls_info = tls::tls_info(connection);
 
         request.set_cert(client_cert);
+        request.set_remote_addr(Some(peer_addr));
+        request.set_local_addr(local_addr);
+        request.set_tls_info(tls_info);
+        request.set_state(self.state.clone());
+
+        if let Some(normalizer) = &self.path_normalizer {
+            if let Err(err) = util::normalize_path(&mut request, normalizer.as_ref()) {
+                debug!("Failed to normalize path of {}: {:#}", access.uri, err);
+            }
+        }
 
-        let response = if let Some((trailing, handler)) = self.routes.match_request(&request) {
+        #[cfg(feature="drain")]
+        {
+            *drain_policy.lock().expect("twinstar BUG") = self.drain_policies.policy_for(&request);
+        }
 
-            request.set_trailing(trailing);
+        let proxied = match &self.hostnames {
+            Some(hostnames) if !hostnames.matches(&request, local_addr.map(|addr| addr.port())) => {
+                if self.proxy_handler.is_none() {
+                    debug!("Refusing proxy request for {}", access.uri);
+                    return self.finish_request(Hostnames::refusal(), &mut stream, access).await;
+                }
+                true
+            },
+            _ => false,
+        };
 
-            let handler = (handler)(request);
-            let handler = AssertUnwindSafe(handler);
+        #[cfg(feature="load_shedding")]
+        if let (false, Some(limit)) = (admitted, &self.connection_limit) {
+            debug!("Too many connections, rejecting {}", access.uri);
+            return self.finish_request(limit.response(), &mut stream, access).await;
+        }
 
-            util::HandlerCatchUnwind::new(handler).await
-                .unwrap_or_else(|_| Response::server_error(""))
-                .or_else(|err| {
-                    error!("Handler failed: {:?}", err);
-                    Response::server_error("")
-                })
-                .context("Request handler failed")?
-        } else {
-            Response::not_found()
+        #[cfg(feature="x509")]
+        if self.validate_client_cert_expiry {
+            // Certificates which can't be parsed are rejected as well, as not valid
+            if let Some(Err(reason)) = request.certificate().map(|cert| cert.check_validity(SystemTime::now())) {
+                debug!("Rejecting client certificate for {}: {}", access.uri, reason);
+                return self.finish_request(Response::certificate_not_valid_lossy(reason), &mut stream, access).await;
+            }
+        }
+
+        #[cfg(feature="maintenance")]
+        if let Some(response) = self.maintenance.check(&request) {
+            debug!("Route is under maintenance: {}", access.uri);
+            return self.finish_request(response, &mut stream, access).await;
+        }
+
+        #[cfg(feature="rate_limit")]
+        if let Some(response) = self.rate_limiter.as_ref().and_then(|limiter| limiter.limit(&request)) {
+            debug!("Rate limiting {} for {}", peer_addr.ip(), access.uri);
+            return self.finish_request(response, &mut stream, access).await;
+        }
+
+        #[cfg(feature="load_shedding")]
+        let in_flight = match &self.load_shedder {
+            Some(shedder) => match shedder.try_admit() {
+                Some(in_flight) => Some(in_flight),
+                None => {
+                    debug!("Shedding request for {}", access.uri);
+                    return self.finish_request(shedder.response(), &mut stream, access).await;
+                }
+            },
+            None => None,
+        };
+
+        #[cfg(feature="route_debug")]
+        if let Some(labels) = &self.route_labels {
+            info!("Routing {}", labels.trace_request(&request, String::clone));
+        }
+
+        let handler_start = Instant::now();
+
+        let handler = AssertUnwindSafe(self.handle(request, proxied));
+
+        let mut response = match util::HandlerCatchUnwind::new(handler).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(err)) => {
+                error!("Handler failed: {:?}", err);
+                #[cfg(feature="failures")]
+                self.record_failure(FailureKind::HandlerError);
+                Response::new(ResponseHeader::server_error_lossy(""))
+            },
+            Err(_) => {
+                #[cfg(feature="failures")]
+                self.record_failure(FailureKind::HandlerPanic);
+                Response::new(ResponseHeader::server_error_lossy(""))
+            },
         };
 
-        self.send_response(response, &mut stream).await
-            .context("Failed to send response")?;
+        let handler_duration = handler_start.elapsed();
+        access.handler_duration = Some(handler_duration);
+
+        #[cfg(feature="load_shedding")]
+        if let Some(in_flight) = &in_flight {
+            in_flight.record_latency(handler_duration);
+        }
+
+        self.meta_defaults.apply(&mut response);
+
+        if self.strict {
+            if let Err(err) = response.header().validate() {
+                error!("Handler sent an invalid response for {}: {:#}", access.uri, err);
+                #[cfg(feature="failures")]
+                self.record_failure(FailureKind::InvalidResponse);
+                #[cfg(feature="logging")]
+                self.log(LogRecord::Error(ErrorRecord {
+                    time: SystemTime::now(),
+                    peer_addr: Some(access.peer_addr),
+                    message: format!("Invalid response for {}: {:#}", access.uri, err),
+                    #[cfg(feature="failures")]
+                    kind: Some(FailureKind::InvalidResponse),
+                }));
+
+                response = Response::new(ResponseHeader {
+                    status: Status::CGI_ERROR,
+                    meta: Meta::new_lossy("Invalid response"),
+                });
+            }
+
+            lint_gemtext(&response, &access.uri);
+        }
+
+        self.finish_request(response, &mut stream, access).await
+    }
+
+    /// Send the response to a request, and log it to the access log
+    async fn finish_request(
+        &self,
+        response: Response,
+        stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+        access: PendingAccessRecord,
+    ) -> Result<(), Failure> {
+        let header = response.header().clone();
+
+        self.query_redaction.observe(&access.uri, header.status);
+
+        #[cfg_attr(not(feature="logging"), allow(unused_variables))]
+        let body_bytes = self.send_response(response, stream).await
+            .context("Failed to send response")
+            .map_err(|error| {
+                let timed_out = error.chain().any(|cause| cause.is::<tokio::time::error::Elapsed>());
+                if timed_out { failure!(Timeout)(error) } else { failure!(ResponseWrite)(error) }
+            })?;
+
+        #[cfg(feature="logging")]
+        self.record_access(access, header, body_bytes);
+
+        self.close_connection(stream).await;
 
         Ok(())
     }
 
-    async fn send_response(&self, mut response: Response, stream: &mut (impl AsyncWrite + Unpin)) -> Result<()> {
+    /// Pass a request on to the middleware and its handler, or the proxy handler
+    #[cfg(feature="middleware")]
+    fn handle(&self, request: Request, proxied: bool) -> HandlerResponse {
+        let next = match (proxied, &self.proxy_handler) {
+            (true, Some(proxy_handler)) => Next::new(self.middleware.clone(), Arc::default())
+                .with_fallback(Some(proxy_handler.clone())),
+            _ => Next::new(self.middleware.clone(), self.routes.clone())
+                .with_fallback(self.fallback.clone()),
+        };
+
+        next.run(request)
+    }
+
+    /// Pass a request on to its handler, or the proxy handler
+    #[cfg(not(feature="middleware"))]
+    fn handle(&self, request: Request, proxied: bool) -> HandlerResponse {
+        match (proxied, &self.proxy_handler) {
+            (true, Some(proxy_handler)) => proxy_handler(request),
+            _ => route_request(&self.routes, self.fallback.as_ref(), request),
+        }
+    }
+
+    /// Pass the record of an answered request to the callbacks, events and log sink
+    #[cfg(feature="logging")]
+    fn record_access(&self, access: PendingAccessRecord, header: ResponseHeader, body_bytes: u64) {
+        let record = AccessRecord {
+            time: access.time,
+            peer_addr: access.peer_addr,
+            uri: access.uri,
+            status: header.status,
+            meta: header.meta.as_str().to_owned(),
+            body_bytes,
+            duration: access.start.elapsed(),
+            handler_duration: access.handler_duration,
+            #[cfg(feature="geoip")]
+            geo: access.geo,
+            tls_fingerprint: access.tls_fingerprint,
+        };
+
+        for callback in self.on_request_complete.iter() {
+            callback(&record);
+        }
+
+        #[cfg(feature="events")]
+        if self.events.has_subscribers() {
+            self.events.publish(Event::RequestCompleted(record.clone()));
+        }
+
+        self.log(LogRecord::Access(record));
+    }
+
+    /// Close the TLS session after a response has been sent
+    ///
+    /// The response has already been delivered at this point, so failing to close the
+    /// connection cleanly isn't treated as a failure.
+    async fn close_connection(&self, stream: &mut (impl AsyncRead + AsyncWrite + Unpin)) {
+        if !self.close_notify {
+            return;
+        }
+
+        let close = async {
+            stream.shutdown().await?;
+
+            // Wait for the client to close its side, since closing the socket while the
+            // client is still sending would reset the connection, possibly before the
+            // client read the whole response
+            io::copy(&mut (&mut *stream).take(MAX_LINGER_BYTES), &mut io::sink()).await?;
+
+            Ok::<_, io::Error>(())
+        };
+
+        match timeout(self.timeout.min(MAX_LINGER), close).await {
+            Ok(Ok(())) => {},
+            Ok(Err(err)) => debug!("Failed to close connection cleanly: {}", err),
+            Err(_) => debug!("Client didn't close the connection in time"),
+        }
+    }
+
+    #[cfg(feature="logging")]
+    fn log(&self, record: LogRecord) {
+        if let Some(log_sink) = &self.log_sink {
+            log_sink.log(record);
+        }
+    }
+
+    /// Send a response, returning the number of body bytes sent
+    async fn send_response(&self, mut response: Response, stream: &mut (impl AsyncWrite + Unpin)) -> Result<u64> {
         let maybe_body = response.take_body();
         let header = response.header();
+        let flush_header = maybe_body.is_none()
+            || response.header_flush().unwrap_or(self.header_flush) == HeaderFlush::Immediately;
 
-        let use_complex_timeout =
-            header.status.is_success() &&
-            maybe_body.is_some() &&
-            header.meta.as_str() != "text/plain" &&
-            header.meta.as_str() != "text/gemini" &&
-            self.complex_timeout.is_some();
+        let body_timeout = match maybe_body {
+            Some(_) if header.status.is_success() => self.body_timeouts.body_timeout(header.meta.as_str()),
+            _ => None,
+        };
 
         let send_general_timeout;
         let send_header_timeout;
         let send_body_timeout;
 
-        if use_complex_timeout {
+        if let Some(body_timeout) = body_timeout {
             send_general_timeout = None;
             send_header_timeout = Some(self.timeout);
-            send_body_timeout = self.complex_timeout;
+            send_body_timeout = Some(body_timeout);
         } else {
             send_general_timeout = Some(self.timeout);
             send_header_timeout = None;
@@ -147,47 +961,203 @@ impl Server {
 
         opt_timeout(send_general_timeout, async {
             // Send the header
-            opt_timeout(send_header_timeout, send_response_header(response.header(), stream))
+            opt_timeout(send_header_timeout, send_response_header(response.header(), flush_header, stream))
                 .await
                 .context("Timed out while sending response header")?
                 .context("Failed to write response header")?;
 
             // Send the body
-            opt_timeout(send_body_timeout, maybe_send_response_body(maybe_body, stream))
+            let body_bytes = opt_timeout(send_body_timeout, maybe_send_response_body(maybe_body, stream))
                 .await
                 .context("Timed out while sending response body")?
                 .context("Failed to write response body")?;
 
-            Ok::<_,anyhow::Error>(())
+            Ok::<_,anyhow::Error>(body_bytes)
         })
         .await
-        .context("Timed out while sending response data")??;
-
-        Ok(())
+        .context("Timed out while sending response data")?
     }
 }
 
+#[cfg(feature="std")]
 pub struct Builder<A> {
     addr: A,
-    cert_path: PathBuf,
-    key_path: PathBuf,
+    listeners: Vec<ExistingListener>,
+    cert: PemSource,
+    key: PemSource,
+    additional_certs: Vec<(PemSource, PemSource)>,
+    #[cfg(feature="auth")]
+    client_cert_policy: Option<Arc<dyn ClientCertPolicy>>,
+    min_tls_version: TlsVersion,
+    custom_tls_config: Option<Arc<ServerConfig>>,
+    #[cfg(feature="generate_cert")]
+    generated_cert_hostname: Option<String>,
     timeout: Duration,
-    complex_body_timeout_override: Option<Duration>,
+    body_timeouts: BodyTimeouts,
     routes: RoutingNode<Handler>,
+    route_origins: Vec<(String, String)>,
+    fallback: Option<Handler>,
+    #[cfg(feature="middleware")]
+    middleware: Vec<Arc<dyn Middleware>>,
+    #[cfg(feature="load_shedding")]
+    load_shedding: Option<LoadShedding>,
+    #[cfg(feature="load_shedding")]
+    max_connections: Option<usize>,
+    #[cfg(feature="load_shedding")]
+    at_connection_limit: AtConnectionLimit,
+    #[cfg(feature="rate_limit")]
+    rate_limiter: Option<RateLimiter>,
+    #[cfg(feature="logging")]
+    log_sink: Option<Box<dyn LogSink>>,
+    #[cfg(feature="logging")]
+    log_buffer: usize,
+    #[cfg(feature="logging")]
+    log_metrics: LogMetrics,
+    query_redaction: QueryRedaction,
+    #[cfg(feature="logging")]
+    on_request_complete: Vec<RequestCallback>,
+    #[cfg(feature="events")]
+    events: EventBus,
+    #[cfg(feature="trusted_proxies")]
+    trusted_proxies: Option<TrustedProxies>,
+    #[cfg(feature="geoip")]
+    geoip: Option<GeoIp>,
+    meta_defaults: MetaDefaults,
+    #[cfg(feature="metrics")]
+    failures: FailureStats,
+    strict: bool,
+    #[cfg(feature="fingerprint")]
+    tls_fingerprinting: bool,
+    close_notify: bool,
+    #[cfg(feature="x509")]
+    validate_client_cert_expiry: bool,
+    header_flush: HeaderFlush,
+    #[cfg(feature="drain")]
+    drain_policies: DrainPolicies,
+    #[cfg(feature="titan")]
+    max_upload_size: usize,
+    #[cfg(feature="titan")]
+    upload_timeout: Duration,
+    favicons: Favicons,
+    capsule_info: Option<util::CapsuleInfo>,
+    #[cfg(feature="route_debug")]
+    route_tracing: bool,
+    #[cfg(feature="route_debug")]
+    route_trace_page: Option<&'static str>,
+    hostnames: Hostnames,
+    proxy_handler: Option<Handler>,
+    path_normalizer: Option<util::PathNormalizer>,
+    state: Extensions,
 }
 
+#[cfg(feature="std")]
 impl<A: ToSocketAddrs> Builder<A> {
     fn bind(addr: A) -> Self {
         Self {
             addr,
+            listeners: Vec::new(),
             timeout: Duration::from_secs(1),
-            complex_body_timeout_override: Some(Duration::from_secs(30)),
-            cert_path: PathBuf::from("cert/cert.pem"),
-            key_path: PathBuf::from("cert/key.pem"),
+            body_timeouts: BodyTimeouts::default(),
+            cert: PemSource::File(PathBuf::from("cert/cert.pem")),
+            key: PemSource::File(PathBuf::from("cert/key.pem")),
+            additional_certs: Vec::new(),
+            #[cfg(feature="auth")]
+            client_cert_policy: None,
+            min_tls_version: TlsVersion::Tls12,
+            custom_tls_config: None,
+            #[cfg(feature="generate_cert")]
+            generated_cert_hostname: None,
             routes: RoutingNode::default(),
+            route_origins: Vec::new(),
+            fallback: None,
+            #[cfg(feature="middleware")]
+            middleware: Vec::new(),
+            #[cfg(feature="load_shedding")]
+            load_shedding: None,
+            #[cfg(feature="load_shedding")]
+            max_connections: None,
+            #[cfg(feature="load_shedding")]
+            at_connection_limit: AtConnectionLimit::default(),
+            #[cfg(feature="rate_limit")]
+            rate_limiter: None,
+            #[cfg(feature="logging")]
+            log_sink: None,
+            #[cfg(feature="logging")]
+            log_buffer: DEFAULT_LOG_BUFFER,
+            #[cfg(feature="logging")]
+            log_metrics: LogMetrics::new(),
+            query_redaction: QueryRedaction::default(),
+            #[cfg(feature="logging")]
+            on_request_complete: Vec::new(),
+            #[cfg(feature="events")]
+            events: EventBus::new(),
+            #[cfg(feature="trusted_proxies")]
+            trusted_proxies: None,
+            #[cfg(feature="geoip")]
+            geoip: None,
+            meta_defaults: MetaDefaults::default(),
+            #[cfg(feature="metrics")]
+            failures: FailureStats::new(),
+            strict: false,
+            #[cfg(feature="fingerprint")]
+            tls_fingerprinting: false,
+            close_notify: true,
+            #[cfg(feature="x509")]
+            validate_client_cert_expiry: false,
+            header_flush: HeaderFlush::default(),
+            #[cfg(feature="drain")]
+            drain_policies: DrainPolicies::default(),
+            #[cfg(feature="titan")]
+            max_upload_size: DEFAULT_MAX_UPLOAD_SIZE,
+            #[cfg(feature="titan")]
+            upload_timeout: titan::DEFAULT_UPLOAD_TIMEOUT,
+            favicons: Favicons::default(),
+            capsule_info: None,
+            #[cfg(feature="route_debug")]
+            route_tracing: false,
+            #[cfg(feature="route_debug")]
+            route_trace_page: None,
+            hostnames: Hostnames::default(),
+            proxy_handler: None,
+            path_normalizer: None,
+            state: Extensions::new(),
         }
     }
 
+    /// Accept connections on an existing listener, instead of binding to an address
+    ///
+    /// The address given to [`Server::bind()`] is ignored.  This is useful for
+    /// listeners set up with custom socket options, or bound to port 0 in tests.  Call
+    /// this more than once to accept connections on all of the listeners.
+    ///
+    /// ```no_run
+    /// # use twinstar::{Server, GEMINI_PORT};
+    /// # async fn run() -> anyhow::Result<()> {
+    /// let listener = tokio::net::TcpListener::bind(("localhost", 0)).await?;
+    /// let server = Server::bind(("localhost", GEMINI_PORT))
+    ///     .bind_existing(listener)
+    ///     .build()
+    ///     .await?;
+    ///
+    /// println!("Listening on {:?}", server.local_addrs());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn bind_existing(mut self, listener: TcpListener) -> Self {
+        self.listeners.push(ExistingListener::Tokio(listener));
+        self
+    }
+
+    /// Accept connections on an existing standard library listener
+    ///
+    /// Like [`bind_existing()`](Self::bind_existing()), but for listeners which don't
+    /// come from tokio, e.g. passed in by systemd socket activation.  The listener is
+    /// switched to non-blocking mode when the server is built.
+    pub fn from_std(mut self, listener: std::net::TcpListener) -> Self {
+        self.listeners.push(ExistingListener::Std(listener));
+        self
+    }
+
     /// Sets the directory that twinstar should look for TLS certs and keys into
     ///
     /// Northstar will look for files called `cert.pem` and `key.pem` in the provided
@@ -210,7 +1180,30 @@ impl<A: ToSocketAddrs> Builder<A> {
     /// This does not need to be called it [`set_tls_dir()`](Self::set_tls_dir()) has been
     /// called.
     pub fn set_cert(mut self, cert_path: impl Into<PathBuf>) -> Self {
-        self.cert_path = cert_path.into();
+        self.cert = PemSource::File(cert_path.into());
+        self
+    }
+
+    /// Use a PEM encoded TLS certificate chain held in memory
+    ///
+    /// This replaces the path set with [`set_cert()`](Self::set_cert()), and is meant
+    /// for certificates fetched from a secrets manager, or embedded with
+    /// [`include_bytes!`].
+    ///
+    /// ```no_run
+    /// # use twinstar::{Server, GEMINI_PORT};
+    /// # fn fetch_secret(_: &str) -> Vec<u8> { Vec::new() }
+    /// # async fn run() -> anyhow::Result<()> {
+    /// Server::bind(("localhost", GEMINI_PORT))
+    ///     .set_cert_bytes(&fetch_secret("gemini/cert.pem"))
+    ///     .set_key_bytes(&fetch_secret("gemini/key.pem"))
+    ///     .serve()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_cert_bytes(mut self, cert_pem: &[u8]) -> Self {
+        self.cert = PemSource::Memory(cert_pem.to_vec());
         self
     }
 
@@ -224,7 +1217,64 @@ impl<A: ToSocketAddrs> Builder<A> {
     /// This should of course correspond to the key set in
     /// [`set_cert()`](Self::set_cert())
     pub fn set_key(mut self, key_path: impl Into<PathBuf>) -> Self {
-        self.key_path = key_path.into();
+        self.key = PemSource::File(key_path.into());
+        self
+    }
+
+    /// Use a PEM encoded private key held in memory
+    ///
+    /// This replaces the path set with [`set_key()`](Self::set_key()), see
+    /// [`set_cert_bytes()`](Self::set_cert_bytes()) for an example.
+    pub fn set_key_bytes(mut self, key_pem: &[u8]) -> Self {
+        self.key = PemSource::Memory(key_pem.to_vec());
+        self
+    }
+
+    /// Also serve the certificate at `cert_path`, with its key at `key_path`
+    ///
+    /// With more than one certificate, each client gets the first one it supports,
+    /// trying ECDSA and Ed25519 keys before RSA keys.  This lets a capsule move to a
+    /// faster ECDSA certificate while keeping an RSA certificate for older clients.
+    ///
+    /// ```no_run
+    /// # use twinstar::{Server, GEMINI_PORT};
+    /// # async fn run() -> anyhow::Result<()> {
+    /// Server::bind(("localhost", GEMINI_PORT))
+    ///     .set_cert("cert/ecdsa.pem")
+    ///     .set_key("cert/ecdsa-key.pem")
+    ///     .add_cert("cert/rsa.pem", "cert/rsa-key.pem")
+    ///     .serve()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn add_cert(mut self, cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> Self {
+        self.additional_certs.push((PemSource::File(cert_path.into()), PemSource::File(key_path.into())));
+        self
+    }
+
+    /// Also serve a PEM encoded certificate chain and key held in memory
+    ///
+    /// See [`add_cert()`](Self::add_cert()) and [`set_cert_bytes()`](Self::set_cert_bytes()).
+    pub fn add_cert_bytes(mut self, cert_pem: &[u8], key_pem: &[u8]) -> Self {
+        self.additional_certs.push((PemSource::Memory(cert_pem.to_vec()), PemSource::Memory(key_pem.to_vec())));
+        self
+    }
+
+    /// Generate a self-signed certificate for `hostname` if there is none yet
+    ///
+    /// When neither the [certificate](Self::set_cert()) nor the [key](Self::set_key())
+    /// exist when the server starts, a new key and a certificate valid for
+    /// [`GENERATED_CERT_VALIDITY`] are written to their paths, creating missing
+    /// directories.  Gemini clients trust a capsule's certificate the first time they
+    /// see it, so a self-signed certificate is all a new capsule needs.  Existing files
+    /// are never replaced, and nothing is generated if the certificate or key are
+    /// [held in memory](Self::set_cert_bytes()).
+    ///
+    /// This requires the `generate_cert` feature.
+    #[cfg(feature="generate_cert")]
+    pub fn generate_cert_if_missing(mut self, hostname: impl Into<String>) -> Self {
+        self.generated_cert_hostname = Some(hostname.into());
         self
     }
 
@@ -243,8 +1293,7 @@ impl<A: ToSocketAddrs> Builder<A> {
     /// shortcomings of the specification, this timeout, and any timeout set using this
     /// method, is overridden in special cases, specifically for MIME types outside of
     /// `text/plain` and `text/gemini`, to be 30 seconds.  If you would like to change or
-    /// prevent this, please see
-    /// [`override_complex_body_timeout`](Self::override_complex_body_timeout()).
+    /// prevent this, please see [`set_body_timeout()`](Self::set_body_timeout()).
     pub fn set_timeout(mut self, timeout: Duration) -> Self {
         self.timeout = timeout;
         self
@@ -281,219 +1330,1712 @@ impl<A: ToSocketAddrs> Builder<A> {
     /// will have the default amount of time to recieve the header, and an *additional*
     /// alotment of time to recieve the body.
     ///
-    /// The default timeout for this is 30 seconds.
-    pub fn override_complex_body_timeout(mut self, timeout: Option<Duration>) -> Self {
-        self.complex_body_timeout_override = timeout;
+    /// The default timeout for this is 30 seconds.  This sets the timeout for the `*/*`
+    /// pattern, see [`set_body_timeout()`](Self::set_body_timeout()) for finer control.
+    pub fn override_complex_body_timeout(self, timeout: Option<Duration>) -> Self {
+        self.set_body_timeout(ANY_MIME, timeout)
+    }
+
+    /// Set the timeout for sending bodies of MIME types matching `pattern`
+    ///
+    /// Patterns are either a full MIME type like `application/zip`, a top-level type
+    /// like `image/*`, or `*/*` for everything else.  Parameters of the response's MIME
+    /// type are ignored, and the most specific matching pattern wins.
+    ///
+    /// As with [`override_complex_body_timeout()`](Self::override_complex_body_timeout()),
+    /// this only affects successful responses, whose header is still sent within the
+    /// [general timeout](Self::set_timeout()), after which the client has `timeout` to
+    /// receive the body.  A timeout of [`None`] sends matching responses within the
+    /// general timeout instead.
+    ///
+    /// By default, `text/gemini` and `text/plain` are mapped to [`None`], and `*/*` to
+    /// 30 seconds.
+    ///
+    /// ```
+    /// # use twinstar::{Server, GEMINI_PORT};
+    /// # use std::time::Duration;
+    /// let builder = Server::bind(("localhost", GEMINI_PORT))
+    ///     .set_body_timeout("text/*", Some(Duration::from_secs(5)))
+    ///     .set_body_timeout("image/*", Some(Duration::from_secs(60)))
+    ///     .set_body_timeout("application/zip", Some(Duration::from_secs(300)));
+    /// ```
+    pub fn set_body_timeout(mut self, pattern: &str, timeout: Option<Duration>) -> Self {
+        self.body_timeouts.set(pattern, timeout);
         self
     }
 
-    /// Add a handler for a route
+    /// Enable load shedding
     ///
-    /// A route must be an absolute path, for example "/endpoint" or "/", but not
-    /// "endpoint".  Entering a relative or malformed path will result in a panic.
+    /// When the thresholds configured in `load_shedding` are exceeded, the server will
+    /// answer new requests with `41 SERVER UNAVAILABLE` instead of calling their
+    /// handler, asking clients to retry later.  This lets an overloaded server degrade
+    /// gracefully, rather than slowing down until every client times out.
     ///
-    /// For more information about routing mechanics, see the docs for [`RoutingNode`].
-    pub fn add_route<H>(mut self, path: &'static str, handler: H) -> Self
-    where
-        H: Fn(Request) -> HandlerResponse + Send + Sync + 'static,
-    {
-        self.routes.add_route(path, Arc::new(handler));
+    /// See the [`load_shedding`] module for more details.  Load shedding is disabled by
+    /// default.
+    ///
+    /// This requires the `load_shedding` feature.
+    #[cfg(feature="load_shedding")]
+    pub fn set_load_shedding(mut self, load_shedding: LoadShedding) -> Self {
+        self.load_shedding = Some(load_shedding);
         self
     }
 
-    pub async fn serve(mut self) -> Result<()> {
-        let config = tls_config(&self.cert_path, &self.key_path)
-            .context("Failed to create TLS config")?;
+    /// Limit the number of connections open at the same time to `max_connections`
+    ///
+    /// Unlike [load shedding](Self::set_load_shedding()), which limits the requests
+    /// being handled, this counts every connection, including those still in the TLS
+    /// handshake, or slowly receiving a response.  What happens to connections
+    /// arriving at the limit is set with
+    /// [`set_at_connection_limit()`](Self::set_at_connection_limit()).  Connections
+    /// are not limited by default.  This requires the `load_shedding` feature.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature="load_shedding")] {
+    /// # use twinstar::{Server, GEMINI_PORT, load_shedding::AtConnectionLimit};
+    /// # async fn run() -> anyhow::Result<()> {
+    /// Server::bind(("localhost", GEMINI_PORT))
+    ///     .set_max_connections(1024)
+    ///     .set_at_connection_limit(AtConnectionLimit::StopAccepting)
+    ///     .serve()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// # }
+    /// ```
+    #[cfg(feature="load_shedding")]
+    pub fn set_max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = Some(max_connections);
+        self
+    }
 
-        let listener = TcpListener::bind(self.addr).await
-            .context("Failed to create socket")?;
+    /// Set what happens to connections arriving while the
+    /// [connection limit](Self::set_max_connections()) is reached
+    ///
+    /// The default is [`AtConnectionLimit::Reject`].  This requires the `load_shedding`
+    /// feature.
+    #[cfg(feature="load_shedding")]
+    pub fn set_at_connection_limit(mut self, at_limit: AtConnectionLimit) -> Self {
+        self.at_connection_limit = at_limit;
+        self
+    }
 
-        self.routes.shrink();
+    /// Allow each client address `requests` requests per `per`
+    ///
+    /// Requests beyond the limit are answered with `44 SLOW DOWN` instead of calling
+    /// their handler, with the number of seconds until the client may retry as the meta.
+    /// Short bursts of up to `requests` requests are allowed.
+    ///
+    /// See the [`rate_limit`] module for more details.  Requests aren't limited by
+    /// default.
+    ///
+    /// This requires the `rate_limit` feature.
+    #[cfg(feature="rate_limit")]
+    pub fn set_rate_limit(mut self, requests: u32, per: Duration) -> Self {
+        self.rate_limiter = Some(RateLimiter::new(requests, per));
+        self
+    }
 
-        let server = Server {
-            tls_acceptor: TlsAcceptor::from(config),
-            listener: Arc::new(listener),
-            routes: Arc::new(self.routes),
-            timeout: self.timeout,
-            complex_timeout: self.complex_body_timeout_override,
-        };
+    /// Set the gateways which are allowed to pass on the address of the real client
+    ///
+    /// Connections from these addresses must start with a PROXY protocol header, see
+    /// the [`trusted_proxies`] module for details.  By default, no one is trusted.
+    ///
+    /// This requires the `trusted_proxies` feature.
+    #[cfg(feature="trusted_proxies")]
+    pub fn set_trusted_proxies(mut self, trusted_proxies: TrustedProxies) -> Self {
+        self.trusted_proxies = Some(trusted_proxies);
+        self
+    }
 
-        server.serve().await
+    /// Look up the country and autonomous system of every client
+    ///
+    /// See the [`geoip`] module for details.  This requires the `geoip` feature.
+    #[cfg(feature="geoip")]
+    pub fn set_geoip(mut self, geoip: GeoIp) -> Self {
+        self.geoip = Some(geoip);
+        self
     }
-}
 
-async fn receive_request(stream: &mut (impl AsyncBufRead + Unpin)) -> Result<Request> {
-    let limit = REQUEST_URI_MAX_LEN + "\r\n".len();
-    let mut stream = stream.take(limit as u64);
-    let mut uri = Vec::new();
+    /// Set where access and error records are written to
+    ///
+    /// After every request, an [`AccessRecord`] is passed to the sink, and whenever a
+    /// connection fails, an [`ErrorRecord`] is.  The sink is run on a dedicated thread,
+    /// so a slow sink never holds up request handling.  Instead, if the sink falls too
+    /// far behind, records are dropped, which can be monitored using
+    /// [`log_metrics()`](Self::log_metrics()).
+    ///
+    /// The answers to `11 SENSITIVE INPUT` prompts, like passwords, are redacted from
+    /// the records, see [`set_query_redaction()`](Self::set_query_redaction()).  See the
+    /// [`logging`] module for more details.  By default, no records are produced.
+    ///
+    /// This requires the `logging` feature.
+    #[cfg(feature="logging")]
+    pub fn set_log_sink(mut self, sink: impl LogSink) -> Self {
+        self.log_sink = Some(Box::new(sink));
+        self
+    }
 
-    stream.read_until(b'\n', &mut uri).await?;
+    /// Set how many records may be queued for the log sink before they are dropped
+    ///
+    /// The default is [`DEFAULT_LOG_BUFFER`].  This requires
+    /// the `logging` feature.
+    #[cfg(feature="logging")]
+    pub fn set_log_buffer(mut self, capacity: usize) -> Self {
+        self.log_buffer = capacity;
+        self
+    }
 
-    if !uri.ends_with(b"\r\n") {
-        if uri.len() < REQUEST_URI_MAX_LEN {
-            bail!("Request header not terminated with CRLF")
-        } else {
-            bail!("Request URI too long")
-        }
+    /// A handle to the metrics of the log sink
+    ///
+    /// This can be called before starting the server to monitor how many records are
+    /// written or dropped while it runs.  This requires the `logging` feature.
+    #[cfg(feature="logging")]
+    pub fn log_metrics(&self) -> LogMetrics {
+        self.log_metrics.clone()
     }
 
-    // Strip CRLF
-    uri.pop();
-    uri.pop();
+    /// Choose which queries are redacted from access records
+    ///
+    /// By default, only queries answering a `11 SENSITIVE INPUT` prompt are redacted.
+    /// See [`QueryRedaction`] for details.  This requires the
+    /// `logging` feature.
+    #[cfg(feature="logging")]
+    pub fn set_query_redaction(mut self, query_redaction: QueryRedaction) -> Self {
+        self.query_redaction = query_redaction;
+        self
+    }
 
-    let uri = URIReference::try_from(&*uri)
-        .context("Request URI is invalid")?
-        .into_owned();
-    let request = Request::from_uri(uri)
-        .context("Failed to create request from URI")?;
+    /// Call `callback` after each request has been answered
+    ///
+    /// The callback receives the same [`AccessRecord`] the access log would, including
+    /// the client address, the requested URI, the status, the number of body bytes and
+    /// how long the handler took.  This makes it easy to write logs in any format, or to
+    /// collect statistics.
+    ///
+    /// Unlike a [`LogSink`], the callback is called by the task serving the client, so
+    /// it should return quickly.  Each call adds another callback.  This requires the
+    /// `logging` feature.
+    ///
+    /// ```no_run
+    /// # use twinstar::{Server, GEMINI_PORT};
+    /// # async fn run() -> anyhow::Result<()> {
+    /// Server::bind(("localhost", GEMINI_PORT))
+    ///     .on_request_complete(|record| {
+    ///         println!("{} {} {} {}", record.peer_addr, record.uri, record.status.code(), record.body_bytes);
+    ///     })
+    ///     .serve()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature="logging")]
+    pub fn on_request_complete(mut self, callback: impl Fn(&AccessRecord) + Send + Sync + 'static) -> Self {
+        self.on_request_complete.push(Arc::new(callback));
+        self
+    }
 
-    Ok(request)
-}
+    /// A handle to the counters of failed requests
+    ///
+    /// This can be called before starting the server to export the counters, see the
+    /// [`failures`] module.  This requires the `metrics` feature.
+    #[cfg(feature="metrics")]
+    pub fn failure_stats(&self) -> FailureStats {
+        self.failures.clone()
+    }
 
-async fn send_response_header(header: &ResponseHeader, stream: &mut (impl AsyncWrite + Unpin)) -> Result<()> {
-    let header = format!(
-        "{status} {meta}\r\n",
-        status = header.status.code(),
-        meta = header.meta.as_str(),
-    );
+    /// The bus the server will publish [`Event`]s on
+    ///
+    /// Bridges can be added to it before the server is started, see the [`events`]
+    /// module for details.  This requires the `events` feature.
+    #[cfg(feature="events")]
+    pub fn events(&self) -> EventBus {
+        self.events.clone()
+    }
 
-    stream.write_all(header.as_bytes()).await?;
-    stream.flush().await?;
+    /// Set the language of gemtext responses which don't specify one
+    ///
+    /// The `lang` parameter is added to the meta of every successful `text/gemini`
+    /// response returned by a handler without one, e.g. `text/gemini; lang=en`.
+    /// Handlers can still override it by setting the parameter themselves.
+    pub fn set_default_lang(mut self, lang: impl Into<String>) -> Self {
+        self.meta_defaults.lang = Some(lang.into());
+        self
+    }
 
-    Ok(())
-}
+    /// Set the charset of gemtext responses which don't specify one
+    ///
+    /// Like [`set_default_lang()`](Self::set_default_lang()), but for the `charset`
+    /// parameter, e.g. `utf-8`.
+    pub fn set_default_charset(mut self, charset: impl Into<String>) -> Self {
+        self.meta_defaults.charset = Some(charset.into());
+        self
+    }
 
-async fn maybe_send_response_body(maybe_body: Option<Body>, stream: &mut (impl AsyncWrite + Unpin)) -> Result<()> {
-    if let Some(body) = maybe_body {
-        send_response_body(body, stream).await?;
+    /// Set whether responses are checked against the Gemini specification
+    ///
+    /// In strict mode, every response returned by a handler is checked using
+    /// [`ResponseHeader::validate()`] before it is sent.  Responses failing validation
+    /// are replaced with `42 CGI ERROR`, logged, and counted as
+    /// [`FailureKind::InvalidResponse`].  This catches bugs in handlers before clients
+    /// see malformed headers.
+    ///
+    /// Gemtext bodies held in memory are also checked using [`Document::lint()`], and
+    /// malformed lines are logged as warnings, while the response is sent unchanged.
+    /// This catches bugs in templates which only show in picky clients.  Strict mode is
+    /// off by default.
+    pub fn set_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
     }
 
-    Ok(())
-}
+    /// Set whether clients are fingerprinted by their TLS handshake
+    ///
+    /// When enabled, a [`TlsFingerprint`] of every client is attached to its requests
+    /// and included in the access log, so software rotating its address can still be
+    /// recognized.  See the [`fingerprint`] module for details.  Fingerprinting is off
+    /// by default.
+    ///
+    /// This requires the `fingerprint` feature.
+    #[cfg(feature="fingerprint")]
+    pub fn set_tls_fingerprinting(mut self, enabled: bool) -> Self {
+        self.tls_fingerprinting = enabled;
+        self
+    }
 
-async fn send_response_body(body: Body, stream: &mut (impl AsyncWrite + Unpin)) -> Result<()> {
-    match body {
-        Body::Bytes(bytes) => stream.write_all(&bytes).await?,
-        Body::Reader(mut reader) => { io::copy(&mut reader, stream).await?; },
+    /// Set whether connections are closed with a TLS `close_notify` alert
+    ///
+    /// Gemini signals the end of a response by closing the connection.  Without a
+    /// `close_notify`, clients can't tell a complete response from one cut short by an
+    /// attacker or a network failure, and strict clients report every response as
+    /// truncated.  When enabled, the server sends a `close_notify` after each response,
+    /// and then waits up to a second, or the [timeout](Self::set_timeout()) if that is
+    /// shorter, for the client to close its side of the connection.  Clients which
+    /// already closed their side early, right after sending the request, are handled as
+    /// well.
+    ///
+    /// This is enabled by default.  When disabled, the connection is simply dropped
+    /// after the response has been sent.
+    pub fn set_close_notify(mut self, enabled: bool) -> Self {
+        self.close_notify = enabled;
+        self
     }
 
-    stream.flush().await?;
+    /// Set whether client certificates outside of their validity period are rejected
+    ///
+    /// Clients accept any self-signed certificate, without checking when it expires.
+    /// When enabled, requests with a certificate which has expired, isn't valid yet or
+    /// can't be read are answered with `62 CERTIFICATE NOT VALID`, before they reach
+    /// any middleware or handler.  Requests without a certificate are unaffected.
+    ///
+    /// This is disabled by default.
+    ///
+    /// This requires the `x509` feature.
+    #[cfg(feature="x509")]
+    pub fn validate_client_cert_expiry(mut self, enabled: bool) -> Self {
+        self.validate_client_cert_expiry = enabled;
+        self
+    }
 
-    Ok(())
+    /// Set which client certificates are accepted during the TLS handshake
+    ///
+    /// By default, any certificate is accepted, including self-signed ones.  With a
+    /// policy, e.g. [`SignedBy`](client_cert::SignedBy) for certificates issued by an
+    /// internal CA, rejected certificates fail the handshake.  Clients without a
+    /// certificate are still let through.  See the [`client_cert`] module for details.
+    ///
+    /// This requires the `auth` feature.
+    #[cfg(feature="auth")]
+    pub fn set_client_cert_verifier(mut self, policy: impl ClientCertPolicy) -> Self {
+        self.client_cert_policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// Set the oldest version of TLS clients may use
+    ///
+    /// Gemini requires at least TLS 1.2 and prefers TLS 1.3.  The default of
+    /// [`TlsVersion::Tls12`] accepts both, while [`TlsVersion::Tls13`] turns away
+    /// clients which don't support TLS 1.3 during the handshake.
+    pub fn set_min_tls_version(mut self, version: TlsVersion) -> Self {
+        self.min_tls_version = version;
+        self
+    }
+
+    /// Use a fully custom rustls server config
+    ///
+    /// This is for tuning what twinstar doesn't expose, like cipher suites, session
+    /// tickets, OCSP stapling, or choosing certificates with a custom resolver.  The
+    /// config is used as is, so the [certificate](Self::set_cert()),
+    /// [key](Self::set_key()), [client certificate
+    /// policy](Self::set_client_cert_verifier()) and [minimum TLS
+    /// version](Self::set_min_tls_version()) are ignored.  Most Gemini clients
+    /// present self-signed certificates, which the config's client certificate verifier
+    /// has to accept if the capsule relies on them.
+    ///
+    /// The config has to be built with the version of rustls re-exported as
+    /// [`twinstar::rustls`](rustls).
+    pub fn set_tls_config(mut self, config: Arc<ServerConfig>) -> Self {
+        self.custom_tls_config = Some(config);
+        self
+    }
+
+    /// Set when response headers are sent, unless a response chooses otherwise
+    ///
+    /// By default, headers are sent [immediately](HeaderFlush::Immediately).  Routes
+    /// serving bulk content can [coalesce](HeaderFlush::Coalesce) the header with the
+    /// body by using [`Response::with_header_flush()`], e.g. through
+    /// [`map_response()`](handler::HandlerExt::map_response()).
+    pub fn set_header_flush(mut self, header_flush: HeaderFlush) -> Self {
+        self.header_flush = header_flush;
+        self
+    }
+
+    /// Add a handler for a route
+    ///
+    /// A route must be an absolute path, for example "/endpoint" or "/", but not
+    /// "endpoint".  Entering a relative or malformed path will result in a panic.
+    ///
+    /// For more information about routing mechanics, see the docs for [`RoutingNode`].
+    ///
+    /// Adding the same route twice makes [`build()`](Self::build()) fail, naming the
+    /// source locations both routes were added at.  With the `routing` feature, see
+    /// [`route_report()`](Self::route_report()).
+    #[track_caller]
+    pub fn add_route<H>(self, path: &'static str, handler: H) -> Self
+    where
+        H: Fn(Request) -> HandlerResponse + Send + Sync + 'static,
+    {
+        let origin = std::panic::Location::caller().to_string();
+        self.add_labeled_route(path, origin, handler)
+    }
+
+    /// Set the handler for requests which match no route
+    ///
+    /// Without a fallback, these requests are answered with `51 NOT FOUND`.  A fallback
+    /// can e.g. serve a custom error page, or redirect moved pages.  Since no route
+    /// matched, the request has no trailing segments.
+    ///
+    /// ```no_run
+    /// # use twinstar::{Server, Request, Response, GEMINI_PORT};
+    /// # async fn run() -> anyhow::Result<()> {
+    /// Server::bind(("localhost", GEMINI_PORT))
+    ///     .add_route("/blog", |_: Request| Box::pin(async { Ok(Response::success_plain("Blog")) }) as _)
+    ///     .set_fallback(|request: Request| Box::pin(async move {
+    ///         let path = request.path_segments().join("/");
+    ///         Ok(Response::success_gemini(format!("# Nothing at /{}\n=> / Home\n", path)))
+    ///     }) as _)
+    ///     .serve()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_fallback<H>(mut self, handler: H) -> Self
+    where
+        H: Fn(Request) -> HandlerResponse + Send + Sync + 'static,
+    {
+        self.fallback = Some(Arc::new(handler));
+        self
+    }
+
+    /// Add a handler for a route, labeled with where the route came from
+    ///
+    /// This works like [`add_route()`](Self::add_route()), but uses `label` instead of
+    /// the source location when reporting conflicts and shadowing.  This is useful
+    /// for routes added from configuration files or by helper functions, e.g.
+    /// `sites.toml: [blog]`.
+    pub fn add_labeled_route<H>(mut self, path: &'static str, label: impl Into<String>, handler: H) -> Self
+    where
+        H: Fn(Request) -> HandlerResponse + Send + Sync + 'static,
+    {
+        let route = uriparse::path::Path::try_from(path).expect("Malformed path route received");
+        self.route_origins.push((routing::route_path(&route), label.into()));
+
+        // Conflicts are reported all at once when the server is built
+        let _ = self.routes.add_route_by_path(route, Arc::new(handler));
+        self
+    }
+
+    /// Check the routes added so far for conflicts and shadowing
+    ///
+    /// When the server is built, conflicting routes make building fail, and shadowed
+    /// routes are logged.  This requires the `routing` feature.
+    #[cfg(feature="routing")]
+    pub fn route_report(&self) -> RouteReport {
+        RouteReport::new(self.route_origins.iter().map(|(path, origin)| (path, origin.clone())))
+    }
+
+    /// Log how every request is routed
+    ///
+    /// Each request logs a [`RouteTrace`](routing::RouteTrace) at the info level, naming
+    /// the route it matched, the shorter routes that one shadowed, and the trailing
+    /// segments passed to the handler, e.g.
+    /// `Routing /docs/api/v2 matched /docs/api (src/main.rs:12:10) with trailing segments [v2], shadowing / (src/main.rs:11:10)`.
+    /// Routes are labeled like in [route reports](Self::route_report()).
+    ///
+    /// This is meant for troubleshooting complex route tables, and is disabled by default.
+    /// It requires the `route_debug` feature.
+    #[cfg(feature="route_debug")]
+    pub fn set_route_tracing(mut self, enabled: bool) -> Self {
+        self.route_tracing = enabled;
+        self
+    }
+
+    /// Serve a page at `path` explaining how any path is routed
+    ///
+    /// The page asks for the path to trace as input, and shows the same details as
+    /// [route tracing](Self::set_route_tracing()).  Since it lists where routes were
+    /// registered, it shouldn't be exposed to the public.  This requires the `route_debug`
+    /// feature.
+    ///
+    /// A route must be an absolute path, like for [`add_route()`](Self::add_route()).
+    /// Entering a relative or malformed path will result in a panic.
+    ///
+    /// ```no_run
+    /// # use twinstar::{Server, Request, Response, GEMINI_PORT};
+    /// # async fn run() -> anyhow::Result<()> {
+    /// Server::bind(("localhost", GEMINI_PORT))
+    ///     .add_route("/docs", |_: Request| Box::pin(async { Ok(Response::success_plain("Docs")) }) as _)
+    ///     .add_route_trace_page("/debug/routes")
+    ///     .serve()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature="route_debug")]
+    pub fn add_route_trace_page(mut self, path: &'static str) -> Self {
+        uriparse::path::Path::try_from(path).expect("Malformed path route received");
+        self.route_trace_page = Some(path);
+        self
+    }
+
+    /// The labels of all routes added so far, labeled with their first origin
+    #[cfg(feature="route_debug")]
+    fn route_labels(&self) -> RoutingNode<String> {
+        let mut labels = RoutingNode::default();
+        for (path, origin) in &self.route_origins {
+            if let Ok(path) = uriparse::path::Path::try_from(path.as_str()) {
+                let _ = labels.add_route_by_path(path, origin.clone());
+            }
+        }
+        labels
+    }
+
+    /// Add middleware wrapping the handling of every request
+    ///
+    /// Middleware runs in the order it was added, before requests are routed to their
+    /// handlers.  See the [`middleware`] module for details.  This requires the
+    /// `middleware` feature.
+    #[cfg(feature="middleware")]
+    pub fn add_middleware(mut self, middleware: impl Middleware) -> Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Serve `icon` as the icon of the capsule at `/favicon.txt`
+    ///
+    /// Following the community convention, the icon is a single emoji, which clients
+    /// may show next to the capsule's pages, like `'🚀'`.  The icon is served from
+    /// memory as `text/plain; charset=utf-8`.  Hosts with an icon of their own, set
+    /// with [`set_host_favicon()`](Self::set_host_favicon()), are served that instead.
+    ///
+    /// Adding another route for `/favicon.txt` makes [`build()`](Self::build()) fail.
+    ///
+    /// ```no_run
+    /// # use twinstar::{Server, GEMINI_PORT};
+    /// # async fn run() -> anyhow::Result<()> {
+    /// Server::bind(("localhost", GEMINI_PORT))
+    ///     .set_favicon('🚀')
+    ///     .set_host_favicon("blog.example.org", "📝")
+    ///     .serve()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_favicon(mut self, icon: impl Into<String>) -> Self {
+        self.favicons.set_default(icon.into());
+        self
+    }
+
+    /// Serve `icon` at `/favicon.txt` for requests to `host`
+    ///
+    /// Hosts are compared case-insensitively.  See [`set_favicon()`](Self::set_favicon()).
+    pub fn set_host_favicon(mut self, host: &str, icon: impl Into<String>) -> Self {
+        self.favicons.set_host(host, icon.into());
+        self
+    }
+
+    /// Serve a `/.well-known/security.txt` with the contacts in `info`
+    ///
+    /// The file is generated for every request, naming the requested host as its
+    /// canonical location, so every virtual host shares the same contacts.  Nothing is
+    /// served if `info` has no contacts.  The same details can be served as a page with
+    /// [`CapsuleInfo::about_handler()`](util::CapsuleInfo::about_handler()).
+    ///
+    /// Adding another route for `/.well-known/security.txt` makes
+    /// [`build()`](Self::build()) fail.  See [`util::CapsuleInfo`] for an example.
+    pub fn set_capsule_info(mut self, info: util::CapsuleInfo) -> Self {
+        self.capsule_info = Some(info);
+        self
+    }
+
+    /// Only answer requests for `hostnames`, refusing all others as proxy requests
+    ///
+    /// Requests for any other host, for a different port, or with a scheme other than
+    /// `gemini` are answered with `53 PROXY REQUEST REFUSED` before reaching any handler,
+    /// unless a [proxy handler](Self::set_proxy_handler()) is set.
+    /// Hostnames are compared case-insensitively, and may name a port, like
+    /// `example.org:1966` or `[::1]:1965`.  Hostnames without a port match requests for
+    /// the port the server was reached on.  Calling this again adds more hostnames.
+    ///
+    /// By default, requests are answered regardless of the host they are for.
+    ///
+    /// Entering a hostname with a malformed port will result in a panic.
+    ///
+    /// ```no_run
+    /// # use twinstar::{Server, GEMINI_PORT};
+    /// # async fn run() -> anyhow::Result<()> {
+    /// Server::bind(("localhost", GEMINI_PORT))
+    ///     .set_hostnames(["example.org", "www.example.org"])
+    ///     .serve()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_hostnames<I>(mut self, hostnames: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        for hostname in hostnames {
+            self.hostnames.add(hostname.as_ref());
+        }
+        self
+    }
+
+    /// Pass proxy requests to `handler`, instead of refusing them
+    ///
+    /// Proxy requests are requests for hosts, ports or schemes other than the
+    /// [hostnames](Self::set_hostnames()) of the server, so this has no effect unless
+    /// hostnames are set.  Proxy requests pass through [middleware](Self::add_middleware())
+    /// like other requests, but are never routed, so `handler` receives all of them.
+    ///
+    /// With the `client` feature,
+    /// [`Client::into_proxy_handler()`](client::Client::into_proxy_handler()) relays
+    /// requests to the server they are for, turning the server into a Gemini proxy:
+    ///
+    /// ```no_run
+    /// # #[cfg(feature="client")] {
+    /// # use twinstar::{Server, GEMINI_PORT, client::Client};
+    /// # async fn run() -> anyhow::Result<()> {
+    /// Server::bind(("localhost", GEMINI_PORT))
+    ///     .set_hostnames(["proxy.example.org"])
+    ///     .set_proxy_handler(Client::new().into_proxy_handler())
+    ///     .serve()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// # }
+    /// ```
+    pub fn set_proxy_handler<H>(mut self, handler: H) -> Self
+    where
+        H: Fn(Request) -> HandlerResponse + Send + Sync + 'static,
+    {
+        self.proxy_handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Normalize the path of every request with `normalizer` before it is routed
+    ///
+    /// `normalizer` is called with every percent decoded segment of the path, and
+    /// segments it changes are encoded again and replace the original ones.  Routes,
+    /// handlers and middleware only see the normalized path, while the access log shows
+    /// the path as it was requested.
+    ///
+    /// With the `unicode_normalization` feature, [`util::nfc()`] normalizes paths to
+    /// Unicode Normalization Form C, so links typed on different systems reach the same
+    /// route.  Combined with
+    /// [`ServeDir::set_unicode_normalization()`](util::ServeDir::set_unicode_normalization()),
+    /// files named on macOS are found regardless of how their names were normalized:
+    ///
+    /// ```no_run
+    /// # #[cfg(feature="unicode_normalization")]
+    /// # async fn run() -> anyhow::Result<()> {
+    /// # use twinstar::{Server, GEMINI_PORT, util::{self, ServeDir}};
+    /// let files = ServeDir::new("public").set_unicode_normalization(true);
+    ///
+    /// Server::bind(("localhost", GEMINI_PORT))
+    ///     .set_path_normalizer(util::nfc)
+    ///     .add_route("/", files.into_handler())
+    ///     .serve()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_path_normalizer(mut self, normalizer: impl Fn(&str) -> String + Send + Sync + 'static) -> Self {
+        self.path_normalizer = Some(Arc::new(normalizer));
+        self
+    }
+
+    /// Share `state` with every handler and middleware
+    ///
+    /// The state is available from every request using [`Request::state()`], so handlers
+    /// don't have to capture it.  Like [extensions](Request::extensions()), states are
+    /// keyed by their type, and managing a second value of the same type replaces the
+    /// first.  The state is shared between all requests, so anything changing has to
+    /// be wrapped in a `Mutex` or an atomic.
+    ///
+    /// ```no_run
+    /// # use std::sync::atomic::{AtomicU64, Ordering};
+    /// # use twinstar::{Server, Request, Response, GEMINI_PORT};
+    /// struct Visits(AtomicU64);
+    ///
+    /// # async fn run() -> anyhow::Result<()> {
+    /// Server::bind(("localhost", GEMINI_PORT))
+    ///     .manage(Visits(AtomicU64::new(0)))
+    ///     .add_route("/", |request: Request| {
+    ///         let visits = request.state::<Visits>().unwrap().0.fetch_add(1, Ordering::Relaxed) + 1;
+    ///         Box::pin(async move { Ok(Response::success_plain(format!("Visit {}", visits))) }) as _
+    ///     })
+    ///     .serve()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn manage<T: Any + Send + Sync>(mut self, state: T) -> Self {
+        self.state.insert(state);
+        self
+    }
+
+    /// Set what happens to connections to `route_prefix` still in flight on shutdown
+    ///
+    /// Routes are matched the same way as for handlers, so `/files` also covers
+    /// `/files/big.iso`, and `/` covers every route without a policy of its own.  Routes
+    /// without a policy get [`DrainPolicy::default()`].  See the [`drain`] module for
+    /// details.
+    ///
+    /// A route must be an absolute path, like for [`add_route()`](Self::add_route()).
+    /// Entering a relative or malformed path will result in a panic.
+    ///
+    /// This requires the `drain` feature.
+    #[cfg(feature="drain")]
+    pub fn set_drain_policy(mut self, route_prefix: &str, policy: DrainPolicy) -> Self {
+        self.drain_policies.set(route_prefix, policy);
+        self
+    }
+
+    /// Set the size of the largest upload accepted over Titan
+    ///
+    /// Larger uploads are refused before they are read.  See the [`titan`] module for
+    /// details.  The default is [`DEFAULT_MAX_UPLOAD_SIZE`].
+    ///
+    /// This requires the `titan` feature.
+    #[cfg(feature="titan")]
+    pub fn set_max_upload_size(mut self, max_upload_size: usize) -> Self {
+        self.max_upload_size = max_upload_size;
+        self
+    }
+
+    /// Set how long clients have to send a Titan upload, once its request was received
+    ///
+    /// The default is [`titan::DEFAULT_UPLOAD_TIMEOUT`].  This requires the `titan`
+    /// feature.
+    #[cfg(feature="titan")]
+    pub fn set_upload_timeout(mut self, upload_timeout: Duration) -> Self {
+        self.upload_timeout = upload_timeout;
+        self
+    }
+
+    pub async fn serve(self) -> Result<(), Error> {
+        self.serve_until(future::pending()).await
+    }
+
+    /// Start serving requests until `shutdown` completes
+    ///
+    /// Once `shutdown` completes, no further connections are accepted, and connections
+    /// which were already accepted are drained according to the
+    /// [drain policies](Self::set_drain_policy()) of their routes.  This returns `Ok(())`
+    /// once all of them are closed.
+    ///
+    /// See [`Shutdown`] for a handle which can be used to stop the server from
+    /// elsewhere.
+    pub async fn serve_until(self, shutdown: impl Future<Output = ()>) -> Result<(), Error> {
+        self.build().await?
+            .serve_until(shutdown)
+            .await
+    }
+
+    /// Load the TLS certificate and bind the socket, without serving any requests yet
+    ///
+    /// This is useful for inspecting the server using [`Server::describe()`], or for
+    /// keeping a handle to it before calling [`Server::serve()`].
+    pub async fn build(mut self) -> Result<Server, Error> {
+        let favicons = std::mem::take(&mut self.favicons);
+        if !favicons.is_empty() {
+            self = self.add_labeled_route(FAVICON_PATH, "Builder::set_favicon()", move |request| {
+                let response = favicons.respond(&request);
+                Box::pin(async { Ok(response) }) as HandlerResponse
+            });
+        }
+        if let Some(info) = self.capsule_info.take().filter(|info| !info.contacts().is_empty()) {
+            self = self.add_labeled_route(util::SECURITY_TXT_PATH, "Builder::set_capsule_info()", move |request| {
+                let response = info.respond_security_txt(&request);
+                Box::pin(async { Ok(response) }) as HandlerResponse
+            });
+        }
+
+        #[cfg(feature="route_debug")]
+        let route_labels = {
+            const TRACE_PAGE_LABEL: &str = "Builder::add_route_trace_page()";
+            let route_trace_page = self.route_trace_page.take();
+            let mut route_labels = self.route_labels();
+            if let Some(path) = route_trace_page {
+                let route = uriparse::path::Path::try_from(path).expect("twinstar BUG");
+                let _ = route_labels.add_route_by_path(route, TRACE_PAGE_LABEL.to_owned());
+            }
+            let route_labels = Arc::new(route_labels);
+            if let Some(path) = route_trace_page {
+                let labels = route_labels.clone();
+                self = self.add_labeled_route(path, TRACE_PAGE_LABEL, move |request| {
+                    let response = routing::route_trace_page(&labels, &request);
+                    Box::pin(async { Ok(response) }) as HandlerResponse
+                });
+            }
+
+            if self.route_tracing { Some(route_labels) } else { None }
+        };
+
+        routing::check_conflicts(self.route_origins.iter().map(|(path, origin)| (path, origin.clone())))
+            .map_err(Error::Routing)?;
+        // Only shadowed routes are left to report
+        #[cfg(feature="routing")]
+        for line in self.route_report().to_string().lines() {
+            info!("{}", line);
+        }
+
+        #[cfg_attr(not(feature="description"), allow(unused_variables))]
+        let custom_tls_config = self.custom_tls_config.is_some();
+        let config = match self.custom_tls_config {
+            Some(config) => config,
+            None => {
+                #[cfg(feature="generate_cert")]
+                if let (Some(hostname), PemSource::File(cert_path), PemSource::File(key_path))
+                    = (&self.generated_cert_hostname, &self.cert, &self.key)
+                {
+                    generate_cert_if_missing(cert_path, key_path, hostname)
+                        .map_err(Error::Tls)?;
+                }
+
+                let identities = std::iter::once((&self.cert, &self.key))
+                    .chain(self.additional_certs.iter().map(|(cert, key)| (cert, key)))
+                    .collect::<Vec<_>>();
+                #[cfg(feature="auth")]
+                let verifier = match self.client_cert_policy {
+                    Some(policy) => tls::client_cert_policy(policy),
+                    None => tls::allow_any_client(),
+                };
+                #[cfg(not(feature="auth"))]
+                let verifier = tls::allow_any_client();
+                let config = tls_config(&identities, self.min_tls_version, verifier)
+                    .context("Failed to create TLS config")
+                    .map_err(Error::Tls)?;
+
+                Arc::new(config)
+            },
+        };
+
+        let listeners = if self.listeners.is_empty() {
+            bind_all(self.addr).await?
+        } else {
+            self.listeners.into_iter()
+                .map(|listener| match listener {
+                    ExistingListener::Tokio(listener) => Ok(listener),
+                    ExistingListener::Std(listener) => listener.set_nonblocking(true)
+                        .and_then(|()| TcpListener::from_std(listener))
+                        .map_err(Error::io("Failed to use existing socket")),
+                })
+                .collect::<Result<Vec<_>, Error>>()?
+        };
+
+        self.routes.shrink();
+
+        #[cfg(feature="description")]
+        let description = ServerDescription {
+            version: env!("CARGO_PKG_VERSION"),
+            listen_addrs: listeners.iter().filter_map(|listener| listener.local_addr().ok()).collect(),
+            routes: self.routes.routes().into_iter().map(|(path, _)| path).collect(),
+            tls: TlsDescription {
+                cert_path: if custom_tls_config { PathBuf::from("<custom>") } else { self.cert.describe() },
+                key_path: if custom_tls_config { PathBuf::from("<custom>") } else { self.key.describe() },
+                versions: if custom_tls_config {
+                    vec!["<custom>".to_owned()]
+                } else {
+                    self.min_tls_version.accepted_names()
+                },
+                client_certificates: true,
+            },
+            timeout: self.timeout,
+            complex_body_timeout: self.body_timeouts.get(ANY_MIME).flatten(),
+            #[cfg(feature="load_shedding")]
+            load_shedding: self.load_shedding.is_some(),
+            #[cfg(not(feature="load_shedding"))]
+            load_shedding: false,
+            #[cfg(feature="logging")]
+            access_log: self.log_sink.is_some(),
+            #[cfg(not(feature="logging"))]
+            access_log: false,
+            features: ServerDescription::enabled_features(),
+        };
+
+        if self.proxy_handler.is_some() && self.hostnames.is_empty() {
+            warn!("A proxy handler is set without hostnames, so no request is handled as a proxy request");
+        }
+
+        #[cfg(feature="load_shedding")]
+        let at_connection_limit = self.at_connection_limit;
+
+        Ok(Server {
+            tls_acceptor: TlsAcceptor::from(config),
+            listeners: listeners.into(),
+            routes: Arc::new(self.routes),
+            fallback: self.fallback,
+            #[cfg(feature="middleware")]
+            middleware: self.middleware.into(),
+            timeout: self.timeout,
+            body_timeouts: Arc::new(self.body_timeouts),
+            #[cfg(feature="load_shedding")]
+            load_shedder: self.load_shedding.map(LoadShedder::new).map(Arc::new),
+            #[cfg(feature="load_shedding")]
+            connection_limit: self.max_connections
+                .map(|max_connections| Arc::new(ConnectionLimit::new(max_connections, at_connection_limit))),
+            #[cfg(feature="rate_limit")]
+            rate_limiter: self.rate_limiter,
+            #[cfg(feature="logging")]
+            log_sink: match self.log_sink {
+                Some(sink) => Some(Arc::new(NonBlockingSink::with_metrics(sink, self.log_buffer, self.log_metrics))),
+                None => None,
+            },
+            query_redaction: Arc::new(self.query_redaction),
+            #[cfg(feature="logging")]
+            on_request_complete: self.on_request_complete.into(),
+            #[cfg(feature="description")]
+            description: Arc::new(description),
+            #[cfg(feature="maintenance")]
+            maintenance: Arc::new(Maintenance::default()),
+            #[cfg(feature="events")]
+            events: self.events,
+            #[cfg(feature="trusted_proxies")]
+            trusted_proxies: self.trusted_proxies.map(Arc::new),
+            #[cfg(feature="geoip")]
+            geoip: self.geoip.map(Arc::new),
+            meta_defaults: Arc::new(self.meta_defaults),
+            #[cfg(feature="metrics")]
+            failures: self.failures,
+            strict: self.strict,
+            #[cfg(feature="fingerprint")]
+            min_tls_version: self.min_tls_version,
+            #[cfg(feature="fingerprint")]
+            tls_fingerprinting: self.tls_fingerprinting,
+            close_notify: self.close_notify,
+            #[cfg(feature="x509")]
+            validate_client_cert_expiry: self.validate_client_cert_expiry,
+            header_flush: self.header_flush,
+            #[cfg(feature="drain")]
+            drain_policies: Arc::new(self.drain_policies),
+            #[cfg(feature="titan")]
+            max_upload_size: self.max_upload_size,
+            #[cfg(feature="titan")]
+            upload_timeout: self.upload_timeout,
+            #[cfg(feature="route_debug")]
+            route_labels,
+            hostnames: if self.hostnames.is_empty() { None } else { Some(Arc::new(self.hostnames)) },
+            proxy_handler: self.proxy_handler,
+            path_normalizer: self.path_normalizer,
+            state: Arc::new(self.state),
+        })
+    }
+}
+
+/// Pass a request on to the handler of its route, or the fallback handler
+#[cfg(feature="std")]
+pub(crate) fn route_request(routes: &RoutingNode<Handler>, fallback: Option<&Handler>, mut request: Request) -> HandlerResponse {
+    match routes.match_request(&request) {
+        Some((trailing, handler)) => {
+            request.set_trailing(trailing);
+            (handler)(request)
+        },
+        None => match fallback {
+            Some(fallback) => (fallback)(request),
+            None => Box::pin(async { Ok(Response::not_found()) }),
+        },
+    }
 }
 
-fn tls_config(cert_path: &PathBuf, key_path: &PathBuf) -> Result<Arc<ServerConfig>> {
-    let mut config = ServerConfig::new(AllowAnonOrSelfsignedClient::new());
+/// The most warnings logged for the gemtext of a single response
+#[cfg(feature="std")]
+const MAX_LINT_WARNINGS: usize = 10;
+
+/// Log warnings for malformed lines in a gemtext response
+#[cfg(feature="std")]
+fn lint_gemtext(response: &Response, uri: &str) {
+    let mime = match response.header().meta.to_mime() {
+        Ok(mime) if response.header().status.is_success() && mime.essence_str() == GEMINI_MIME_STR => mime,
+        _ => return,
+    };
+    // Only UTF-8 can be checked, which is also the default
+    if mime.get_param(mime::CHARSET).is_some_and(|charset| charset != mime::UTF_8) {
+        return;
+    }
+    let body = match response.body().and_then(Body::as_bytes) {
+        Some(body) => body,
+        None => return,
+    };
+
+    let gemtext = match std::str::from_utf8(body) {
+        Ok(gemtext) => gemtext,
+        Err(err) => return warn!("Gemtext sent for {} is not valid UTF-8: {}", uri, err),
+    };
+
+    let warnings = Document::lint(gemtext);
+    for warning in warnings.iter().take(MAX_LINT_WARNINGS) {
+        warn!("Malformed gemtext sent for {}, {}", uri, warning);
+    }
+    if warnings.len() > MAX_LINT_WARNINGS {
+        warn!("{} more malformed lines in gemtext sent for {}", warnings.len() - MAX_LINT_WARNINGS, uri);
+    }
+}
 
-    let cert_chain = load_cert_chain(cert_path)
-        .context("Failed to load TLS certificate")?;
-    let key = load_key(key_path)
-        .context("Failed to load TLS key")?;
-    config.set_single_cert(cert_chain, key)
-        .context("Failed to use loaded TLS certificate")?;
+/// Bind to every address `addr` resolves to, skipping those which can't be bound
+#[cfg(feature="std")]
+async fn bind_all(addr: impl ToSocketAddrs) -> Result<Vec<TcpListener>, Error> {
+    let mut addrs = tokio::net::lookup_host(addr).await
+        .map_err(Error::io("Failed to resolve address"))?
+        .collect::<Vec<_>>();
+    addrs.dedup();
+
+    let mut listeners = Vec::new();
+    let mut last_error = None;
+
+    for addr in addrs {
+        match TcpListener::bind(addr).await {
+            Ok(listener) => listeners.push(listener),
+            Err(err) => {
+                warn!("Failed to listen on {}: {}", addr, err);
+                last_error = Some(err);
+            },
+        }
+    }
+
+    match last_error {
+        Some(err) if listeners.is_empty() => Err(Error::io("Failed to create socket")(err)),
+        None if listeners.is_empty() => Err(Error::io("Failed to create socket")(
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "address resolved to nothing")
+        )),
+        _ => Ok(listeners),
+    }
+}
 
-    Ok(config.into())
+/// A listener passed to the [`Builder`] instead of an address to bind to
+#[cfg(feature="std")]
+enum ExistingListener {
+    Tokio(TcpListener),
+    Std(std::net::TcpListener),
 }
 
-fn load_cert_chain(cert_path: &PathBuf) -> Result<Vec<Certificate>> {
-    let certs = std::fs::File::open(cert_path)
-        .with_context(|| format!("Failed to open `{:?}`", cert_path))?;
-    let mut certs = BufReader::new(certs);
-    let certs = rustls::internal::pemfile::certs(&mut certs)
-        .map_err(|_| anyhow!("failed to load certs `{:?}`", cert_path))?;
+/// Where the PEM encoded TLS certificate or key of the server comes from
+#[cfg(feature="std")]
+enum PemSource {
+    File(PathBuf),
+    Memory(Vec<u8>),
+}
 
-    Ok(certs)
+#[cfg(feature="description")]
+impl PemSource {
+    /// The path to show in the [`ServerDescription`]
+    fn describe(&self) -> PathBuf {
+        match self {
+            Self::File(path) => path.clone(),
+            Self::Memory(_) => PathBuf::from("<memory>"),
+        }
+    }
 }
 
-fn load_key(key_path: &PathBuf) -> Result<PrivateKey> {
-    let keys = std::fs::File::open(key_path)
-        .with_context(|| format!("Failed to open `{:?}`", key_path))?;
-    let mut keys = BufReader::new(keys);
-    let mut keys = rustls::internal::pemfile::pkcs8_private_keys(&mut keys)
-        .map_err(|_| anyhow!("failed to load key `{:?}`", key_path))?;
+#[cfg(feature="std")]
+fn tls_config(
+    identities: &[(&PemSource, &PemSource)],
+    min_tls_version: TlsVersion,
+    verifier: Arc<dyn rustls::server::ClientCertVerifier>,
+) -> Result<ServerConfig> {
+    let identities = identities.iter()
+        .map(|(cert, key)| {
+            let cert_chain = match cert {
+                PemSource::File(path) => tls::load_cert_chain(path),
+                PemSource::Memory(pem) => tls::parse_certs(pem),
+            }.context("Failed to load TLS certificate")?;
+            let key = match key {
+                PemSource::File(path) => tls::load_key(path),
+                PemSource::Memory(pem) => tls::parse_private_key(pem),
+            }.context("Failed to load TLS key")?;
+
+            Ok((cert_chain, key))
+        })
+        .collect::<Result<Vec<_>>>()?;
 
-    ensure!(!keys.is_empty(), "no key found");
+    tls::server_config(identities, min_tls_version, verifier)
+}
 
-    let key = keys.swap_remove(0);
+/// How long certificates created by
+/// [`Builder::generate_cert_if_missing()`](Builder::generate_cert_if_missing()) are valid
+#[cfg(feature="generate_cert")]
+pub const GENERATED_CERT_VALIDITY: Duration = Duration::from_secs(10 * 365 * 24 * 60 * 60);
+
+#[cfg(feature="generate_cert")]
+fn generate_cert_if_missing(cert_path: &std::path::Path, key_path: &std::path::Path, hostname: &str) -> Result<()> {
+    match (cert_path.exists(), key_path.exists()) {
+        (true, true) => return Ok(()),
+        (false, false) => {},
+        (true, false) => anyhow::bail!("Found `{}` without a key, refusing to replace it", cert_path.display()),
+        (false, true) => anyhow::bail!("Found `{}` without a certificate, refusing to replace it", key_path.display()),
+    }
+
+    // Start an hour ago, so slightly skewed clocks don't matter
+    let now = SystemTime::now();
+    let mut params = rcgen::CertificateParams::new(vec![hostname.to_owned()]);
+    params.distinguished_name = rcgen::DistinguishedName::new();
+    params.distinguished_name.push(rcgen::DnType::CommonName, hostname);
+    params.not_before = time::OffsetDateTime::from(now - Duration::from_secs(60 * 60));
+    params.not_after = time::OffsetDateTime::from(now + GENERATED_CERT_VALIDITY);
+    let generated = rcgen::Certificate::from_params(params)
+        .context("Failed to generate certificate")?;
+    let cert_pem = generated.serialize_pem()
+        .context("Failed to encode certificate")?;
+
+    for path in &[cert_path, key_path] {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create `{}`", dir.display()))?;
+        }
+    }
 
-    Ok(key)
+    // Only the server should be able to read the key
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(key_path)
+        .and_then(|mut key_file| std::io::Write::write_all(&mut key_file, generated.serialize_private_key_pem().as_bytes()))
+        .with_context(|| format!("Failed to write `{}`", key_path.display()))?;
+    std::fs::write(cert_path, cert_pem)
+        .with_context(|| format!("Failed to write `{}`", cert_path.display()))?;
+
+    info!("Generated a self-signed certificate for {} at `{}`", hostname, cert_path.display());
+    Ok(())
 }
 
 /// Mime for Gemini documents
 pub const GEMINI_MIME_STR: &str = "text/gemini";
 
+#[cfg(feature="std")]
 lazy_static! {
     /// Mime for Gemini documents ("text/gemini")
     pub static ref GEMINI_MIME: Mime = GEMINI_MIME_STR.parse().expect("twinstar BUG");
 }
 
+#[cfg(feature="std")]
 #[deprecated(note = "Use `GEMINI_MIME` instead", since = "0.3.0")]
 pub fn gemini_mime() -> Result<Mime> {
     Ok(GEMINI_MIME.clone())
 }
 
-/// A client cert verifier that accepts all connections
-///
-/// Unfortunately, rustls doesn't provide a ClientCertVerifier that accepts self-signed
-/// certificates, so we need to implement this ourselves.
-struct AllowAnonOrSelfsignedClient { }
-impl AllowAnonOrSelfsignedClient {
+#[cfg(all(test, feature="std"))]
+mod tests {
+    use super::*;
 
-    /// Create a new verifier
-    fn new() -> Arc<Self> {
-        Arc::new(Self {})
+    #[test]
+    fn gemini_mime_parses() {
+        let _: &Mime = &GEMINI_MIME;
     }
 
-}
+    #[cfg(feature="routing")]
+    #[test]
+    fn routes_remember_their_origin() {
+        let handler = |_: Request| Box::pin(async { Ok(Response::not_found()) }) as HandlerResponse;
+        let builder = Server::bind(("localhost", 0))
+            .add_route("/blog", handler)
+            .add_labeled_route("/blog/", "sites.toml", handler);
+
+        let report = builder.route_report();
+        assert_eq!(report.conflicts[0].origins.len(), 2);
+        assert!(report.conflicts[0].origins[0].starts_with(file!()));
+        assert_eq!(report.conflicts[0].origins[1], "sites.toml");
+    }
 
-impl ClientCertVerifier for AllowAnonOrSelfsignedClient {
+    /// Records what was written, and how much of it was flushed
+    #[derive(Default)]
+    struct FlushRecorder {
+        written: Vec<u8>,
+        flushed: usize,
+    }
 
-    fn client_auth_root_subjects(
-        &self,
-        _: Option<&webpki::DNSName>
-    ) -> Option<DistinguishedNames> {
-        Some(Vec::new())
+    impl AsyncWrite for FlushRecorder {
+        fn poll_write(mut self: std::pin::Pin<&mut Self>, _: &mut std::task::Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+            self.written.extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(mut self: std::pin::Pin<&mut Self>, _: &mut std::task::Context<'_>) -> Poll<io::Result<()>> {
+            self.flushed = self.written.len();
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: std::pin::Pin<&mut Self>, _: &mut std::task::Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
     }
 
-    fn client_auth_mandatory(&self, _sni: Option<&webpki::DNSName>) -> Option<bool> {
-        Some(false)
+    #[tokio::test]
+    async fn flushes_headers_as_requested() {
+        let header = ResponseHeader::success(&mime::TEXT_PLAIN);
+
+        let mut immediately = FlushRecorder::default();
+        send_response_header(&header, true, &mut immediately).await.unwrap();
+        assert_eq!(immediately.flushed, immediately.written.len());
+
+        let mut coalesced = FlushRecorder::default();
+        send_response_header(&header, false, &mut coalesced).await.unwrap();
+        assert_eq!(coalesced.flushed, 0);
+        protocol::send_response_body(Body::from("hello"), &mut coalesced).await.unwrap();
+        assert_eq!(coalesced.written, b"20 text/plain\r\nhello");
+        assert_eq!(coalesced.flushed, coalesced.written.len());
+
+        let response = Response::success_plain("hello").with_header_flush(HeaderFlush::Coalesce);
+        assert_eq!(response.header_flush(), Some(HeaderFlush::Coalesce));
     }
 
-    // the below methods are a hack until webpki doesn't break with certain certs
+    /// Build a server on a free port of 127.0.0.1 with a fresh test certificate, after
+    /// `configure` has set it up, and serve it in the background, returning its address
+    #[cfg(feature="client")]
+    async fn spawn_test_server<F>(configure: F) -> SocketAddr
+    where
+        F: FnOnce(Builder<(&'static str, u16)>) -> Builder<(&'static str, u16)>,
+    {
+        let generated = testing::TestCertificate::new("localhost").generate().unwrap();
+        let builder = Server::bind(("127.0.0.1", 0))
+            .set_cert_bytes(generated.certificate_pem().as_bytes())
+            .set_key_bytes(generated.key_pem().as_bytes());
 
-    fn verify_client_cert(
-        &self,
-        _: &[Certificate],
-        _: Option<&webpki::DNSName>
-    ) -> Result<ClientCertVerified, TLSError> {
-        Ok(ClientCertVerified::assertion())
+        let server = configure(builder).build().await.unwrap();
+        let addr = server.local_addrs()[0];
+        tokio::spawn(server.serve());
+
+        addr
     }
 
-    fn verify_tls12_signature(
-        &self,
-        _message: &[u8],
-        _cert: &Certificate,
-        _dss: &DigitallySignedStruct,
-    ) -> Result<HandshakeSignatureValid, TLSError> {
-        Ok(HandshakeSignatureValid::assertion())
+    /// Request `/` from a server, returning the raw response and how the TLS session ended
+    #[cfg(feature="client")]
+    async fn request_raw(close_notify: bool, request: &'static [u8]) -> (Vec<u8>, Option<std::io::ErrorKind>) {
+        let addr = spawn_test_server(|builder| builder
+            .set_close_notify(close_notify)
+            .add_route("/", |_| Box::pin(async { Ok(Response::success_plain("hello")) }) as HandlerResponse)
+        ).await;
+
+        send_raw(addr, request.to_vec()).await
     }
 
-    fn verify_tls13_signature(
-        &self,
-        _message: &[u8],
-        _cert: &Certificate,
-        _dss: &DigitallySignedStruct,
-    ) -> Result<HandshakeSignatureValid, TLSError> {
-        Ok(HandshakeSignatureValid::assertion())
+    /// Send `request` to `addr` as it is, returning the raw response and how it ended
+    #[cfg(feature="client")]
+    async fn send_raw(addr: SocketAddr, request: Vec<u8>) -> (Vec<u8>, Option<std::io::ErrorKind>) {
+        use std::io::{Read, Write};
+
+        tokio::task::spawn_blocking(move || {
+            let config = Arc::new(client::tls_config(None).unwrap());
+            let name = rustls::ServerName::try_from("localhost").unwrap();
+            let mut connection = rustls::ClientConnection::new(config, name).unwrap();
+            let mut socket = std::net::TcpStream::connect(addr).unwrap();
+            let mut tls = rustls::Stream::new(&mut connection, &mut socket);
+
+            tls.write_all(&request).unwrap();
+            let mut response = Vec::new();
+            let end = tls.read_to_end(&mut response).err().map(|err| err.kind());
+
+            (response, end)
+        }).await.unwrap()
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[cfg(feature="client")]
+    #[tokio::test]
+    async fn sends_close_notify() {
+        // rustls reports a connection closed without close_notify as truncated
+        let (response, end) = request_raw(true, b"gemini://localhost/\r\n").await;
+        assert_eq!(response, b"20 text/plain\r\nhello");
+        assert_eq!(end, None);
+
+        let (response, end) = request_raw(false, b"gemini://localhost/\r\n").await;
+        assert_eq!(response, b"20 text/plain\r\nhello");
+        assert_eq!(end, Some(std::io::ErrorKind::UnexpectedEof));
+    }
 
-    #[test]
-    fn gemini_mime_parses() {
-        let _: &Mime = &GEMINI_MIME;
+    #[cfg(feature="client")]
+    #[tokio::test]
+    async fn answers_malformed_requests() {
+        let (response, _) = request_raw(true, b"gemini://localhost/\n").await;
+        assert_eq!(response, b"59 Request header not terminated with CRLF\r\n");
+
+        let (response, _) = request_raw(true, b"gemini://local host/\r\n").await;
+        assert_eq!(response, b"59 Request URI is invalid\r\n");
+    }
+
+    #[cfg(all(feature="client", feature="logging"))]
+    #[tokio::test]
+    async fn reports_completed_requests() {
+        let (records, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let addr = spawn_test_server(|builder| builder
+            .on_request_complete(move |record| { let _ = records.send(record.clone()); })
+            .add_route("/", |_| Box::pin(async { Ok(Response::success_plain("hello")) }) as HandlerResponse)
+        ).await;
+        let url = format!("gemini://{}/", addr);
+
+        client::Client::new().request(&url).await.unwrap();
+
+        let record = received.recv().await.unwrap();
+        assert_eq!(record.uri, url);
+        assert_eq!(record.status, Status::SUCCESS);
+        assert_eq!(record.body_bytes, 5);
+        assert!(record.handler_duration.is_some_and(|handler| handler <= record.duration));
+    }
+
+    #[tokio::test]
+    async fn loads_tls_identity_from_memory() {
+        let generated = testing::TestCertificate::new("localhost").generate().unwrap();
+        #[cfg_attr(not(feature="description"), allow(unused_variables))]
+        let server = Server::bind(("localhost", 0))
+            .set_cert_bytes(generated.certificate_pem().as_bytes())
+            .set_key_bytes(generated.key_pem().as_bytes())
+            .build()
+            .await
+            .unwrap();
+        #[cfg(feature="description")]
+        assert_eq!(server.describe().tls.cert_path, PathBuf::from("<memory>"));
+
+        let built = Server::bind(("localhost", 0))
+            .set_cert_bytes(generated.certificate_pem().as_bytes())
+            .set_key_bytes(b"")
+            .build()
+            .await;
+        assert!(matches!(built, Err(Error::Tls(_))));
+    }
+
+    #[cfg(feature="metrics")]
+    #[tokio::test]
+    async fn counts_failed_handshakes() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let generated = testing::TestCertificate::new("localhost").generate().unwrap();
+        let server = Server::bind(("localhost", 0))
+            .set_cert_bytes(generated.certificate_pem().as_bytes())
+            .set_key_bytes(generated.key_pem().as_bytes())
+            .build()
+            .await
+            .unwrap();
+        let addr = server.local_addrs()[0];
+        let failures = server.failure_stats().clone();
+        tokio::spawn(server.serve());
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        stream.read_to_end(&mut Vec::new()).await.unwrap();
+
+        assert_eq!(failures.handshake_failures(HandshakeFailureCause::NotTls), 1);
+        assert_eq!(failures.handshake_failures(HandshakeFailureCause::Other), 0);
+    }
+
+    #[cfg(feature="client")]
+    #[tokio::test]
+    async fn loads_additional_certs() {
+        let second = testing::TestCertificate::new("localhost").generate().unwrap();
+
+        let addr = spawn_test_server(|builder| builder
+            .add_cert_bytes(second.certificate_pem().as_bytes(), second.key_pem().as_bytes())
+            .add_route("/", |_| Box::pin(async { Ok(Response::success_plain("hello")) }) as HandlerResponse)
+        ).await;
+        let response = client::Client::new().request(&format!("gemini://{}/", addr)).await.unwrap();
+        assert_eq!(response.body_string().await.unwrap(), "hello");
+
+        let first = testing::TestCertificate::new("localhost").generate().unwrap();
+        let built = Server::bind(("localhost", 0))
+            .set_cert_bytes(first.certificate_pem().as_bytes())
+            .set_key_bytes(first.key_pem().as_bytes())
+            .add_cert_bytes(second.certificate_pem().as_bytes(), b"")
+            .build()
+            .await;
+        assert!(matches!(built, Err(Error::Tls(_))));
+    }
+
+    #[cfg(all(feature="client", feature="drain"))]
+    #[tokio::test]
+    async fn drains_connections_by_route() {
+        let generated = testing::TestCertificate::new("localhost").generate().unwrap();
+        let (started, mut in_flight) = tokio::sync::mpsc::unbounded_channel();
+        let slow_started = started.clone();
+        let server = Server::bind(("localhost", 0))
+            .set_cert_bytes(generated.certificate_pem().as_bytes())
+            .set_key_bytes(generated.key_pem().as_bytes())
+            .set_drain_policy("/stream", DrainPolicy::Cancel)
+            .add_route("/stream", move |_| {
+                let _ = started.send(());
+                Box::pin(future::pending()) as HandlerResponse
+            })
+            .add_route("/slow", move |_| {
+                let _ = slow_started.send(());
+                Box::pin(async {
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                    Ok(Response::success_plain("done"))
+                }) as HandlerResponse
+            })
+            .build()
+            .await
+            .unwrap();
+        let addr = server.local_addrs()[0];
+        let shutdown = Shutdown::new();
+        let serving = tokio::spawn(server.serve_until(shutdown.wait()));
+
+        let client = client::Client::new();
+        let stream = tokio::spawn({
+            let client = client.clone();
+            async move { client.request(&format!("gemini://{}/stream", addr)).await.map(drop) }
+        });
+        let slow = tokio::spawn(async move {
+            client.request(&format!("gemini://{}/slow", addr)).await.unwrap().body_string().await.unwrap()
+        });
+        in_flight.recv().await.unwrap();
+        in_flight.recv().await.unwrap();
+
+        shutdown.trigger();
+        timeout(Duration::from_secs(5), serving).await.unwrap().unwrap().unwrap();
+        assert_eq!(slow.await.unwrap(), "done");
+        assert!(stream.await.unwrap().is_err());
+    }
+
+    #[cfg(all(feature="client", feature="load_shedding"))]
+    #[tokio::test]
+    async fn limits_connections() {
+        let spawn = |at_limit| spawn_test_server(move |builder| builder
+            .set_timeout(Duration::from_secs(5))
+            .set_max_connections(1)
+            .set_at_connection_limit(at_limit)
+            .add_route("/", |_| Box::pin(async { Ok(Response::success_plain("hello")) }) as HandlerResponse)
+        );
+
+        let addr = spawn(AtConnectionLimit::Reject).await;
+        let url = format!("gemini://{}/", addr);
+        let idle = TcpStream::connect(addr).await.unwrap();
+        let response = client::Client::new().request(&url).await.unwrap();
+        assert_eq!(response.status(), Status::SERVER_UNAVAILABLE);
+        drop(idle);
+
+        let addr = spawn(AtConnectionLimit::StopAccepting).await;
+        let url = format!("gemini://{}/", addr);
+        let idle = TcpStream::connect(addr).await.unwrap();
+        let waiting = client::Client::new().set_timeout(Duration::from_millis(200));
+        assert!(waiting.request(&url).await.is_err());
+        drop(idle);
+        let response = client::Client::new().request(&url).await.unwrap();
+        assert_eq!(response.body_string().await.unwrap(), "hello");
+    }
+
+    #[cfg(feature="client")]
+    #[tokio::test]
+    async fn exposes_remote_addr() {
+        let addr = spawn_test_server(|builder| builder
+            .add_route("/", |request: Request| {
+                let ip = request.remote_addr().map(|addr| addr.ip().to_string()).unwrap_or_default();
+                Box::pin(async move { Ok(Response::success_plain(ip)) }) as HandlerResponse
+            })
+        ).await;
+
+        let response = client::Client::new().request(&format!("gemini://{}/", addr)).await.unwrap();
+        assert_eq!(response.body_string().await.unwrap(), "127.0.0.1");
+    }
+
+    #[cfg(feature="client")]
+    #[tokio::test]
+    async fn trusts_server_certificates_on_first_use() {
+        let addr = spawn_test_server(|builder| builder
+            .add_route("/", |_| Box::pin(async { Ok(Response::success_plain("hello")) }) as HandlerResponse)
+        ).await;
+        let url = format!("gemini://{}/", addr);
+
+        let store = Arc::new(storage::MemoryStore::new());
+        let client = client::Client::new().set_known_hosts(client::KnownHosts::new(store.clone()));
+        client.request(&url).await.unwrap();
+        client.request(&url).await.unwrap();
+
+        let key = format!("known_hosts/127.0.0.1:{}", addr.port());
+        storage::KvStore::put(&*store, &key, b"00".to_vec(), None).await.unwrap();
+        assert!(client.request(&url).await.is_err());
+    }
+
+    #[cfg(all(feature="client", feature="x509"))]
+    #[tokio::test]
+    async fn rejects_unreadable_client_certificates() {
+        let addr = spawn_test_server(|builder| builder
+            .validate_client_cert_expiry(true)
+            .add_route("/", |_| Box::pin(async { Ok(Response::success_plain("hello")) }) as HandlerResponse)
+        ).await;
+        let url = format!("gemini://{}/", addr);
+
+        // Put a multi-byte character into the notBefore UTCTime, keeping its length
+        let client = testing::TestCertificate::new("alice").generate().unwrap();
+        let mut der = client.certificate().as_der().to_vec();
+        let time = der.windows(2).position(|window| window == [0x17, 0x0d]).unwrap() + 2;
+        der[time + 1..time + 4].copy_from_slice("€".as_bytes());
+        let identity = client::Identity::new(vec![rustls::Certificate(der)], rustls::PrivateKey(client.key().to_vec()));
+
+        let response = client::Client::new().set_identity(identity).request(&url).await.unwrap();
+        assert_eq!(response.status(), Status::CERTIFICATE_NOT_VALID);
+        assert_eq!(response.meta(), "Your certificate could not be read");
+    }
+
+    #[cfg(feature="client")]
+    #[tokio::test]
+    async fn shares_managed_state() {
+        struct Greeting(&'static str);
+
+        let addr = spawn_test_server(|builder| builder
+            .manage(Greeting("Hello"))
+            .add_route("/", |request: Request| {
+                let greeting = request.state::<Greeting>().map(|greeting| greeting.0).unwrap_or_default();
+                Box::pin(async move { Ok(Response::success_plain(greeting)) }) as HandlerResponse
+            })
+        ).await;
+
+        let response = client::Client::new().request(&format!("gemini://{}/", addr)).await.unwrap();
+        assert_eq!(response.body_string().await.unwrap(), "Hello");
+    }
+
+    #[cfg(feature="client")]
+    #[tokio::test]
+    async fn exposes_tls_info() {
+        let addr = spawn_test_server(|builder| builder
+            .set_min_tls_version(TlsVersion::Tls13)
+            .add_route("/", |request: Request| {
+                let info = request.tls_info().cloned().unwrap();
+                let body = format!("{:?} {} {:?}", info.version, info.cipher_suite, info.server_name);
+                Box::pin(async move { Ok(Response::success_plain(body)) }) as HandlerResponse
+            })
+        ).await;
+
+        let url = format!("gemini://localhost:{}/", addr.port());
+        let response = client::Client::new().request(&url).await.unwrap();
+        let body = response.body_string().await.unwrap();
+        assert!(body.starts_with("Tls13 TLS13_"), "{}", body);
+        assert!(body.ends_with(" Some(\"localhost\")"), "{}", body);
+    }
+
+    #[cfg(feature="client")]
+    #[tokio::test]
+    async fn serves_favicon() {
+        let addr = spawn_test_server(|builder| builder.set_favicon('🚀')).await;
+        let response = client::Client::new().request(&format!("gemini://{}/favicon.txt", addr)).await.unwrap();
+        assert_eq!(response.body_string().await.unwrap(), "🚀");
+
+        let conflicting = Server::bind(("localhost", 0))
+            .set_favicon('🚀')
+            .add_route("/favicon.txt", |_| Box::pin(async { Ok(Response::success_plain("🌱")) }) as HandlerResponse)
+            .build()
+            .await;
+        assert!(matches!(conflicting, Err(Error::Routing(_))));
+    }
+
+    #[cfg(feature="client")]
+    #[tokio::test]
+    async fn serves_security_txt() {
+        let info = util::CapsuleInfo::new().add_contact("mailto:alice@example.org");
+        let addr = spawn_test_server(|builder| builder.set_capsule_info(info)).await;
+
+        let url = format!("gemini://localhost:{}/.well-known/security.txt", addr.port());
+        let body = client::Client::new().request(&url).await.unwrap().body_string().await.unwrap();
+        assert!(body.starts_with("Contact: mailto:alice@example.org\nExpires: "));
+        assert!(body.ends_with("Canonical: gemini://localhost/.well-known/security.txt\n"));
+    }
+
+    #[cfg(all(feature="client", feature="route_debug"))]
+    #[tokio::test]
+    async fn serves_route_trace_page() {
+        let addr = spawn_test_server(|builder| builder
+            .add_labeled_route("/", "root", |_| Box::pin(async { Ok(Response::success_plain("root")) }) as HandlerResponse)
+            .add_labeled_route("/docs", "docs", |_| Box::pin(async { Ok(Response::success_plain("docs")) }) as HandlerResponse)
+            .add_route_trace_page("/debug/routes")
+            .set_route_tracing(true)
+        ).await;
+
+        let base = format!("gemini://{}/debug/routes", addr);
+
+        let prompt = client::Client::new().request(&base).await.unwrap();
+        assert_eq!(prompt.header().status, Status::INPUT);
+
+        let page = client::Client::new().request(&format!("{}?/docs/guide", base)).await.unwrap();
+        let page = page.body_string().await.unwrap();
+        assert!(page.starts_with("# Routing /docs/guide\n\nMatched /docs (docs)\nTrailing segments: guide\n"));
+        assert!(page.ends_with("## Shadowed routes\n* / (root)\n"));
+
+        let page = client::Client::new().request(&format!("{}?/debug/routes", base)).await.unwrap();
+        let page = page.body_string().await.unwrap();
+        assert!(page.contains("Matched /debug/routes (Builder::add_route_trace_page())\n"));
+    }
+
+    #[cfg(feature="client")]
+    #[tokio::test]
+    async fn refuses_proxy_requests() {
+        let addr = spawn_test_server(|builder| builder
+            .add_route("/", |_| Box::pin(async { Ok(Response::success_plain("hello")) }) as HandlerResponse)
+            .set_hostnames(vec!["localhost"])
+        ).await;
+
+        let response = client::Client::new().request(&format!("gemini://localhost:{}/", addr.port())).await.unwrap();
+        assert_eq!(response.body_string().await.unwrap(), "hello");
+
+        let response = client::Client::new().request(&format!("gemini://{}/", addr)).await.unwrap();
+        assert_eq!(response.header().status, Status::PROXY_REQUEST_REFUSED);
+    }
+
+    #[cfg(feature="client")]
+    #[tokio::test]
+    async fn relays_proxy_requests() {
+        let hello = |_| Box::pin(async { Ok(Response::success_plain("hello")) }) as HandlerResponse;
+
+        let upstream_addr = spawn_test_server(|builder| builder
+            .set_hostnames(vec!["127.0.0.1"])
+            .add_route("/", hello)
+        ).await;
+        let proxy_addr = spawn_test_server(|builder| builder
+            .set_hostnames(vec!["proxy.example.org"])
+            .add_route("/", hello)
+            .set_proxy_handler(client::Client::new().set_proxy_loopback(true).into_proxy_handler())
+        ).await;
+
+        let request = format!("gemini://{}/\r\n", upstream_addr);
+        let (response, _) = send_raw(proxy_addr, request.into_bytes()).await;
+        assert_eq!(response, b"20 text/plain\r\nhello");
+
+        let (response, _) = send_raw(proxy_addr, b"https://example.org/\r\n".to_vec()).await;
+        assert!(response.starts_with(b"53 "));
+
+        // The upstream server is gone, and why isn't told to the client
+        let unreachable = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let (response, _) = send_raw(proxy_addr, format!("gemini://{}/\r\n", unreachable).into_bytes()).await;
+        assert_eq!(response, b"43 Failed to reach the server\r\n");
+    }
+
+    #[cfg(feature="client")]
+    #[tokio::test]
+    async fn refuses_to_relay_proxy_requests_to_itself() {
+        let relaying = spawn_test_server(|builder| builder
+            .set_hostnames(vec!["proxy.example.org"])
+            .set_proxy_handler(client::Client::new().set_proxy_loopback(true).into_proxy_handler())
+        ).await;
+        let (response, _) = send_raw(relaying, format!("gemini://{}/\r\n", relaying).into_bytes()).await;
+        assert!(response.starts_with(b"53 "));
+
+        let guarded = spawn_test_server(|builder| builder
+            .set_hostnames(vec!["proxy.example.org"])
+            .set_proxy_handler(client::Client::new().into_proxy_handler())
+        ).await;
+        let (response, _) = send_raw(guarded, format!("gemini://localhost:{}/\r\n", relaying.port()).into_bytes()).await;
+        assert!(response.starts_with(b"53 "));
+    }
+
+    #[cfg(feature="client")]
+    #[tokio::test]
+    async fn binds_every_address() {
+        let generated = testing::TestCertificate::new("localhost").generate().unwrap();
+        let addrs = ["127.0.0.1:0".parse().unwrap(), "127.0.0.2:0".parse().unwrap()];
+        let server = Server::bind(&addrs[..])
+            .set_cert_bytes(generated.certificate_pem().as_bytes())
+            .set_key_bytes(generated.key_pem().as_bytes())
+            .add_route("/", |_| Box::pin(async { Ok(Response::success_plain("hello")) }) as HandlerResponse)
+            .build()
+            .await
+            .unwrap();
+        let listen_addrs = server.local_addrs();
+        assert_eq!(listen_addrs.iter().map(SocketAddr::ip).collect::<Vec<_>>(), addrs.iter().map(SocketAddr::ip).collect::<Vec<_>>());
+        tokio::spawn(server.serve());
+
+        for addr in listen_addrs {
+            let response = client::Client::new().request(&format!("gemini://{}/", addr)).await.unwrap();
+            assert_eq!(response.body_string().await.unwrap(), "hello");
+        }
+    }
+
+    #[tokio::test]
+    async fn binds_existing_listeners() {
+        let generated = testing::TestCertificate::new("localhost").generate().unwrap();
+        let builder = || Server::bind(("localhost", GEMINI_PORT))
+            .set_cert_bytes(generated.certificate_pem().as_bytes())
+            .set_key_bytes(generated.key_pem().as_bytes());
+
+        let listener = TcpListener::bind(("localhost", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = builder().bind_existing(listener).build().await.unwrap();
+        assert_eq!(server.local_addrs(), [addr]);
+
+        let listener = std::net::TcpListener::bind(("localhost", 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = builder().from_std(listener).build().await.unwrap();
+        assert_eq!(server.local_addrs(), [addr]);
+    }
+
+    #[cfg(feature="client")]
+    #[tokio::test]
+    async fn uses_custom_tls_config() {
+        let generated = testing::TestCertificate::new("localhost").generate().unwrap();
+        let config = ServerConfig::builder()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![rustls::Certificate(generated.certificate().as_der().to_vec())],
+                rustls::PrivateKey(generated.key().to_vec()),
+            )
+            .unwrap();
+
+        let server = Server::bind(("localhost", 0))
+            .set_cert("missing/cert.pem")
+            .set_tls_config(Arc::new(config))
+            .add_route("/", |_| Box::pin(async { Ok(Response::success_plain("hello")) }) as HandlerResponse)
+            .build()
+            .await
+            .unwrap();
+        #[cfg(feature="description")]
+        {
+            assert_eq!(server.describe().tls.versions, ["<custom>"]);
+            assert_eq!(server.describe().tls.cert_path, PathBuf::from("<custom>"));
+        }
+
+        let url = format!("gemini://{}/", server.local_addrs()[0]);
+        tokio::spawn(server.serve());
+        let response = client::Client::new().request(&url).await.unwrap();
+        assert_eq!(response.header().status, Status::SUCCESS);
+    }
+
+    #[cfg(feature="generate_cert")]
+    #[tokio::test]
+    async fn generates_missing_certificates() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let build = || Server::bind(("localhost", 0))
+            .set_tls_dir(dir.join("tls"))
+            .generate_cert_if_missing("localhost")
+            .build();
+
+        build().await.unwrap();
+        let cert = std::fs::read(dir.join("tls/cert.pem")).unwrap();
+        build().await.unwrap();
+        assert_eq!(std::fs::read(dir.join("tls/cert.pem")).unwrap(), cert);
+
+        std::fs::remove_file(dir.join("tls/key.pem")).unwrap();
+        assert!(matches!(build().await, Err(Error::Tls(_))));
     }
 }
+
@@ -0,0 +1,293 @@
+//! Graceful degradation for overloaded servers
+//!
+//! When a server receives more traffic than it can handle, the default behaviour is for
+//! every request to get slower and slower until clients start timing out.  Load
+//! shedding instead answers some requests early with `41 SERVER UNAVAILABLE`, telling
+//! clients when to try again, while the requests that are admitted are served at a
+//! normal pace.
+//!
+//! See [`LoadShedding`] for the available thresholds, and
+//! [`Builder::set_load_shedding()`](crate::Builder::set_load_shedding()) for enabling it.
+//!
+//! Independently, the number of open connections can be limited using
+//! [`Builder::set_max_connections()`](crate::Builder::set_max_connections()), so a
+//! traffic spike can't exhaust file descriptors or memory.  Connections arriving at the
+//! limit are handled according to [`AtConnectionLimit`].
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::types::Response;
+
+/// Thresholds at which the server starts shedding load
+///
+/// Both thresholds are optional, and a server with neither set will never shed any
+/// requests.
+///
+/// ```
+/// # use std::time::Duration;
+/// # use twinstar::load_shedding::LoadShedding;
+/// let shedding = LoadShedding::new()
+///     .set_max_in_flight(256)
+///     .set_max_latency(Duration::from_secs(2))
+///     .set_retry_after(Duration::from_secs(10));
+/// ```
+#[derive(Debug, Clone)]
+pub struct LoadShedding {
+    max_in_flight: Option<usize>,
+    max_latency: Option<Duration>,
+    retry_after: Duration,
+}
+
+impl LoadShedding {
+    /// Create a new configuration with no thresholds set
+    ///
+    /// The retry delay suggested to clients defaults to 5 seconds.
+    pub fn new() -> Self {
+        Self {
+            max_in_flight: None,
+            max_latency: None,
+            retry_after: Duration::from_secs(5),
+        }
+    }
+
+    /// Set the maximum number of requests that may be handled at the same time
+    ///
+    /// Any request arriving while this many requests are already being handled will be
+    /// answered with `41 SERVER UNAVAILABLE`.
+    pub fn set_max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.max_in_flight = Some(max_in_flight);
+        self
+    }
+
+    /// Set the maximum average handler latency
+    ///
+    /// The server keeps a moving average of how long handlers take to produce a
+    /// response.  While this average exceeds `max_latency`, new requests are answered
+    /// with `41 SERVER UNAVAILABLE`.
+    ///
+    /// Every shed request decays the average a little, so that the server will
+    /// eventually start admitting requests again and re-measure its latency.
+    pub fn set_max_latency(mut self, max_latency: Duration) -> Self {
+        self.max_latency = Some(max_latency);
+        self
+    }
+
+    /// Set the delay clients are asked to wait before retrying
+    ///
+    /// This is included in the meta of the `41` response.  The default is 5 seconds.
+    pub fn set_retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = retry_after;
+        self
+    }
+}
+
+impl Default for LoadShedding {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The runtime state backing a [`LoadShedding`] configuration
+pub(crate) struct LoadShedder {
+    config: LoadShedding,
+    in_flight: AtomicUsize,
+    avg_latency_micros: AtomicU64,
+}
+
+impl LoadShedder {
+    pub(crate) fn new(config: LoadShedding) -> Self {
+        Self {
+            config,
+            in_flight: AtomicUsize::new(0),
+            avg_latency_micros: AtomicU64::new(0),
+        }
+    }
+
+    /// Attempt to admit a request
+    ///
+    /// Returns [`None`] if the request should be shed.  Otherwise, the request counts as
+    /// in flight until the returned guard is dropped.
+    pub(crate) fn try_admit(self: &Arc<Self>) -> Option<InFlightGuard> {
+        if let Some(max_latency) = self.config.max_latency {
+            let max_latency = max_latency.as_micros() as u64;
+            if self.avg_latency_micros.load(Ordering::Relaxed) > max_latency {
+                self.update_latency(|avg| avg - avg / 8);
+                return None;
+            }
+        }
+
+        let in_flight = self.in_flight.fetch_add(1, Ordering::AcqRel);
+        let guard = InFlightGuard { shedder: self.clone() };
+
+        match self.config.max_in_flight {
+            Some(max_in_flight) if in_flight >= max_in_flight => None,
+            _ => Some(guard),
+        }
+    }
+
+    /// The response sent to clients whose requests are shed
+    pub(crate) fn response(&self) -> Response {
+        Response::server_unavailable_lossy(format!(
+            "Server is overloaded, please retry in {} seconds",
+            self.config.retry_after.as_secs().max(1),
+        ))
+    }
+
+    fn update_latency(&self, f: impl Fn(u64) -> u64) {
+        // The closure always returns `Some`, so this can't fail
+        let _ = self.avg_latency_micros.fetch_update(
+            Ordering::AcqRel,
+            Ordering::Acquire,
+            |avg| Some(f(avg)),
+        );
+    }
+}
+
+/// Marks a request as in flight for as long as it is alive
+pub(crate) struct InFlightGuard {
+    shedder: Arc<LoadShedder>,
+}
+
+impl InFlightGuard {
+    /// Record how long the handler took to produce a response
+    pub(crate) fn record_latency(&self, latency: Duration) {
+        let sample = latency.as_micros() as u64;
+        self.shedder.update_latency(|avg| {
+            if sample >= avg {
+                avg + (sample - avg) / 8
+            } else {
+                avg - (avg - sample) / 8
+            }
+        });
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.shedder.in_flight.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// What happens to new connections while the server has as many open connections as
+/// it may have
+///
+/// See [`Builder::set_max_connections()`](crate::Builder::set_max_connections()).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AtConnectionLimit {
+    /// Accept them, and answer their request with `41 SERVER UNAVAILABLE` right away
+    ///
+    /// These connections are only kept open until their request is answered, which is
+    /// bounded by the [timeout](crate::Builder::set_timeout()).  This is the default.
+    #[default]
+    Reject,
+    /// Stop accepting connections until one closes, leaving new ones waiting in the
+    /// backlog of the operating system
+    StopAccepting,
+}
+
+/// The runtime state backing [`Builder::set_max_connections()`](crate::Builder::set_max_connections())
+pub(crate) struct ConnectionLimit {
+    permits: Arc<Semaphore>,
+    at_limit: AtConnectionLimit,
+}
+
+impl ConnectionLimit {
+    pub(crate) fn new(max_connections: usize, at_limit: AtConnectionLimit) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(max_connections)),
+            at_limit,
+        }
+    }
+
+    pub(crate) const fn at_limit(&self) -> AtConnectionLimit {
+        self.at_limit
+    }
+
+    /// Count a connection as open until the returned permit is dropped, if it may be
+    pub(crate) fn try_admit(&self) -> Option<OwnedSemaphorePermit> {
+        self.permits.clone().try_acquire_owned().ok()
+    }
+
+    /// Wait until a connection may be opened, and count it as open until the returned
+    /// permit is dropped
+    pub(crate) async fn admit(&self) -> OwnedSemaphorePermit {
+        self.permits.clone().acquire_owned().await.expect("twinstar BUG")
+    }
+
+    /// The response sent to connections which are [rejected](AtConnectionLimit::Reject)
+    pub(crate) fn response(&self) -> Response {
+        Response::server_unavailable_lossy("Server has too many connections, please retry later")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Status;
+
+    #[test]
+    fn no_thresholds_never_sheds() {
+        let shedder = Arc::new(LoadShedder::new(LoadShedding::new()));
+        let guards: Vec<_> = (0..1000).map(|_| shedder.try_admit()).collect();
+
+        assert!(guards.iter().all(Option::is_some));
+    }
+
+    #[test]
+    fn sheds_above_max_in_flight() {
+        let shedder = Arc::new(LoadShedder::new(LoadShedding::new().set_max_in_flight(2)));
+
+        let first = shedder.try_admit();
+        let second = shedder.try_admit();
+        assert!(first.is_some());
+        assert!(second.is_some());
+        assert!(shedder.try_admit().is_none());
+
+        drop(first);
+        assert!(shedder.try_admit().is_some());
+    }
+
+    #[test]
+    fn sheds_above_max_latency_and_recovers() {
+        let config = LoadShedding::new().set_max_latency(Duration::from_millis(100));
+        let shedder = Arc::new(LoadShedder::new(config));
+
+        for _ in 0..32 {
+            let guard = shedder.try_admit().expect("admitted while latency is low");
+            guard.record_latency(Duration::from_secs(1));
+            if shedder.avg_latency_micros.load(Ordering::Relaxed) > 100_000 {
+                break;
+            }
+        }
+
+        assert!(shedder.try_admit().is_none());
+        assert!((0..64).any(|_| shedder.try_admit().is_some()));
+    }
+
+    #[test]
+    fn response_is_server_unavailable() {
+        let shedder = LoadShedder::new(LoadShedding::new());
+        let response = shedder.response();
+
+        assert_eq!(response.header().status, Status::SERVER_UNAVAILABLE);
+        assert!(response.header().meta.as_str().contains("retry in 5 seconds"));
+    }
+
+    #[tokio::test]
+    async fn limits_connections() {
+        let limit = ConnectionLimit::new(1, AtConnectionLimit::default());
+        let first = limit.try_admit().unwrap();
+        assert!(limit.try_admit().is_none());
+        assert_eq!(limit.at_limit(), AtConnectionLimit::Reject);
+        assert_eq!(limit.response().header().status, Status::SERVER_UNAVAILABLE);
+
+        let waiting = tokio::spawn(async move { drop(limit.admit().await) });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        drop(first);
+        tokio::time::timeout(Duration::from_secs(1), waiting).await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,428 @@
+//! Access and error logs
+//!
+//! Besides the diagnostics twinstar emits through the [`log`](https://docs.rs/log)
+//! crate, a server can produce a structured record for every request it answers and
+//! every connection that fails.  These records are passed to a [`LogSink`], which can
+//! write them wherever the operator wants them.
+//!
+//! Writing logs must never slow down request handling, so sinks are not called by the
+//! tasks serving clients.  Instead, records are passed through a bounded queue to a
+//! dedicated thread, see [`NonBlockingSink`].  If the sink can't keep up, records are
+//! dropped and counted in [`LogMetrics`].
+//!
+//! Queries often contain private user input, so some of them are redacted before they
+//! reach any record, see [`QueryRedaction`].
+//!
+//! Besides [`WriterSink`], which writes plain lines to a file or any other writer,
+//! unix systems can send records to syslog using `SyslogSink`, or to systemd-journald
+//! with structured fields using `JournaldSink`.  On Windows, the `windows-service`
+//! feature adds `EventLogSink` for writing to the event log.
+//!
+//! This module requires the `logging` feature.
+//!
+//! ```no_run
+//! # use twinstar::{Server, GEMINI_PORT, logging::WriterSink};
+//! # async fn run() -> anyhow::Result<()> {
+//! let access_log = std::fs::File::create("access.log")?;
+//! let builder = Server::bind(("localhost", GEMINI_PORT))
+//!     .set_log_sink(WriterSink::new(access_log));
+//! let metrics = builder.log_metrics();
+//!
+//! builder.serve().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Result, Context};
+
+#[cfg(feature="failures")]
+use crate::failures::FailureKind;
+#[cfg(feature="geoip")]
+use crate::geoip::GeoInfo;
+use crate::types::Status;
+use crate::util::rfc3339;
+
+pub use crate::redaction::{QueryRedaction, RedactQueries};
+
+#[cfg(unix)]
+mod syslog;
+#[cfg(unix)]
+pub use self::syslog::{SyslogSink, Facility, SYSLOG_SOCKET};
+
+#[cfg(unix)]
+mod journald;
+#[cfg(unix)]
+pub use self::journald::{JournaldSink, JOURNALD_SOCKET};
+
+#[cfg(all(windows, feature="windows-service"))]
+mod eventlog;
+#[cfg(all(windows, feature="windows-service"))]
+pub use self::eventlog::EventLogSink;
+
+/// The number of records buffered by default before records are dropped
+pub const DEFAULT_LOG_BUFFER: usize = 1024;
+
+/// A single entry in the access or error log
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum LogRecord {
+    /// A request was answered
+    Access(AccessRecord),
+    /// A connection failed before a request could be answered
+    Error(ErrorRecord),
+}
+
+impl fmt::Display for LogRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Access(record) => record.fmt(f),
+            Self::Error(record) => record.fmt(f),
+        }
+    }
+}
+
+/// A request which was answered by the server
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct AccessRecord {
+    /// When the request was received
+    pub time: SystemTime,
+    /// The address of the client
+    pub peer_addr: SocketAddr,
+    /// The requested URI
+    pub uri: String,
+    /// The status the request was answered with
+    pub status: Status,
+    /// The meta the request was answered with
+    pub meta: String,
+    /// The number of body bytes sent to the client
+    pub body_bytes: u64,
+    /// How long it took to handle the request, including sending the response
+    pub duration: Duration,
+    /// How long the middleware and handler took to produce the response
+    ///
+    /// This is `None` if the request was answered before reaching them, e.g. because
+    /// it was rate limited.
+    pub handler_duration: Option<Duration>,
+    /// Where the client connects from, if GeoIP lookups are enabled
+    ///
+    /// This requires the `geoip` feature.
+    #[cfg(feature="geoip")]
+    pub geo: Option<GeoInfo>,
+    /// The [hash of the client's TLS fingerprint](crate::fingerprint::TlsFingerprint::hash),
+    /// if fingerprinting is enabled
+    pub tls_fingerprint: Option<String>,
+}
+
+/// Formats the record like a line of an NCSA common log, followed by the duration
+impl fmt::Display for AccessRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} - - [{}] \"{}\" {} {} {}ms",
+            self.peer_addr.ip(),
+            rfc3339(self.time),
+            self.uri,
+            self.status.code(),
+            self.body_bytes,
+            self.duration.as_millis(),
+        )
+    }
+}
+
+/// A connection which failed
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ErrorRecord {
+    /// When the error occured
+    pub time: SystemTime,
+    /// The address of the client, if known
+    pub peer_addr: Option<SocketAddr>,
+    /// A description of the error, including its causes
+    pub message: String,
+    /// What kind of failure this was, if it was a failure to serve a request
+    ///
+    /// This requires the `failures` feature.
+    #[cfg(feature="failures")]
+    pub kind: Option<FailureKind>,
+}
+
+impl fmt::Display for ErrorRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.peer_addr {
+            Some(peer_addr) => write!(f, "{} [{}] error: {}", peer_addr.ip(), rfc3339(self.time), self.message),
+            None => write!(f, "- [{}] error: {}", rfc3339(self.time), self.message),
+        }
+    }
+}
+
+/// A destination for [`LogRecord`]s
+///
+/// Sinks are free to block, since the server only ever calls them from a dedicated
+/// logging thread.
+pub trait LogSink: Send + Sync + 'static {
+    /// Write a single record
+    fn write(&self, record: &LogRecord) -> Result<()>;
+
+    /// Flush any buffered records
+    ///
+    /// This is called whenever the queue of pending records runs empty.
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<S: LogSink + ?Sized> LogSink for Arc<S> {
+    fn write(&self, record: &LogRecord) -> Result<()> {
+        (**self).write(record)
+    }
+
+    fn flush(&self) -> Result<()> {
+        (**self).flush()
+    }
+}
+
+impl<S: LogSink + ?Sized> LogSink for Box<S> {
+    fn write(&self, record: &LogRecord) -> Result<()> {
+        (**self).write(record)
+    }
+
+    fn flush(&self) -> Result<()> {
+        (**self).flush()
+    }
+}
+
+/// A [`LogSink`] writing one line per record to any [`Write`]r, like a file
+///
+/// See the [`Display`](fmt::Display) implementations of [`AccessRecord`] and
+/// [`ErrorRecord`] for the format.
+pub struct WriterSink<W> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write + Send + 'static> WriterSink<W> {
+    /// Write records to `writer`
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+impl<W: Write + Send + 'static> LogSink for WriterSink<W> {
+    fn write(&self, record: &LogRecord) -> Result<()> {
+        let mut writer = self.writer.lock().expect("twinstar BUG");
+        writeln!(writer, "{}", record).context("Failed to write log record")
+    }
+
+    fn flush(&self) -> Result<()> {
+        let mut writer = self.writer.lock().expect("twinstar BUG");
+        writer.flush().context("Failed to flush log")
+    }
+}
+
+/// Counters describing the health of a [`NonBlockingSink`]
+///
+/// This is a cheap handle which can be cloned and read from anywhere.
+#[derive(Debug, Clone, Default)]
+pub struct LogMetrics {
+    counters: Arc<LogCounters>,
+}
+
+#[derive(Debug, Default)]
+struct LogCounters {
+    written: AtomicU64,
+    dropped: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl LogMetrics {
+    /// Create a new set of counters, all at zero
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of records successfully written by the sink
+    pub fn written(&self) -> u64 {
+        self.counters.written.load(Ordering::Relaxed)
+    }
+
+    /// The number of records dropped because the sink couldn't keep up
+    pub fn dropped(&self) -> u64 {
+        self.counters.dropped.load(Ordering::Relaxed)
+    }
+
+    /// The number of records the sink failed to write
+    pub fn failed(&self) -> u64 {
+        self.counters.failed.load(Ordering::Relaxed)
+    }
+}
+
+/// A [`LogSink`] handing records to another sink running on a dedicated thread
+///
+/// Up to `capacity` records are queued for the inner sink.  Once the queue is full,
+/// further records are dropped instead of waiting for the sink, and counted in the
+/// [`LogMetrics`].
+///
+/// The server wraps every sink passed to
+/// [`Builder::set_log_sink()`](crate::Builder::set_log_sink()) in one of these.
+pub struct NonBlockingSink {
+    sender: SyncSender<LogRecord>,
+    metrics: LogMetrics,
+}
+
+impl NonBlockingSink {
+    /// Start a logging thread writing to `sink`, with room for `capacity` queued records
+    pub fn new(sink: impl LogSink, capacity: usize) -> Self {
+        Self::with_metrics(sink, capacity, LogMetrics::new())
+    }
+
+    /// Like [`new()`](Self::new()), but counting into existing metrics
+    pub fn with_metrics(sink: impl LogSink, capacity: usize, metrics: LogMetrics) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<LogRecord>(capacity);
+        let counters = metrics.counters.clone();
+
+        let spawned = std::thread::Builder::new()
+            .name("twinstar-log".to_owned())
+            .spawn(move || {
+                let mut pending = receiver.recv().ok();
+
+                while let Some(record) = pending {
+                    match sink.write(&record) {
+                        Ok(()) => counters.written.fetch_add(1, Ordering::Relaxed),
+                        Err(err) => {
+                            warn!("Failed to write log record: {:?}", err);
+                            counters.failed.fetch_add(1, Ordering::Relaxed)
+                        },
+                    };
+
+                    pending = match receiver.try_recv() {
+                        Ok(record) => Some(record),
+                        Err(_) => {
+                            if let Err(err) = sink.flush() {
+                                warn!("Failed to flush log: {:?}", err);
+                            }
+                            receiver.recv().ok()
+                        },
+                    };
+                }
+            });
+
+        if let Err(err) = spawned {
+            error!("Failed to spawn logging thread, log records will be dropped: {:?}", err);
+        }
+
+        Self { sender, metrics }
+    }
+
+    /// A handle to the counters of this sink
+    pub fn metrics(&self) -> LogMetrics {
+        self.metrics.clone()
+    }
+
+    /// Queue a record for writing, dropping it if the queue is full
+    pub fn log(&self, record: LogRecord) {
+        match self.sender.try_send(record) {
+            Ok(()) => {},
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                self.metrics.counters.dropped.fetch_add(1, Ordering::Relaxed);
+            },
+        }
+    }
+}
+
+impl LogSink for NonBlockingSink {
+    fn write(&self, record: &LogRecord) -> Result<()> {
+        self.log(record.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    fn error_record(message: &str) -> LogRecord {
+        LogRecord::Error(ErrorRecord {
+            time: UNIX_EPOCH,
+            peer_addr: None,
+            message: message.to_owned(),
+            #[cfg(feature="failures")]
+            kind: None,
+        })
+    }
+
+    #[test]
+    fn formats_access_records() {
+        let record = AccessRecord {
+            time: UNIX_EPOCH,
+            peer_addr: "127.0.0.1:1965".parse().unwrap(),
+            uri: "gemini://localhost/".to_owned(),
+            status: Status::SUCCESS,
+            meta: "text/gemini".to_owned(),
+            body_bytes: 42,
+            duration: Duration::from_millis(3),
+            handler_duration: None,
+            #[cfg(feature="geoip")]
+            geo: None,
+            tls_fingerprint: None,
+        };
+
+        assert_eq!(
+            record.to_string(),
+            "127.0.0.1 - - [1970-01-01T00:00:00Z] \"gemini://localhost/\" 20 42 3ms",
+        );
+    }
+
+    /// A sink which blocks until the test allows it to continue
+    struct StuckSink {
+        entered: Mutex<mpsc::Sender<()>>,
+        release: Mutex<mpsc::Receiver<()>>,
+    }
+
+    impl LogSink for StuckSink {
+        fn write(&self, _: &LogRecord) -> Result<()> {
+            let _ = self.entered.lock().unwrap().send(());
+            let _ = self.release.lock().unwrap().recv();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn drops_records_when_full() {
+        let (entered_sender, entered) = mpsc::channel();
+        let (release, release_receiver) = mpsc::channel();
+        let sink = NonBlockingSink::new(StuckSink {
+            entered: Mutex::new(entered_sender),
+            release: Mutex::new(release_receiver),
+        }, 2);
+        let metrics = sink.metrics();
+
+        // Wait until the logging thread is stuck writing the first record
+        sink.log(error_record("first"));
+        entered.recv().unwrap();
+
+        for i in 0..10 {
+            sink.log(error_record(&i.to_string()));
+        }
+
+        assert_eq!(metrics.dropped(), 8);
+
+        drop(release);
+        while metrics.written() < 3 {
+            std::thread::yield_now();
+        }
+
+        assert_eq!(metrics.written(), 3);
+        assert_eq!(metrics.failed(), 0);
+    }
+}
@@ -0,0 +1,83 @@
+use std::ffi::OsStr;
+use std::os::windows::ffi::OsStrExt;
+use std::ptr;
+
+use anyhow::{Result, bail};
+use winapi::um::winbase::{DeregisterEventSource, RegisterEventSourceW, ReportEventW};
+use winapi::um::winnt::{EVENTLOG_ERROR_TYPE, EVENTLOG_INFORMATION_TYPE, HANDLE};
+
+use super::{LogRecord, LogSink};
+
+/// A [`LogSink`] writing records to the Windows event log
+///
+/// Access records are reported as information events, error records as error events.
+/// The message of each event is the record formatted like its
+/// [`Display`](std::fmt::Display) implementation.
+///
+/// The event source should be registered when installing the service, otherwise the
+/// event viewer will complain about missing message descriptions.
+pub struct EventLogSink {
+    handle: HANDLE,
+}
+
+// The handle returned by `RegisterEventSourceW` may be used from any thread
+unsafe impl Send for EventLogSink {}
+unsafe impl Sync for EventLogSink {}
+
+impl EventLogSink {
+    /// Report events under the event source named `source`
+    pub fn new(source: impl AsRef<OsStr>) -> Result<Self> {
+        let source = to_wide(source.as_ref());
+        let handle = unsafe { RegisterEventSourceW(ptr::null(), source.as_ptr()) };
+
+        if handle.is_null() {
+            bail!("Failed to register event source: {}", std::io::Error::last_os_error());
+        }
+
+        Ok(Self { handle })
+    }
+}
+
+impl LogSink for EventLogSink {
+    fn write(&self, record: &LogRecord) -> Result<()> {
+        let event_type = match record {
+            LogRecord::Access(_) => EVENTLOG_INFORMATION_TYPE,
+            LogRecord::Error(_) => EVENTLOG_ERROR_TYPE,
+        };
+        let message = to_wide(OsStr::new(&record.to_string()));
+        let mut strings = [message.as_ptr()];
+
+        let reported = unsafe {
+            ReportEventW(
+                self.handle,
+                event_type,
+                0,
+                0,
+                ptr::null_mut(),
+                strings.len() as u16,
+                0,
+                strings.as_mut_ptr(),
+                ptr::null_mut(),
+            )
+        };
+
+        if reported == 0 {
+            bail!("Failed to report event: {}", std::io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for EventLogSink {
+    fn drop(&mut self) {
+        unsafe {
+            DeregisterEventSource(self.handle);
+        }
+    }
+}
+
+/// Encode a string as a nul terminated UTF-16 string
+fn to_wide(value: &OsStr) -> Vec<u16> {
+    value.encode_wide().chain(Some(0)).collect()
+}
@@ -0,0 +1,179 @@
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+
+use anyhow::{Result, Context};
+
+use super::{LogRecord, LogSink};
+
+/// The path of the socket journald receives native log messages on
+pub const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// A [`LogSink`] sending records to systemd-journald
+///
+/// Records are sent using journald's native protocol, so every field of a record can
+/// be queried on its own, e.g. `journalctl GEMINI_STATUS=51`.  The fields are:
+///
+/// * `MESSAGE`, the record formatted like its [`Display`](std::fmt::Display)
+///   implementation
+/// * `PRIORITY`, `6` (info) for access records and `3` (err) for error records
+/// * `SYSLOG_IDENTIFIER`, `twinstar` unless changed with
+///   [`set_identifier()`](Self::set_identifier())
+/// * `GEMINI_PEER`, `GEMINI_URI`, `GEMINI_STATUS`, `GEMINI_META`, `GEMINI_BYTES` and
+///   `GEMINI_DURATION_MS` for access records, plus `GEMINI_COUNTRY` and `GEMINI_ASN`
+///   if they were looked up (see the `geoip` module), and `GEMINI_TLS_FINGERPRINT`
+///   if fingerprinting is enabled (see [`fingerprint`](crate::fingerprint))
+/// * `GEMINI_PEER` for error records, if the peer is known, and `GEMINI_FAILURE` with the
+///   kind of failure, if known (see the `failures` module)
+///
+/// ```no_run
+/// # use twinstar::{Server, GEMINI_PORT, logging::JournaldSink};
+/// # async fn run() -> anyhow::Result<()> {
+/// Server::bind(("localhost", GEMINI_PORT))
+///     .set_log_sink(JournaldSink::new()?)
+///     .serve()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct JournaldSink {
+    socket: UnixDatagram,
+    identifier: String,
+}
+
+impl JournaldSink {
+    /// Send records to the journal listening on [`JOURNALD_SOCKET`]
+    pub fn new() -> Result<Self> {
+        Self::with_socket_path(JOURNALD_SOCKET)
+    }
+
+    /// Send records to a journal listening on the socket at `path`
+    pub fn with_socket_path(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let socket = UnixDatagram::unbound()
+            .context("Failed to create journald socket")?;
+        socket.connect(path)
+            .with_context(|| format!("Failed to connect to journald at `{}`", path.display()))?;
+
+        Ok(Self {
+            socket,
+            identifier: "twinstar".to_owned(),
+        })
+    }
+
+    /// Set the `SYSLOG_IDENTIFIER` records are tagged with
+    ///
+    /// The default is `twinstar`.
+    pub fn set_identifier(mut self, identifier: impl Into<String>) -> Self {
+        self.identifier = identifier.into();
+        self
+    }
+
+    fn encode(&self, record: &LogRecord) -> Vec<u8> {
+        let mut message = Vec::new();
+        let priority = match record {
+            LogRecord::Access(_) => "6",
+            LogRecord::Error(_) => "3",
+        };
+
+        add_field(&mut message, "MESSAGE", &record.to_string());
+        add_field(&mut message, "PRIORITY", priority);
+        add_field(&mut message, "SYSLOG_IDENTIFIER", &self.identifier);
+
+        match record {
+            LogRecord::Access(record) => {
+                add_field(&mut message, "GEMINI_PEER", &record.peer_addr.to_string());
+                add_field(&mut message, "GEMINI_URI", &record.uri);
+                add_field(&mut message, "GEMINI_STATUS", &record.status.code().to_string());
+                add_field(&mut message, "GEMINI_META", &record.meta);
+                add_field(&mut message, "GEMINI_BYTES", &record.body_bytes.to_string());
+                add_field(&mut message, "GEMINI_DURATION_MS", &record.duration.as_millis().to_string());
+
+                #[cfg(feature="geoip")]
+                if let Some(geo) = &record.geo {
+                    if let Some(country) = &geo.country {
+                        add_field(&mut message, "GEMINI_COUNTRY", country);
+                    }
+                    if let Some(asn) = geo.asn {
+                        add_field(&mut message, "GEMINI_ASN", &asn.to_string());
+                    }
+                }
+                if let Some(hash) = &record.tls_fingerprint {
+                    add_field(&mut message, "GEMINI_TLS_FINGERPRINT", hash);
+                }
+            },
+            LogRecord::Error(record) => {
+                if let Some(peer_addr) = record.peer_addr {
+                    add_field(&mut message, "GEMINI_PEER", &peer_addr.to_string());
+                }
+                #[cfg(feature="failures")]
+                if let Some(kind) = record.kind {
+                    add_field(&mut message, "GEMINI_FAILURE", kind.name());
+                }
+            },
+        }
+
+        message
+    }
+}
+
+impl LogSink for JournaldSink {
+    fn write(&self, record: &LogRecord) -> Result<()> {
+        self.socket.send(&self.encode(record))
+            .context("Failed to send record to journald")?;
+
+        Ok(())
+    }
+}
+
+/// Append a field in journald's native format
+///
+/// Values containing newlines are written with an explicit length instead of being
+/// terminated by a newline.
+fn add_field(message: &mut Vec<u8>, name: &str, value: &str) {
+    message.extend_from_slice(name.as_bytes());
+
+    if value.contains('\n') {
+        message.push(b'\n');
+        message.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        message.push(b'=');
+    }
+
+    message.extend_from_slice(value.as_bytes());
+    message.push(b'\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+    use super::*;
+    use crate::logging::ErrorRecord;
+
+    #[test]
+    fn encodes_native_fields() {
+        let (socket, peer) = UnixDatagram::pair().unwrap();
+        let sink = JournaldSink {
+            socket,
+            identifier: "capsule".to_owned(),
+        };
+
+        sink.write(&LogRecord::Error(ErrorRecord {
+            time: UNIX_EPOCH,
+            peer_addr: Some("[::1]:4242".parse().unwrap()),
+            message: "oops\nbad".to_owned(),
+            #[cfg(feature="failures")]
+            kind: None,
+        })).unwrap();
+
+        let mut received = [0; 256];
+        let len = peer.recv(&mut received).unwrap();
+
+        let mut expected = b"MESSAGE\n".to_vec();
+        let message = "::1 [1970-01-01T00:00:00Z] error: oops\nbad";
+        expected.extend_from_slice(&(message.len() as u64).to_le_bytes());
+        expected.extend_from_slice(message.as_bytes());
+        expected.extend_from_slice(b"\nPRIORITY=3\nSYSLOG_IDENTIFIER=capsule\nGEMINI_PEER=[::1]:4242\n");
+
+        assert_eq!(&received[..len], &expected[..]);
+    }
+}
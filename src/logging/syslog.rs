@@ -0,0 +1,291 @@
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+
+use anyhow::{Result, Context, anyhow};
+
+use super::{AccessRecord, LogRecord, LogSink};
+use crate::util::rfc3339;
+
+/// The path of the local syslog socket on most systems
+pub const SYSLOG_SOCKET: &str = "/dev/log";
+
+/// The private enterprise number used for the structured data of syslog messages
+const SD_ID: &str = "twinstar@32473";
+
+/// A [`LogSink`] sending records to a syslog daemon
+///
+/// Records are sent as [RFC 5424](https://tools.ietf.org/html/rfc5424) messages, with
+/// the fields of the record attached as structured data.  Access records are logged
+/// with severity `info`, error records with severity `err`.
+///
+/// ```no_run
+/// # use twinstar::{Server, GEMINI_PORT, logging::{SyslogSink, Facility}};
+/// # async fn run() -> anyhow::Result<()> {
+/// Server::bind(("localhost", GEMINI_PORT))
+///     .set_log_sink(SyslogSink::local()?.set_facility(Facility::Local3))
+///     .serve()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct SyslogSink {
+    socket: SyslogSocket,
+    facility: Facility,
+    app_name: String,
+}
+
+enum SyslogSocket {
+    Unix(UnixDatagram),
+    Udp(UdpSocket),
+}
+
+impl SyslogSink {
+    /// Send records to the local syslog daemon listening on [`SYSLOG_SOCKET`]
+    pub fn local() -> Result<Self> {
+        Self::unix(SYSLOG_SOCKET)
+    }
+
+    /// Send records to a syslog daemon listening on the unix datagram socket at `path`
+    pub fn unix(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let socket = UnixDatagram::unbound()
+            .context("Failed to create syslog socket")?;
+        socket.connect(path)
+            .with_context(|| format!("Failed to connect to syslog at `{}`", path.display()))?;
+
+        Ok(Self::with_socket(SyslogSocket::Unix(socket)))
+    }
+
+    /// Send records to a remote syslog daemon over UDP
+    ///
+    /// If `addr` resolves to several addresses, the first one is used.
+    pub fn udp(addr: impl ToSocketAddrs) -> Result<Self> {
+        let addr = addr.to_socket_addrs()
+            .context("Failed to resolve syslog address")?
+            .next()
+            .ok_or_else(|| anyhow!("Syslog address resolved to no addresses"))?;
+        // The local socket has to be of the same family as the daemon's
+        let local_addr = match addr {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        };
+        let socket = UdpSocket::bind(local_addr)
+            .context("Failed to create syslog socket")?;
+        socket.connect(addr)
+            .context("Failed to connect to syslog")?;
+
+        Ok(Self::with_socket(SyslogSocket::Udp(socket)))
+    }
+
+    fn with_socket(socket: SyslogSocket) -> Self {
+        Self {
+            socket,
+            facility: Facility::Daemon,
+            app_name: "twinstar".to_owned(),
+        }
+    }
+
+    /// Set the facility messages are logged under
+    ///
+    /// The default is [`Facility::Daemon`].
+    pub fn set_facility(mut self, facility: Facility) -> Self {
+        self.facility = facility;
+        self
+    }
+
+    /// Set the name messages are tagged with
+    ///
+    /// The default is `twinstar`.
+    pub fn set_app_name(mut self, app_name: impl Into<String>) -> Self {
+        self.app_name = app_name.into();
+        self
+    }
+
+    fn format(&self, record: &LogRecord) -> String {
+        let (severity, time, data, message) = match record {
+            LogRecord::Access(record) => (
+                Severity::Info,
+                record.time,
+                format!(
+                    "[{} peer=\"{}\" uri=\"{}\" status=\"{}\" meta=\"{}\" bytes=\"{}\" duration_ms=\"{}\"{}{}]",
+                    SD_ID,
+                    record.peer_addr,
+                    escape_param(&record.uri),
+                    record.status.code(),
+                    escape_param(&record.meta),
+                    record.body_bytes,
+                    record.duration.as_millis(),
+                    geo_params(record),
+                    record.tls_fingerprint.as_ref()
+                        .map(|hash| format!(" tls_fingerprint=\"{}\"", hash))
+                        .unwrap_or_default(),
+                ),
+                format!("{} {} {}", record.peer_addr.ip(), record.status.code(), record.uri),
+            ),
+            LogRecord::Error(record) => (
+                Severity::Err,
+                record.time,
+                match record.peer_addr {
+                    Some(peer_addr) => format!("[{} peer=\"{}\"]", SD_ID, peer_addr),
+                    None => "-".to_owned(),
+                },
+                record.message.clone(),
+            ),
+        };
+
+        format!(
+            "<{}>1 {} - {} {} - {} {}",
+            self.facility as u8 * 8 + severity as u8,
+            rfc3339(time),
+            self.app_name,
+            std::process::id(),
+            data,
+            message,
+        )
+    }
+}
+
+impl LogSink for SyslogSink {
+    fn write(&self, record: &LogRecord) -> Result<()> {
+        let message = self.format(record);
+        let sent = match &self.socket {
+            SyslogSocket::Unix(socket) => socket.send(message.as_bytes()),
+            SyslogSocket::Udp(socket) => socket.send(message.as_bytes()),
+        };
+
+        sent.context("Failed to send record to syslog")?;
+
+        Ok(())
+    }
+}
+
+/// The structured data parameters for what is known about where a client connects from
+#[cfg(feature="geoip")]
+fn geo_params(record: &AccessRecord) -> String {
+    let geo = record.geo.as_ref();
+    let mut params = String::new();
+
+    if let Some(country) = geo.and_then(|geo| geo.country.as_ref()) {
+        params.push_str(&format!(" country=\"{}\"", escape_param(country)));
+    }
+    if let Some(asn) = geo.and_then(|geo| geo.asn) {
+        params.push_str(&format!(" asn=\"{}\"", asn));
+    }
+
+    params
+}
+
+#[cfg(not(feature="geoip"))]
+fn geo_params(_record: &AccessRecord) -> String {
+    String::new()
+}
+
+/// Escape a value for use as a structured data parameter
+fn escape_param(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+/// The facility syslog messages are logged under
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Facility {
+    User = 1,
+    Daemon = 3,
+    Local0 = 16,
+    Local1 = 17,
+    Local2 = 18,
+    Local3 = 19,
+    Local4 = 20,
+    Local5 = 21,
+    Local6 = 22,
+    Local7 = 23,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Severity {
+    Err = 3,
+    Info = 6,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+    use super::*;
+    use crate::logging::{AccessRecord, ErrorRecord};
+    use crate::types::Status;
+
+    #[test]
+    fn formats_rfc5424_messages() {
+        let (socket, _peer) = UnixDatagram::pair().unwrap();
+        let sink = SyslogSink::with_socket(SyslogSocket::Unix(socket))
+            .set_facility(Facility::Local0);
+        let pid = std::process::id();
+
+        let access = LogRecord::Access(AccessRecord {
+            time: UNIX_EPOCH,
+            peer_addr: "127.0.0.1:1965".parse().unwrap(),
+            uri: "gemini://localhost/\"quoted\"".to_owned(),
+            status: Status::SUCCESS,
+            meta: "text/gemini".to_owned(),
+            body_bytes: 42,
+            duration: Duration::from_millis(3),
+            handler_duration: None,
+            #[cfg(feature="geoip")]
+            geo: None,
+            tls_fingerprint: None,
+        });
+
+        assert_eq!(sink.format(&access), format!(
+            "<134>1 1970-01-01T00:00:00Z - twinstar {} - [twinstar@32473 peer=\"127.0.0.1:1965\" \
+             uri=\"gemini://localhost/\\\"quoted\\\"\" status=\"20\" meta=\"text/gemini\" bytes=\"42\" \
+             duration_ms=\"3\"] 127.0.0.1 20 gemini://localhost/\"quoted\"",
+            pid,
+        ));
+
+        let error = LogRecord::Error(ErrorRecord {
+            time: UNIX_EPOCH,
+            peer_addr: None,
+            message: "Failed to establish TLS session".to_owned(),
+            #[cfg(feature="failures")]
+            kind: None,
+        });
+
+        assert_eq!(sink.format(&error), format!(
+            "<131>1 1970-01-01T00:00:00Z - twinstar {} - - Failed to establish TLS session",
+            pid,
+        ));
+    }
+
+    #[test]
+    fn sends_over_udp() {
+        for daemon_addr in ["127.0.0.1:0", "[::1]:0"] {
+            let daemon = match UdpSocket::bind(daemon_addr) {
+                Ok(daemon) => daemon,
+                // IPv6 may be unavailable
+                Err(_) => continue,
+            };
+            let sink = SyslogSink::udp(daemon.local_addr().unwrap()).unwrap();
+            let error = LogRecord::Error(ErrorRecord {
+                time: UNIX_EPOCH,
+                peer_addr: None,
+                message: "hello".to_owned(),
+                #[cfg(feature="failures")]
+                kind: None,
+            });
+            sink.write(&error).unwrap();
+
+            let mut received = [0; 1024];
+            let len = daemon.recv(&mut received).unwrap();
+            assert_eq!(&received[..len], sink.format(&error).as_bytes());
+        }
+    }
+}
@@ -0,0 +1,114 @@
+//! Taking parts of a server offline without redeploying
+//!
+//! See [`Server::set_maintenance()`](crate::Server::set_maintenance()).
+
+use std::convert::TryFrom;
+use std::sync::RwLock;
+
+use anyhow::{Result, Context, ensure};
+use uriparse::path::Path;
+
+use crate::routing::RoutingNode;
+use crate::types::{Document, Request, Response, ResponseHeader};
+
+/// How requests to a route under maintenance are answered
+enum Notice {
+    /// With a `41 SERVER UNAVAILABLE` header
+    Unavailable(ResponseHeader),
+    /// With this rendered gemtext document
+    Page(String),
+}
+
+/// The routes which are currently under maintenance
+///
+/// Maintenance is checked before the regular routes, using the same matching rules, so
+/// putting `/blog` under maintenance affects `/blog/2020/hello.gmi` as well.
+#[derive(Default)]
+pub(crate) struct Maintenance {
+    routes: RwLock<RoutingNode<Notice>>,
+}
+
+impl Maintenance {
+    pub fn set_unavailable(&self, route_prefix: &str, message: &str) -> Result<()> {
+        let header = ResponseHeader::server_unavailable(message)
+            .context("Invalid maintenance message")?;
+        self.set(route_prefix, Notice::Unavailable(header))
+    }
+
+    pub fn set_page(&self, route_prefix: &str, document: &Document) -> Result<()> {
+        self.set(route_prefix, Notice::Page(document.to_string()))
+    }
+
+    fn set(&self, route_prefix: &str, notice: Notice) -> Result<()> {
+        let path = parse_route(route_prefix)?;
+        let mut routes = self.routes.write().expect("twinstar BUG");
+
+        routes.remove_route_by_path(path.clone());
+        routes.add_route_by_path(path, notice)
+            .context("Failed to put route under maintenance")?;
+
+        Ok(())
+    }
+
+    pub fn clear(&self, route_prefix: &str) -> Result<bool> {
+        let path = parse_route(route_prefix)?;
+        let mut routes = self.routes.write().expect("twinstar BUG");
+
+        Ok(routes.remove_route_by_path(path).is_some())
+    }
+
+    pub fn routes(&self) -> Vec<String> {
+        let routes = self.routes.read().expect("twinstar BUG");
+
+        routes.routes().into_iter()
+            .map(|(path, _)| path)
+            .collect()
+    }
+
+    /// The response to send instead of calling the handler, if the request is affected
+    pub fn check(&self, request: &Request) -> Option<Response> {
+        let routes = self.routes.read().expect("twinstar BUG");
+        let (_, notice) = routes.match_request(request)?;
+
+        Some(match notice {
+            Notice::Unavailable(header) => Response::new(header.clone()),
+            Notice::Page(page) => Response::success_gemini(page.clone()),
+        })
+    }
+}
+
+fn parse_route(route_prefix: &str) -> Result<Path<'static>> {
+    let path = Path::try_from(route_prefix)
+        .with_context(|| format!("Malformed route `{}`", route_prefix))?
+        .into_owned();
+    ensure!(path.is_absolute(), "Route `{}` is not absolute", route_prefix);
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Status;
+    use crate::uri::URIReference;
+
+    fn request(uri: &'static str) -> Request {
+        Request::from_uri(URIReference::try_from(uri).unwrap().into_owned()).unwrap()
+    }
+
+    #[test]
+    fn swaps_and_restores_routes() {
+        let maintenance = Maintenance::default();
+        maintenance.set_unavailable("/blog", "Migrating the blog, back soon").unwrap();
+
+        let response = maintenance.check(&request("gemini://localhost/blog/2020/hello.gmi")).unwrap();
+        assert_eq!(response.header().status, Status::SERVER_UNAVAILABLE);
+        assert_eq!(response.header().meta.as_str(), "Migrating the blog, back soon");
+        assert!(maintenance.check(&request("gemini://localhost/about")).is_none());
+        assert_eq!(maintenance.routes(), vec!["/blog".to_owned()]);
+
+        assert!(maintenance.clear("/blog/").unwrap());
+        assert!(!maintenance.clear("/blog").unwrap());
+        assert!(maintenance.check(&request("gemini://localhost/blog/2020/hello.gmi")).is_none());
+    }
+}
@@ -0,0 +1,70 @@
+//! Site-wide parameters for gemtext responses
+//!
+//! See [`Builder::set_default_lang()`](crate::Builder::set_default_lang()).
+
+use crate::types::{Meta, Response};
+
+/// The `lang` and `charset` parameters added to gemtext responses lacking them
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MetaDefaults {
+    pub lang: Option<String>,
+    pub charset: Option<String>,
+}
+
+impl MetaDefaults {
+    pub fn is_empty(&self) -> bool {
+        self.lang.is_none() && self.charset.is_none()
+    }
+
+    /// Add the missing parameters to the meta of a successful `text/gemini` response
+    pub fn apply(&self, response: &mut Response) {
+        if self.is_empty() || !response.header().status.is_success() {
+            return;
+        }
+
+        let mime = match response.header().meta.to_mime() {
+            Ok(mime) if mime.essence_str() == "text/gemini" => mime,
+            _ => return,
+        };
+
+        let mut meta = response.header().meta.as_str().to_owned();
+
+        if let (Some(lang), None) = (&self.lang, mime.get_param("lang")) {
+            meta.push_str("; lang=");
+            meta.push_str(lang);
+        }
+
+        if let (Some(charset), None) = (&self.charset, mime.get_param(mime::CHARSET)) {
+            meta.push_str("; charset=");
+            meta.push_str(charset);
+        }
+
+        response.header_mut().meta = Meta::new_lossy(meta);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta(defaults: &MetaDefaults, mut response: Response) -> String {
+        defaults.apply(&mut response);
+        response.header().meta.as_str().to_owned()
+    }
+
+    #[test]
+    fn fills_in_missing_params() {
+        let defaults = MetaDefaults {
+            lang: Some("en".to_owned()),
+            charset: Some("utf-8".to_owned()),
+        };
+
+        assert_eq!(meta(&defaults, Response::success_gemini("# Hi")), "text/gemini; lang=en; charset=utf-8");
+        assert_eq!(
+            meta(&defaults, Response::success(&"text/gemini; lang=de".parse().unwrap(), "# Hallo")),
+            "text/gemini; lang=de; charset=utf-8",
+        );
+        assert_eq!(meta(&defaults, Response::success_plain("Hi")), "text/plain");
+        assert_eq!(meta(&defaults, Response::not_found()), Response::not_found().header().meta.as_str());
+    }
+}
@@ -0,0 +1,132 @@
+//! Code running around every request
+//!
+//! Middleware sees each request before it is routed to a handler, and each response
+//! after the handler produced it.  This makes it the place for cross-cutting concerns
+//! like access control or experiments, which would otherwise have to be repeated in
+//! every handler.
+//!
+//! Middleware is added using [`Builder::add_middleware()`](crate::Builder::add_middleware()),
+//! and runs in the order it was added.  Each piece of middleware decides whether to pass
+//! the request on using [`Next::run()`], or to answer it itself.
+//!
+//! ```no_run
+//! # use twinstar::{Server, Request, Response, GEMINI_PORT, middleware::Next};
+//! # async fn run() -> anyhow::Result<()> {
+//! Server::bind(("localhost", GEMINI_PORT))
+//!     .add_middleware(|request: Request, next: Next| {
+//!         Box::pin(async move {
+//!             if request.certificate().is_none() && request.path().to_string().starts_with("/private") {
+//!                 return Ok(Response::client_certificate_required());
+//!             }
+//!
+//!             next.run(request).await
+//!         }) as _
+//!     })
+//!     .serve()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! This module requires the `middleware` feature.  Of the ready-made middleware,
+//! `Archive` and `ContentStore` also require the `storage` feature, `Polite` the
+//! `fingerprint` feature, and `SignedLinks` the `auth` feature.
+
+use std::sync::Arc;
+
+use crate::routing::RoutingNode;
+use crate::types::Request;
+use crate::{Handler, HandlerResponse};
+
+mod variants;
+pub use self::variants::{Variants, BucketBy};
+
+mod footer;
+pub use self::footer::Footer;
+
+#[cfg(feature="storage")]
+mod archive;
+#[cfg(feature="storage")]
+pub use self::archive::{
+    Archive, ArchiveSink, ArchivedResponse, ArchiveDir, ContentStore, DEFAULT_MAX_ARCHIVED_BODY,
+};
+
+mod budget;
+pub use self::budget::Budget;
+
+#[cfg(feature="auth")]
+mod signed_links;
+#[cfg(feature="auth")]
+pub use self::signed_links::{SignedLinks, DEFAULT_LINK_TTL};
+
+mod redirects;
+pub use self::redirects::{Redirects, Redirect};
+
+#[cfg(feature="fingerprint")]
+mod polite;
+#[cfg(feature="fingerprint")]
+pub use self::polite::{
+    Polite, CrawlerPolicy, DEFAULT_BURST_REQUESTS, DEFAULT_BURST_WINDOW, DEFAULT_CRAWLER_MEMORY,
+    DEFAULT_BACKOFF, DEFAULT_MAX_BACKOFF,
+};
+
+/// Code wrapping the handling of every request
+///
+/// This is implemented for all functions and closures taking a [`Request`] and [`Next`]
+/// and returning a boxed future, so simple middleware doesn't need a dedicated type.
+pub trait Middleware: Send + Sync + 'static {
+    /// Handle a request, usually by passing it on to `next`
+    fn handle(&self, request: Request, next: Next) -> HandlerResponse;
+}
+
+impl<F> Middleware for F
+where
+    F: Fn(Request, Next) -> HandlerResponse + Send + Sync + 'static,
+{
+    fn handle(&self, request: Request, next: Next) -> HandlerResponse {
+        (self)(request, next)
+    }
+}
+
+/// The rest of the middleware chain, ending in the routed handler
+pub struct Next {
+    middleware: Arc<[Arc<dyn Middleware>]>,
+    routes: Arc<RoutingNode<Handler>>,
+    fallback: Option<Handler>,
+    index: usize,
+}
+
+impl Next {
+    pub(crate) fn new(middleware: Arc<[Arc<dyn Middleware>]>, routes: Arc<RoutingNode<Handler>>) -> Self {
+        Self {
+            middleware,
+            routes,
+            fallback: None,
+            index: 0,
+        }
+    }
+
+    /// Answer requests matching no route using `fallback`, instead of `51 NOT FOUND`
+    pub(crate) fn with_fallback(mut self, fallback: Option<Handler>) -> Self {
+        self.fallback = fallback;
+        self
+    }
+
+    /// Pass the request on to the next middleware, or to the handler of its route
+    ///
+    /// If no route matches the request, it is passed to the
+    /// [fallback handler](crate::Builder::set_fallback()), or answered with
+    /// `51 NOT FOUND` if there is none.
+    pub fn run(self, request: Request) -> HandlerResponse {
+        if let Some(middleware) = self.middleware.get(self.index).cloned() {
+            let next = Self {
+                index: self.index + 1,
+                ..self
+            };
+
+            return middleware.handle(request, next);
+        }
+
+        crate::route_request(&self.routes, self.fallback.as_ref(), request)
+    }
+}
@@ -0,0 +1,415 @@
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Result, Context as _};
+use futures_core::future::BoxFuture;
+use tokio::io::{AsyncRead, ReadBuf};
+
+use crate::storage::KvStore;
+use crate::types::{Body, Request, Response, Status};
+use crate::HandlerResponse;
+use crate::util::hex;
+use super::{Middleware, Next};
+
+/// The largest body archived by default
+pub const DEFAULT_MAX_ARCHIVED_BODY: usize = 1024 * 1024;
+
+/// A response which was sent to a client, as handed to an [`ArchiveSink`]
+#[derive(Debug, Clone)]
+pub struct ArchivedResponse {
+    /// When the request was received
+    pub time: SystemTime,
+    /// The requested URI
+    pub uri: String,
+    /// The status of the response
+    pub status: Status,
+    /// The meta of the response
+    pub meta: String,
+    /// The body of the response, empty if it had none or it was excluded
+    pub body: Vec<u8>,
+    /// Whether the body was left out because of its size or MIME type
+    pub body_excluded: bool,
+}
+
+impl ArchivedResponse {
+    /// The exchange as it happened on the wire: the request line, the response header,
+    /// and the body
+    pub fn to_transcript(&self) -> Vec<u8> {
+        let mut transcript = format!("{}\r\n{} {}\r\n", self.uri, self.status.code(), self.meta)
+            .into_bytes();
+        transcript.extend_from_slice(&self.body);
+        transcript
+    }
+}
+
+/// Where archived responses are kept
+pub trait ArchiveSink: Send + Sync + 'static {
+    /// Store a single response
+    fn archive(&self, response: ArchivedResponse) -> BoxFuture<'static, Result<()>>;
+}
+
+/// An [`ArchiveSink`] writing one file per response into a directory
+///
+/// Each file contains the [transcript](ArchivedResponse::to_transcript()) of the
+/// exchange, and is named after the time of the request in milliseconds since the unix
+/// epoch, followed by a sequence number, e.g. `1607126400000-42.gemini`.
+#[derive(Debug, Clone)]
+pub struct ArchiveDir {
+    dir: PathBuf,
+    sequence: Arc<AtomicU64>,
+}
+
+impl ArchiveDir {
+    /// Archive into `dir`, which is created if it doesn't exist
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            sequence: Arc::new(AtomicU64::new(0)),
+        }
+    }
+}
+
+impl ArchiveSink for ArchiveDir {
+    fn archive(&self, response: ArchivedResponse) -> BoxFuture<'static, Result<()>> {
+        let millis = response.time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        let dir = self.dir.clone();
+        let path = dir.join(format!("{}-{}.gemini", millis, sequence));
+
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || {
+                std::fs::create_dir_all(&dir)
+                    .with_context(|| format!("Failed to create archive directory `{}`", dir.display()))?;
+                std::fs::write(&path, response.to_transcript())
+                    .with_context(|| format!("Failed to write `{}`", path.display()))
+            })
+            .await
+            .context("Archiving task failed")?
+        })
+    }
+}
+
+/// An [`ArchiveSink`] deduplicating bodies in a [`KvStore`]
+///
+/// Bodies are stored under `body/<sha256>`, so a body which is sent many times is only
+/// stored once.  Each response is recorded under `response/<millis>-<sequence>`, with
+/// the request line, the response header, and the SHA-256 of the body on three
+/// CRLF-terminated lines.
+pub struct ContentStore {
+    store: Arc<dyn KvStore>,
+    sequence: AtomicU64,
+}
+
+impl ContentStore {
+    /// Archive into `store`
+    pub fn new(store: impl KvStore + 'static) -> Self {
+        Self {
+            store: Arc::new(store),
+            sequence: AtomicU64::new(0),
+        }
+    }
+}
+
+impl ArchiveSink for ContentStore {
+    fn archive(&self, response: ArchivedResponse) -> BoxFuture<'static, Result<()>> {
+        let store = self.store.clone();
+        let millis = response.time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+
+        Box::pin(async move {
+            let digest = sha256_hex(&response.body);
+            let body_key = format!("body/{}", digest);
+
+            if store.get(&body_key).await?.is_none() {
+                store.put(&body_key, response.body, None).await?;
+            }
+
+            let record = format!(
+                "{}\r\n{} {}\r\n{}\r\n",
+                response.uri, response.status.code(), response.meta, digest,
+            );
+            store.put(&format!("response/{}-{}", millis, sequence), record.into_bytes(), None).await
+        })
+    }
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    hex(ring::digest::digest(&ring::digest::SHA256, bytes).as_ref())
+}
+
+/// Middleware keeping a copy of every response sent, for auditing
+///
+/// Bodies are copied while they are being sent, so large files aren't held in memory
+/// just for archiving.  Responses are handed to the [`ArchiveSink`] once their body was
+/// sent completely, which happens in the background, so a slow sink doesn't delay the
+/// response.  Responses whose body couldn't be sent completely, for example because
+/// the client disconnected, are not archived.
+///
+/// Only bodies of up to [`DEFAULT_MAX_ARCHIVED_BODY`] bytes and of `text/*` MIME types
+/// are archived by default.  Other responses are still archived, but without their
+/// body, see [`ArchivedResponse::body_excluded`].
+///
+/// ```no_run
+/// # use twinstar::{Server, GEMINI_PORT, middleware::{Archive, ArchiveDir}};
+/// # async fn run() -> anyhow::Result<()> {
+/// Server::bind(("localhost", GEMINI_PORT))
+///     .add_middleware(Archive::new(ArchiveDir::new("archive")).add_mime("image/*"))
+///     .serve()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Archive {
+    sink: Arc<dyn ArchiveSink>,
+    max_body: usize,
+    mimes: Vec<String>,
+}
+
+impl Archive {
+    /// Archive responses into `sink`
+    pub fn new(sink: impl ArchiveSink) -> Self {
+        Self {
+            sink: Arc::new(sink),
+            max_body: DEFAULT_MAX_ARCHIVED_BODY,
+            mimes: vec!["text/*".to_owned()],
+        }
+    }
+
+    /// Set the size of the largest body which is archived
+    pub fn set_max_body_size(mut self, max_body: usize) -> Self {
+        self.max_body = max_body;
+        self
+    }
+
+    /// Also archive bodies of the given MIME type
+    ///
+    /// This is either a full MIME type like `application/pdf`, or a pattern like
+    /// `image/*` matching all subtypes.
+    pub fn add_mime(mut self, mime: impl Into<String>) -> Self {
+        self.mimes.push(mime.into());
+        self
+    }
+
+    /// Archive bodies of none but the given MIME types, see [`add_mime()`](Self::add_mime())
+    pub fn set_mimes<S: Into<String>>(mut self, mimes: impl IntoIterator<Item = S>) -> Self {
+        self.mimes = mimes.into_iter().map(Into::into).collect();
+        self
+    }
+}
+
+impl Middleware for Archive {
+    fn handle(&self, request: Request, next: Next) -> HandlerResponse {
+        let time = SystemTime::now();
+        let uri = request.uri().to_string();
+        let sink = self.sink.clone();
+        let max_body = self.max_body;
+        let mimes = self.mimes.clone();
+        let response = next.run(request);
+
+        Box::pin(async move {
+            let mut response = response.await?;
+            let header = response.header().clone();
+            let archived = ArchivedResponse {
+                time,
+                uri,
+                status: header.status,
+                meta: header.meta.as_str().to_owned(),
+                body: Vec::new(),
+                body_excluded: false,
+            };
+
+            let body = match response.take_body() {
+                Some(body) => body,
+                None => {
+                    spawn_archive(sink, archived);
+                    return Ok(response);
+                },
+            };
+
+            let response = Response::new(header);
+
+            if !wants_body(&mimes, &response) {
+                spawn_archive(sink, ArchivedResponse { body_excluded: true, ..archived });
+                return Ok(response.with_body(body));
+            }
+
+            let body = match body {
+                Body::Bytes(bytes) if bytes.len() > max_body => {
+                    spawn_archive(sink, ArchivedResponse { body_excluded: true, ..archived });
+                    Body::Bytes(bytes)
+                },
+                Body::Bytes(bytes) => {
+                    spawn_archive(sink, ArchivedResponse { body: bytes.clone(), ..archived });
+                    Body::Bytes(bytes)
+                },
+                Body::Inline(bytes) => {
+                    spawn_archive(sink, ArchivedResponse { body: bytes.to_vec(), ..archived });
+                    Body::Inline(bytes)
+                },
+                #[cfg(feature="mmap")]
+                Body::Mmap(mapped) if mapped.len() > max_body => {
+                    spawn_archive(sink, ArchivedResponse { body_excluded: true, ..archived });
+                    Body::Mmap(mapped)
+                },
+                #[cfg(feature="mmap")]
+                Body::Mmap(mapped) => {
+                    spawn_archive(sink, ArchivedResponse { body: mapped.to_vec(), ..archived });
+                    Body::Mmap(mapped)
+                },
+                Body::Reader(reader) => Body::Reader(Box::new(TeeReader {
+                    reader,
+                    captured: Vec::new(),
+                    max_body,
+                    pending: Some((sink, archived)),
+                })),
+            };
+
+            Ok(response.with_body(body))
+        })
+    }
+}
+
+/// Whether the body of a response should be archived, according to the MIME patterns
+fn wants_body(mimes: &[String], response: &Response) -> bool {
+    let header = response.header();
+
+    if !header.status.is_success() {
+        return false;
+    }
+
+    let mime = match header.meta.to_mime() {
+        Ok(mime) => mime,
+        Err(_) => return false,
+    };
+
+    mimes.iter().any(|pattern| match pattern.strip_suffix("/*") {
+        Some(type_) => mime.type_().as_str().eq_ignore_ascii_case(type_),
+        None => mime.essence_str().eq_ignore_ascii_case(pattern),
+    })
+}
+
+fn spawn_archive(sink: Arc<dyn ArchiveSink>, response: ArchivedResponse) {
+    tokio::spawn(async move {
+        if let Err(err) = sink.archive(response).await {
+            warn!("Failed to archive response: {:?}", err);
+        }
+    });
+}
+
+/// A reader copying everything read from it, and archiving it once it's exhausted
+struct TeeReader {
+    reader: Box<dyn AsyncRead + Send + Sync + Unpin>,
+    captured: Vec<u8>,
+    max_body: usize,
+    pending: Option<(Arc<dyn ArchiveSink>, ArchivedResponse)>,
+}
+
+impl AsyncRead for TeeReader {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.reader).poll_read(cx, buf);
+
+        if let Poll::Ready(Ok(())) = poll {
+            let read = &buf.filled()[before..];
+
+            if read.is_empty() {
+                if let Some((sink, mut archived)) = self.pending.take() {
+                    archived.body = std::mem::take(&mut self.captured);
+                    spawn_archive(sink, archived);
+                }
+            } else if self.pending.is_some() {
+                if self.captured.len() + read.len() > self.max_body {
+                    if let Some((sink, archived)) = self.pending.take() {
+                        self.captured = Vec::new();
+                        spawn_archive(sink, ArchivedResponse { body_excluded: true, ..archived });
+                    }
+                } else {
+                    self.captured.extend_from_slice(read);
+                }
+            }
+        }
+
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use tokio::io::AsyncReadExt;
+    use super::*;
+    use crate::storage::MemoryStore;
+
+    #[derive(Clone, Default)]
+    struct Collect(Arc<Mutex<Vec<ArchivedResponse>>>);
+
+    impl ArchiveSink for Collect {
+        fn archive(&self, response: ArchivedResponse) -> BoxFuture<'static, Result<()>> {
+            self.0.lock().unwrap().push(response);
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    fn archived(status: Status, meta: &str) -> ArchivedResponse {
+        ArchivedResponse {
+            time: UNIX_EPOCH,
+            uri: "gemini://localhost/".to_owned(),
+            status,
+            meta: meta.to_owned(),
+            body: Vec::new(),
+            body_excluded: false,
+        }
+    }
+
+    #[test]
+    fn filters_by_mime() {
+        let mimes = ["text/*".to_owned(), "application/pdf".to_owned()];
+
+        assert!(wants_body(&mimes, &Response::success_gemini("# Hi")));
+        assert!(wants_body(&mimes, &Response::success(&"application/pdf".parse().unwrap(), "")));
+        assert!(!wants_body(&mimes, &Response::success(&mime::IMAGE_PNG, "")));
+        assert!(!wants_body(&mimes, &Response::not_found()));
+    }
+
+    #[tokio::test]
+    async fn tees_streamed_bodies() {
+        let sink = Collect::default();
+        let mut reader = TeeReader {
+            reader: Box::new(&b"streamed body"[..]),
+            captured: Vec::new(),
+            max_body: 1024,
+            pending: Some((Arc::new(sink.clone()), archived(Status::SUCCESS, "text/plain"))),
+        };
+
+        let mut sent = Vec::new();
+        reader.read_to_end(&mut sent).await.unwrap();
+        while sink.0.lock().unwrap().is_empty() {
+            tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+        }
+
+        let archived = sink.0.lock().unwrap();
+        assert_eq!(sent, b"streamed body");
+        assert_eq!(archived.len(), 1);
+        assert_eq!(archived[0].body, b"streamed body");
+        assert_eq!(archived[0].to_transcript(), b"gemini://localhost/\r\n20 text/plain\r\nstreamed body");
+    }
+
+    #[tokio::test]
+    async fn deduplicates_bodies() {
+        let store = Arc::new(MemoryStore::new());
+        let sink = ContentStore::new(store.clone());
+
+        for _ in 0..2 {
+            let response = ArchivedResponse { body: b"same".to_vec(), ..archived(Status::SUCCESS, "text/plain") };
+            sink.archive(response).await.unwrap();
+        }
+
+        assert_eq!(store.scan("body/").await.unwrap().len(), 1);
+        assert_eq!(store.scan("response/").await.unwrap().len(), 2);
+    }
+}
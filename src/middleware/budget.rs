@@ -0,0 +1,167 @@
+use std::time::Duration;
+
+use anyhow::anyhow;
+use tokio::time;
+
+use crate::types::{Request, Response, ResponseHeader};
+use crate::util::Deadline;
+use crate::HandlerResponse;
+use super::{Middleware, Next};
+
+/// Middleware answering requests which take too long with a "please retry" response
+///
+/// Gemini has no way to tell a client that a response is on its way, so clients
+/// waiting for an expensive response may give up, or leave their user staring at a
+/// blank screen.  This middleware gives every request a soft time budget.  Handlers can
+/// check how much of it is left using the [`Deadline`] attached to the request.  If
+/// the handler hasn't responded once the budget is used up, the client is answered with
+/// `41 SERVER UNAVAILABLE` and a message asking them to retry shortly.
+///
+/// By default the handler keeps running in the background after the client was
+/// answered, so handlers caching their results can answer the retry quickly.  This can
+/// be turned off using [`set_finish_in_background()`](Self::set_finish_in_background()),
+/// in which case handlers are cancelled.
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use twinstar::{Server, GEMINI_PORT, ResponseHeader, middleware::Budget};
+/// # async fn run() -> anyhow::Result<()> {
+/// Server::bind(("localhost", GEMINI_PORT))
+///     .add_middleware(
+///         Budget::new(Duration::from_secs(3))
+///             .set_response(ResponseHeader::slow_down(10))
+///     )
+///     .serve()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Budget {
+    budget: Duration,
+    response: ResponseHeader,
+    finish_in_background: bool,
+}
+
+impl Budget {
+    /// Give every request `budget` to be answered in
+    pub fn new(budget: Duration) -> Self {
+        Self {
+            budget,
+            response: ResponseHeader::server_unavailable_lossy("Still working on it, please retry shortly"),
+            finish_in_background: true,
+        }
+    }
+
+    /// Set the header requests exceeding the budget are answered with
+    ///
+    /// [`ResponseHeader::slow_down()`] is a good alternative to the default, telling
+    /// clients exactly how long to wait.
+    pub fn set_response(mut self, response: ResponseHeader) -> Self {
+        self.response = response;
+        self
+    }
+
+    /// Set whether handlers keep running after their budget is used up
+    pub fn set_finish_in_background(mut self, finish_in_background: bool) -> Self {
+        self.finish_in_background = finish_in_background;
+        self
+    }
+}
+
+impl Middleware for Budget {
+    fn handle(&self, mut request: Request, next: Next) -> HandlerResponse {
+        let budget = self.budget;
+        let exceeded = self.response.clone();
+        let finish_in_background = self.finish_in_background;
+        let uri = request.uri().to_string();
+
+        request.extensions_mut().insert(Deadline::after(budget));
+        let response = next.run(request);
+
+        Box::pin(async move {
+            let response = if finish_in_background {
+                let handle = tokio::spawn(response);
+
+                time::timeout(budget, handle).await
+                    .map(|joined| joined.unwrap_or_else(|err| Err(anyhow!("Handler panicked: {}", err))))
+            } else {
+                time::timeout(budget, response).await
+            };
+
+            response.unwrap_or_else(|_| {
+                debug!("Handler exceeded its budget of {:?}: {}", budget, uri);
+                Ok(Response::new(exceeded))
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use super::*;
+    use crate::routing::RoutingNode;
+    use crate::types::Status;
+    use crate::uri::URIReference;
+    use crate::Handler;
+
+    fn request(path: &str) -> Request {
+        let uri = URIReference::try_from(format!("gemini://localhost{}", path).as_str()).unwrap().into_owned();
+        Request::from_uri(uri).unwrap()
+    }
+
+    fn next(done: Arc<AtomicBool>) -> Next {
+        let mut routes = RoutingNode::<Handler>::default();
+
+        routes.add_route("/quick", Arc::new(|request: Request| Box::pin(async move {
+            assert!(Deadline::of(&request).is_some());
+            Ok(Response::success_plain("quick"))
+        }) as HandlerResponse));
+
+        routes.add_route("/slow", Arc::new(move |_: Request| {
+            let done = done.clone();
+            Box::pin(async move {
+                time::sleep(Duration::from_millis(50)).await;
+                done.store(true, Ordering::SeqCst);
+                Ok(Response::success_plain("slow"))
+            }) as HandlerResponse
+        }));
+
+        Next::new(Arc::from(Vec::new()), Arc::new(routes))
+    }
+
+    #[tokio::test]
+    async fn answers_slow_requests() {
+        let done = Arc::new(AtomicBool::new(false));
+        let budget = Budget::new(Duration::from_millis(10));
+
+        let response = budget.handle(request("/quick"), next(done.clone())).await.unwrap();
+        assert_eq!(response.header().status, Status::SUCCESS);
+
+        let response = budget.handle(request("/slow"), next(done.clone())).await.unwrap();
+        assert_eq!(response.header().status, Status::SERVER_UNAVAILABLE);
+        assert!(!done.load(Ordering::SeqCst));
+
+        // The handler keeps running in the background
+        time::sleep(Duration::from_millis(100)).await;
+        assert!(done.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn cancels_slow_requests() {
+        let done = Arc::new(AtomicBool::new(false));
+        let budget = Budget::new(Duration::from_millis(10))
+            .set_finish_in_background(false)
+            .set_response(ResponseHeader::slow_down(3));
+
+        let response = budget.handle(request("/slow"), next(done.clone())).await.unwrap();
+        assert_eq!(response.header().status, Status::SLOW_DOWN);
+        assert_eq!(response.header().meta.as_str(), "3");
+
+        time::sleep(Duration::from_millis(100)).await;
+        assert!(!done.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,182 @@
+use std::time::SystemTime;
+
+use crate::util::rfc3339;
+use crate::types::{Body, Request, Response};
+use crate::HandlerResponse;
+use super::{Middleware, Next};
+
+/// Middleware appending a standard footer to every gemtext response
+///
+/// The footer is added to the body of all successful `text/gemini` responses, whether
+/// they are generated documents or files, so handlers don't need to know about it.  It
+/// is separated from the page by a blank line and can contain:
+///
+/// * when the response was generated, e.g. `Generated 2020-12-05T13:37:00Z` (on by
+///   default)
+/// * the version of twinstar, e.g. `twinstar 0.4.0` (on by default)
+/// * a link to the license of the content (off by default)
+/// * any further lines of gemtext
+///
+/// ```no_run
+/// # use twinstar::{Server, GEMINI_PORT, middleware::Footer};
+/// # async fn run() -> anyhow::Result<()> {
+/// Server::bind(("localhost", GEMINI_PORT))
+///     .add_middleware(
+///         Footer::new()
+///             .set_license("https://creativecommons.org/licenses/by/4.0/", "CC BY 4.0")
+///     )
+///     .serve()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Footer {
+    timestamp: bool,
+    server_version: bool,
+    license: Option<(String, String)>,
+    lines: Vec<String>,
+}
+
+impl Footer {
+    /// Create a footer containing the generation time and server version
+    pub fn new() -> Self {
+        Self {
+            timestamp: true,
+            server_version: true,
+            license: None,
+            lines: Vec::new(),
+        }
+    }
+
+    /// Set whether the time the response was generated is included
+    pub fn set_timestamp(mut self, timestamp: bool) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// Set whether the twinstar version is included
+    pub fn set_server_version(mut self, server_version: bool) -> Self {
+        self.server_version = server_version;
+        self
+    }
+
+    /// Link to the license the content is published under
+    pub fn set_license(mut self, uri: impl Into<String>, name: impl Into<String>) -> Self {
+        self.license = Some((uri.into(), name.into()));
+        self
+    }
+
+    /// Add a line of gemtext to the end of the footer
+    pub fn add_line(mut self, line: impl Into<String>) -> Self {
+        self.lines.push(line.into());
+        self
+    }
+
+    /// Render the footer for a response generated at `time`
+    fn render(&self, time: SystemTime) -> String {
+        let mut generated = Vec::new();
+
+        if self.timestamp {
+            generated.push(format!("Generated {}", rfc3339(time)));
+        }
+
+        if self.server_version {
+            generated.push(format!("twinstar {}", env!("CARGO_PKG_VERSION")));
+        }
+
+        let mut footer = String::from("\n");
+
+        if !generated.is_empty() {
+            footer.push_str(&generated.join(" · "));
+            footer.push('\n');
+        }
+
+        if let Some((uri, name)) = &self.license {
+            footer.push_str(&format!("=> {} License: {}\n", uri, name));
+        }
+
+        for line in &self.lines {
+            footer.push_str(line);
+            footer.push('\n');
+        }
+
+        footer
+    }
+}
+
+impl Default for Footer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether a response carries a gemtext body a footer should be added to
+fn is_gemtext(response: &Response) -> bool {
+    let header = response.header();
+
+    header.status.is_success() && header.meta.to_mime()
+        .map(|mime| mime.essence_str() == "text/gemini")
+        .unwrap_or(false)
+}
+
+impl Middleware for Footer {
+    fn handle(&self, request: Request, next: Next) -> HandlerResponse {
+        let footer = self.render(SystemTime::now());
+        let response = next.run(request);
+
+        Box::pin(async move {
+            let response = response.await?;
+
+            if !is_gemtext(&response) {
+                return Ok(response);
+            }
+
+            Ok(response.map_body(|body| {
+                // Make sure the footer starts on a line of its own
+                let body = match body {
+                    Body::Bytes(mut bytes) if !bytes.ends_with(b"\n") && !bytes.is_empty() => {
+                        bytes.push(b'\n');
+                        Body::Bytes(bytes)
+                    },
+                    Body::Inline(mut bytes) if !bytes.ends_with(b"\n") && !bytes.is_empty() => {
+                        bytes.push(b'\n');
+                        Body::Inline(bytes)
+                    },
+                    body => body,
+                };
+
+                body.chain(footer)
+            }))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+    use super::*;
+
+    #[test]
+    fn renders_footer() {
+        let footer = Footer::new()
+            .set_server_version(false)
+            .set_license("https://example.org/license", "MIT")
+            .add_line("=> mailto:me@example.org Contact");
+
+        assert_eq!(footer.render(UNIX_EPOCH + Duration::from_secs(1_607_126_400)), "\
+            \n\
+            Generated 2020-12-05T00:00:00Z\n\
+            => https://example.org/license License: MIT\n\
+            => mailto:me@example.org Contact\n\
+        ");
+    }
+
+    #[test]
+    fn only_touches_gemtext() {
+        assert!(is_gemtext(&Response::success_gemini("# Hi")));
+        assert!(is_gemtext(&Response::success(&"text/gemini; lang=en".parse().unwrap(), "# Hi")));
+        assert!(!is_gemtext(&Response::success_plain("Hi")));
+        assert!(!is_gemtext(&Response::not_found()));
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::fingerprint::TlsFingerprint;
+use crate::types::{Request, Response};
+use crate::HandlerResponse;
+use super::{Middleware, Next};
+
+/// How many requests within [`DEFAULT_BURST_WINDOW`] make a client look like a crawler
+pub const DEFAULT_BURST_REQUESTS: usize = 20;
+
+/// The window in which [`DEFAULT_BURST_REQUESTS`] requests make a client look like a crawler
+pub const DEFAULT_BURST_WINDOW: Duration = Duration::from_secs(10);
+
+/// How long a client is treated as a crawler after it last looked like one, by default
+pub const DEFAULT_CRAWLER_MEMORY: Duration = Duration::from_secs(60 * 60);
+
+/// How long crawlers are first asked to wait by default
+pub const DEFAULT_BACKOFF: Duration = Duration::from_secs(30);
+
+/// The longest crawlers are asked to wait by default
+pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+/// How many clients are tracked at most
+const MAX_TRACKED: usize = 10_000;
+
+/// What crawlers get when requesting a protected path, see [`Polite::protect()`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CrawlerPolicy {
+    /// Answer with `44 SLOW DOWN`
+    ///
+    /// The delay starts out at the configured backoff, and doubles for every request
+    /// the crawler makes before the previous delay ran out, up to the maximum backoff.
+    SlowDown,
+    /// Serve a different path instead, e.g. a cheap static snapshot of a dynamic page
+    ///
+    /// The path must be percent encoded, and replaces the path of the request before
+    /// it is routed.  The query is kept.
+    Serve(String),
+}
+
+/// Middleware recognizing crawlers, and treating them politely but firmly
+///
+/// Crawlers and feed fetchers can request dynamic pages far more often than any human
+/// would.  This middleware guesses which clients are crawlers, and lets protected
+/// paths answer them differently, see [`CrawlerPolicy`].  A client is considered a
+/// crawler for a while after it
+///
+/// * sent a burst of requests, more than [`set_burst()`](Self::set_burst()) allows,
+/// * requested `/robots.txt`, which only crawlers do, or
+/// * connected with a [TLS fingerprint](crate::fingerprint) known to belong to a
+///   crawler, see [`add_known_fingerprint()`](Self::add_known_fingerprint()).
+///
+/// Handlers can check whether a request was made by a suspected crawler using
+/// [`Polite::is_crawler()`], e.g. to leave out expensive parts of a page.  Clients are
+/// tracked by address, so requests without one are always passed on unchanged.  Clones
+/// share the tracked clients.
+///
+/// ```no_run
+/// # use twinstar::{Server, GEMINI_PORT, middleware::{Polite, CrawlerPolicy}};
+/// # async fn run() -> anyhow::Result<()> {
+/// Server::bind(("localhost", GEMINI_PORT))
+///     .add_middleware(Polite::new()
+///         .protect("/search", CrawlerPolicy::SlowDown)
+///         .protect("/feed", CrawlerPolicy::Serve("/feed-snapshot.gmi".into())))
+///     .serve()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Polite {
+    routes: Vec<(String, CrawlerPolicy)>,
+    burst_requests: usize,
+    burst_window: Duration,
+    memory: Duration,
+    backoff: Duration,
+    max_backoff: Duration,
+    known_fingerprints: HashSet<String>,
+    peers: Arc<Mutex<HashMap<IpAddr, Peer>>>,
+}
+
+/// Marks requests of suspected crawlers
+#[derive(Debug)]
+struct Crawler;
+
+#[derive(Debug, Default)]
+struct Peer {
+    /// The most recent requests, within the burst window
+    recent: VecDeque<Instant>,
+    crawler_until: Option<Instant>,
+    /// Until when the crawler was asked to wait, and for how long
+    slowed_down: Option<(Instant, Duration)>,
+}
+
+impl Polite {
+    /// Create middleware recognizing crawlers, without protecting any paths yet
+    pub fn new() -> Self {
+        Self {
+            routes: Vec::new(),
+            burst_requests: DEFAULT_BURST_REQUESTS,
+            burst_window: DEFAULT_BURST_WINDOW,
+            memory: DEFAULT_CRAWLER_MEMORY,
+            backoff: DEFAULT_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            known_fingerprints: HashSet::new(),
+            peers: Arc::default(),
+        }
+    }
+
+    /// Apply `policy` to crawlers requesting `prefix` or anything below it
+    ///
+    /// When several prefixes match, the longest one wins.
+    pub fn protect(mut self, prefix: impl Into<String>, policy: CrawlerPolicy) -> Self {
+        let prefix = prefix.into();
+        self.routes.push((prefix.trim_end_matches('/').to_owned(), policy));
+        self
+    }
+
+    /// Consider clients sending more than `requests` requests within `window` crawlers
+    ///
+    /// The defaults are [`DEFAULT_BURST_REQUESTS`] and [`DEFAULT_BURST_WINDOW`].
+    pub fn set_burst(mut self, requests: usize, window: Duration) -> Self {
+        self.burst_requests = requests;
+        self.burst_window = window;
+        self
+    }
+
+    /// Set how long a client is treated as a crawler after it last looked like one
+    ///
+    /// The default is [`DEFAULT_CRAWLER_MEMORY`].
+    pub fn set_memory(mut self, memory: Duration) -> Self {
+        self.memory = memory;
+        self
+    }
+
+    /// Set the first and the longest delay crawlers are asked to wait
+    ///
+    /// The defaults are [`DEFAULT_BACKOFF`] and [`DEFAULT_MAX_BACKOFF`].
+    pub fn set_backoff(mut self, backoff: Duration, max_backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self.max_backoff = max_backoff.max(backoff);
+        self
+    }
+
+    /// Consider clients with the TLS fingerprint `hash` crawlers
+    ///
+    /// This takes the [`hash`](TlsFingerprint::hash) of a fingerprint, and requires
+    /// fingerprinting to be enabled on the server.
+    pub fn add_known_fingerprint(mut self, hash: impl Into<String>) -> Self {
+        self.known_fingerprints.insert(hash.into());
+        self
+    }
+
+    /// Whether the request was made by a suspected crawler
+    pub fn is_crawler(request: &Request) -> bool {
+        request.extensions().contains::<Crawler>()
+    }
+
+    /// The policy for crawlers requesting `path`, if it is protected
+    pub fn policy(&self, path: &str) -> Option<&CrawlerPolicy> {
+        self.routes.iter()
+            .filter(|(prefix, _)| {
+                path.strip_prefix(prefix.as_str())
+                    .map(|rest| rest.is_empty() || rest.starts_with('/') || prefix.is_empty())
+                    .unwrap_or(false)
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, policy)| policy)
+    }
+
+    /// Record a request, returning whether its client looks like a crawler
+    fn observe(&self, ip: IpAddr, path: &str, fingerprint: Option<&TlsFingerprint>, now: Instant) -> bool {
+        let mut peers = self.peers.lock().expect("twinstar BUG");
+
+        if !peers.contains_key(&ip) && peers.len() >= MAX_TRACKED {
+            let window = self.burst_window;
+            peers.retain(|_, peer| peer.is_active(now, window));
+
+            if peers.len() >= MAX_TRACKED {
+                return false;
+            }
+        }
+
+        let peer = peers.entry(ip).or_default();
+        while peer.recent.front().is_some_and(|time| now.saturating_duration_since(*time) > self.burst_window) {
+            peer.recent.pop_front();
+        }
+        peer.recent.push_back(now);
+
+        let suspicious = peer.recent.len() > self.burst_requests
+            || path == "/robots.txt"
+            || fingerprint.is_some_and(|fingerprint| self.known_fingerprints.contains(&fingerprint.hash));
+
+        // Only the window matters for bursts
+        if peer.recent.len() > self.burst_requests {
+            peer.recent.pop_front();
+        }
+
+        if suspicious {
+            peer.crawler_until = Some(now + self.memory);
+        }
+
+        peer.crawler_until.is_some_and(|until| until > now)
+    }
+
+    /// How long a crawler is asked to wait for its current request
+    fn backoff(&self, ip: IpAddr, now: Instant) -> Duration {
+        let mut peers = self.peers.lock().expect("twinstar BUG");
+        let peer = peers.entry(ip).or_default();
+
+        let backoff = match peer.slowed_down {
+            Some((until, backoff)) if until > now => (backoff * 2).min(self.max_backoff),
+            _ => self.backoff,
+        };
+
+        peer.slowed_down = Some((now + backoff, backoff));
+        backoff
+    }
+}
+
+impl Peer {
+    fn is_active(&self, now: Instant, window: Duration) -> bool {
+        let recent = self.recent.back().is_some_and(|time| now.saturating_duration_since(*time) <= window);
+        let crawler = self.crawler_until.is_some_and(|until| until > now);
+        let slowed_down = self.slowed_down.is_some_and(|(until, _)| until > now);
+
+        recent || crawler || slowed_down
+    }
+}
+
+impl Default for Polite {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Middleware for Polite {
+    fn handle(&self, mut request: Request, next: Next) -> HandlerResponse {
+        let ip = match request.remote_addr() {
+            Some(addr) => addr.ip(),
+            None => return next.run(request),
+        };
+
+        let now = Instant::now();
+        let path = request.uri().path().to_string();
+
+        if !self.observe(ip, &path, TlsFingerprint::of(&request), now) {
+            return next.run(request);
+        }
+
+        request.extensions_mut().insert(Crawler);
+
+        match self.policy(&path) {
+            Some(CrawlerPolicy::SlowDown) => {
+                let backoff = self.backoff(ip, now);
+                debug!("Asking suspected crawler {} to wait {:?}", ip, backoff);
+                Box::pin(async move { Ok(Response::slow_down(backoff.as_secs().max(1))) })
+            },
+            Some(CrawlerPolicy::Serve(variant)) => {
+                if let Err(e) = request.set_path(variant) {
+                    let e = e.context("Failed to serve crawler variant");
+                    return Box::pin(async move { Err(e) });
+                }
+
+                next.run(request)
+            },
+            None => next.run(request),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_crawlers() {
+        let polite = Polite::new()
+            .set_burst(3, Duration::from_secs(10))
+            .add_known_fingerprint("abc");
+        let now = Instant::now();
+        let human = IpAddr::from([10, 0, 0, 1]);
+        let crawler = IpAddr::from([10, 0, 0, 2]);
+        let fingerprinted = IpAddr::from([10, 0, 0, 3]);
+
+        for i in 0..3 {
+            assert!(!polite.observe(human, "/", None, now + Duration::from_secs(i * 5)));
+        }
+        assert!(!polite.observe(crawler, "/", None, now));
+        assert!(polite.observe(crawler, "/robots.txt", None, now));
+        assert!(polite.observe(crawler, "/", None, now + Duration::from_secs(60)));
+
+        let fingerprint = TlsFingerprint { ja3: String::new(), hash: "abc".into() };
+        assert!(polite.observe(fingerprinted, "/", Some(&fingerprint), now));
+
+        for _ in 0..3 {
+            polite.observe(human, "/", None, now + Duration::from_secs(20));
+        }
+        assert!(polite.observe(human, "/", None, now + Duration::from_secs(20)));
+    }
+
+    #[test]
+    fn backs_off() {
+        let polite = Polite::new()
+            .protect("/search", CrawlerPolicy::SlowDown)
+            .protect("/search/cached", CrawlerPolicy::Serve("/snapshot".into()))
+            .set_backoff(Duration::from_secs(10), Duration::from_secs(25));
+        let now = Instant::now();
+        let ip = IpAddr::from([10, 0, 0, 1]);
+
+        assert_eq!(polite.policy("/"), None);
+        assert_eq!(polite.policy("/search"), Some(&CrawlerPolicy::SlowDown));
+        assert_eq!(polite.policy("/search/cached/x"), Some(&CrawlerPolicy::Serve("/snapshot".into())));
+        assert_eq!(polite.policy("/searching"), None);
+
+        assert_eq!(polite.backoff(ip, now), Duration::from_secs(10));
+        assert_eq!(polite.backoff(ip, now + Duration::from_secs(5)), Duration::from_secs(20));
+        assert_eq!(polite.backoff(ip, now + Duration::from_secs(6)), Duration::from_secs(25));
+        assert_eq!(polite.backoff(ip, now + Duration::from_secs(60)), Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use anyhow::{Result, Context, bail, ensure};
+
+use crate::types::{Request, Response};
+use crate::uri::URIReference;
+use crate::HandlerResponse;
+use super::{Middleware, Next};
+
+/// What a path in a [`Redirects`] table leads to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Redirect {
+    /// Answer with `31 REDIRECT - PERMANENT` to a URI reference
+    Permanent(String),
+    /// Answer with `30 REDIRECT - TEMPORARY` to a URI reference
+    Temporary(String),
+    /// Serve the request as if this absolute path had been requested
+    Alias(String),
+}
+
+/// Middleware redirecting or aliasing paths listed in a table
+///
+/// Migrating a large capsule often leaves thousands of old paths which should keep
+/// working.  Instead of adding a route for each of them, they can be listed in a file
+/// which is loaded into a hash table at startup, so looking up a request costs the same
+/// no matter how many entries there are.
+///
+/// The file contains one entry per line, with the old path and its target separated by
+/// a tab, optionally followed by another tab and the kind of entry:
+///
+/// * `permanent` (the default) answers with `31 REDIRECT - PERMANENT` to the target,
+///   which may be any URI reference
+/// * `temporary` answers with `30 REDIRECT - TEMPORARY` to the target
+/// * `alias` serves the target, which must be an absolute path, without the client
+///   noticing
+///
+/// Empty lines and lines starting with `#` are ignored.  Paths are matched exactly as
+/// they appear in the request, i.e. percent encoded, ignoring a trailing slash.
+///
+/// ```text
+/// # Old blog
+/// /blog/2019/hello.gmi	/posts/hello.gmi
+/// /blog	/posts
+/// /feed.xml	/posts/atom.xml	alias
+/// /mirror	gemini://mirror.example.org/	temporary
+/// ```
+///
+/// Clones share the same table, so one can be kept around to [`reload()`](Self::reload())
+/// it after the file changed, e.g. on a signal.
+///
+/// ```no_run
+/// # use twinstar::{Server, GEMINI_PORT, middleware::Redirects};
+/// # async fn run() -> anyhow::Result<()> {
+/// let redirects = Redirects::from_file("redirects.tsv")?;
+///
+/// Server::bind(("localhost", GEMINI_PORT))
+///     .add_middleware(redirects.clone())
+///     .serve()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[allow(clippy::tabs_in_doc_comments)]
+#[derive(Debug, Clone)]
+pub struct Redirects {
+    source: Option<PathBuf>,
+    table: Arc<RwLock<Arc<HashMap<String, Redirect>>>>,
+}
+
+impl Redirects {
+    /// Load a table from a file in the format described above
+    pub fn from_file(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let table = load(&path)?;
+
+        Ok(Self {
+            source: Some(path),
+            table: Arc::new(RwLock::new(Arc::new(table))),
+        })
+    }
+
+    /// Parse a table in the format described above
+    ///
+    /// Tables created this way can't be reloaded.
+    pub fn parse(table: &str) -> Result<Self> {
+        Ok(Self {
+            source: None,
+            table: Arc::new(RwLock::new(Arc::new(parse(table)?))),
+        })
+    }
+
+    /// Load the file again, replacing the table of every clone
+    ///
+    /// If the file can't be loaded, the old table is kept and the error is returned.
+    pub fn reload(&self) -> Result<()> {
+        let source = match &self.source {
+            Some(source) => source,
+            None => bail!("Redirects weren't loaded from a file"),
+        };
+
+        let table = load(source)?;
+        *self.table.write().unwrap() = Arc::new(table);
+
+        Ok(())
+    }
+
+    /// Look up where a percent encoded path leads, if anywhere
+    pub fn lookup(&self, path: &str) -> Option<Redirect> {
+        let table = self.table.read().unwrap().clone();
+        table.get(normalize(path)).cloned()
+    }
+
+    /// The number of entries in the table
+    pub fn len(&self) -> usize {
+        self.table.read().unwrap().len()
+    }
+
+    /// Whether the table has no entries
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Middleware for Redirects {
+    fn handle(&self, mut request: Request, next: Next) -> HandlerResponse {
+        let path = request.uri().path().to_string();
+
+        let response = match self.lookup(&path) {
+            None => return next.run(request),
+            Some(Redirect::Permanent(target)) => Response::redirect_permanent_lossy(target.as_str()),
+            Some(Redirect::Temporary(target)) => Response::redirect_temporary_lossy(target.as_str()),
+            Some(Redirect::Alias(target)) => match request.set_path(&target) {
+                Ok(()) => return next.run(request),
+                Err(err) => {
+                    error!("Failed to alias {} to {}: {:?}", path, target, err);
+                    Response::not_found()
+                },
+            },
+        };
+
+        Box::pin(async move { Ok(response) })
+    }
+}
+
+/// Strip the trailing slash of a path, except for the root
+fn normalize(path: &str) -> &str {
+    match path.strip_suffix('/') {
+        Some(stripped) if !stripped.is_empty() => stripped,
+        _ => path,
+    }
+}
+
+fn load(path: &Path) -> Result<HashMap<String, Redirect>> {
+    let table = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read redirects from `{}`", path.display()))?;
+
+    parse(&table)
+        .with_context(|| format!("Failed to parse redirects from `{}`", path.display()))
+}
+
+fn parse(table: &str) -> Result<HashMap<String, Redirect>> {
+    let mut redirects = HashMap::new();
+
+    for (number, line) in table.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (path, redirect) = parse_entry(line)
+            .with_context(|| format!("Invalid redirect on line {}", number + 1))?;
+        ensure!(
+            redirects.insert(path.clone(), redirect).is_none(),
+            "Duplicate redirect for `{}` on line {}", path, number + 1,
+        );
+    }
+
+    Ok(redirects)
+}
+
+fn parse_entry(line: &str) -> Result<(String, Redirect)> {
+    let mut fields = line.split('\t');
+    let path = fields.next().unwrap_or_default();
+    let target = fields.next().context("Missing target, separate it from the path with a tab")?;
+    let kind = fields.next().unwrap_or("permanent");
+    ensure!(fields.next().is_none(), "Too many fields");
+
+    ensure!(path.starts_with('/'), "Path `{}` is not absolute", path);
+    URIReference::try_from(target)
+        .with_context(|| format!("Target `{}` is not a URI reference", target))?;
+
+    let redirect = match kind {
+        "permanent" => Redirect::Permanent(target.to_owned()),
+        "temporary" => Redirect::Temporary(target.to_owned()),
+        "alias" => {
+            ensure!(target.starts_with('/'), "Alias target `{}` is not an absolute path", target);
+            Redirect::Alias(target.to_owned())
+        },
+        _ => bail!("Unknown kind of redirect `{}`", kind),
+    };
+
+    Ok((normalize(path).to_owned(), redirect))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routing::RoutingNode;
+    use crate::types::Status;
+
+    const TABLE: &str = "\
+# Old blog
+/blog/2019/hello.gmi\t/posts/hello.gmi
+/blog/\t/posts
+
+/feed.xml\t/posts/atom.xml\talias
+/mirror\tgemini://mirror.example.org/\ttemporary
+";
+
+    fn request(uri: &str) -> Request {
+        Request::from_uri(URIReference::try_from(uri).unwrap().into_owned()).unwrap()
+    }
+
+    #[test]
+    fn parses_tables() {
+        let redirects = Redirects::parse(TABLE).unwrap();
+
+        assert_eq!(redirects.len(), 4);
+        assert_eq!(redirects.lookup("/blog"), Some(Redirect::Permanent("/posts".to_owned())));
+        assert_eq!(redirects.lookup("/blog/"), Some(Redirect::Permanent("/posts".to_owned())));
+        assert_eq!(redirects.lookup("/feed.xml"), Some(Redirect::Alias("/posts/atom.xml".to_owned())));
+        assert_eq!(redirects.lookup("/posts"), None);
+
+        let err = Redirects::parse("/a\t/b\n/c /d\n").unwrap_err();
+        assert_eq!(err.to_string(), "Invalid redirect on line 2");
+        assert!(Redirects::parse("/a\t/b\n/a\t/c\n").is_err());
+        assert!(Redirects::parse("/a\thttps://example.org\talias\n").is_err());
+        assert!(Redirects::parse("/a\t/b\tsometimes\n").is_err());
+        assert!(Redirects::parse(TABLE).unwrap().reload().is_err());
+    }
+
+    #[tokio::test]
+    async fn redirects_and_aliases() {
+        let mut routes = RoutingNode::<crate::Handler>::default();
+        routes.add_route("/posts/atom.xml", Arc::new(|request: Request| {
+            Box::pin(async move { Ok(Response::success_plain(request.uri().to_string())) }) as HandlerResponse
+        }));
+        let routes = Arc::new(routes);
+        let redirects = Redirects::parse(TABLE).unwrap();
+
+        let run = |uri: &str| {
+            let next = Next::new(Arc::from(Vec::new()), routes.clone());
+            redirects.handle(request(uri), next)
+        };
+
+        let response = run("gemini://localhost/blog/2019/hello.gmi").await.unwrap();
+        assert_eq!(response.header().status, Status::REDIRECT_PERMANENT);
+        assert_eq!(response.header().meta.as_str(), "/posts/hello.gmi");
+
+        let response = run("gemini://localhost/mirror").await.unwrap();
+        assert_eq!(response.header().status, Status::REDIRECT_TEMPORARY);
+
+        let response = run("gemini://localhost/feed.xml?latest").await.unwrap();
+        assert_eq!(response.header().status, Status::SUCCESS);
+    }
+}
@@ -0,0 +1,211 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ring::hmac;
+
+use crate::types::{Request, Response};
+use crate::HandlerResponse;
+use crate::util::hex;
+use super::{Middleware, Next};
+
+/// How long links are valid by default
+pub const DEFAULT_LINK_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Middleware only serving heavy content through links generated by the capsule itself
+///
+/// Gemini has no referrer, so there is no way to tell whether a request for a large
+/// file was made by following a link on one of your pages, or by a client which was
+/// sent the URI from somewhere else.  This middleware protects paths by requiring an
+/// expiring token in the query, which only the server can generate.  Pages link to
+/// protected content using [`sign()`](Self::sign()), and requests to protected paths
+/// without a valid token are answered with `59 BAD REQUEST`.
+///
+/// A path is protected if it is one of the protected prefixes, or below one of them.
+/// Tokens are bound to the exact path they were generated for, but not to the host.
+/// Clones share the secret, so handlers can keep a clone for signing links.
+///
+/// ```no_run
+/// # use twinstar::{Server, Request, Response, GEMINI_PORT, middleware::SignedLinks};
+/// # async fn run() -> anyhow::Result<()> {
+/// let links = SignedLinks::new(b"a long random secret").protect("/media");
+/// let page_links = links.clone();
+///
+/// Server::bind(("localhost", GEMINI_PORT))
+///     .add_middleware(links)
+///     .add_route("/", move |_: Request| {
+///         let video = page_links.sign("/media/talk.webm");
+///         Box::pin(async move {
+///             Ok(Response::success_gemini(format!("=> {} Watch the talk\n", video)))
+///         }) as _
+///     })
+///     .serve()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct SignedLinks {
+    key: hmac::Key,
+    prefixes: Vec<String>,
+    ttl: Duration,
+}
+
+impl SignedLinks {
+    /// Create middleware signing links with `secret`, without protecting any paths yet
+    ///
+    /// Links stay valid across restarts as long as the secret doesn't change, so it
+    /// should be loaded from configuration rather than generated on startup when
+    /// running multiple servers.
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            key: hmac::Key::new(hmac::HMAC_SHA256, secret),
+            prefixes: Vec::new(),
+            ttl: DEFAULT_LINK_TTL,
+        }
+    }
+
+    /// Require a signed link for `prefix` and everything below it, e.g. `/media`
+    pub fn protect(mut self, prefix: impl Into<String>) -> Self {
+        let prefix = prefix.into();
+        self.prefixes.push(prefix.trim_end_matches('/').to_owned());
+        self
+    }
+
+    /// Set how long generated links stay valid
+    ///
+    /// The default is [`DEFAULT_LINK_TTL`].
+    pub fn set_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Generate a link to `path` which is valid for the configured time
+    ///
+    /// The path must be given as it appears in the URI, i.e. percent encoded, and
+    /// must not contain a query.  The link is returned as an absolute path including
+    /// the token, e.g. `/media/talk.webm?1607126400.3f2a…`.
+    pub fn sign(&self, path: &str) -> String {
+        self.sign_until(path, SystemTime::now() + self.ttl)
+    }
+
+    /// Generate a link to `path` which is valid until `expires`
+    pub fn sign_until(&self, path: &str, expires: SystemTime) -> String {
+        let expires = unix_secs(expires);
+        let tag = hmac::sign(&self.key, &message(path, expires));
+
+        format!("{}?{}.{}", path, expires, hex(tag.as_ref()))
+    }
+
+    /// Whether `token` is a valid and unexpired token for `path` at time `now`
+    pub fn verify(&self, path: &str, token: &str, now: SystemTime) -> bool {
+        let (expires, tag) = match token.split_once('.') {
+            Some(parts) => parts,
+            None => return false,
+        };
+
+        let (expires, tag) = match (expires.parse::<u64>(), hex_decode(tag)) {
+            (Ok(expires), Some(tag)) => (expires, tag),
+            _ => return false,
+        };
+
+        expires > unix_secs(now) && hmac::verify(&self.key, &message(path, expires), &tag).is_ok()
+    }
+
+    /// Whether requests to `path` need a signed link
+    pub fn is_protected(&self, path: &str) -> bool {
+        self.prefixes.iter().any(|prefix| {
+            path.strip_prefix(prefix.as_str())
+                .map(|rest| rest.is_empty() || rest.starts_with('/') || prefix.is_empty())
+                .unwrap_or(false)
+        })
+    }
+
+    fn check(&self, request: &Request) -> bool {
+        let path = request.uri().path().to_string();
+
+        if !self.is_protected(&path) {
+            return true;
+        }
+
+        match request.uri().query() {
+            Some(token) => self.verify(&path, token.as_str(), SystemTime::now()),
+            None => false,
+        }
+    }
+}
+
+impl Middleware for SignedLinks {
+    fn handle(&self, request: Request, next: Next) -> HandlerResponse {
+        if !self.check(&request) {
+            return Box::pin(async move {
+                Ok(Response::bad_request_lossy("This link has expired, please reload the page linking to it"))
+            });
+        }
+
+        next.run(request)
+    }
+}
+
+/// The message authenticated by a token
+fn message(path: &str, expires: u64) -> Vec<u8> {
+    let mut message = expires.to_be_bytes().to_vec();
+    message.extend_from_slice(path.as_bytes());
+    message
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    if !hex.is_ascii() || !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use super::*;
+    use crate::uri::URIReference;
+
+    fn request(uri: &str) -> Request {
+        Request::from_uri(URIReference::try_from(uri).unwrap().into_owned()).unwrap()
+    }
+
+    #[test]
+    fn verifies_tokens() {
+        let links = SignedLinks::new(b"secret");
+        let now = UNIX_EPOCH + Duration::from_secs(1_607_126_400);
+        let link = links.sign_until("/media/talk.webm", now + Duration::from_secs(60));
+        let (path, token) = link.split_once('?').unwrap();
+
+        assert_eq!(path, "/media/talk.webm");
+        assert!(links.verify(path, token, now));
+        assert!(!links.verify(path, token, now + Duration::from_secs(60)));
+        assert!(!links.verify("/media/other.webm", token, now));
+        assert!(!SignedLinks::new(b"other secret").verify(path, token, now));
+        assert!(!links.verify(path, &token.replace("1607126460", "1607999999"), now));
+        assert!(!links.verify(path, "garbage", now));
+    }
+
+    #[test]
+    fn checks_protected_paths() {
+        let links = SignedLinks::new(b"secret").protect("/media/");
+
+        assert!(links.is_protected("/media"));
+        assert!(links.is_protected("/media/talk.webm"));
+        assert!(!links.is_protected("/mediagoblin"));
+
+        assert!(links.check(&request("gemini://localhost/")));
+        assert!(!links.check(&request("gemini://localhost/media/talk.webm")));
+        assert!(!links.check(&request("gemini://localhost/media/talk.webm?1.00")));
+
+        let link = links.sign("/media/talk.webm");
+        assert!(links.check(&request(&format!("gemini://localhost{}", link))));
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use crate::types::Request;
+use crate::HandlerResponse;
+use super::{Middleware, Next};
+
+/// What requests are bucketed by when assigning them to a variant
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BucketBy {
+    /// The client certificate, requests without one get the first variant
+    Certificate,
+    /// The IP address of the client
+    RemoteAddr,
+    /// The client certificate if there is one, otherwise the IP address
+    CertificateOrRemoteAddr,
+}
+
+/// Middleware assigning every request to one of several named variants
+///
+/// This is meant for experimenting with different layouts of a capsule.  Requests are
+/// bucketed deterministically by their client certificate or IP address, see
+/// [`BucketBy`], so a user keeps seeing the same variant for as long as they use the
+/// same certificate.  Each variant receives a share of the buckets proportional to its
+/// weight.  The bucketing also depends on the name of the experiment, so users aren't
+/// assigned to the same variants in every experiment.
+///
+/// Handlers look up the assignment using [`Variants::assigned()`].
+///
+/// ```no_run
+/// # use twinstar::{Server, Request, Response, GEMINI_PORT, middleware::Variants};
+/// # async fn run() -> anyhow::Result<()> {
+/// Server::bind(("localhost", GEMINI_PORT))
+///     .add_middleware(Variants::new("layout").add_variant("classic", 3).add_variant("compact", 1))
+///     .add_route("/", |request: Request| Box::pin(async move {
+///         let page = match Variants::assigned(&request, "layout") {
+///             Some("compact") => "# Home\n=> /posts Posts",
+///             _ => "# Welcome home!\n\nHave a look around:\n=> /posts Posts",
+///         };
+///         Ok(Response::success_gemini(page))
+///     }) as _)
+///     .serve()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Variants {
+    experiment: String,
+    variants: Vec<(String, u32)>,
+    bucket_by: BucketBy,
+}
+
+/// The variants a request was assigned to, by experiment
+#[derive(Debug, Default)]
+struct Assignments(HashMap<String, String>);
+
+impl Variants {
+    /// Create an experiment named `experiment`, without any variants yet
+    pub fn new(experiment: impl Into<String>) -> Self {
+        Self {
+            experiment: experiment.into(),
+            variants: Vec::new(),
+            bucket_by: BucketBy::CertificateOrRemoteAddr,
+        }
+    }
+
+    /// Add a variant receiving a share of requests proportional to `weight`
+    ///
+    /// Variants with a weight of `0` are never assigned.
+    pub fn add_variant(mut self, name: impl Into<String>, weight: u32) -> Self {
+        self.variants.push((name.into(), weight));
+        self
+    }
+
+    /// Set what requests are bucketed by
+    ///
+    /// The default is [`BucketBy::CertificateOrRemoteAddr`].
+    pub fn set_bucket_by(mut self, bucket_by: BucketBy) -> Self {
+        self.bucket_by = bucket_by;
+        self
+    }
+
+    /// The variant of `experiment` the request was assigned to by the middleware
+    pub fn assigned<'a>(request: &'a Request, experiment: &str) -> Option<&'a str> {
+        request.extensions()
+            .get::<Assignments>()?
+            .0
+            .get(experiment)
+            .map(String::as_str)
+    }
+
+    /// Pick the variant for a request, without recording it
+    pub fn assign(&self, request: &Request) -> Option<&str> {
+        let total = self.variants.iter()
+            .map(|(_, weight)| u64::from(*weight))
+            .sum::<u64>();
+
+        if total == 0 {
+            return None;
+        }
+
+        let bucket = match self.bucket_key(request) {
+            Some(key) => fnv1a(&[self.experiment.as_bytes(), b"\0", &key]) % total,
+            None => 0,
+        };
+
+        let mut start = 0;
+        for (name, weight) in &self.variants {
+            start += u64::from(*weight);
+            if bucket < start {
+                return Some(name);
+            }
+        }
+
+        None
+    }
+
+    fn bucket_key(&self, request: &Request) -> Option<Vec<u8>> {
+        let certificate = || request.peer_cert_der().map(<[u8]>::to_vec);
+        let remote_addr = || request.remote_addr().map(|addr| match addr.ip() {
+            IpAddr::V4(ip) => ip.octets().to_vec(),
+            IpAddr::V6(ip) => ip.octets().to_vec(),
+        });
+
+        match self.bucket_by {
+            BucketBy::Certificate => certificate(),
+            BucketBy::RemoteAddr => remote_addr(),
+            BucketBy::CertificateOrRemoteAddr => certificate().or_else(remote_addr),
+        }
+    }
+}
+
+impl Middleware for Variants {
+    fn handle(&self, mut request: Request, next: Next) -> HandlerResponse {
+        if let Some(variant) = self.assign(&request).map(str::to_owned) {
+            let extensions = request.extensions_mut();
+
+            if !extensions.contains::<Assignments>() {
+                extensions.insert(Assignments::default());
+            }
+
+            if let Some(assignments) = extensions.get_mut::<Assignments>() {
+                assignments.0.insert(self.experiment.clone(), variant);
+            }
+        }
+
+        next.run(request)
+    }
+}
+
+/// The 64 bit FNV-1a hash of the concatenation of `parts`
+///
+/// This is used instead of the standard library's hasher, since assignments need to
+/// stay the same across restarts and compiler versions.
+fn fnv1a(parts: &[&[u8]]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+
+    for byte in parts.iter().flat_map(|part| part.iter()) {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use super::*;
+    use crate::types::PeerCertificate;
+    use crate::uri::URIReference;
+
+    fn request(certificate: Option<&[u8]>, remote_addr: &str) -> Request {
+        let uri = URIReference::try_from("gemini://localhost/").unwrap().into_owned();
+        let certificate = certificate.map(|cert| PeerCertificate::from_der(cert.to_vec()));
+        let mut request = Request::with_certificate(uri, certificate).unwrap();
+        request.set_remote_addr(Some(remote_addr.parse().unwrap()));
+        request
+    }
+
+    #[test]
+    fn hashes_like_fnv1a() {
+        assert_eq!(fnv1a(&[]), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(&[b"fo", b"obar"]), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn assignments_are_sticky_and_weighted() {
+        let variants = Variants::new("layout")
+            .add_variant("a", 1)
+            .add_variant("never", 0)
+            .add_variant("b", 1);
+
+        let mut counts = HashMap::new();
+        for i in 0..1000u32 {
+            let request = request(Some(&i.to_be_bytes()), "127.0.0.1:1965");
+            let variant = variants.assign(&request).unwrap();
+
+            // Moving to another network doesn't change the variant
+            let moved = self::request(Some(&i.to_be_bytes()), "10.0.0.1:1965");
+            assert_eq!(variants.assign(&moved), Some(variant));
+
+            *counts.entry(variant.to_owned()).or_insert(0) += 1;
+        }
+
+        assert!(!counts.contains_key("never"));
+        assert!(counts["a"] > 400 && counts["b"] > 400);
+    }
+
+    #[test]
+    fn falls_back_to_first_variant() {
+        let variants = Variants::new("layout")
+            .add_variant("control", 1)
+            .add_variant("b", 1000)
+            .set_bucket_by(BucketBy::Certificate);
+
+        assert_eq!(variants.assign(&request(None, "127.0.0.1:1965")), Some("control"));
+    }
+}
@@ -0,0 +1,766 @@
+//! Hosting many users' capsules on one server
+//!
+//! [`MultiTenant`] turns twinstar into a small hosting daemon, in the style of the
+//! `~user` directories of shared servers.  Each [`Tenant`] gets a content root, served
+//! under `/~name/`, and optionally a directory of CGI scripts, a storage quota and an
+//! access log of their own.
+//!
+//! Tenants are either taken from a directory layout, see
+//! [`MultiTenant::from_directory()`], or from a config file, see
+//! [`MultiTenant::from_config()`]:
+//!
+//! ```text
+//! # Lines starting with `#` are ignored
+//! [alice]
+//! root = /home/alice/public_gemini
+//! cgi = /home/alice/cgi-bin
+//! quota = 50M
+//! log = /var/log/twinstar/alice.log
+//!
+//! [bob]
+//! root = /home/bob/public_gemini
+//! ```
+//!
+//! The requests, bytes served and CPU time of every tenant are tracked, see
+//! [`TenantMetrics`].
+//!
+//! The `examples/multi_tenant.rs` example is a ready to run daemon built on this.
+//!
+//! ```no_run
+//! # use twinstar::{Server, GEMINI_PORT};
+//! # use twinstar::multi_tenant::MultiTenant;
+//! # async fn run() -> anyhow::Result<()> {
+//! let tenants = MultiTenant::from_directory("/srv/gemini/users")?
+//!     .set_default_quota(Some(100 * 1024 * 1024))
+//!     .set_log_dir("/var/log/twinstar");
+//!
+//! Server::bind(("localhost", GEMINI_PORT))
+//!     .add_route("/", tenants.into_handler())
+//!     .serve()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! This requires the `serve_dir` feature.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{Result, Context, anyhow, bail, ensure};
+
+use crate::types::{Document, Request, Response, ResponseHeader, Status, document::HeadingLevel::*};
+use crate::logging::QueryRedaction;
+use crate::util::ServeDir;
+use crate::HandlerResponse;
+
+mod metrics;
+pub use self::metrics::{TenantMetrics, TenantUsage};
+use self::metrics::{Busy, TenantCounters};
+
+/// How long CGI scripts may run by default
+pub const DEFAULT_CGI_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The path segment under which a tenant's CGI scripts are served
+const CGI_SEGMENT: &str = "cgi-bin";
+
+/// How long a computed storage usage is trusted before it is computed again
+const USAGE_MAX_AGE: Duration = Duration::from_secs(60);
+
+/// A single user of a [`MultiTenant`] server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tenant {
+    name: String,
+    root: PathBuf,
+    cgi_dir: Option<PathBuf>,
+    quota: Option<u64>,
+    log_file: Option<PathBuf>,
+}
+
+impl Tenant {
+    /// A tenant called `name`, serving the files in `root` under `/~name/`
+    ///
+    /// Names may only contain ASCII letters, digits, `-`, `_` and `.`, and may not start
+    /// with a `.`.
+    pub fn new(name: impl Into<String>, root: impl Into<PathBuf>) -> Result<Self> {
+        let name = name.into();
+        ensure!(is_valid_name(&name), "Invalid tenant name `{}`", name);
+
+        Ok(Self {
+            name,
+            root: root.into(),
+            cgi_dir: None,
+            quota: None,
+            log_file: None,
+        })
+    }
+
+    /// Run the scripts in `cgi_dir` for requests to `/~name/cgi-bin/<script>`
+    ///
+    /// Scripts get the usual CGI environment variables, like `QUERY_STRING` and
+    /// `PATH_INFO`, and must print a complete Gemini response, header included.
+    pub fn set_cgi_dir(mut self, cgi_dir: impl Into<PathBuf>) -> Self {
+        self.cgi_dir = Some(cgi_dir.into());
+        self
+    }
+
+    /// Limit the size of the content root to `quota` bytes
+    ///
+    /// While the content root is larger, the tenant's capsule is answered with
+    /// `41 SERVER UNAVAILABLE`.  The size is computed at most once a minute.
+    pub fn set_quota(mut self, quota: u64) -> Self {
+        self.quota = Some(quota);
+        self
+    }
+
+    /// Append a line to `log_file` for every request to this tenant
+    pub fn set_log_file(mut self, log_file: impl Into<PathBuf>) -> Self {
+        self.log_file = Some(log_file.into());
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn cgi_dir(&self) -> Option<&Path> {
+        self.cgi_dir.as_deref()
+    }
+
+    pub const fn quota(&self) -> Option<u64> {
+        self.quota
+    }
+
+    pub fn log_file(&self) -> Option<&Path> {
+        self.log_file.as_deref()
+    }
+}
+
+/// Serves the capsules of many [`Tenant`]s, see the [module documentation](self)
+#[derive(Debug, Clone, Default)]
+pub struct MultiTenant {
+    tenants: BTreeMap<String, Tenant>,
+    default_quota: Option<u64>,
+    log_dir: Option<PathBuf>,
+    cgi_timeout: Option<Duration>,
+    metrics: TenantMetrics,
+}
+
+impl MultiTenant {
+    /// A server without any tenants yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take the tenants from a directory layout
+    ///
+    /// Every subdirectory of `base` which contains a `public_gemini` directory is a
+    /// tenant named after the subdirectory, with `public_gemini` as its content root.  A
+    /// `cgi-bin` directory next to `public_gemini` is used as the tenant's CGI directory.
+    ///
+    /// Subdirectories with names which aren't valid tenant names are skipped.  Quotas
+    /// and logs can be configured using [`set_default_quota()`](Self::set_default_quota())
+    /// and [`set_log_dir()`](Self::set_log_dir()).
+    pub fn from_directory(base: impl AsRef<Path>) -> Result<Self> {
+        let base = base.as_ref();
+        let entries = std::fs::read_dir(base)
+            .with_context(|| format!("Failed to read tenant directory `{}`", base.display()))?;
+        let mut this = Self::new();
+
+        for entry in entries {
+            let entry = entry.context("Failed to read tenant directory entry")?;
+            let path = entry.path();
+            let root = path.join("public_gemini");
+
+            let name = match entry.file_name().into_string() {
+                Ok(name) if is_valid_name(&name) && root.is_dir() => name,
+                _ => continue,
+            };
+
+            let mut tenant = Tenant::new(name, root)?;
+            let cgi_dir = path.join(CGI_SEGMENT);
+            if cgi_dir.is_dir() {
+                tenant = tenant.set_cgi_dir(cgi_dir);
+            }
+
+            this = this.add_tenant(tenant)?;
+        }
+
+        Ok(this)
+    }
+
+    /// Take the tenants from a config file, see the [module documentation](self)
+    ///
+    /// Each tenant starts with its name in brackets, followed by `key = value` lines.
+    /// `root` is required, while `cgi`, `quota` and `log` are optional.  Quotas are
+    /// given in bytes, optionally followed by `K`, `M` or `G`.
+    pub fn from_config(config: &str) -> Result<Self> {
+        let mut tenants = Vec::<(String, BTreeMap<&str, &str>)>::new();
+
+        for (index, line) in config.lines().enumerate() {
+            let line = line.trim();
+            let context = || format!("Invalid tenant config on line {}", index + 1);
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                tenants.push((name.trim().to_owned(), BTreeMap::new()));
+                continue;
+            }
+
+            let (key, value) = line.split_once('=')
+                .ok_or_else(|| anyhow!("Expected `key = value` or `[name]`"))
+                .with_context(context)?;
+            let (_, settings) = tenants.last_mut()
+                .ok_or_else(|| anyhow!("Setting outside of a tenant"))
+                .with_context(context)?;
+            settings.insert(key.trim(), value.trim());
+        }
+
+        let mut this = Self::new();
+
+        for (name, mut settings) in tenants {
+            let context = || format!("Invalid config for tenant `{}`", name);
+            let root = settings.remove("root")
+                .ok_or_else(|| anyhow!("Missing `root`"))
+                .with_context(context)?;
+            let mut tenant = Tenant::new(name.as_str(), root)?;
+
+            if let Some(cgi_dir) = settings.remove("cgi") {
+                tenant = tenant.set_cgi_dir(cgi_dir);
+            }
+            if let Some(quota) = settings.remove("quota") {
+                tenant = tenant.set_quota(parse_size(quota).with_context(context)?);
+            }
+            if let Some(log_file) = settings.remove("log") {
+                tenant = tenant.set_log_file(log_file);
+            }
+            if let Some(key) = settings.keys().next() {
+                return Err(anyhow!("Unknown setting `{}`", key)).with_context(context);
+            }
+
+            this = this.add_tenant(tenant)?;
+        }
+
+        Ok(this)
+    }
+
+    /// Read the tenants from the config file at `path`, see [`from_config()`](Self::from_config())
+    pub fn from_config_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let config = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read tenant config `{}`", path.display()))?;
+
+        Self::from_config(&config)
+            .with_context(|| format!("Failed to load tenant config `{}`", path.display()))
+    }
+
+    /// Add a tenant, failing if there already is one with the same name
+    pub fn add_tenant(mut self, tenant: Tenant) -> Result<Self> {
+        ensure!(!self.tenants.contains_key(&tenant.name), "Duplicate tenant `{}`", tenant.name);
+        self.tenants.insert(tenant.name.clone(), tenant);
+        Ok(self)
+    }
+
+    /// Set the quota of tenants which don't have one of their own
+    ///
+    /// There is no default quota.
+    pub fn set_default_quota(mut self, quota: Option<u64>) -> Self {
+        self.default_quota = quota;
+        self
+    }
+
+    /// Log requests of tenants without a log file of their own to `<log_dir>/<name>.log`
+    pub fn set_log_dir(mut self, log_dir: impl Into<PathBuf>) -> Self {
+        self.log_dir = Some(log_dir.into());
+        self
+    }
+
+    /// Set how long CGI scripts may run before they are killed
+    ///
+    /// The default is [`DEFAULT_CGI_TIMEOUT`].
+    pub fn set_cgi_timeout(mut self, timeout: Duration) -> Self {
+        self.cgi_timeout = Some(timeout);
+        self
+    }
+
+    /// Track the resource usage of the tenants in `metrics`
+    ///
+    /// By default, the usage is tracked in a fresh set of counters, which can be
+    /// retrieved using [`metrics()`](Self::metrics()).
+    pub fn set_metrics(mut self, metrics: TenantMetrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// The counters tracking the resource usage of the tenants
+    pub fn metrics(&self) -> TenantMetrics {
+        self.metrics.clone()
+    }
+
+    /// The tenants, ordered by name
+    pub fn tenants(&self) -> impl Iterator<Item = &Tenant> {
+        self.tenants.values()
+    }
+
+    /// Turn this into a handler which can be passed to
+    /// [`Builder::add_route()`](crate::Builder::add_route())
+    ///
+    /// The handler lists all tenants at the route itself, and serves each tenant under
+    /// `~name/` below it.  Log files which can't be opened are reported, and skipped.
+    pub fn into_handler(self) -> impl Fn(Request) -> HandlerResponse + Send + Sync {
+        let Self { tenants, default_quota, log_dir, cgi_timeout, metrics } = self;
+        let cgi_timeout = cgi_timeout.unwrap_or(DEFAULT_CGI_TIMEOUT);
+        let tenants = tenants.into_iter()
+            .map(|(name, tenant)| {
+                let quota = tenant.quota.or(default_quota);
+                let log_file = tenant.log_file.clone()
+                    .or_else(|| log_dir.as_ref().map(|dir| dir.join(format!("{}.log", name))));
+                let log = log_file.and_then(|path| match open_log(&path) {
+                    Ok(file) => Some(Mutex::new(file)),
+                    Err(e) => {
+                        warn!("Not logging requests of tenant `{}`: {:?}", name, e);
+                        None
+                    },
+                });
+
+                let state = TenantState {
+                    counters: metrics.register(&name),
+                    serve_dir: ServeDir::new(&tenant.root),
+                    tenant,
+                    quota,
+                    usage: Mutex::new(None),
+                    log,
+                    query_redaction: QueryRedaction::default(),
+                };
+
+                (name, state)
+            })
+            .collect();
+        let served = Arc::new(Served { tenants, cgi_timeout });
+
+        move |request: Request| {
+            let served = served.clone();
+            Box::pin(async move { served.handle(request).await }) as HandlerResponse
+        }
+    }
+}
+
+/// The state of a [`MultiTenant`] handler
+struct Served {
+    tenants: BTreeMap<String, TenantState>,
+    cgi_timeout: Duration,
+}
+
+struct TenantState {
+    tenant: Tenant,
+    serve_dir: ServeDir,
+    quota: Option<u64>,
+    /// The size of the content root, and when it was computed
+    usage: Mutex<Option<(Instant, u64)>>,
+    log: Option<Mutex<File>>,
+    query_redaction: QueryRedaction,
+    counters: Arc<TenantCounters>,
+}
+
+impl Served {
+    async fn handle(&self, mut request: Request) -> Result<Response> {
+        let mut segments = request.trailing_segments().clone();
+
+        if segments.iter().all(String::is_empty) {
+            return Ok(self.index());
+        }
+
+        let state = match segments[0].strip_prefix('~').and_then(|name| self.tenants.get(name)) {
+            Some(state) => state,
+            None => return Ok(Response::not_found()),
+        };
+
+        // Relative links only work within `~name/`
+        if segments.len() == 1 {
+            let location = format!("{}/", segments[0]);
+            return Ok(Response::redirect_permanent_lossy(location.as_str()));
+        }
+
+        segments.remove(0);
+        let start = Instant::now();
+        let (response, busy) = Busy::new(state.handle(&mut request, segments, self.cgi_timeout)).await;
+        state.counters.add_busy(busy);
+        let response = response?;
+        state.log(&request, &response, start.elapsed());
+
+        Ok(state.counters.count(response))
+    }
+
+    fn index(&self) -> Response {
+        let mut document = Document::new();
+        document.add_heading(H1, "Capsules").add_blank_line();
+
+        for name in self.tenants.keys() {
+            document.add_link(format!("~{}/", name).as_str(), format!("~{}", name));
+        }
+
+        Response::success_gemini(document)
+    }
+}
+
+impl TenantState {
+    async fn handle(&self, request: &mut Request, segments: Vec<String>, cgi_timeout: Duration) -> Result<Response> {
+        if let Some(quota) = self.quota {
+            let usage = self.usage().await?;
+            if usage > quota {
+                return Ok(Response::server_unavailable_lossy("This capsule exceeds its storage quota"));
+            }
+        }
+
+        if let Some(cgi_dir) = &self.tenant.cgi_dir {
+            if segments[0] == CGI_SEGMENT {
+                return self.run_cgi(cgi_dir, request, &segments[1..], cgi_timeout).await;
+            }
+        }
+
+        request.set_trailing(segments);
+        self.serve_dir.serve_request(request).await
+    }
+
+    /// The size of the content root, computed again if it is too old
+    async fn usage(&self) -> Result<u64> {
+        if let Some((computed, usage)) = *self.usage.lock().expect("twinstar BUG") {
+            if computed.elapsed() < USAGE_MAX_AGE {
+                return Ok(usage);
+            }
+        }
+
+        let root = self.tenant.root.clone();
+        let usage = tokio::task::spawn_blocking(move || dir_size(&root))
+            .await
+            .context("Computing storage usage panicked")?
+            .with_context(|| format!("Failed to compute storage usage of tenant `{}`", self.tenant.name))?;
+        *self.usage.lock().expect("twinstar BUG") = Some((Instant::now(), usage));
+
+        Ok(usage)
+    }
+
+    async fn run_cgi(&self, cgi_dir: &Path, request: &Request, segments: &[String], timeout: Duration) -> Result<Response> {
+        let script_name = match segments.first() {
+            Some(name) if !name.is_empty() && !name.starts_with('.') => name,
+            _ => return Ok(Response::not_found()),
+        };
+
+        let script = cgi_dir.join(script_name);
+        if !script.is_file() {
+            return Ok(Response::not_found());
+        }
+
+        let env = cgi_env(&self.tenant.name, request, script_name, &segments[1..]);
+        let start = Instant::now();
+        let output = tokio::task::spawn_blocking(move || run_script(&script, env, timeout))
+            .await
+            .context("Running CGI script panicked")?;
+        self.counters.add_busy(start.elapsed());
+
+        let response = output.and_then(|output| parse_cgi_output(&output));
+        match response {
+            Ok(response) => Ok(response),
+            Err(e) => {
+                warn!("CGI script `{}` of tenant `{}` failed: {:?}", script_name, self.tenant.name, e);
+                Ok(Response::new(ResponseHeader::new(Status::CGI_ERROR, "CGI error")?))
+            },
+        }
+    }
+
+    fn log(&self, request: &Request, response: &Response, duration: Duration) {
+        let log = match &self.log {
+            Some(log) => log,
+            None => return,
+        };
+
+        let uri = self.query_redaction.redact(request);
+        self.query_redaction.observe(&uri, response.header().status);
+
+        let peer = request.remote_addr()
+            .map(|addr| addr.ip().to_string())
+            .unwrap_or_else(|| "-".to_owned());
+        let line = format!(
+            "{} - - [{}] \"{}\" {} {}ms",
+            peer,
+            crate::util::rfc3339(SystemTime::now()),
+            uri,
+            response.header().status.code(),
+            duration.as_millis(),
+        );
+
+        let mut log = log.lock().expect("twinstar BUG");
+        if let Err(e) = writeln!(log, "{}", line) {
+            warn!("Failed to log request of tenant `{}`: {}", self.tenant.name, e);
+        }
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Parse a size like `512`, `64K` or `10M`
+fn parse_size(size: &str) -> Result<u64> {
+    let (digits, unit) = match size.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((index, _)) => size.split_at(index),
+        None => (size, ""),
+    };
+
+    let factor = match unit.trim() {
+        "" => 1,
+        "K" | "k" => 1 << 10,
+        "M" | "m" => 1 << 20,
+        "G" | "g" => 1 << 30,
+        unit => bail!("Unknown size unit `{}`", unit),
+    };
+
+    digits.parse::<u64>().ok()
+        .and_then(|size| size.checked_mul(factor))
+        .ok_or_else(|| anyhow!("Invalid size `{}`", size))
+}
+
+fn open_log(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open log file `{}`", path.display()))
+}
+
+/// The total size of the files below `path`, not following symlinks
+fn dir_size(path: &Path) -> Result<u64> {
+    let mut size = 0;
+
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+
+        size += if metadata.is_dir() {
+            dir_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+
+    Ok(size)
+}
+
+/// The environment of a CGI script, following RFC 3875 where it applies to Gemini
+fn cgi_env(tenant: &str, request: &Request, script_name: &str, path_info: &[String]) -> Vec<(&'static str, String)> {
+    let uri = request.uri();
+    let mut env = vec![
+        ("GATEWAY_INTERFACE", "CGI/1.1".to_owned()),
+        ("SERVER_PROTOCOL", "GEMINI".to_owned()),
+        ("SERVER_SOFTWARE", format!("twinstar/{}", env!("CARGO_PKG_VERSION"))),
+        ("PATH", "/usr/local/bin:/usr/bin:/bin".to_owned()),
+        ("GEMINI_URL", uri.to_string()),
+        ("SCRIPT_NAME", format!("/~{}/{}/{}", tenant, CGI_SEGMENT, script_name)),
+        ("PATH_INFO", path_info.iter().fold(String::new(), |mut path, segment| {
+            let _ = write!(path, "/{}", segment);
+            path
+        })),
+        ("QUERY_STRING", uri.query().map(|query| query.to_string()).unwrap_or_default()),
+    ];
+
+    if let Some(host) = uri.host() {
+        env.push(("SERVER_NAME", host.to_string()));
+    }
+
+    if let Some(addr) = request.remote_addr() {
+        env.push(("REMOTE_ADDR", addr.ip().to_string()));
+        env.push(("REMOTE_HOST", addr.ip().to_string()));
+    }
+
+    if let Some(hash) = request.certificate_fingerprint() {
+        env.push(("AUTH_TYPE", "CERTIFICATE".to_owned()));
+        env.push(("TLS_CLIENT_HASH", hash));
+    }
+
+    env
+}
+
+/// Run a script to completion, killing it after `timeout`, and return its output
+fn run_script(script: &Path, env: Vec<(&'static str, String)>, timeout: Duration) -> Result<Vec<u8>> {
+    let mut child = Command::new(script)
+        .current_dir(script.parent().unwrap_or(script))
+        .env_clear()
+        .envs(env)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Failed to start `{}`", script.display()))?;
+
+    // Read concurrently, so a script filling the pipe doesn't block forever
+    let mut stdout = child.stdout.take().expect("twinstar BUG");
+    let reader = std::thread::spawn(move || {
+        let mut output = Vec::new();
+        stdout.read_to_end(&mut output).map(|_| output)
+    });
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("Timed out after {:?}", timeout);
+        }
+
+        std::thread::sleep(Duration::from_millis(10));
+    };
+
+    let output = reader.join()
+        .map_err(|_| anyhow!("Reading the output panicked"))?
+        .context("Failed to read the output")?;
+    ensure!(status.success(), "Exited with {}", status);
+
+    Ok(output)
+}
+
+/// Split the output of a CGI script into a response
+///
+/// The header may be terminated by a bare LF, which is easy to do by accident in a
+/// shell script.
+fn parse_cgi_output(output: &[u8]) -> Result<Response> {
+    let end = output.iter().position(|&byte| byte == b'\n')
+        .context("No response header")?;
+    let line = output[..end].strip_suffix(b"\r").unwrap_or(&output[..end]);
+
+    let header = crate::protocol::parse_header(&[line, b"\r\n"].concat())?;
+    let body = &output[end + 1..];
+
+    let response = Response::new(header);
+    Ok(if body.is_empty() { response } else { response.with_body(body) })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use super::*;
+    use crate::uri::URIReference;
+
+    #[test]
+    fn parses_config() {
+        let tenants = MultiTenant::from_config("
+            # users
+            [alice]
+            root = /home/alice/public_gemini
+            cgi = /home/alice/cgi-bin
+            quota = 50M
+
+            [bob]
+            root=/home/bob/public_gemini
+            log = /var/log/bob.log
+        ").unwrap();
+
+        let tenants = tenants.tenants().collect::<Vec<_>>();
+        assert_eq!(tenants.len(), 2);
+        assert_eq!(tenants[0].name(), "alice");
+        assert_eq!(tenants[0].cgi_dir(), Some(Path::new("/home/alice/cgi-bin")));
+        assert_eq!(tenants[0].quota(), Some(50 * 1024 * 1024));
+        assert_eq!(tenants[1].root(), Path::new("/home/bob/public_gemini"));
+        assert_eq!(tenants[1].log_file(), Some(Path::new("/var/log/bob.log")));
+
+        assert!(MultiTenant::from_config("root = /srv").is_err());
+        assert!(MultiTenant::from_config("[carol]\ncgi = /srv").is_err());
+        assert!(MultiTenant::from_config("[../etc]\nroot = /srv").is_err());
+        assert!(MultiTenant::from_config("[dave]\nroot = /srv\nquota = 1T").is_err());
+        assert!(MultiTenant::from_config("[dave]\nroot = /a\n[dave]\nroot = /b").is_err());
+    }
+
+    async fn get(handler: &impl Fn(Request) -> HandlerResponse, uri: &str) -> Response {
+        let uri = URIReference::try_from(uri).unwrap().into_owned();
+        let mut request = Request::from_uri(uri).unwrap();
+        let trailing = request.path_segments();
+        request.set_trailing(trailing);
+        handler(request).await.unwrap()
+    }
+
+    async fn body(mut response: Response) -> String {
+        use tokio::io::AsyncReadExt;
+        let mut body = String::new();
+        response.take_body().unwrap().into_reader().read_to_string(&mut body).await.unwrap();
+        body
+    }
+
+    #[tokio::test]
+    async fn serves_tenants() {
+        let temp = tempfile::tempdir().unwrap();
+        let base = temp.path();
+        let alice = base.join("alice");
+        std::fs::create_dir_all(alice.join("public_gemini")).unwrap();
+        std::fs::create_dir_all(base.join("bob/public_gemini")).unwrap();
+        std::fs::create_dir_all(base.join("not-a-tenant")).unwrap();
+        std::fs::write(alice.join("public_gemini/hello.gmi"), "# Hello").unwrap();
+        std::fs::write(base.join("bob/public_gemini/big.txt"), "x".repeat(100)).unwrap();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let script = alice.join("cgi-bin/greet");
+            std::fs::create_dir_all(alice.join("cgi-bin")).unwrap();
+            std::fs::write(&script, "#!/bin/sh\nprintf '20 text/plain\\n%s %s' \"$QUERY_STRING\" \"$PATH_INFO\"\n").unwrap();
+            std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        let tenants = MultiTenant::from_directory(base).unwrap()
+            .set_default_quota(Some(50))
+            .set_log_dir(base);
+        let metrics = tenants.metrics();
+        assert_eq!(tenants.tenants().map(Tenant::name).collect::<Vec<_>>(), ["alice", "bob"]);
+        let handler = tenants.into_handler();
+
+        let index = get(&handler, "gemini://localhost/").await;
+        assert!(body(index).await.contains("=> ~bob/"));
+
+        let response = get(&handler, "gemini://localhost/~alice").await;
+        assert_eq!(response.header().status, Status::REDIRECT_PERMANENT);
+
+        let response = get(&handler, "gemini://localhost/~alice/hello.gmi").await;
+        assert_eq!(body(response).await, "# Hello");
+
+        let response = get(&handler, "gemini://localhost/~bob/big.txt").await;
+        assert_eq!(response.header().status, Status::SERVER_UNAVAILABLE);
+
+        let response = get(&handler, "gemini://localhost/~carol/").await;
+        assert_eq!(response.header().status, Status::NOT_FOUND);
+
+        #[cfg(unix)]
+        {
+            let response = get(&handler, "gemini://localhost/~alice/cgi-bin/greet/a/b?hi").await;
+            assert_eq!(response.header().status, Status::SUCCESS);
+            assert_eq!(body(response).await, "hi /a/b");
+
+            let response = get(&handler, "gemini://localhost/~alice/cgi-bin/missing").await;
+            assert_eq!(response.header().status, Status::NOT_FOUND);
+        }
+
+        let alice = metrics.usage("alice").unwrap();
+        assert!(alice.requests >= 2);
+        assert!(alice.bytes_served >= 7);
+        assert_eq!(metrics.usage("bob").unwrap().requests, 1);
+
+        let log = std::fs::read_to_string(base.join("alice.log")).unwrap();
+        assert!(log.contains("\"gemini://localhost/~alice/hello.gmi\" 20"));
+    }
+}
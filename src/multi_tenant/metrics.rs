@@ -0,0 +1,243 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt::Write as _;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::Result;
+use anyhow::Context as _;
+use tokio::io::{self, AsyncRead, ReadBuf};
+
+use crate::types::{Body, Document, Response, document::HeadingLevel::*};
+
+/// Resource usage of each tenant of a [`MultiTenant`](super::MultiTenant) server
+///
+/// This is a cheap handle which can be cloned and read from anywhere.  Pass a clone to
+/// [`MultiTenant::set_metrics()`](super::MultiTenant::set_metrics()) and keep another
+/// one to read the usage from, or to periodically
+/// [write a summary](Self::write_summaries()) for the operator:
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use twinstar::{Server, GEMINI_PORT};
+/// # use twinstar::multi_tenant::{MultiTenant, TenantMetrics};
+/// # async fn run() -> anyhow::Result<()> {
+/// let metrics = TenantMetrics::new();
+/// let tenants = MultiTenant::from_directory("/srv/gemini/users")?
+///     .set_metrics(metrics.clone());
+///
+/// tokio::spawn(metrics.write_summaries("/srv/gemini/usage.gmi", Duration::from_secs(300)));
+///
+/// Server::bind(("localhost", GEMINI_PORT))
+///     .add_route("/", tenants.into_handler())
+///     .serve()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TenantMetrics {
+    tenants: Arc<Mutex<BTreeMap<String, Arc<TenantCounters>>>>,
+}
+
+#[derive(Debug, Default)]
+pub(super) struct TenantCounters {
+    requests: AtomicU64,
+    bytes_served: AtomicU64,
+    busy_nanos: AtomicU64,
+}
+
+/// The resources used by a single tenant, see [`TenantMetrics`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TenantUsage {
+    /// The name of the tenant
+    pub name: String,
+    /// The number of requests answered
+    pub requests: u64,
+    /// The number of body bytes handed to the server for sending
+    ///
+    /// Bodies cut short by clients disconnecting are only counted as far as they were
+    /// read.
+    pub bytes_served: u64,
+    /// An estimate of the CPU time spent on the tenant's requests
+    ///
+    /// This is the time spent actually running the handler, excluding time spent
+    /// waiting, plus the total run time of CGI scripts.
+    pub cpu_time: Duration,
+}
+
+impl TenantMetrics {
+    /// Create a new set of counters, without any tenants
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The usage of the tenant called `name`, if it is served
+    pub fn usage(&self, name: &str) -> Option<TenantUsage> {
+        let tenants = self.tenants.lock().expect("twinstar BUG");
+        tenants.get(name).map(|counters| counters.usage(name))
+    }
+
+    /// The usage of all tenants, ordered by name
+    pub fn all(&self) -> Vec<TenantUsage> {
+        let tenants = self.tenants.lock().expect("twinstar BUG");
+        tenants.iter()
+            .map(|(name, counters)| counters.usage(name))
+            .collect()
+    }
+
+    /// A gemtext summary of the usage of all tenants
+    pub fn summary(&self) -> Document {
+        let mut table = format!("{:<20} {:>10} {:>14} {:>12}\n", "tenant", "requests", "bytes", "cpu ms");
+        for usage in self.all() {
+            let _ = writeln!(
+                table,
+                "{:<20} {:>10} {:>14} {:>12}",
+                usage.name, usage.requests, usage.bytes_served, usage.cpu_time.as_millis(),
+            );
+        }
+
+        let mut document = Document::new();
+        document
+            .add_heading(H1, "Tenant usage")
+            .add_blank_line()
+            .add_text(format!("As of {}", crate::util::rfc3339(SystemTime::now())))
+            .add_blank_line()
+            .add_preformatted_with_alt("usage", table.trim_end());
+
+        document
+    }
+
+    /// Write the [`summary()`](Self::summary()) to `path` every `interval`, forever
+    ///
+    /// The file is replaced atomically, so it can be served while it is being updated.
+    /// This only returns if writing the summary fails.
+    pub async fn write_summaries(self, path: impl Into<PathBuf>, interval: Duration) -> Result<()> {
+        let path = path.into();
+        let temp_path = path.with_extension("tmp");
+
+        loop {
+            let summary = self.summary().to_string();
+            let written = std::fs::write(&temp_path, summary)
+                .and_then(|_| std::fs::rename(&temp_path, &path));
+            written.with_context(|| format!("Failed to write tenant usage to `{}`", path.display()))?;
+
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// The counters of the tenant called `name`, adding them if necessary
+    pub(super) fn register(&self, name: &str) -> Arc<TenantCounters> {
+        let mut tenants = self.tenants.lock().expect("twinstar BUG");
+        tenants.entry(name.to_owned()).or_default().clone()
+    }
+}
+
+impl TenantCounters {
+    fn usage(&self, name: &str) -> TenantUsage {
+        TenantUsage {
+            name: name.to_owned(),
+            requests: self.requests.load(Ordering::Relaxed),
+            bytes_served: self.bytes_served.load(Ordering::Relaxed),
+            cpu_time: Duration::from_nanos(self.busy_nanos.load(Ordering::Relaxed)),
+        }
+    }
+
+    pub(super) fn add_busy(&self, busy: Duration) {
+        let nanos = u64::try_from(busy.as_nanos()).unwrap_or(u64::MAX);
+        self.busy_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    /// Count a request, and the body of its response as it is read
+    pub(super) fn count(self: &Arc<Self>, response: Response) -> Response {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+
+        response.map_body(|body| match body.as_bytes() {
+            Some(bytes) => {
+                self.bytes_served.fetch_add(bytes.len() as u64, Ordering::Relaxed);
+                body
+            },
+            None => Body::Reader(Box::new(CountingReader {
+                inner: body.into_reader(),
+                counters: self.clone(),
+            })),
+        })
+    }
+}
+
+/// Counts the bytes read from a body
+struct CountingReader {
+    inner: Box<dyn AsyncRead + Send + Sync + Unpin>,
+    counters: Arc<TenantCounters>,
+}
+
+impl AsyncRead for CountingReader {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let read = buf.filled().len() - filled;
+        self.counters.bytes_served.fetch_add(read as u64, Ordering::Relaxed);
+        result
+    }
+}
+
+/// Measures how long a future spends being polled
+pub(super) struct Busy<F> {
+    inner: Pin<Box<F>>,
+    busy: Duration,
+}
+
+impl<F: Future> Busy<F> {
+    pub(super) fn new(inner: F) -> Self {
+        Self { inner: Box::pin(inner), busy: Duration::default() }
+    }
+}
+
+impl<F: Future> Future for Busy<F> {
+    type Output = (F::Output, Duration);
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let start = Instant::now();
+        let poll = self.inner.as_mut().poll(cx);
+        self.busy += start.elapsed();
+
+        poll.map(|output| (output, self.busy))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn counts_usage() {
+        let metrics = TenantMetrics::new();
+        let counters = metrics.register("alice");
+        metrics.register("bob");
+
+        let mut response = counters.count(Response::success_plain("hello"));
+        assert_eq!(response.take_body().unwrap().as_bytes(), Some(&b"hello"[..]));
+
+        let reader = Body::Reader(Box::new(&b"streamed"[..]));
+        let mut response = counters.count(Response::success_plain(reader));
+        let mut body = Vec::new();
+        response.take_body().unwrap().into_reader().read_to_end(&mut body).await.unwrap();
+
+        let ((), busy) = Busy::new(async {}).await;
+        counters.add_busy(busy + Duration::from_millis(3));
+
+        let alice = metrics.usage("alice").unwrap();
+        assert_eq!((alice.requests, alice.bytes_served), (2, 13));
+        assert!(alice.cpu_time >= Duration::from_millis(3));
+        assert_eq!(metrics.usage("bob").unwrap().requests, 0);
+        assert!(metrics.usage("carol").is_none());
+        assert_eq!(metrics.all().len(), 2);
+        assert!(metrics.summary().to_string().contains("alice"));
+    }
+}
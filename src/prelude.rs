@@ -0,0 +1,32 @@
+//! The most commonly used items, for glob importing
+//!
+//! A capsule usually only needs this one import:
+//!
+//! ```no_run
+//! # #[cfg(feature="handler")] {
+//! use twinstar::prelude::*;
+//!
+//! async fn index(_request: Request) -> anyhow::Result<Response> {
+//!     let mut document = Document::new();
+//!     document.add_heading(H1, "Welcome");
+//!
+//!     Ok(Response::success_gemini(document))
+//! }
+//!
+//! # async fn run() -> anyhow::Result<()> {
+//! Server::bind(("localhost", GEMINI_PORT))
+//!     .add_route("/", from_fn(index))
+//!     .serve()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! # }
+//! ```
+
+pub use crate::{Server, Builder, BoxedHandler, GEMINI_MIME, GEMINI_PORT};
+pub use crate::types::{Request, Response, IntoResponse, Status, Meta, Body, Document};
+pub use crate::types::document::HeadingLevel::{self, *};
+#[cfg(feature="handler")]
+pub use crate::handler::{HandlerExt, from_fn};
+#[cfg(feature="middleware")]
+pub use crate::middleware::{Middleware, Next};
@@ -0,0 +1,263 @@
+//! Reading requests and writing responses in the Gemini wire format
+//!
+//! The server uses these functions on every TLS connection, but they work on any
+//! stream.  This makes them useful for custom transports, like a server behind a TLS
+//! terminating proxy, and for test harnesses working on in-memory buffers:
+//!
+//! ```
+//! # use twinstar::{Response, protocol};
+//! # async fn run() -> anyhow::Result<()> {
+//! let request = protocol::read_request(&mut &b"gemini://localhost/\r\n"[..]).await?;
+//! assert_eq!(request.uri().path().to_string(), "/");
+//!
+//! let mut raw = Vec::new();
+//! protocol::write_response(Response::success_plain("hi"), &mut raw).await?;
+//! assert_eq!(raw, b"20 text/plain\r\nhi");
+//! # Ok(())
+//! # }
+//! # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(run()).unwrap();
+//! ```
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use anyhow::{Result, Context, bail};
+use tokio::io::{self, AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::types::{Body, HeaderFlush, Meta, Request, Response, ResponseHeader, Status, URIReference};
+use crate::REQUEST_URI_MAX_LEN;
+
+/// Read a request, i.e. a URI terminated by CRLF
+///
+/// At most [`REQUEST_URI_MAX_LEN`] bytes and the CRLF are read from the stream.  The
+/// returned request has no certificate, remote address or trailing segments set.
+///
+/// Requests breaking the specification fail with an error which can be downcast to
+/// [`MalformedRequest`], and should be answered with its
+/// [`response()`](MalformedRequest::response()).  Other errors come from the stream.
+pub async fn read_request(stream: &mut (impl AsyncBufRead + Unpin)) -> Result<Request> {
+    let limit = REQUEST_URI_MAX_LEN + "\r\n".len();
+    let mut stream = stream.take(limit as u64);
+    let mut uri = Vec::new();
+
+    stream.read_until(b'\n', &mut uri).await?;
+
+    parse_request(uri)
+}
+
+/// Parse the raw line read by [`read_request()`], including the CRLF
+pub(crate) fn parse_request(mut uri: Vec<u8>) -> Result<Request> {
+    if uri.is_empty() {
+        bail!("Connection closed before sending a request")
+    }
+
+    if !uri.ends_with(b"\r\n") {
+        if uri.len() < REQUEST_URI_MAX_LEN {
+            return Err(MalformedRequest::new("Request header not terminated with CRLF").into());
+        } else {
+            return Err(MalformedRequest::new("Request URI too long").into());
+        }
+    }
+
+    // Strip CRLF
+    uri.pop();
+    uri.pop();
+
+    let uri = URIReference::try_from(&*uri)
+        .map_err(|err| anyhow::Error::new(err).context(MalformedRequest::new("Request URI is invalid")))?
+        .into_owned();
+    let request = Request::from_uri(uri)?;
+
+    Ok(request)
+}
+
+/// The error [`read_request()`] fails with for requests breaking the specification
+///
+/// Unlike errors reading from the stream, these can be answered, with a
+/// `59 BAD REQUEST` telling the client what was wrong, instead of just closing the
+/// connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MalformedRequest {
+    reason: &'static str,
+}
+
+impl MalformedRequest {
+    const fn new(reason: &'static str) -> Self {
+        Self { reason }
+    }
+
+    /// What was wrong with the request, e.g. `Request URI too long`
+    pub const fn reason(&self) -> &'static str {
+        self.reason
+    }
+
+    /// The `59 BAD REQUEST` response telling the client what was wrong
+    pub fn response(&self) -> Response {
+        Response::bad_request_lossy(self.reason)
+    }
+}
+
+impl fmt::Display for MalformedRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.reason)
+    }
+}
+
+impl std::error::Error for MalformedRequest {}
+
+/// Write a complete response, returning the number of body bytes written
+///
+/// The header is flushed on its own unless the response asks for
+/// [`HeaderFlush::Coalesce`].  The stream is flushed at the end.
+pub async fn write_response(mut response: Response, stream: &mut (impl AsyncWrite + Unpin)) -> Result<u64> {
+    let body = response.take_body();
+    let flush = body.is_none() || response.header_flush().unwrap_or_default() == HeaderFlush::Immediately;
+
+    send_response_header(response.header(), flush, stream).await?;
+    maybe_send_response_body(body, stream).await
+}
+
+/// Write a response header, including the CRLF, and flush it
+pub async fn write_response_header(header: &ResponseHeader, stream: &mut (impl AsyncWrite + Unpin)) -> Result<()> {
+    send_response_header(header, true, stream).await
+}
+
+/// Write a response body and flush it, returning the number of bytes written
+pub async fn write_response_body(body: Body, stream: &mut (impl AsyncWrite + Unpin)) -> Result<u64> {
+    send_response_body(body, stream).await
+}
+
+/// A response header as sent to the client, including the CRLF
+pub(crate) fn header_line(header: &ResponseHeader) -> String {
+    format!(
+        "{status} {meta}\r\n",
+        status = header.status.code(),
+        meta = header.meta.as_str(),
+    )
+}
+
+pub(crate) async fn send_response_header(header: &ResponseHeader, flush: bool, stream: &mut (impl AsyncWrite + Unpin)) -> Result<()> {
+    stream.write_all(header_line(header).as_bytes()).await?;
+
+    if flush {
+        stream.flush().await?;
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn maybe_send_response_body(maybe_body: Option<Body>, stream: &mut (impl AsyncWrite + Unpin)) -> Result<u64> {
+    match maybe_body {
+        Some(body) => send_response_body(body, stream).await,
+        None => Ok(0),
+    }
+}
+
+pub(crate) async fn send_response_body(body: Body, stream: &mut (impl AsyncWrite + Unpin)) -> Result<u64> {
+    let body_bytes = match body {
+        Body::Bytes(bytes) => {
+            stream.write_all(&bytes).await?;
+            bytes.len() as u64
+        },
+        Body::Inline(bytes) => {
+            stream.write_all(&bytes).await?;
+            bytes.len() as u64
+        },
+        Body::Reader(mut reader) => io::copy(&mut reader, stream).await?,
+        #[cfg(feature="mmap")]
+        Body::Mmap(mapped) => {
+            stream.write_all(&mapped).await?;
+            mapped.len() as u64
+        },
+    };
+
+    stream.flush().await?;
+
+    Ok(body_bytes)
+}
+
+/// Parse a response header line, including the CRLF
+#[cfg_attr(not(any(feature="client", feature="multi_tenant")), allow(dead_code))]
+pub(crate) fn parse_header(line: &[u8]) -> Result<ResponseHeader> {
+    let line = std::str::from_utf8(line).context("Response header is not UTF-8")?;
+    let line = line.strip_suffix("\r\n")
+        .context("Response header is not terminated by CRLF")?;
+
+    let (code, meta) = match line.split_once(' ') {
+        Some((code, meta)) => (code, meta),
+        None => (line, ""),
+    };
+
+    let status = code.parse::<u8>().ok()
+        .filter(|_| code.len() == 2)
+        .and_then(Status::from_code)
+        .with_context(|| format!("Invalid status `{}`", code))?;
+
+    Ok(ResponseHeader {
+        status,
+        meta: Meta::new(meta)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reads_requests() {
+        let request = read_request(&mut &b"gemini://localhost/a?b\r\nrest"[..]).await.unwrap();
+        assert_eq!(request.uri().to_string(), "gemini://localhost/a?b");
+        assert_eq!(request.input(), Some("b"));
+
+        assert!(read_request(&mut &b"gemini://localhost/\n"[..]).await.is_err());
+        let too_long = format!("gemini://localhost/{}\r\n", "a".repeat(REQUEST_URI_MAX_LEN));
+        assert!(read_request(&mut too_long.as_bytes()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn answers_malformed_requests() {
+        async fn malformed(raw: &[u8]) -> Option<MalformedRequest> {
+            let err = read_request(&mut &*raw).await.err().unwrap();
+            err.downcast_ref::<MalformedRequest>().cloned()
+        }
+
+        let missing_crlf = malformed(b"gemini://localhost/\n").await.unwrap();
+        assert_eq!(missing_crlf.reason(), "Request header not terminated with CRLF");
+        let too_long = format!("gemini://localhost/{}\r\n", "a".repeat(REQUEST_URI_MAX_LEN));
+        assert_eq!(malformed(too_long.as_bytes()).await.unwrap().reason(), "Request URI too long");
+        assert_eq!(malformed(b"gemini://local host/\r\n").await.unwrap().reason(), "Request URI is invalid");
+        assert_eq!(malformed(b"").await, None);
+
+        let mut raw = Vec::new();
+        write_response(missing_crlf.response(), &mut raw).await.unwrap();
+        assert_eq!(raw, b"59 Request header not terminated with CRLF\r\n");
+    }
+
+    #[tokio::test]
+    async fn writes_responses() {
+        let mut raw = Vec::new();
+        let body_bytes = write_response(Response::success_plain("hello"), &mut raw).await.unwrap();
+        assert_eq!(raw, b"20 text/plain\r\nhello");
+        assert_eq!(body_bytes, 5);
+
+        let mut raw = Vec::new();
+        write_response(Response::not_found(), &mut raw).await.unwrap();
+        assert_eq!(raw, b"51 Not found\r\n");
+    }
+
+    #[test]
+    fn parses_headers() {
+        let header = parse_header(b"20 text/gemini; lang=en\r\n").unwrap();
+        assert_eq!(header.status, Status::SUCCESS);
+        assert_eq!(header.meta.as_str(), "text/gemini; lang=en");
+
+        let header = parse_header(b"51\r\n").unwrap();
+        assert_eq!(header.status, Status::NOT_FOUND);
+        assert_eq!(header.meta.as_str(), "");
+
+        assert!(parse_header(b"20 text/gemini\n").is_err());
+        assert!(parse_header(b"2 text/gemini\r\n").is_err());
+        assert!(parse_header(b"99 text/gemini\r\n").is_err());
+        assert!(parse_header(b"HTTP/1.1 200 OK\r\n").is_err());
+    }
+}
@@ -0,0 +1,184 @@
+//! Limiting how many requests each client may send
+//!
+//! A single misbehaving client, like a crawler ignoring `robots.txt` or a script stuck
+//! in a loop, can keep a small server busy all by itself.  A [`RateLimiter`] allows
+//! every client address a number of requests per period, and answers any request
+//! beyond that with `44 SLOW DOWN`, telling the client how many seconds to wait.
+//!
+//! The easiest way to limit all requests is
+//! [`Builder::set_rate_limit()`](crate::Builder::set_rate_limit()).  With the
+//! `middleware` feature, a rate limiter is also middleware, so it can be added using
+//! `Builder::add_middleware()` instead, e.g. with a stricter limit in front of a few
+//! expensive routes.
+//!
+//! ```no_run
+//! # #[cfg(feature="serve_dir")] {
+//! # use std::time::Duration;
+//! # use twinstar::{Server, GEMINI_PORT, util::ServeDir};
+//! # async fn run() -> anyhow::Result<()> {
+//! Server::bind(("localhost", GEMINI_PORT))
+//!     .set_rate_limit(60, Duration::from_secs(60))
+//!     .add_route("/", ServeDir::new("public").into_handler())
+//!     .serve()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! # }
+//! ```
+//!
+//! This module requires the `rate_limit` feature.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[cfg(feature="middleware")]
+use crate::middleware::{Middleware, Next};
+use crate::types::{Request, Response};
+#[cfg(feature="middleware")]
+use crate::HandlerResponse;
+
+/// How many client addresses are tracked at most by default
+pub const DEFAULT_MAX_TRACKED: usize = 10_000;
+
+/// Allows each client address a number of requests per period
+///
+/// Every address has a bucket holding up to `requests` tokens, which refills evenly
+/// over `per`.  Each request takes a token, and requests finding the bucket empty are
+/// answered with `44 SLOW DOWN`.  This allows short bursts of up to `requests`
+/// requests, like a page with a few images being fetched, while limiting the long term
+/// rate.
+///
+/// Clones share the same buckets.  Requests without a known client address are never
+/// limited.  See the [module documentation](self) for an example.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    requests: u32,
+    per: Duration,
+    max_tracked: usize,
+    buckets: Arc<Mutex<HashMap<IpAddr, Bucket>>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    /// Allow each client `requests` requests per `per`
+    pub fn new(requests: u32, per: Duration) -> Self {
+        Self {
+            requests: requests.max(1),
+            per,
+            max_tracked: DEFAULT_MAX_TRACKED,
+            buckets: Arc::default(),
+        }
+    }
+
+    /// Set how many client addresses are tracked at most
+    ///
+    /// When there are more clients, the ones seen least recently are forgotten, so they
+    /// start with a full bucket again.  The default is [`DEFAULT_MAX_TRACKED`].
+    pub fn set_max_tracked(mut self, max_tracked: usize) -> Self {
+        self.max_tracked = max_tracked.max(1);
+        self
+    }
+
+    /// Take a token for a request from `ip`
+    ///
+    /// If the bucket is empty, this returns how long the client has to wait for the
+    /// next token.
+    pub fn check(&self, ip: IpAddr) -> Result<(), Duration> {
+        self.check_at(ip, Instant::now())
+    }
+
+    fn check_at(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let capacity = f64::from(self.requests);
+        let rate = capacity / self.per.as_secs_f64().max(f64::MIN_POSITIVE);
+        let mut buckets = self.buckets.lock().expect("twinstar BUG");
+
+        if !buckets.contains_key(&ip) && buckets.len() >= self.max_tracked {
+            // Full buckets are the same as no bucket at all
+            buckets.retain(|_, bucket| bucket.refilled(now, rate, capacity) < capacity);
+
+            if buckets.len() >= self.max_tracked {
+                let stalest = buckets.iter()
+                    .min_by_key(|(_, bucket)| bucket.updated)
+                    .map(|(ip, _)| *ip)
+                    .expect("twinstar BUG");
+                buckets.remove(&stalest);
+            }
+        }
+
+        let bucket = buckets.entry(ip).or_insert(Bucket { tokens: capacity, updated: now });
+        bucket.tokens = bucket.refilled(now, rate, capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+
+    /// The `44 SLOW DOWN` response for a client which has to wait for `retry_after`
+    pub fn response(retry_after: Duration) -> Response {
+        // Round up, so clients don't retry too early
+        let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        Response::slow_down(seconds.max(1))
+    }
+
+    /// The response for `request`, if it exceeds the limit of its client
+    pub(crate) fn limit(&self, request: &Request) -> Option<Response> {
+        let ip = request.remote_addr()?.ip();
+        self.check(ip).err().map(Self::response)
+    }
+}
+
+impl Bucket {
+    fn refilled(&self, now: Instant, rate: f64, capacity: f64) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        (self.tokens + elapsed * rate).min(capacity)
+    }
+}
+
+#[cfg(feature="middleware")]
+impl Middleware for RateLimiter {
+    fn handle(&self, request: Request, next: Next) -> HandlerResponse {
+        match self.limit(&request) {
+            Some(response) => Box::pin(async { Ok(response) }),
+            None => next.run(request),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Status;
+
+    #[test]
+    fn limits_clients() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(10)).set_max_tracked(2);
+        let start = Instant::now();
+        let alice = IpAddr::from([10, 0, 0, 1]);
+        let bob = IpAddr::from([10, 0, 0, 2]);
+
+        assert!(limiter.check_at(alice, start).is_ok());
+        assert!(limiter.check_at(alice, start).is_ok());
+        assert_eq!(limiter.check_at(alice, start), Err(Duration::from_secs(5)));
+        assert!(limiter.check_at(bob, start).is_ok());
+        assert!(limiter.check_at(alice, start + Duration::from_secs(5)).is_ok());
+
+        // Tracking a third client forgets bob, whose bucket has refilled
+        assert!(limiter.check_at(IpAddr::from([10, 0, 0, 3]), start + Duration::from_secs(5)).is_ok());
+        assert_eq!(limiter.buckets.lock().unwrap().len(), 2);
+
+        let response = RateLimiter::response(Duration::from_millis(4200));
+        assert_eq!(response.header().status, Status::SLOW_DOWN);
+        assert_eq!(response.header().meta.as_str(), "5");
+    }
+}
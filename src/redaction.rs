@@ -0,0 +1,180 @@
+//! Keeping the input of users out of logs
+//!
+//! See [`QueryRedaction`].
+
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use crate::routing::RoutingNode;
+use crate::types::{Request, Status};
+
+/// What's left of a redacted query in logs
+const REDACTED_QUERY: &str = "[redacted]";
+
+/// How many prompting URIs are remembered, before all queries are redacted
+const MAX_PROMPTED_URIS: usize = 10_000;
+
+/// Which queries are redacted, besides those to routes added with
+/// [`QueryRedaction::redact_route()`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RedactQueries {
+    /// Log all queries as they are
+    Never,
+    /// Redact queries answering a `11 SENSITIVE INPUT` prompt
+    ///
+    /// This is the default.
+    #[default]
+    AfterSensitiveInput,
+    /// Redact queries answering a `10 INPUT` or `11 SENSITIVE INPUT` prompt
+    AfterInput,
+    /// Redact all queries
+    Always,
+}
+
+/// Decides which queries are kept out of the access log
+///
+/// Queries usually contain what users typed, which may well be private, and
+/// occasionally contain credentials.  Instead of asking every handler to sanitize its
+/// input, the server redacts queries before they end up in an
+/// [`AccessRecord`](crate::logging::AccessRecord), replacing them with `[redacted]`.
+/// The same goes for the callbacks added with
+/// [`Builder::on_request_complete()`](crate::Builder::on_request_complete()), for
+/// [events](crate::events) and for debug logs.
+///
+/// Clients answer an input prompt by requesting the same URI again, with the input as
+/// the query.  So to redact input, the server remembers which URIs were answered with a
+/// prompt, and redacts the queries of later requests to them.  If a huge number of URIs
+/// prompt, all queries are redacted from then on.
+///
+/// ```no_run
+/// # #[cfg(feature="logging")] {
+/// # use twinstar::{Server, GEMINI_PORT, logging::{QueryRedaction, RedactQueries}};
+/// # async fn run() -> anyhow::Result<()> {
+/// Server::bind(("localhost", GEMINI_PORT))
+///     .set_query_redaction(QueryRedaction::new(RedactQueries::AfterInput).redact_route("/api"))
+///     .serve()
+///     .await?;
+/// # Ok(())
+/// # }
+/// # }
+/// ```
+pub struct QueryRedaction {
+    policy: RedactQueries,
+    routes: RoutingNode<()>,
+    prompted: Mutex<HashSet<String>>,
+}
+
+impl QueryRedaction {
+    /// Redact queries according to `policy`
+    pub fn new(policy: RedactQueries) -> Self {
+        Self {
+            policy,
+            routes: RoutingNode::default(),
+            prompted: Mutex::default(),
+        }
+    }
+
+    /// Always redact queries to `route`, and the routes below it
+    ///
+    /// Routes are matched the same way as for handlers.  Like
+    /// [`Builder::add_route()`](crate::Builder::add_route()), this panics if the route
+    /// is malformed.
+    pub fn redact_route(mut self, route: &'static str) -> Self {
+        self.routes.add_route(route, ());
+        self
+    }
+
+    /// The policy for queries to other routes
+    pub const fn policy(&self) -> RedactQueries {
+        self.policy
+    }
+
+    /// Take note of the status a URI was answered with
+    pub(crate) fn observe(&self, uri: &str, status: Status) {
+        let prompted = match self.policy {
+            RedactQueries::AfterSensitiveInput => status == Status::SENSITIVE_INPUT,
+            RedactQueries::AfterInput => status == Status::INPUT || status == Status::SENSITIVE_INPUT,
+            RedactQueries::Never | RedactQueries::Always => false,
+        };
+
+        if !prompted {
+            return;
+        }
+
+        let mut uris = self.prompted.lock().expect("twinstar BUG");
+
+        // Once full, everything is redacted anyway
+        if uris.len() <= MAX_PROMPTED_URIS {
+            uris.insert(without_query(uri).to_owned());
+        }
+    }
+
+    /// The URI of `request`, as it should be logged
+    pub(crate) fn redact(&self, request: &Request) -> String {
+        let uri = request.uri().to_string();
+        if request.uri().query().is_none() {
+            return uri;
+        }
+
+        let base = without_query(&uri);
+        let redact = match self.policy {
+            _ if self.routes.match_request(request).is_some() => true,
+            RedactQueries::Never => false,
+            RedactQueries::Always => true,
+            RedactQueries::AfterSensitiveInput | RedactQueries::AfterInput => {
+                let uris = self.prompted.lock().expect("twinstar BUG");
+                uris.len() > MAX_PROMPTED_URIS || uris.contains(base)
+            },
+        };
+
+        if redact {
+            format!("{}?{}", base, REDACTED_QUERY)
+        } else {
+            uri
+        }
+    }
+}
+
+impl Default for QueryRedaction {
+    fn default() -> Self {
+        Self::new(RedactQueries::default())
+    }
+}
+
+/// Strip the query, and the fragment which might follow it, from a URI
+fn without_query(uri: &str) -> &str {
+    uri.split(['?', '#']).next().unwrap_or(uri)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_queries() {
+        use std::convert::TryFrom;
+
+        let request = |uri: &str| {
+            Request::from_uri(crate::uri::URIReference::try_from(uri).unwrap().into_owned()).unwrap()
+        };
+        let redaction = QueryRedaction::default().redact_route("/api");
+        assert_eq!(redaction.redact(&request("gemini://localhost/login?hunter2")), "gemini://localhost/login?hunter2");
+        assert_eq!(redaction.redact(&request("gemini://localhost/api/v1?token")), "gemini://localhost/api/v1?[redacted]");
+
+        redaction.observe("gemini://localhost/login", Status::SENSITIVE_INPUT);
+        redaction.observe("gemini://localhost/search", Status::INPUT);
+        assert_eq!(redaction.redact(&request("gemini://localhost/login?hunter2")), "gemini://localhost/login?[redacted]");
+        assert_eq!(redaction.redact(&request("gemini://localhost/login")), "gemini://localhost/login");
+        assert_eq!(redaction.redact(&request("gemini://localhost/search?cats")), "gemini://localhost/search?cats");
+
+        let redaction = QueryRedaction::new(RedactQueries::AfterInput);
+        redaction.observe("gemini://localhost/search", Status::INPUT);
+        assert_eq!(redaction.redact(&request("gemini://localhost/search?cats")), "gemini://localhost/search?[redacted]");
+
+        let redaction = QueryRedaction::new(RedactQueries::Always);
+        assert_eq!(redaction.redact(&request("gemini://localhost/?a")), "gemini://localhost/?[redacted]");
+        let redaction = QueryRedaction::new(RedactQueries::Never);
+        redaction.observe("gemini://localhost/login", Status::SENSITIVE_INPUT);
+        assert_eq!(redaction.redact(&request("gemini://localhost/login?a")), "gemini://localhost/login?a");
+    }
+}
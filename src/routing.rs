@@ -6,8 +6,16 @@ use uriparse::path::{Path, Segment};
 
 use std::collections::HashMap;
 use std::convert::TryInto;
+#[cfg(feature="routing")]
+use std::fmt;
 
 use crate::types::Request;
+#[cfg(feature="route_debug")]
+use std::convert::TryFrom;
+#[cfg(feature="route_debug")]
+use crate::types::{Document, Response, document::HeadingLevel::*};
+#[cfg(feature="route_debug")]
+use crate::util::json_string;
 
 /// A node for linking values to routes
 ///
@@ -117,6 +125,77 @@ impl<T> RoutingNode<T> {
             ))
     }
 
+    /// Explain how a path is routed, see [`RouteTrace`]
+    ///
+    /// Every route which is a prefix of `path` is listed along with its value rendered
+    /// by `label`, like in [`to_dot()`](Self::to_dot()).  The path is given as segments,
+    /// like for [`match_path()`](Self::match_path()), which this matches the same way.
+    ///
+    /// ```
+    /// # use twinstar::routing::RoutingNode;
+    /// let mut routes = RoutingNode::<&str>::default();
+    /// routes.add_route("/", "base");
+    /// routes.add_route("/docs", "docs");
+    /// routes.add_route("/docs/api", "api docs");
+    ///
+    /// let trace = routes.trace_path(&["docs", "guide"], |name| name.to_string());
+    /// assert_eq!(trace.matched(), Some(&("/docs".to_owned(), "docs".to_owned())));
+    /// assert_eq!(trace.shadowed(), [("/".to_owned(), "base".to_owned())]);
+    /// assert_eq!(trace.trailing, ["guide"]);
+    /// ```
+    ///
+    /// This requires the `route_debug` feature.
+    #[cfg(feature="route_debug")]
+    pub fn trace_path<I, S>(&self, path: I, label: impl Fn(&T) -> String) -> RouteTrace
+    where
+        I: IntoIterator<Item=S>,
+        S: AsRef<str>,
+    {
+        let segments = path.into_iter()
+            .map(|segment| segment.as_ref().to_owned())
+            .filter(|segment| !segment.is_empty())
+            .collect::<Vec<_>>();
+
+        let mut node = Some(self);
+        let mut considered = Vec::new();
+        let mut matched_len = 0;
+        for depth in 0..=segments.len() {
+            let Self(value, map) = match node {
+                Some(node) => node,
+                None => break,
+            };
+
+            if let Some(value) = value {
+                let route = format!("/{}", segments[..depth].join("/"));
+                considered.push((route, label(value)));
+                matched_len = depth;
+            }
+
+            node = segments.get(depth).and_then(|segment| map.get(segment));
+        }
+
+        let trailing = match considered.is_empty() {
+            true => Vec::new(),
+            false => segments[matched_len..].to_vec(),
+        };
+
+        RouteTrace {
+            path: format!("/{}", segments.join("/")),
+            considered,
+            trailing,
+        }
+    }
+
+    /// Explain how a [`Request`] is routed
+    ///
+    /// See [`RoutingNode::trace_path()`] for more information
+    #[cfg(feature="route_debug")]
+    pub fn trace_request(&self, req: &Request, label: impl Fn(&T) -> String) -> RouteTrace {
+        let mut path = req.path().to_borrowed();
+        path.normalize(false);
+        self.trace_path(path.segments().iter().map(Segment::as_str), label)
+    }
+
     /// Add a route to the network
     ///
     /// This method wraps [`add_route_by_path()`](Self::add_route_by_path()) while
@@ -154,6 +233,23 @@ impl<T> RoutingNode<T> {
         }
     }
 
+    /// Remove the value attached to a route, returning it if there was one
+    ///
+    /// Only the exact route is removed, routes nested below it are kept.  The path is
+    /// normalized the same way as in [`add_route_by_path()`](Self::add_route_by_path()).
+    pub fn remove_route_by_path(&mut self, mut path: Path) -> Option<T> {
+        path.normalize(false);
+
+        let mut node = self;
+        for segment in path.segments() {
+            if !segment.is_empty() {
+                node = node.1.get_mut(segment.as_str())?;
+            }
+        }
+
+        node.0.take()
+    }
+
     /// Recursively shrink maps to fit
     pub fn shrink(&mut self) {
         let mut to_shrink = vec![&mut self.1];
@@ -188,6 +284,155 @@ impl<T> RoutingNode<T> {
             unexplored: vec![self],
         }
     }
+
+    /// List every route in this map along with its value, sorted by path
+    ///
+    /// Paths are normalized and absolute, like `/hello/world`, with the root being `/`.
+    ///
+    /// ```
+    /// # use twinstar::routing::RoutingNode;
+    /// let mut map = RoutingNode::<usize>::default();
+    /// map.add_route("/hello/world/", 1312);
+    /// map.add_route("/", 0);
+    ///
+    /// assert_eq!(map.routes(), vec![("/".to_owned(), &0), ("/hello/world".to_owned(), &1312)]);
+    /// ```
+    pub fn routes(&self) -> Vec<(String, &T)> {
+        let mut routes = Vec::new();
+        let mut unexplored = vec![(String::new(), self)];
+
+        while let Some((path, node)) = unexplored.pop() {
+            if let Some(value) = &node.0 {
+                let path = if path.is_empty() { "/".to_owned() } else { path.clone() };
+                routes.push((path, value));
+            }
+
+            for (segment, child) in &node.1 {
+                unexplored.push((format!("{}/{}", path, segment), child));
+            }
+        }
+
+        routes.sort_by(|(a, _), (b, _)| a.cmp(b));
+        routes
+    }
+
+    /// Render the routing tree as a [Graphviz](https://graphviz.org) graph
+    ///
+    /// Every node is labeled with its path, and nodes with a value attached are drawn as
+    /// boxes which are additionally labeled using `label`.  Children are listed in
+    /// alphabetical order, so the output is stable.
+    ///
+    /// ```
+    /// # use twinstar::routing::RoutingNode;
+    /// let mut map = RoutingNode::<&str>::default();
+    /// map.add_route("/docs/api", "api docs");
+    ///
+    /// let dot = map.to_dot(|name| name.to_string());
+    /// assert!(dot.starts_with("digraph routes {"));
+    /// assert!(dot.contains(r#"n1 [label="/docs", shape=ellipse];"#));
+    /// assert!(dot.contains(r#"n2 [label="/docs/api\napi docs", shape=box];"#));
+    /// assert!(dot.contains("n1 -> n2;"));
+    /// ```
+    ///
+    /// This requires the `route_debug` feature.
+    #[cfg(feature="route_debug")]
+    pub fn to_dot(&self, label: impl Fn(&T) -> String) -> String {
+        let mut dot = String::from("digraph routes {\n");
+        let mut next_id = 0;
+        let mut unexplored = vec![(None, String::new(), self)];
+
+        while let Some((parent, path, node)) = unexplored.pop() {
+            let id = next_id;
+            next_id += 1;
+
+            let shown_path = if path.is_empty() { "/" } else { path.as_str() };
+            match &node.0 {
+                Some(value) => dot.push_str(&format!(
+                    "    n{} [label=\"{}\\n{}\", shape=box];\n",
+                    id,
+                    escape_dot(shown_path),
+                    escape_dot(&label(value)),
+                )),
+                None => dot.push_str(&format!(
+                    "    n{} [label=\"{}\", shape=ellipse];\n",
+                    id,
+                    escape_dot(shown_path),
+                )),
+            }
+
+            if let Some(parent) = parent {
+                dot.push_str(&format!("    n{} -> n{};\n", parent, id));
+            }
+
+            // Pushed in reverse, so children are visited in alphabetical order
+            for (segment, child) in node.sorted_children().into_iter().rev() {
+                unexplored.push((Some(id), format!("{}/{}", path, segment), child));
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Render the routing tree as nested JSON objects
+    ///
+    /// Each node is an object with the fields `segment`, `path`, `value` and
+    /// `children`, where `value` is the result of `label`, or `null` if no value is
+    /// attached to the node, and `children` is an array of nodes, sorted by segment.
+    ///
+    /// ```
+    /// # use twinstar::routing::RoutingNode;
+    /// let mut map = RoutingNode::<&str>::default();
+    /// map.add_route("/docs", "docs");
+    ///
+    /// assert_eq!(
+    ///     map.to_json(|name| name.to_string()),
+    ///     concat!(
+    ///         r#"{"segment":"","path":"/","value":null,"children":["#,
+    ///         r#"{"segment":"docs","path":"/docs","value":"docs","children":[]}]}"#,
+    ///     ),
+    /// );
+    /// ```
+    ///
+    /// This requires the `route_debug` feature.
+    #[cfg(feature="route_debug")]
+    pub fn to_json(&self, label: impl Fn(&T) -> String) -> String {
+        self.node_json("", "", &label)
+    }
+
+    #[cfg(feature="route_debug")]
+    fn node_json(&self, segment: &str, path: &str, label: &impl Fn(&T) -> String) -> String {
+        let children = self.sorted_children().into_iter()
+            .map(|(segment, child)| child.node_json(segment, &format!("{}/{}", path, segment), label))
+            .collect::<Vec<_>>();
+
+        format!(
+            "{{\"segment\":{},\"path\":{},\"value\":{},\"children\":[{}]}}",
+            json_string(segment),
+            json_string(if path.is_empty() { "/" } else { path }),
+            self.0.as_ref().map(|value| json_string(&label(value))).unwrap_or_else(|| "null".to_owned()),
+            children.join(","),
+        )
+    }
+
+    #[cfg(feature="route_debug")]
+    fn sorted_children(&self) -> Vec<(&str, &Self)> {
+        let mut children = self.1.iter()
+            .map(|(segment, child)| (segment.as_str(), child))
+            .collect::<Vec<_>>();
+
+        children.sort_by_key(|(segment, _)| *segment);
+        children
+    }
+}
+
+/// Escape a string for use in a quoted Graphviz label
+#[cfg(feature="route_debug")]
+fn escape_dot(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
 }
 
 impl<'a, T> IntoIterator for &'a RoutingNode<T> {
@@ -205,6 +450,298 @@ impl<T> Default for RoutingNode<T> {
     }
 }
 
+/// Render a path the way routes are listed, e.g. `/hello/world`, or `/` for the root
+pub(crate) fn route_path(path: &Path) -> String {
+    let mut path = path.clone();
+    path.normalize(false);
+
+    let segments = path.segments().iter()
+        .filter(|segment| !segment.is_empty())
+        .map(Segment::as_str)
+        .collect::<Vec<_>>();
+
+    format!("/{}", segments.join("/"))
+}
+
+/// Problems found in a set of routes
+///
+/// Routes are often registered in many places, like different modules or
+/// configuration files, which makes it easy to register a route twice, or to register a
+/// route which takes over part of another one.  A report lists all such cases at once,
+/// along with where each route came from, like `src/blog.rs:12:10` or a label.
+///
+/// Builders check their routes when the server is built, failing on conflicts and
+/// logging shadowed routes.  See [`Builder::route_report()`](crate::Builder::route_report()).
+/// This requires the `routing` feature.
+///
+/// ```
+/// # use twinstar::routing::RouteReport;
+/// let report = RouteReport::new(vec![
+///     ("/blog", "blog module"),
+///     ("/blog/2020", "archive module"),
+///     ("/blog/", "config.toml"),
+/// ]);
+///
+/// assert_eq!(report.conflicts[0].origins, ["blog module", "config.toml"]);
+/// assert_eq!(report.shadowed[0].by, [("/blog/2020".to_owned(), "archive module".to_owned())]);
+/// ```
+#[cfg(feature="routing")]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RouteReport {
+    /// Routes which were registered more than once
+    pub conflicts: Vec<RouteConflict>,
+    /// Routes which are partially handled by longer routes
+    ///
+    /// The root route is never listed, since it is meant to catch everything not
+    /// handled by other routes.
+    pub shadowed: Vec<ShadowedRoute>,
+}
+
+/// A route which was registered more than once, see [`RouteReport`]
+#[cfg(feature="routing")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteConflict {
+    /// The normalized path of the route
+    pub path: String,
+    /// Where each registration came from, in the order they were made
+    pub origins: Vec<String>,
+}
+
+/// A route which is partially handled by longer routes, see [`RouteReport`]
+#[cfg(feature="routing")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShadowedRoute {
+    /// The normalized path of the route
+    pub path: String,
+    /// Where the route came from
+    pub origin: String,
+    /// The paths and origins of the longer routes taking over part of this route
+    pub by: Vec<(String, String)>,
+}
+
+#[cfg(feature="routing")]
+impl RouteReport {
+    /// Check a list of routes, given as pairs of path and origin, in registration order
+    pub fn new<P, O>(routes: impl IntoIterator<Item = (P, O)>) -> Self
+    where
+        P: AsRef<str>,
+        O: Into<String>,
+    {
+        let registrations = registrations(routes);
+
+        let conflicts = registrations.iter()
+            .filter(|(_, origins)| origins.len() > 1)
+            .map(|(path, origins)| RouteConflict {
+                path: path.clone(),
+                origins: origins.clone(),
+            })
+            .collect();
+
+        let shadowed = registrations.iter()
+            .filter(|(path, _)| path != "/")
+            .filter_map(|(path, origins)| {
+                let prefix = format!("{}/", path);
+                let by = registrations.iter()
+                    .filter(|(longer, _)| longer.starts_with(&prefix))
+                    .map(|(longer, origins)| (longer.clone(), origins[0].clone()))
+                    .collect::<Vec<_>>();
+
+                if by.is_empty() {
+                    return None;
+                }
+
+                Some(ShadowedRoute {
+                    path: path.clone(),
+                    origin: origins[0].clone(),
+                    by,
+                })
+            })
+            .collect();
+
+        Self { conflicts, shadowed }
+    }
+
+    /// Whether no route was registered more than once
+    pub fn is_ok(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
+
+#[cfg(feature="routing")]
+impl fmt::Display for RouteReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut lines = self.conflicts.iter()
+            .map(|conflict| conflict_line(&conflict.path, &conflict.origins))
+            .collect::<Vec<_>>();
+
+        for shadowed in &self.shadowed {
+            let by = shadowed.by.iter()
+                .map(|(path, origin)| format!("{} ({})", path, origin))
+                .collect::<Vec<_>>();
+
+            lines.push(format!(
+                "shadowed: {} ({}) is partially handled by {}",
+                shadowed.path,
+                shadowed.origin,
+                by.join(", "),
+            ));
+        }
+
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+/// Fail if any of `routes`, given as pairs of path and origin, was registered more
+/// than once, listing the conflicts like a [`RouteReport`] would
+pub(crate) fn check_conflicts<P, O>(routes: impl IntoIterator<Item = (P, O)>) -> anyhow::Result<()>
+where
+    P: AsRef<str>,
+    O: Into<String>,
+{
+    let conflicts = registrations(routes).into_iter()
+        .filter(|(_, origins)| origins.len() > 1)
+        .map(|(path, origins)| conflict_line(&path, &origins))
+        .collect::<Vec<_>>();
+
+    anyhow::ensure!(conflicts.is_empty(), "Conflicting routes:\n{}", conflicts.join("\n"));
+
+    Ok(())
+}
+
+/// The normalized paths of `routes` with the origins of each, sorted by path
+fn registrations<P, O>(routes: impl IntoIterator<Item = (P, O)>) -> Vec<(String, Vec<String>)>
+where
+    P: AsRef<str>,
+    O: Into<String>,
+{
+    let mut registrations: Vec<(String, Vec<String>)> = Vec::new();
+
+    for (path, origin) in routes {
+        let path = match path.as_ref().try_into() {
+            Ok(path) => route_path(&path),
+            Err(_) => path.as_ref().to_owned(),
+        };
+
+        match registrations.iter_mut().find(|(existing, _)| *existing == path) {
+            Some((_, origins)) => origins.push(origin.into()),
+            None => registrations.push((path, vec![origin.into()])),
+        }
+    }
+
+    registrations.sort_by(|(a, _), (b, _)| a.cmp(b));
+    registrations
+}
+
+fn conflict_line(path: &str, origins: &[String]) -> String {
+    format!("conflict: {} is registered {} times, at {}", path, origins.len(), origins.join(", "))
+}
+
+/// How a path was routed, for troubleshooting complex route tables
+///
+/// Routes match by prefix, and the longest matching route wins, so a request may
+/// match several routes.  A trace lists all of them, which route won, and the
+/// segments which were left over for the handler as
+/// [trailing segments](crate::Request::trailing_segments()).
+///
+/// See [`RoutingNode::trace_path()`], and
+/// [`Builder::set_route_tracing()`](crate::Builder::set_route_tracing()) for tracing
+/// the requests of a server.  This requires the `route_debug` feature.
+#[cfg(feature="route_debug")]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RouteTrace {
+    /// The normalized path which was routed
+    pub path: String,
+    /// The path and label of every route matching the path, shortest first
+    pub considered: Vec<(String, String)>,
+    /// The segments of the path after the matched route
+    pub trailing: Vec<String>,
+}
+
+#[cfg(feature="route_debug")]
+impl RouteTrace {
+    /// The path and label of the route the path was routed to, if any
+    pub fn matched(&self) -> Option<&(String, String)> {
+        self.considered.last()
+    }
+
+    /// The paths and labels of the shorter routes which the matched route took over from
+    pub fn shadowed(&self) -> &[(String, String)] {
+        match self.considered.split_last() {
+            Some((_, shadowed)) => shadowed,
+            None => &[],
+        }
+    }
+}
+
+#[cfg(feature="route_debug")]
+impl fmt::Display for RouteTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (route, label) = match self.matched() {
+            Some(matched) => matched,
+            None => return write!(f, "{} matched no route", self.path),
+        };
+        write!(f, "{} matched {} ({})", self.path, route, label)?;
+
+        if !self.trailing.is_empty() {
+            write!(f, " with trailing segments [{}]", self.trailing.join(", "))?;
+        }
+
+        if !self.shadowed().is_empty() {
+            let shadowed = self.shadowed().iter()
+                .map(|(path, label)| format!("{} ({})", path, label))
+                .collect::<Vec<_>>();
+            write!(f, ", shadowing {}", shadowed.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Answer a request to a [route trace page](crate::Builder::add_route_trace_page())
+///
+/// The path to trace is sent as input, so the page can be used from any client.
+#[cfg(feature="route_debug")]
+pub(crate) fn route_trace_page(labels: &RoutingNode<String>, request: &Request) -> Response {
+    let input = match request.input() {
+        Some(input) if !input.is_empty() => input,
+        _ => return Response::input_lossy("Path to trace, e.g. /docs/api"),
+    };
+
+    let mut path = match Path::try_from(input) {
+        Ok(path) if path.is_absolute() => path,
+        _ => return Response::bad_request_lossy("Not an absolute path"),
+    };
+    path.normalize(false);
+
+    let trace = labels.trace_path(path.segments().iter().map(Segment::as_str), String::clone);
+
+    let mut document = Document::new();
+    document.add_heading(H1, format!("Routing {}", trace.path));
+    document.add_blank_line();
+
+    match trace.matched() {
+        Some((route, label)) => {
+            document.add_text(format!("Matched {} ({})", route, label));
+            if !trace.trailing.is_empty() {
+                document.add_text(format!("Trailing segments: {}", trace.trailing.join(", ")));
+            }
+        },
+        None => {
+            document.add_text("Matched no route");
+        },
+    }
+
+    if !trace.shadowed().is_empty() {
+        document.add_blank_line();
+        document.add_heading(H2, "Shadowed routes");
+        for (route, label) in trace.shadowed() {
+            document.add_unordered_list_item(format!("{} ({})", route, label));
+        }
+    }
+
+    Response::success_gemini(document)
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct ConflictingRouteError();
 
@@ -237,3 +774,67 @@ impl<'a, T> Iterator for Iter<'a, T> {
 }
 
 impl<T> std::iter::FusedIterator for Iter<'_, T> { }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_for_conflicts() {
+        let error = check_conflicts(vec![("/", "main.rs:1"), ("/docs/", "main.rs:2"), ("/docs", "sites.toml")]).unwrap_err();
+        assert_eq!(error.to_string(), "Conflicting routes:\nconflict: /docs is registered 2 times, at main.rs:2, sites.toml");
+
+        assert!(check_conflicts(vec![("/", "a"), ("/b", "b")]).is_ok());
+    }
+
+    #[cfg(feature="routing")]
+    #[test]
+    fn reports_conflicts_and_shadowing() {
+        let report = RouteReport::new(vec![
+            ("/", "main.rs:1"),
+            ("/docs", "main.rs:2"),
+            ("/docs/api/", "api.rs:7"),
+            ("/docs/api", "sites.toml: [api]"),
+            ("/docsearch", "main.rs:3"),
+        ]);
+
+        assert!(!report.is_ok());
+        assert_eq!(report.to_string(), "\
+            conflict: /docs/api is registered 2 times, at api.rs:7, sites.toml: [api]\n\
+            shadowed: /docs (main.rs:2) is partially handled by /docs/api (api.rs:7)\
+        ");
+
+        assert_eq!(RouteReport::new(vec![("/", "a"), ("/b", "b")]), RouteReport::default());
+    }
+
+    #[cfg(feature="route_debug")]
+    #[test]
+    fn traces_routes() {
+        let mut map = RoutingNode::<&str>::default();
+        map.add_route("/", "main.rs:1");
+        map.add_route("/docs", "main.rs:2");
+        map.add_route("/docs/api", "api.rs:7");
+
+        let trace = map.trace_path(["docs", "api", "", "v2", "index.gmi"], |label| label.to_string());
+        assert_eq!(trace.to_string(), "\
+            /docs/api/v2/index.gmi matched /docs/api (api.rs:7) with trailing segments [v2, index.gmi], \
+            shadowing / (main.rs:1), /docs (main.rs:2)\
+        ");
+
+        let trace = map.trace_path(["docsearch"], |label| label.to_string());
+        assert_eq!(trace.to_string(), "/docsearch matched / (main.rs:1) with trailing segments [docsearch]");
+
+        let empty = RoutingNode::<&str>::default();
+        assert_eq!(empty.trace_path(["docs"], |label| label.to_string()).to_string(), "/docs matched no route");
+    }
+
+    #[cfg(feature="route_debug")]
+    #[test]
+    fn escapes_labels() {
+        let mut map = RoutingNode::<&str>::default();
+        map.add_route("/", "say \"hi\"\\");
+
+        assert!(map.to_dot(|value| value.to_string()).contains(r#"[label="/\nsay \"hi\"\\", shape=box]"#));
+        assert!(map.to_json(|value| value.to_string()).contains(r#""value":"say \"hi\"\\""#));
+    }
+}
@@ -0,0 +1,119 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll, Waker};
+
+/// A handle for stopping a server from outside of it
+///
+/// All clones of a `Shutdown` share the same state, so one clone can be passed to
+/// [`Builder::serve_until()`](crate::Builder::serve_until()) while another one is kept
+/// around to [`trigger()`](Self::trigger()) the shutdown, for example from a signal or
+/// service control handler.  Unlike most of the server, triggering a shutdown doesn't
+/// depend on the async runtime, so it can be done from any thread.
+///
+/// ```no_run
+/// # use twinstar::{Server, Shutdown, GEMINI_PORT};
+/// # async fn run() -> anyhow::Result<()> {
+/// let shutdown = Shutdown::new();
+/// let trigger = shutdown.clone();
+///
+/// std::thread::spawn(move || {
+///     std::thread::sleep(std::time::Duration::from_secs(60));
+///     trigger.trigger();
+/// });
+///
+/// Server::bind(("localhost", GEMINI_PORT))
+///     .serve_until(shutdown.wait())
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    triggered: AtomicBool,
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl Shutdown {
+    /// Create a new handle which hasn't been triggered yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request the shutdown, waking up everyone waiting for it
+    ///
+    /// Triggering a shutdown more than once has no further effect.
+    pub fn trigger(&self) {
+        self.inner.triggered.store(true, Ordering::SeqCst);
+
+        let wakers = std::mem::take(&mut *self.inner.wakers.lock().expect("twinstar BUG"));
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    /// Whether the shutdown has been triggered
+    pub fn is_triggered(&self) -> bool {
+        self.inner.triggered.load(Ordering::SeqCst)
+    }
+
+    /// A future which completes once the shutdown has been triggered
+    pub fn wait(&self) -> impl Future<Output = ()> + Send + 'static {
+        Wait {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+struct Wait {
+    inner: Arc<Inner>,
+}
+
+impl Future for Wait {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.inner.triggered.load(Ordering::SeqCst) {
+            return Poll::Ready(());
+        }
+
+        let mut wakers = self.inner.wakers.lock().expect("twinstar BUG");
+
+        // Check again, the shutdown might have been triggered while waiting for the lock
+        if self.inner.triggered.load(Ordering::SeqCst) {
+            return Poll::Ready(());
+        }
+
+        if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+            wakers.push(cx.waker().clone());
+        }
+
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn wakes_waiters() {
+        let shutdown = Shutdown::new();
+        let waiter = tokio::spawn(shutdown.wait());
+
+        let trigger = shutdown.clone();
+        std::thread::spawn(move || trigger.trigger());
+
+        waiter.await.unwrap();
+        assert!(shutdown.is_triggered());
+
+        // Waiting after the fact completes immediately
+        shutdown.wait().await;
+    }
+}
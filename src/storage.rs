@@ -0,0 +1,191 @@
+//! Pluggable key-value storage for server components
+//!
+//! Components which need to remember things between requests store their data through
+//! the [`KvStore`] trait, like the server certificates a client has seen
+//! ([`client::KnownHosts`](crate::client::KnownHosts)), client identities
+//! ([`client::Identity::from_store()`](crate::client::Identity::from_store())), or
+//! archived responses ([`middleware::ContentStore`](crate::middleware::ContentStore)).
+//! This lets deployers decide where that data lives without changing any application
+//! code:
+//!
+//! * [`MemoryStore`] keeps everything in memory, and is always available
+//! * [`FileStore`] keeps one file per key in a directory (feature `file_store`)
+//! * [`SledStore`] uses an embedded [sled](https://docs.rs/sled) database (feature
+//!   `sled_store`)
+//!
+//! All stores support expiring entries.  Expired entries are never returned, but may
+//! only be deleted from the underlying storage lazily.
+
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+#[cfg(any(feature="file_store", feature="sled_store"))]
+use anyhow::ensure;
+use futures_core::future::BoxFuture;
+
+mod memory;
+pub use self::memory::MemoryStore;
+
+#[cfg(feature="file_store")]
+mod file;
+#[cfg(feature="file_store")]
+pub use self::file::FileStore;
+
+#[cfg(feature="sled_store")]
+mod sled;
+#[cfg(feature="sled_store")]
+pub use self::sled::SledStore;
+
+/// The entries found by [`KvStore::scan()`], as pairs of keys and values
+pub type KeyValues = Vec<(String, Vec<u8>)>;
+
+/// A stored value along with the point in time it expires
+type Entry = (Vec<u8>, Option<SystemTime>);
+
+/// An asynchronous key-value store
+///
+/// Keys are strings, and values are arbitrary bytes.  Implementations must be safe to
+/// share between all connections of a server.
+pub trait KvStore: Send + Sync {
+    /// Get the value stored under `key`, if it exists and hasn't expired
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>>>;
+
+    /// Store `value` under `key`, replacing any previous value
+    ///
+    /// If a `ttl` is given, the entry expires once it has elapsed.
+    fn put<'a>(&'a self, key: &'a str, value: Vec<u8>, ttl: Option<Duration>) -> BoxFuture<'a, Result<()>>;
+
+    /// Remove the entry stored under `key`, returning its value if it hadn't expired
+    fn remove<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>>>;
+
+    /// Get all unexpired entries whose key starts with `prefix`, ordered by key
+    fn scan<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<KeyValues>>;
+
+    /// Get the time left until the entry stored under `key` expires
+    ///
+    /// Returns [`None`] if there is no such entry, and `Some(None)` if the entry never
+    /// expires.
+    fn ttl<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Option<Duration>>>>;
+}
+
+impl<T: KvStore + ?Sized> KvStore for Arc<T> {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>>> {
+        (**self).get(key)
+    }
+
+    fn put<'a>(&'a self, key: &'a str, value: Vec<u8>, ttl: Option<Duration>) -> BoxFuture<'a, Result<()>> {
+        (**self).put(key, value, ttl)
+    }
+
+    fn remove<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>>> {
+        (**self).remove(key)
+    }
+
+    fn scan<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<KeyValues>> {
+        (**self).scan(prefix)
+    }
+
+    fn ttl<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Option<Duration>>>> {
+        (**self).ttl(key)
+    }
+}
+
+/// The point in time an entry stored now with the given `ttl` expires
+fn expiry(ttl: Option<Duration>) -> Option<SystemTime> {
+    ttl.map(|ttl| SystemTime::now() + ttl)
+}
+
+fn is_expired(expires: Option<SystemTime>) -> bool {
+    matches!(expires, Some(expires) if expires <= SystemTime::now())
+}
+
+fn remaining(expires: Option<SystemTime>) -> Option<Duration> {
+    expires.map(|expires| {
+        expires.duration_since(SystemTime::now()).unwrap_or_default()
+    })
+}
+
+/// Serialize a value along with its expiry, for stores persisting raw bytes
+///
+/// The format is the expiry in milliseconds since the unix epoch as a big endian
+/// `u64`, with `0` meaning no expiry, followed by the value itself.
+#[cfg(any(feature="file_store", feature="sled_store"))]
+fn encode_entry(value: &[u8], expires: Option<SystemTime>) -> Vec<u8> {
+    let expires = expires
+        .and_then(|expires| expires.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map(|expires| (expires.as_millis() as u64).max(1))
+        .unwrap_or(0);
+
+    let mut entry = Vec::with_capacity(8 + value.len());
+    entry.extend_from_slice(&expires.to_be_bytes());
+    entry.extend_from_slice(value);
+    entry
+}
+
+/// Deserialize an entry written by [`encode_entry()`]
+#[cfg(any(feature="file_store", feature="sled_store"))]
+fn decode_entry(mut entry: Vec<u8>) -> Result<Entry> {
+    use std::convert::TryInto;
+
+    ensure!(entry.len() >= 8, "Stored entry is truncated");
+
+    let expires = u64::from_be_bytes(entry[..8].try_into().expect("twinstar BUG"));
+    let expires = match expires {
+        0 => None,
+        millis => Some(SystemTime::UNIX_EPOCH + Duration::from_millis(millis)),
+    };
+
+    entry.drain(..8);
+
+    Ok((entry, expires))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Exercise the behaviour every store needs to share
+    pub(crate) async fn check_store(store: &dyn KvStore) {
+        assert_eq!(store.get("missing").await.unwrap(), None);
+        assert_eq!(store.ttl("missing").await.unwrap(), None);
+
+        store.put("user/alice", b"a".to_vec(), None).await.unwrap();
+        store.put("user/bob", b"b".to_vec(), Some(Duration::from_secs(60))).await.unwrap();
+        store.put("other", b"o".to_vec(), None).await.unwrap();
+        store.put("user/expired", b"e".to_vec(), Some(Duration::from_secs(0))).await.unwrap();
+
+        assert_eq!(store.get("user/alice").await.unwrap(), Some(b"a".to_vec()));
+        assert_eq!(store.get("user/expired").await.unwrap(), None);
+        assert_eq!(store.ttl("user/alice").await.unwrap(), Some(None));
+
+        let ttl = store.ttl("user/bob").await.unwrap().unwrap().unwrap();
+        assert!(ttl <= Duration::from_secs(60) && ttl > Duration::from_secs(50));
+
+        assert_eq!(store.scan("user/").await.unwrap(), vec![
+            ("user/alice".to_owned(), b"a".to_vec()),
+            ("user/bob".to_owned(), b"b".to_vec()),
+        ]);
+
+        store.put("user/alice", b"A".to_vec(), None).await.unwrap();
+        assert_eq!(store.remove("user/alice").await.unwrap(), Some(b"A".to_vec()));
+        assert_eq!(store.get("user/alice").await.unwrap(), None);
+        assert_eq!(store.remove("user/alice").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn memory_store() {
+        check_store(&MemoryStore::new()).await;
+    }
+
+    #[cfg(any(feature="file_store", feature="sled_store"))]
+    #[test]
+    fn entries_roundtrip() {
+        let expires = SystemTime::UNIX_EPOCH + Duration::from_millis(1_600_000_000_000);
+        let entry = encode_entry(b"value", Some(expires));
+
+        assert_eq!(decode_entry(entry).unwrap(), (b"value".to_vec(), Some(expires)));
+        assert_eq!(decode_entry(encode_entry(b"", None)).unwrap(), (Vec::new(), None));
+        assert!(decode_entry(vec![0; 7]).is_err());
+    }
+}
@@ -0,0 +1,216 @@
+use std::convert::TryInto;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use anyhow::{Result, Context, ensure};
+use futures_core::future::BoxFuture;
+use tokio::fs;
+
+use crate::util::hex;
+use super::{Entry, KeyValues, KvStore, decode_entry, encode_entry, expiry, is_expired, remaining};
+
+/// A [`KvStore`] keeping one file per entry in a directory
+///
+/// File names are the hex encoded SHA-256 hashes of the keys, so keys of any length and
+/// with any characters can be stored, regardless of what the file system allows.  The
+/// key itself is stored in the file along with the value.  This store is meant for
+/// small amounts of data that need to survive restarts, like registered users of a
+/// small capsule.
+///
+/// Requires the `file_store` feature.
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    /// Store entries in `dir`, creating it if it doesn't exist yet
+    pub async fn open(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+
+        fs::create_dir_all(&dir).await
+            .with_context(|| format!("Failed to create `{}`", dir.display()))?;
+
+        Ok(Self { dir })
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        let digest = ring::digest::digest(&ring::digest::SHA256, key.as_bytes());
+        self.dir.join(hex(digest.as_ref()))
+    }
+
+    /// Read a file along with the key it was stored under, treating a missing file as a
+    /// missing entry
+    async fn read(path: &Path) -> Result<Option<(String, Entry)>> {
+        match fs::read(path).await {
+            Ok(file) => decode_file(file)
+                .with_context(|| format!("Failed to decode `{}`", path.display()))
+                .map(Some),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err).with_context(|| format!("Failed to read `{}`", path.display())),
+        }
+    }
+
+    /// Read an unexpired entry, deleting it if it has expired
+    async fn read_live(path: &Path) -> Result<Option<(String, Entry)>> {
+        match Self::read(path).await? {
+            Some((_, (_, expires))) if is_expired(expires) => {
+                // Someone else may have deleted it in the meantime, which is fine
+                let _ = fs::remove_file(path).await;
+                Ok(None)
+            },
+            entry => Ok(entry),
+        }
+    }
+
+    /// Read the unexpired entry stored under `key`
+    async fn read_key(&self, key: &str) -> Result<Option<Entry>> {
+        let path = self.path(key);
+
+        match Self::read_live(&path).await? {
+            Some((stored_key, entry)) => {
+                ensure!(stored_key == key, "`{}` holds `{}` instead of `{}`", path.display(), stored_key, key);
+                Ok(Some(entry))
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+impl KvStore for FileStore {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>>> {
+        Box::pin(async move {
+            let entry = self.read_key(key).await?;
+            Ok(entry.map(|(value, _)| value))
+        })
+    }
+
+    fn put<'a>(&'a self, key: &'a str, value: Vec<u8>, ttl: Option<Duration>) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let path = self.path(key);
+            // Concurrent puts of the same key each need a temporary file of their own
+            let mut tmp_path = path.clone().into_os_string();
+            tmp_path.push(format!(".{}.{}.tmp", std::process::id(), NEXT_TMP.fetch_add(1, Ordering::Relaxed)));
+
+            // Write to a temporary file first, so readers never see half-written entries
+            fs::write(&tmp_path, encode_file(key, &value, expiry(ttl))).await
+                .with_context(|| format!("Failed to write `{}`", path.display()))?;
+            fs::rename(&tmp_path, &path).await
+                .with_context(|| format!("Failed to write `{}`", path.display()))?;
+
+            Ok(())
+        })
+    }
+
+    fn remove<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>>> {
+        Box::pin(async move {
+            let path = self.path(key);
+            let entry = self.read_key(key).await?;
+
+            if entry.is_some() {
+                fs::remove_file(&path).await
+                    .with_context(|| format!("Failed to remove `{}`", path.display()))?;
+            }
+
+            Ok(entry.map(|(value, _)| value))
+        })
+    }
+
+    fn scan<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<KeyValues>> {
+        Box::pin(async move {
+            let mut found = Vec::new();
+            let mut entries = fs::read_dir(&self.dir).await
+                .with_context(|| format!("Failed to list `{}`", self.dir.display()))?;
+
+            while let Some(entry) = entries.next_entry().await.context("Failed to list directory")? {
+                // Skip temporary files
+                if entry.file_name().to_string_lossy().contains('.') {
+                    continue;
+                }
+
+                match Self::read_live(&entry.path()).await? {
+                    Some((key, (value, _))) if key.starts_with(prefix) => found.push((key, value)),
+                    _ => {},
+                }
+            }
+
+            found.sort();
+
+            Ok(found)
+        })
+    }
+
+    fn ttl<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Option<Duration>>>> {
+        Box::pin(async move {
+            let entry = self.read_key(key).await?;
+            Ok(entry.map(|(_, expires)| remaining(expires)))
+        })
+    }
+}
+
+/// Tells apart the temporary files of concurrent puts
+static NEXT_TMP: AtomicU64 = AtomicU64::new(0);
+
+/// Serialize an entry along with its key
+///
+/// The format is the length of the key as a big endian `u32`, followed by the key and
+/// the entry as written by [`encode_entry()`].
+fn encode_file(key: &str, value: &[u8], expires: Option<std::time::SystemTime>) -> Vec<u8> {
+    let mut file = Vec::with_capacity(4 + key.len());
+    file.extend_from_slice(&(key.len() as u32).to_be_bytes());
+    file.extend_from_slice(key.as_bytes());
+    file.extend_from_slice(&encode_entry(value, expires));
+    file
+}
+
+/// Deserialize a file written by [`encode_file()`]
+fn decode_file(mut file: Vec<u8>) -> Result<(String, Entry)> {
+    ensure!(file.len() >= 4, "Stored key is truncated");
+
+    let key_len = u32::from_be_bytes(file[..4].try_into().expect("twinstar BUG")) as usize;
+    ensure!(file.len() >= 4 + key_len, "Stored key is truncated");
+
+    let entry = file.split_off(4 + key_len);
+    let key = String::from_utf8(file.split_off(4)).context("Stored key is not UTF-8")?;
+
+    Ok((key, decode_entry(entry)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn file_store() {
+        let temp = tempfile::tempdir().unwrap();
+        let store = FileStore::open(temp.path()).await.unwrap();
+
+        super::super::tests::check_store(&store).await;
+    }
+
+    #[tokio::test]
+    async fn stores_long_keys_and_concurrent_puts() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let store = FileStore::open(dir).await.unwrap();
+        let key = "k".repeat(1000);
+
+        let puts = (0..8u8).map(|i| store.put(&key, vec![i], None));
+        for put in futures_util::future::join_all(puts).await {
+            put.unwrap();
+        }
+
+        assert_eq!(store.get(&key).await.unwrap().map(|value| value.len()), Some(1));
+        assert_eq!(store.scan("kkk").await.unwrap().len(), 1);
+        assert_eq!(std::fs::read_dir(dir).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn files_roundtrip() {
+        let file = encode_file("a/ü", b"value", None);
+
+        assert_eq!(decode_file(file).unwrap(), ("a/ü".to_owned(), (b"value".to_vec(), None)));
+        assert!(decode_file(vec![0, 0, 0, 9, b'a']).is_err());
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Result;
+use futures_core::future::BoxFuture;
+
+use super::{Entry, KeyValues, KvStore, expiry, is_expired, remaining};
+
+/// A [`KvStore`] keeping all entries in memory
+///
+/// Entries are lost when the server shuts down.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    entries: Mutex<BTreeMap<String, Entry>>,
+}
+
+impl MemoryStore {
+    /// Create an empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remove all expired entries, freeing their memory
+    ///
+    /// Expired entries are never returned by the store either way, so calling this is
+    /// only necessary to reclaim memory.
+    pub fn purge_expired(&self) {
+        self.entries.lock().expect("twinstar BUG")
+            .retain(|_, (_, expires)| !is_expired(*expires));
+    }
+
+    fn with_entries<T>(&self, f: impl FnOnce(&mut BTreeMap<String, Entry>) -> T) -> T {
+        let mut entries = self.entries.lock().expect("twinstar BUG");
+        f(&mut entries)
+    }
+}
+
+impl KvStore for MemoryStore {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>>> {
+        let value = self.with_entries(|entries| {
+            entries.get(key)
+                .filter(|(_, expires)| !is_expired(*expires))
+                .map(|(value, _)| value.clone())
+        });
+
+        Box::pin(async move { Ok(value) })
+    }
+
+    fn put<'a>(&'a self, key: &'a str, value: Vec<u8>, ttl: Option<Duration>) -> BoxFuture<'a, Result<()>> {
+        self.with_entries(|entries| {
+            entries.insert(key.to_owned(), (value, expiry(ttl)));
+        });
+
+        Box::pin(async move { Ok(()) })
+    }
+
+    fn remove<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>>> {
+        let value = self.with_entries(|entries| {
+            entries.remove(key)
+                .filter(|(_, expires)| !is_expired(*expires))
+                .map(|(value, _)| value)
+        });
+
+        Box::pin(async move { Ok(value) })
+    }
+
+    fn scan<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<KeyValues>> {
+        let found = self.with_entries(|entries| {
+            entries.range(prefix.to_owned()..)
+                .take_while(|(key, _)| key.starts_with(prefix))
+                .filter(|(_, (_, expires))| !is_expired(*expires))
+                .map(|(key, (value, _))| (key.clone(), value.clone()))
+                .collect()
+        });
+
+        Box::pin(async move { Ok(found) })
+    }
+
+    fn ttl<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Option<Duration>>>> {
+        let ttl = self.with_entries(|entries| {
+            entries.get(key)
+                .filter(|(_, expires)| !is_expired(*expires))
+                .map(|(_, expires)| remaining(*expires))
+        });
+
+        Box::pin(async move { Ok(ttl) })
+    }
+}
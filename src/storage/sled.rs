@@ -0,0 +1,137 @@
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Result, Context};
+use futures_core::future::BoxFuture;
+use tokio::task;
+
+use super::{Entry, KeyValues, KvStore, decode_entry, encode_entry, expiry, is_expired, remaining};
+
+/// A [`KvStore`] backed by an embedded [sled](https://docs.rs/sled) database
+///
+/// Suitable for larger amounts of data that need to survive restarts.  Since sled may
+/// block on disk I/O, its operations run on tokio's blocking thread pool.
+///
+/// Requires the `sled_store` feature.
+#[derive(Debug, Clone)]
+pub struct SledStore {
+    tree: sled::Tree,
+}
+
+impl SledStore {
+    /// Open (or create) the database at `path`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let db = sled::open(path)
+            .with_context(|| format!("Failed to open sled database at `{}`", path.display()))?;
+
+        Ok(Self::from_tree((*db).clone()))
+    }
+
+    /// Store entries in an existing sled tree
+    ///
+    /// This allows sharing one database between multiple stores, by opening a separate
+    /// tree for each.
+    pub fn from_tree(tree: sled::Tree) -> Self {
+        Self { tree }
+    }
+
+    /// Run `operation` on the tree from the blocking thread pool
+    fn blocking<'a, T, F>(&self, operation: F) -> BoxFuture<'a, Result<T>>
+    where
+        T: Send + 'static,
+        F: FnOnce(&sled::Tree) -> Result<T> + Send + 'static,
+    {
+        let tree = self.tree.clone();
+
+        Box::pin(async move {
+            task::spawn_blocking(move || operation(&tree)).await
+                .context("sled operation panicked")?
+        })
+    }
+}
+
+fn get_live(tree: &sled::Tree, key: &str) -> Result<Option<Entry>> {
+    let entry = match tree.get(key).context("Failed to read from sled")? {
+        Some(entry) => entry,
+        None => return Ok(None),
+    };
+    let (value, expires) = decode_entry(entry.to_vec())?;
+
+    if is_expired(expires) {
+        tree.remove(key).context("Failed to remove from sled")?;
+        return Ok(None);
+    }
+
+    Ok(Some((value, expires)))
+}
+
+impl KvStore for SledStore {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>>> {
+        let key = key.to_owned();
+        self.blocking(move |tree| {
+            Ok(get_live(tree, &key)?.map(|(value, _)| value))
+        })
+    }
+
+    fn put<'a>(&'a self, key: &'a str, value: Vec<u8>, ttl: Option<Duration>) -> BoxFuture<'a, Result<()>> {
+        let key = key.to_owned();
+        let entry = encode_entry(&value, expiry(ttl));
+        self.blocking(move |tree| {
+            tree.insert(key, entry)
+                .map(|_| ())
+                .context("Failed to write to sled")
+        })
+    }
+
+    fn remove<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Vec<u8>>>> {
+        let key = key.to_owned();
+        self.blocking(move |tree| {
+            let entry = match tree.remove(key).context("Failed to remove from sled")? {
+                Some(entry) => entry,
+                None => return Ok(None),
+            };
+            let (value, expires) = decode_entry(entry.to_vec())?;
+
+            Ok(Some(value).filter(|_| !is_expired(expires)))
+        })
+    }
+
+    fn scan<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<KeyValues>> {
+        let prefix = prefix.to_owned();
+        self.blocking(move |tree| {
+            let mut found = Vec::new();
+
+            for entry in tree.scan_prefix(prefix) {
+                let (key, entry) = entry.context("Failed to read from sled")?;
+                let (value, expires) = decode_entry(entry.to_vec())?;
+
+                if !is_expired(expires) {
+                    found.push((String::from_utf8_lossy(&key).into_owned(), value));
+                }
+            }
+
+            Ok(found)
+        })
+    }
+
+    fn ttl<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Option<Duration>>>> {
+        let key = key.to_owned();
+        self.blocking(move |tree| {
+            Ok(get_live(tree, &key)?.map(|(_, expires)| remaining(expires)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn sled_store() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let store = SledStore::from_tree((*db).clone());
+
+        super::super::tests::check_store(&store).await;
+    }
+}
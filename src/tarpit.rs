@@ -0,0 +1,320 @@
+//! Wasting the time of vulnerability scanners
+//!
+//! Scanners probe every server they find for well known paths, like `/wp-login.php` or
+//! `/.env`, which no Gemini capsule serves.  Instead of answering them right away, such
+//! decoy paths can be routed to a [`Tarpit`], which keeps the scanner waiting for a
+//! long time before answering.  A waiting connection costs the server nothing but a
+//! timer, while a scanner waiting on it can't move on to the next target.
+//!
+//! Clients walking into a tarpit are also recorded in [`FlaggedPeers`], which is a
+//! piece of middleware answering every further request from them with `44 SLOW DOWN`
+//! for a while.  This keeps scanners away from the real content of the capsule.
+//!
+//! ```no_run
+//! # use twinstar::{Server, GEMINI_PORT, tarpit::{Tarpit, FlaggedPeers}};
+//! # async fn run() -> anyhow::Result<()> {
+//! let flagged = FlaggedPeers::new();
+//! let tarpit = Tarpit::new().set_flagged_peers(flagged.clone());
+//!
+//! Server::bind(("localhost", GEMINI_PORT))
+//!     .add_middleware(flagged)
+//!     .add_route("/wp-login.php", tarpit.clone().into_handler())
+//!     .add_route("/.env", tarpit.into_handler())
+//!     .serve()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use tokio::time;
+
+use crate::middleware::{Middleware, Next};
+use crate::types::{Request, Response};
+use crate::HandlerResponse;
+
+/// How long a tarpit holds a request by default
+pub const DEFAULT_TARPIT_DELAY: Duration = Duration::from_secs(30);
+
+/// How many requests a tarpit holds at most by default
+pub const DEFAULT_MAX_HELD: usize = 128;
+
+/// How long a peer stays flagged by default
+pub const DEFAULT_FLAG_DURATION: Duration = Duration::from_secs(60 * 60);
+
+/// How many peers are remembered at most by default
+pub const DEFAULT_MAX_FLAGGED: usize = 10_000;
+
+/// A handler answering requests as slowly as possible
+///
+/// Every request is held for the configured delay, and then answered with
+/// `51 NOT FOUND`, like any other missing page.  To keep the server from being
+/// exhausted by its own tarpit, at most a limited number of requests is held at once.
+/// Requests beyond that are answered right away.
+///
+/// Clones share the count of held requests, so the limit applies across every route a
+/// tarpit is mounted on.  See the [module documentation](self) for an example.
+#[derive(Debug, Clone)]
+pub struct Tarpit {
+    delay: Duration,
+    max_held: usize,
+    held: Arc<AtomicUsize>,
+    flagged: Option<FlaggedPeers>,
+}
+
+impl Tarpit {
+    /// Create a tarpit with the default delay and limit
+    pub fn new() -> Self {
+        Self {
+            delay: DEFAULT_TARPIT_DELAY,
+            max_held: DEFAULT_MAX_HELD,
+            held: Arc::new(AtomicUsize::new(0)),
+            flagged: None,
+        }
+    }
+
+    /// Set how long each request is held before it is answered
+    ///
+    /// The default is [`DEFAULT_TARPIT_DELAY`].
+    pub fn set_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Set how many requests are held at the same time at most
+    ///
+    /// The default is [`DEFAULT_MAX_HELD`].
+    pub fn set_max_held(mut self, max_held: usize) -> Self {
+        self.max_held = max_held;
+        self
+    }
+
+    /// Flag every client walking into the tarpit in `flagged`
+    pub fn set_flagged_peers(mut self, flagged: FlaggedPeers) -> Self {
+        self.flagged = Some(flagged);
+        self
+    }
+
+    /// The number of requests currently being held
+    pub fn held(&self) -> usize {
+        self.held.load(Ordering::Relaxed)
+    }
+
+    /// Turn this into a handler which can be passed to
+    /// [`Builder::add_route()`](crate::Builder::add_route())
+    pub fn into_handler(self) -> impl Fn(Request) -> HandlerResponse + Send + Sync {
+        move |request: Request| self.serve(&request)
+    }
+
+    /// Answer a request, after holding it if there is room
+    pub fn serve(&self, request: &Request) -> HandlerResponse {
+        if let (Some(flagged), Some(peer_addr)) = (&self.flagged, request.remote_addr()) {
+            flagged.flag(peer_addr.ip());
+        }
+
+        let held = match HeldRequest::try_new(self) {
+            Some(held) => held,
+            None => return Box::pin(async { Ok(Response::not_found()) }),
+        };
+
+        let delay = self.delay;
+        Box::pin(async move {
+            time::sleep(delay).await;
+            drop(held);
+            Ok(Response::not_found())
+        })
+    }
+}
+
+impl Default for Tarpit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Counts a request as held by a tarpit until it is dropped
+struct HeldRequest {
+    held: Arc<AtomicUsize>,
+}
+
+impl HeldRequest {
+    fn try_new(tarpit: &Tarpit) -> Option<Self> {
+        let held = tarpit.held.fetch_add(1, Ordering::AcqRel);
+        let guard = Self { held: tarpit.held.clone() };
+
+        if held >= tarpit.max_held {
+            return None;
+        }
+
+        Some(guard)
+    }
+}
+
+impl Drop for HeldRequest {
+    fn drop(&mut self) {
+        self.held.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Clients which recently misbehaved, answered with `44 SLOW DOWN` until they expire
+///
+/// Peers are flagged by a [`Tarpit`], or by calling [`flag()`](Self::flag()) from any
+/// other handler or middleware.  Added as middleware, every request from a flagged peer
+/// is answered with `44 SLOW DOWN`, asking the client to wait until the flag expires.
+///
+/// At most a limited number of peers are remembered, so a flood of addresses can't
+/// exhaust the server's memory.  Once full, no new peers are flagged until existing
+/// flags expire.  This is a cheap handle, clones share the same set of peers.
+#[derive(Debug, Clone)]
+pub struct FlaggedPeers {
+    peers: Arc<Mutex<HashMap<IpAddr, Instant>>>,
+    duration: Duration,
+    max_peers: usize,
+}
+
+impl FlaggedPeers {
+    /// Create an empty set, flagging peers for [`DEFAULT_FLAG_DURATION`]
+    pub fn new() -> Self {
+        Self {
+            peers: Arc::default(),
+            duration: DEFAULT_FLAG_DURATION,
+            max_peers: DEFAULT_MAX_FLAGGED,
+        }
+    }
+
+    /// Set how long peers stay flagged
+    pub fn set_duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    /// Set how many peers are remembered at most
+    ///
+    /// The default is [`DEFAULT_MAX_FLAGGED`].
+    pub fn set_max_peers(mut self, max_peers: usize) -> Self {
+        self.max_peers = max_peers;
+        self
+    }
+
+    /// Flag `ip` for the configured duration, starting now
+    pub fn flag(&self, ip: IpAddr) {
+        let now = Instant::now();
+        let mut peers = self.peers.lock().unwrap();
+
+        if peers.len() >= self.max_peers && !peers.contains_key(&ip) {
+            peers.retain(|_, until| *until > now);
+
+            if peers.len() >= self.max_peers {
+                return;
+            }
+        }
+
+        peers.insert(ip, now + self.duration);
+    }
+
+    /// Remove the flag of `ip`, if any
+    pub fn unflag(&self, ip: IpAddr) {
+        self.peers.lock().unwrap().remove(&ip);
+    }
+
+    /// How long `ip` stays flagged, or `None` if it isn't flagged
+    pub fn remaining(&self, ip: IpAddr) -> Option<Duration> {
+        let now = Instant::now();
+        let until = *self.peers.lock().unwrap().get(&ip)?;
+
+        if until > now {
+            Some(until - now)
+        } else {
+            None
+        }
+    }
+
+    /// Whether `ip` is currently flagged
+    pub fn is_flagged(&self, ip: IpAddr) -> bool {
+        self.remaining(ip).is_some()
+    }
+}
+
+impl Default for FlaggedPeers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Middleware for FlaggedPeers {
+    fn handle(&self, request: Request, next: Next) -> HandlerResponse {
+        let remaining = request.remote_addr()
+            .and_then(|peer_addr| self.remaining(peer_addr.ip()));
+
+        if let Some(remaining) = remaining {
+            // Round up, so clients don't come back a moment too early
+            let seconds = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+            return Box::pin(async move { Ok(Response::slow_down(seconds)) });
+        }
+
+        next.run(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use super::*;
+    use crate::types::Status;
+    use crate::uri::URIReference;
+
+    fn request(peer: &str) -> Request {
+        let uri = URIReference::try_from("gemini://localhost/wp-login.php").unwrap().into_owned();
+        let mut request = Request::from_uri(uri).unwrap();
+        request.set_remote_addr(Some(peer.parse().unwrap()));
+        request
+    }
+
+    #[tokio::test]
+    async fn holds_and_flags_peers() {
+        let flagged = FlaggedPeers::new();
+        let tarpit = Tarpit::new()
+            .set_delay(Duration::from_millis(50))
+            .set_max_held(1)
+            .set_flagged_peers(flagged.clone());
+
+        let start = Instant::now();
+        let held = tarpit.serve(&request("192.0.2.1:1965"));
+        assert_eq!(tarpit.held(), 1);
+
+        // Over the limit, answered right away
+        let response = tarpit.serve(&request("192.0.2.2:1965")).await.unwrap();
+        assert_eq!(response.header().status, Status::NOT_FOUND);
+        assert!(start.elapsed() < Duration::from_millis(50));
+
+        let response = held.await.unwrap();
+        assert_eq!(response.header().status, Status::NOT_FOUND);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(tarpit.held(), 0);
+
+        assert!(flagged.is_flagged("192.0.2.1".parse().unwrap()));
+        assert!(flagged.is_flagged("192.0.2.2".parse().unwrap()));
+        assert!(!flagged.is_flagged("192.0.2.3".parse().unwrap()));
+    }
+
+    #[test]
+    fn limits_flagged_peers() {
+        let flagged = FlaggedPeers::new().set_max_peers(1);
+        flagged.flag("192.0.2.1".parse().unwrap());
+        flagged.flag("192.0.2.2".parse().unwrap());
+        assert!(!flagged.is_flagged("192.0.2.2".parse().unwrap()));
+
+        flagged.unflag("192.0.2.1".parse().unwrap());
+        flagged.flag("192.0.2.2".parse().unwrap());
+        assert!(flagged.is_flagged("192.0.2.2".parse().unwrap()));
+
+        let expired = FlaggedPeers::new().set_duration(Duration::from_secs(0));
+        expired.flag("192.0.2.1".parse().unwrap());
+        assert_eq!(expired.remaining("192.0.2.1".parse().unwrap()), None);
+    }
+}
@@ -0,0 +1,440 @@
+//! Utilities for testing capsules
+//!
+//! Areas of a capsule which require a client certificate answer with
+//! `60 CLIENT CERTIFICATE REQUIRED`, `61 CERTIFICATE NOT AUTHORISED` or
+//! `62 CERTIFICATE NOT VALID`.  Testing those paths needs certificates with a known
+//! common name and validity, which [`TestCertificate`] generates on the fly, without
+//! keeping any key material in the repository or calling out to `openssl`.
+//!
+//! The certificates are self-signed ECDSA P-256 certificates.  They can be presented by a
+//! [`Client`](crate::client::Client) with the `client` feature, or written to disk and
+//! used as the server's own certificate:
+//!
+//! ```no_run
+//! # #[cfg(all(feature="client", feature="testing"))] {
+//! # use twinstar::{client::Client, testing::TestCertificate};
+//! # async fn run() -> anyhow::Result<()> {
+//! let alice = TestCertificate::new("alice").generate()?;
+//! let expired = TestCertificate::new("bob").expired().generate()?;
+//!
+//! let client = Client::new().set_identity(alice.identity());
+//! let response = client.request("gemini://localhost/private").await?;
+//! # Ok(())
+//! # }
+//! # }
+//! ```
+//!
+//! This requires the `testing` feature.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Result, Context, anyhow};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+#[cfg(feature="client")]
+use rustls::{Certificate, PrivateKey};
+
+#[cfg(feature="client")]
+use crate::client::Identity;
+use crate::types::PeerCertificate;
+
+/// How long generated certificates are valid by default
+pub const DEFAULT_TEST_VALIDITY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How far in the past the validity of generated certificates starts by default,
+/// so slightly skewed clocks don't matter
+const CLOCK_SKEW: Duration = Duration::from_secs(60 * 60);
+
+// Object identifiers, DER encoded without tag and length
+const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+const OID_EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+const OID_PRIME256V1: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+const OID_ECDSA_WITH_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+const OID_BASIC_CONSTRAINTS: &[u8] = &[0x55, 0x1d, 0x13];
+const OID_SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+
+/// Parameters for generating a throwaway certificate
+///
+/// By default, certificates are valid from an hour ago until [`DEFAULT_TEST_VALIDITY`]
+/// from now.  See the [module documentation](self) for an example.
+#[derive(Debug, Clone)]
+pub struct TestCertificate {
+    common_name: String,
+    dns_names: Vec<String>,
+    not_before: SystemTime,
+    not_after: SystemTime,
+}
+
+impl TestCertificate {
+    /// Create a certificate for `common_name`, which is also used as the issuer
+    pub fn new(common_name: impl Into<String>) -> Self {
+        let now = SystemTime::now();
+
+        Self {
+            common_name: common_name.into(),
+            dns_names: Vec::new(),
+            not_before: now - CLOCK_SKEW,
+            not_after: now + DEFAULT_TEST_VALIDITY,
+        }
+    }
+
+    /// Add a DNS name to the subject alternative names of the certificate
+    ///
+    /// Clients checking the hostname of a server look at these names, so server
+    /// certificates should list the hostnames they are used for.
+    pub fn add_dns_name(mut self, name: impl Into<String>) -> Self {
+        self.dns_names.push(name.into());
+        self
+    }
+
+    /// Set when the certificate becomes valid
+    pub fn set_not_before(mut self, not_before: SystemTime) -> Self {
+        self.not_before = not_before;
+        self
+    }
+
+    /// Set when the certificate stops being valid
+    pub fn set_not_after(mut self, not_after: SystemTime) -> Self {
+        self.not_after = not_after;
+        self
+    }
+
+    /// Make the certificate valid for `validity`, starting now
+    pub fn set_validity(mut self, validity: Duration) -> Self {
+        let now = SystemTime::now();
+        self.not_before = now;
+        self.not_after = now + validity;
+        self
+    }
+
+    /// Make the certificate one which expired a day ago
+    pub fn expired(mut self) -> Self {
+        let now = SystemTime::now();
+        self.not_before = now - 2 * DEFAULT_TEST_VALIDITY;
+        self.not_after = now - DEFAULT_TEST_VALIDITY;
+        self
+    }
+
+    /// Make the certificate one which only becomes valid in a day
+    pub fn not_yet_valid(mut self) -> Self {
+        let now = SystemTime::now();
+        self.not_before = now + DEFAULT_TEST_VALIDITY;
+        self.not_after = now + 2 * DEFAULT_TEST_VALIDITY;
+        self
+    }
+
+    /// Generate a fresh key pair and a certificate signed by it
+    pub fn generate(&self) -> Result<GeneratedCertificate> {
+        let rng = SystemRandom::new();
+        let key = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng)
+            .map_err(|_| anyhow!("Failed to generate key"))?;
+        let key_pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, key.as_ref(), &rng)
+            .map_err(|_| anyhow!("Failed to load generated key"))?;
+
+        let mut serial = [0; 16];
+        rng.fill(&mut serial).map_err(|_| anyhow!("Failed to generate serial number"))?;
+        // Positive, and without leading zeros
+        serial[0] = (serial[0] & 0x7f) | 0x40;
+
+        let algorithm = der_sequence(&[&der(TAG_OID, OID_ECDSA_WITH_SHA256)]);
+        let name = der_sequence(&[&der(TAG_SET, &der_sequence(&[
+            &der(TAG_OID, OID_COMMON_NAME),
+            &der(TAG_UTF8_STRING, self.common_name.as_bytes()),
+        ]))]);
+        let public_key = der_sequence(&[
+            &der_sequence(&[&der(TAG_OID, OID_EC_PUBLIC_KEY), &der(TAG_OID, OID_PRIME256V1)]),
+            &der_bit_string(key_pair.public_key().as_ref()),
+        ]);
+
+        // webpki rejects certificates without extensions, so always mark it as not being a CA
+        let mut extensions = vec![der_sequence(&[
+            &der(TAG_OID, OID_BASIC_CONSTRAINTS),
+            &der(TAG_OCTET_STRING, &der_sequence(&[])),
+        ])];
+        if !self.dns_names.is_empty() {
+            let names = self.dns_names.iter()
+                .map(|name| der(TAG_DNS_NAME, name.as_bytes()))
+                .collect::<Vec<_>>();
+            extensions.push(der_sequence(&[
+                &der(TAG_OID, OID_SUBJECT_ALT_NAME),
+                &der(TAG_OCTET_STRING, &der_sequence(&names.iter().map(Vec::as_slice).collect::<Vec<_>>())),
+            ]));
+        }
+
+        let tbs_certificate = der_sequence(&[
+            // Version 3
+            &der(TAG_VERSION, &der(TAG_INTEGER, &[2])),
+            &der(TAG_INTEGER, &serial),
+            &algorithm,
+            &name,
+            &der_sequence(&[&der_time(self.not_before)?, &der_time(self.not_after)?]),
+            &name,
+            &public_key,
+            &der(TAG_EXTENSIONS, &der_sequence(&extensions.iter().map(Vec::as_slice).collect::<Vec<_>>())),
+        ]);
+
+        let signature = key_pair.sign(&rng, &tbs_certificate)
+            .map_err(|_| anyhow!("Failed to sign certificate"))?;
+        let certificate = der_sequence(&[
+            &tbs_certificate,
+            &algorithm,
+            &der_bit_string(signature.as_ref()),
+        ]);
+
+        Ok(GeneratedCertificate {
+            certificate: PeerCertificate::from_der(certificate),
+            key: key.as_ref().to_vec(),
+        })
+    }
+}
+
+/// A certificate generated by [`TestCertificate`], along with its private key
+#[derive(Debug, Clone)]
+pub struct GeneratedCertificate {
+    certificate: PeerCertificate,
+    key: Vec<u8>,
+}
+
+impl GeneratedCertificate {
+    /// The DER encoded certificate, as seen by the server in [`Request::certificate()`](Request::certificate())
+    pub fn certificate(&self) -> &PeerCertificate {
+        &self.certificate
+    }
+
+    /// The DER encoded PKCS#8 private key
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// Present this certificate with a [`Client`](crate::client::Client)
+    #[cfg(feature="client")]
+    pub fn identity(&self) -> Identity {
+        Identity::new(
+            vec![Certificate(self.certificate.as_der().to_vec())],
+            PrivateKey(self.key.clone()),
+        )
+    }
+
+    /// The PEM encoded certificate
+    pub fn certificate_pem(&self) -> String {
+        pem("CERTIFICATE", self.certificate.as_der())
+    }
+
+    /// The PEM encoded PKCS#8 private key
+    pub fn key_pem(&self) -> String {
+        pem("PRIVATE KEY", &self.key)
+    }
+
+    /// Write `cert.pem` and `key.pem` into `dir`, e.g. for use with
+    /// [`Builder::set_tls_dir()`](crate::Builder::set_tls_dir())
+    ///
+    /// Returns the paths of the certificate and the key.
+    pub fn write_pem_files(&self, dir: impl AsRef<Path>) -> Result<(PathBuf, PathBuf)> {
+        let dir = dir.as_ref();
+        let cert_path = dir.join("cert.pem");
+        let key_path = dir.join("key.pem");
+
+        std::fs::write(&cert_path, self.certificate_pem())
+            .with_context(|| format!("Failed to write `{}`", cert_path.display()))?;
+        std::fs::write(&key_path, self.key_pem())
+            .with_context(|| format!("Failed to write `{}`", key_path.display()))?;
+
+        Ok((cert_path, key_path))
+    }
+}
+
+const TAG_INTEGER: u8 = 0x02;
+const TAG_BIT_STRING: u8 = 0x03;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_OID: u8 = 0x06;
+const TAG_UTF8_STRING: u8 = 0x0c;
+const TAG_UTC_TIME: u8 = 0x17;
+const TAG_GENERALIZED_TIME: u8 = 0x18;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_SET: u8 = 0x31;
+const TAG_VERSION: u8 = 0xa0;
+const TAG_EXTENSIONS: u8 = 0xa3;
+const TAG_DNS_NAME: u8 = 0x82;
+
+pub(crate) fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![tag];
+    let len = content.len();
+
+    if len < 0x80 {
+        encoded.push(len as u8);
+    } else {
+        let len_bytes = len.to_be_bytes();
+        let skip = len_bytes.iter().take_while(|&&byte| byte == 0).count();
+        encoded.push(0x80 | (len_bytes.len() - skip) as u8);
+        encoded.extend_from_slice(&len_bytes[skip..]);
+    }
+
+    encoded.extend_from_slice(content);
+    encoded
+}
+
+pub(crate) fn der_sequence(elements: &[&[u8]]) -> Vec<u8> {
+    der(TAG_SEQUENCE, &elements.concat())
+}
+
+fn der_bit_string(bits: &[u8]) -> Vec<u8> {
+    // No unused bits
+    der(TAG_BIT_STRING, &[&[0], bits].concat())
+}
+
+/// Encode a time as UTCTime before 2050, and as GeneralizedTime after, as required by RFC 5280
+fn der_time(time: SystemTime) -> Result<Vec<u8>> {
+    let seconds = time.duration_since(UNIX_EPOCH)
+        .context("Certificate validity must not start before 1970")?
+        .as_secs();
+    let (year, month, day) = civil_from_days(seconds / 86400);
+    let time_of_day = seconds % 86400;
+    let rest = format!(
+        "{:02}{:02}{:02}{:02}{:02}Z",
+        month, day, time_of_day / 3600, time_of_day / 60 % 60, time_of_day % 60,
+    );
+
+    Ok(if year < 2050 {
+        der(TAG_UTC_TIME, format!("{:02}{}", year % 100, rest).as_bytes())
+    } else {
+        der(TAG_GENERALIZED_TIME, format!("{:04}{}", year, rest).as_bytes())
+    })
+}
+
+/// Convert days since 1970-01-01 into a year, month and day
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    // Shift the epoch to 0000-03-01, so leap days end the year
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    (year, month, day)
+}
+
+fn pem(label: &str, der: &[u8]) -> String {
+    let encoded = base64::encode(der);
+    let mut pem = format!("-----BEGIN {}-----\n", label);
+
+    for line in encoded.as_bytes().chunks(64) {
+        pem.push_str(std::str::from_utf8(line).expect("twinstar BUG"));
+        pem.push('\n');
+    }
+
+    pem.push_str(&format!("-----END {}-----\n", label));
+    pem
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature="client")]
+    use crate::client::Client;
+    #[cfg(feature="client")]
+    use crate::types::{Request, Response, Status};
+    #[cfg(feature="client")]
+    use crate::{HandlerResponse, Server};
+    use std::convert::TryFrom;
+    use webpki::{EndEntityCert, Time};
+
+    fn verify(generated: &GeneratedCertificate) -> Result<(), webpki::Error> {
+        let der = generated.certificate().as_der();
+        let anchor = webpki::TrustAnchor::try_from_cert_der(der)?;
+        let now = Time::try_from(SystemTime::now()).unwrap();
+
+        EndEntityCert::try_from(der)?.verify_for_usage(
+            &[&webpki::ECDSA_P256_SHA256],
+            &[anchor],
+            &[],
+            now,
+            webpki::KeyUsage::client_auth(),
+            &[],
+        )
+    }
+
+    #[test]
+    fn converts_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(29_220), (2050, 1, 1));
+
+        let time = UNIX_EPOCH + Duration::from_secs(951_782_400 + 3723);
+        assert_eq!(der_time(time).unwrap()[2..], b"000229010203Z"[..]);
+    }
+
+    #[test]
+    fn generates_valid_certificates() {
+        let alice = TestCertificate::new("alice").generate().unwrap();
+        assert_eq!(verify(&alice), Ok(()));
+        #[cfg(feature="client")]
+        assert_eq!(alice.identity().certificate(), alice.certificate().as_der());
+
+        let expired = TestCertificate::new("bob").expired().generate().unwrap();
+        assert_eq!(verify(&expired), Err(webpki::Error::CertExpired));
+
+        let early = TestCertificate::new("carol").not_yet_valid().generate().unwrap();
+        assert_eq!(verify(&early), Err(webpki::Error::CertNotValidYet));
+
+        #[cfg(feature="client")]
+        {
+            let pem = alice.certificate_pem();
+            let parsed = Identity::from_pem(pem.as_bytes(), alice.key_pem().as_bytes()).unwrap();
+            assert_eq!(parsed.certificate(), alice.certificate().as_der());
+        }
+
+        let server = TestCertificate::new("localhost").add_dns_name("localhost").generate().unwrap();
+        let server = EndEntityCert::try_from(server.certificate().as_der()).unwrap();
+        let name = |name| webpki::SubjectNameRef::try_from_ascii_str(name).unwrap();
+        assert_eq!(server.verify_is_valid_for_subject_name(name("localhost")), Ok(()));
+        assert!(server.verify_is_valid_for_subject_name(name("example.com")).is_err());
+    }
+
+    #[cfg(feature="client")]
+    #[tokio::test]
+    async fn authenticates_clients() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        TestCertificate::new("localhost").generate().unwrap().write_pem_files(dir).unwrap();
+
+        let alice = TestCertificate::new("alice").generate().unwrap();
+        let mallory = TestCertificate::new("mallory").generate().unwrap();
+        let authorized = alice.certificate().clone();
+
+        let server = Server::bind(("localhost", 0))
+            .set_tls_dir(dir)
+            .add_route("/", move |request: Request| {
+                let response = match request.certificate() {
+                    None => Response::client_certificate_required(),
+                    Some(cert) if *cert != authorized => Response::certificate_not_authorized(),
+                    Some(_) => Response::success_plain("hello alice"),
+                };
+                Box::pin(async move { Ok(response) }) as HandlerResponse
+            })
+            .build()
+            .await
+            .unwrap();
+        let url = format!("gemini://{}/", server.local_addrs()[0]);
+        tokio::spawn(server.serve());
+
+        let status = |identity: Option<Identity>| {
+            let url = url.clone();
+            async move {
+                let response = Client::new()
+                    .request_with_identity(&url, identity.as_ref())
+                    .await
+                    .unwrap();
+                response.status()
+            }
+        };
+
+        assert_eq!(status(None).await, Status::CLIENT_CERTIFICATE_REQUIRED);
+        assert_eq!(status(Some(mallory.identity())).await, Status::CERTIFICATE_NOT_AUTHORIZED);
+        assert_eq!(status(Some(alice.identity())).await, Status::SUCCESS);
+    }
+}
@@ -0,0 +1,238 @@
+//! Receiving uploads over the Titan protocol
+//!
+//! [Titan](gemini://transjovian.org/titan) is Gemini's companion protocol for
+//! uploading files.  A Titan request uses the `titan` scheme, names the size and MIME
+//! type of the upload as parameters following the path, and is followed by the
+//! uploaded bytes:
+//!
+//! ```text
+//! titan://example.org/notes/today.gmi;mime=text/gemini;size=12;token=hunter2
+//! ```
+//!
+//! The server reads the upload before routing the request, with the parameters
+//! stripped from its path, so the request above is routed to `/notes/today.gmi`.
+//! Handlers find the upload using [`Upload::of()`], and answer as usual, usually with
+//! a redirect to the page that was uploaded:
+//!
+//! ```no_run
+//! # use twinstar::{Server, Request, Response, GEMINI_PORT, titan::Upload};
+//! # async fn run() -> anyhow::Result<()> {
+//! Server::bind(("localhost", GEMINI_PORT))
+//!     .set_max_upload_size(64 * 1024)
+//!     .add_route("/notes", |request: Request| Box::pin(async move {
+//!         let upload = match Upload::of(&request) {
+//!             Some(upload) if upload.token() == Some("hunter2") => upload,
+//!             Some(_) => return Ok(Response::certificate_not_authorized()),
+//!             None => return Ok(Response::success_plain("Upload notes using Titan")),
+//!         };
+//!
+//!         // Store `upload.data()`
+//!         let page = format!("gemini://localhost{}", request.uri().path());
+//!         Ok(Response::redirect_temporary_lossy(page.as_str()))
+//!     }) as _)
+//!     .serve()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Uploads larger than the [limit](crate::Builder::set_max_upload_size()) are answered
+//! with `59 BAD REQUEST` without being read.  Since the server answers for `gemini` and
+//! `titan` URIs alike, [hostnames](crate::Builder::set_hostnames()) cover both.
+//!
+//! This module requires the `titan` feature.
+
+use std::time::Duration;
+
+use anyhow::{Result, Context};
+use percent_encoding::percent_decode_str;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::types::{Request, Response};
+
+/// The scheme of Titan requests
+pub const SCHEME: &str = "titan";
+
+/// The largest upload accepted by default, 10 MiB
+pub const DEFAULT_MAX_UPLOAD_SIZE: usize = 10 * 1024 * 1024;
+
+/// How long clients have to send their upload by default
+pub const DEFAULT_UPLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The MIME type of uploads which don't name one
+const DEFAULT_UPLOAD_MIME: &str = "text/gemini";
+
+/// A file uploaded using a Titan request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Upload {
+    mime: String,
+    token: Option<String>,
+    data: Vec<u8>,
+}
+
+impl Upload {
+    /// The upload sent along with a request, if it is a Titan request
+    pub fn of(request: &Request) -> Option<&Self> {
+        request.extensions().get::<Self>()
+    }
+
+    /// The MIME type of the upload, `text/gemini` unless the client named one
+    pub fn mime(&self) -> &str {
+        &self.mime
+    }
+
+    /// The token the client sent to authorize the upload, if any
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+
+    /// The uploaded bytes
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Take the uploaded bytes
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// The parameters of a Titan request
+#[derive(Debug, PartialEq, Eq)]
+struct Params {
+    path: String,
+    mime: Option<String>,
+    token: Option<String>,
+    size: usize,
+}
+
+/// Whether `request` is a Titan request
+pub(crate) fn is_titan(request: &Request) -> bool {
+    matches!(request.uri().scheme(), Some(scheme) if scheme.as_str().eq_ignore_ascii_case(SCHEME))
+}
+
+/// Split the parameters off the percent encoded `path` of a Titan request
+///
+/// The parameters start at the first `;` which is only followed by `name=value` pairs,
+/// since both the path and the MIME type may contain slashes.
+fn parse_params(path: &str) -> Result<Params, &'static str> {
+    let start = path.match_indices(';')
+        .map(|(start, _)| start)
+        .find(|&start| path[start + 1..].split(';').all(|param| param.contains('=')))
+        .ok_or("Titan request without parameters")?;
+
+    let mut params = Params {
+        path: path[..start].to_owned(),
+        mime: None,
+        token: None,
+        size: 0,
+    };
+    let mut size = None;
+
+    for param in path[start + 1..].split(';') {
+        let (name, value) = param.split_once('=').expect("twinstar BUG");
+        let value = percent_decode_str(value).decode_utf8()
+            .map_err(|_| "Titan parameter is not valid UTF-8")?
+            .into_owned();
+
+        match name {
+            "mime" => params.mime = Some(value),
+            "token" => params.token = Some(value),
+            "size" => size = Some(value.parse().map_err(|_| "Invalid size of upload")?),
+            _ => {},
+        }
+    }
+
+    params.size = size.ok_or("Titan request without size")?;
+    Ok(params)
+}
+
+/// Read the upload following a Titan request, and attach it to the request
+///
+/// The parameters are stripped from the path of the request.  If the request is
+/// malformed or the upload too large, the response refusing it is returned instead,
+/// without reading the upload.
+pub(crate) async fn receive_upload(
+    request: &mut Request,
+    stream: &mut (impl AsyncRead + Unpin),
+    max_size: usize,
+    timeout: Duration,
+) -> Result<Option<Response>> {
+    let params = match parse_params(&request.uri().path().to_string()) {
+        Ok(params) => params,
+        Err(reason) => return Ok(Some(Response::bad_request_lossy(reason))),
+    };
+
+    if params.size > max_size {
+        return Ok(Some(Response::bad_request_lossy(format!("Upload larger than {} bytes", max_size))));
+    }
+
+    request.set_path(if params.path.is_empty() { "/" } else { &params.path })?;
+
+    let mut data = Vec::with_capacity(params.size);
+    let mut upload = stream.take(params.size as u64);
+    tokio::time::timeout(timeout, upload.read_to_end(&mut data)).await
+        .context("Client timed out while sending upload")?
+        .context("Failed to receive upload")?;
+
+    anyhow::ensure!(data.len() == params.size, "Connection closed after {} of {} uploaded bytes", data.len(), params.size);
+
+    request.extensions_mut().insert(Upload {
+        mime: params.mime.unwrap_or_else(|| DEFAULT_UPLOAD_MIME.to_owned()),
+        token: params.token,
+        data,
+    });
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use super::*;
+    use crate::types::{Status, URIReference};
+
+    fn request(uri: &str) -> Request {
+        Request::from_uri(URIReference::try_from(uri).unwrap().into_owned()).unwrap()
+    }
+
+    #[test]
+    fn parses_params() {
+        assert_eq!(parse_params("/notes/today.gmi;mime=text/plain;size=12;token=a%20b"), Ok(Params {
+            path: "/notes/today.gmi".to_owned(),
+            mime: Some("text/plain".to_owned()),
+            token: Some("a b".to_owned()),
+            size: 12,
+        }));
+        assert_eq!(parse_params("/;size=0").map(|params| params.path), Ok("/".to_owned()));
+        assert_eq!(parse_params("/a;b/today.gmi"), Err("Titan request without parameters"));
+        assert_eq!(parse_params("/today.gmi;mime=text/plain"), Err("Titan request without size"));
+        assert_eq!(parse_params("/today.gmi;size=-1"), Err("Invalid size of upload"));
+    }
+
+    #[tokio::test]
+    async fn receives_uploads() {
+        let mut titan = request("titan://localhost/notes/today.gmi;size=5;token=secret");
+        assert!(is_titan(&titan));
+        assert!(!is_titan(&request("gemini://localhost/")));
+
+        let mut stream = &b"hello, and more"[..];
+        let refused = receive_upload(&mut titan, &mut stream, 5, DEFAULT_UPLOAD_TIMEOUT).await.unwrap();
+        assert!(refused.is_none());
+        assert_eq!(titan.uri().to_string(), "titan://localhost/notes/today.gmi");
+
+        let upload = Upload::of(&titan).unwrap();
+        assert_eq!(upload.data(), b"hello");
+        assert_eq!(upload.mime(), "text/gemini");
+        assert_eq!(upload.token(), Some("secret"));
+        assert_eq!(stream, b", and more");
+
+        let mut too_large = request("titan://localhost/big.iso;size=6");
+        let refused = receive_upload(&mut too_large, &mut &b"hello!"[..], 5, DEFAULT_UPLOAD_TIMEOUT).await.unwrap();
+        assert_eq!(refused.unwrap().header().status, Status::BAD_REQUEST);
+        assert!(Upload::of(&too_large).is_none());
+
+        let mut truncated = request("titan://localhost/today.gmi;size=10");
+        assert!(receive_upload(&mut truncated, &mut &b"hello"[..], 10, DEFAULT_UPLOAD_TIMEOUT).await.is_err());
+    }
+}
@@ -0,0 +1,396 @@
+//! Tools for checking a capsule before deploying it
+//!
+//! [`check_links()`] crawls a capsule starting at its root, and reports every internal
+//! link which doesn't lead anywhere.  The capsule can be a [`Server`] in the same
+//! process, which is crawled without any network traffic, or a remote capsule given
+//! by its URL:
+//!
+//! ```no_run
+//! # use twinstar::{Server, GEMINI_PORT, tools::check_links};
+//! # async fn run() -> anyhow::Result<()> {
+//! let server = Server::bind(("localhost", GEMINI_PORT))
+//!     // add routes here
+//!     .build()
+//!     .await?;
+//!
+//! let report = check_links(&server).await?;
+//! assert!(report.is_ok(), "{}", report);
+//!
+//! let report = check_links("gemini://example.org/").await?;
+//! println!("{}", report);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::{HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::{Result, Context};
+use tokio::io::AsyncReadExt;
+use uriparse::URI;
+
+use crate::client::Client;
+use crate::middleware::{Middleware, Next};
+use crate::routing::RoutingNode;
+use crate::types::{Body, Document, Request, Status, URIReference};
+use crate::{Handler, Server, GEMINI_MIME_STR};
+
+/// How many pages are crawled at most by default
+pub const DEFAULT_MAX_PAGES: usize = 1000;
+
+/// The capsule crawled by a [`LinkChecker`]
+pub enum CrawlTarget<'a> {
+    /// A server in the same process, crawled starting at `gemini://localhost/`
+    Server(&'a Server),
+    /// A remote capsule, crawled starting at this URL
+    Url(String),
+}
+
+impl<'a> From<&'a Server> for CrawlTarget<'a> {
+    fn from(server: &'a Server) -> Self {
+        Self::Server(server)
+    }
+}
+
+impl From<&str> for CrawlTarget<'_> {
+    fn from(url: &str) -> Self {
+        Self::Url(url.to_owned())
+    }
+}
+
+impl From<String> for CrawlTarget<'_> {
+    fn from(url: String) -> Self {
+        Self::Url(url)
+    }
+}
+
+/// A link which doesn't lead anywhere
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BrokenLink {
+    /// The page the link was found on
+    pub page: String,
+    /// Where the link leads, resolved against the page
+    pub link: String,
+    /// The status the link was answered with, if it was answered at all
+    pub status: Option<Status>,
+    /// What's wrong with the link, e.g. the meta of the response
+    pub problem: String,
+}
+
+impl fmt::Display for BrokenLink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} links to {}: ", self.page, self.link)?;
+
+        match self.status {
+            Some(status) => write!(f, "{} {}", status.code(), self.problem),
+            None => write!(f, "{}", self.problem),
+        }
+    }
+}
+
+/// The result of crawling a capsule
+#[derive(Debug, Clone, Default)]
+pub struct LinkReport {
+    /// The number of pages and links which were requested
+    pub checked: usize,
+    /// The links which don't lead anywhere
+    pub broken: Vec<BrokenLink>,
+    /// Whether the crawl stopped early because it reached the maximum number of pages
+    pub truncated: bool,
+}
+
+impl LinkReport {
+    /// Whether no broken links were found
+    pub fn is_ok(&self) -> bool {
+        self.broken.is_empty()
+    }
+}
+
+impl fmt::Display for LinkReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Checked {} links, {} broken", self.checked, self.broken.len())?;
+
+        for broken in &self.broken {
+            writeln!(f, "  {}", broken)?;
+        }
+
+        if self.truncated {
+            writeln!(f, "Stopped early, not every page was checked")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A crawler checking the internal links of a capsule
+///
+/// Starting at the root, every page is requested, and every gemtext page is searched for
+/// links to the same host.  Links which are answered with a temporary (`4x`) or
+/// permanent (`5x`) failure, or not at all, are reported as broken.  Input prompts
+/// (`1x`) and requests for a client certificate (`6x`) count as working links, since
+/// the crawler can't satisfy them.  Redirects are followed.
+///
+/// Links to other hosts, or using other schemes than `gemini`, are not checked.
+pub struct LinkChecker {
+    source: Source,
+    client: Client,
+    max_pages: usize,
+}
+
+/// Where a [`LinkChecker`] requests pages from
+enum Source {
+    Local {
+        middleware: Arc<[Arc<dyn Middleware>]>,
+        routes: Arc<RoutingNode<Handler>>,
+        fallback: Option<Handler>,
+    },
+    Remote(String),
+}
+
+impl LinkChecker {
+    /// Create a checker for `target`
+    pub fn new<'a>(target: impl Into<CrawlTarget<'a>>) -> Self {
+        let source = match target.into() {
+            CrawlTarget::Server(server) => Source::Local {
+                middleware: server.middleware.clone(),
+                routes: server.routes.clone(),
+                fallback: server.fallback.clone(),
+            },
+            CrawlTarget::Url(url) => Source::Remote(url),
+        };
+
+        Self::from_source(source)
+    }
+
+    fn from_source(source: Source) -> Self {
+        Self {
+            source,
+            client: Client::new(),
+            max_pages: DEFAULT_MAX_PAGES,
+        }
+    }
+
+    /// Set the client used for crawling remote capsules
+    ///
+    /// This allows e.g. crawling areas requiring a client certificate.
+    pub fn set_client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Set how many links are requested at most
+    ///
+    /// The default is [`DEFAULT_MAX_PAGES`].
+    pub fn set_max_pages(mut self, max_pages: usize) -> Self {
+        self.max_pages = max_pages;
+        self
+    }
+
+    /// Crawl the capsule
+    ///
+    /// This only fails if the starting URL is invalid.  Anything going wrong while
+    /// crawling is reported as a broken link.
+    pub async fn check(&self) -> Result<LinkReport> {
+        let start = match &self.source {
+            Source::Local { .. } => "gemini://localhost/",
+            Source::Remote(url) => url.as_str(),
+        };
+        let start = URI::try_from(start)
+            .with_context(|| format!("Invalid URL `{}`", start))?
+            .into_owned();
+
+        let mut report = LinkReport::default();
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::new();
+
+        seen.insert(start.to_string());
+        queue.push_back((start.clone(), None::<String>));
+
+        while let Some((uri, page)) = queue.pop_front() {
+            if report.checked >= self.max_pages {
+                report.truncated = true;
+                break;
+            }
+            report.checked += 1;
+
+            let broken = |status, problem: String| BrokenLink {
+                page: page.clone().unwrap_or_else(|| "(start)".to_owned()),
+                link: uri.to_string(),
+                status,
+                problem,
+            };
+
+            let (status, meta, body) = match self.fetch(&uri).await {
+                Ok(fetched) => fetched,
+                Err(err) => {
+                    report.broken.push(broken(None, format!("{:#}", err)));
+                    continue;
+                },
+            };
+
+            let category = status.category();
+            if category.is_temporary_failure() || category.is_permanent_failure() {
+                report.broken.push(broken(Some(status), meta));
+                continue;
+            }
+
+            let links = if category.redirect() {
+                vec![meta]
+            } else {
+                body.map(|body| Document::parse(&body).links().map(|(link, _)| link.to_string()).collect())
+                    .unwrap_or_default()
+            };
+
+            for link in links {
+                let link = match resolve(&uri, &link) {
+                    Some(link) => link,
+                    None => {
+                        report.broken.push(BrokenLink {
+                            page: uri.to_string(),
+                            link,
+                            status: None,
+                            problem: "Invalid link".to_owned(),
+                        });
+                        continue;
+                    },
+                };
+
+                if is_internal(&start, &link) && seen.insert(link.to_string()) {
+                    queue.push_back((link, Some(uri.to_string())));
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Request a page, returning the status, meta, and the body if it is gemtext
+    async fn fetch(&self, uri: &URI<'static>) -> Result<(Status, String, Option<String>)> {
+        match &self.source {
+            Source::Local { middleware, routes, fallback } => {
+                let request = Request::from_uri(URIReference::from(uri.clone()))?;
+                let next = Next::new(middleware.clone(), routes.clone())
+                    .with_fallback(fallback.clone());
+                let mut response = next.run(request).await?;
+                let header = response.header().clone();
+
+                let body = match response.take_body() {
+                    Some(body) if header.meta.as_str().starts_with(GEMINI_MIME_STR) => Some(read_body(body).await?),
+                    _ => None,
+                };
+
+                Ok((header.status, header.meta.as_str().to_owned(), body))
+            },
+            Source::Remote(_) => {
+                let response = self.client.request(&uri.to_string()).await?;
+                let status = response.status();
+                let meta = response.meta().to_owned();
+
+                let body = if meta.starts_with(GEMINI_MIME_STR) {
+                    Some(response.body_string().await?)
+                } else {
+                    None
+                };
+
+                Ok((status, meta, body))
+            },
+        }
+    }
+}
+
+/// Crawl a capsule with the default settings, see [`LinkChecker`]
+pub async fn check_links<'a>(target: impl Into<CrawlTarget<'a>>) -> Result<LinkReport> {
+    LinkChecker::new(target).check().await
+}
+
+async fn read_body(body: Body) -> Result<String> {
+    let bytes = match body {
+        Body::Bytes(bytes) => bytes,
+        Body::Inline(bytes) => bytes.to_vec(),
+        Body::Reader(mut reader) => {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await
+                .context("Failed to read response body")?;
+            bytes
+        },
+        #[cfg(feature="mmap")]
+        Body::Mmap(mapped) => mapped.to_vec(),
+    };
+
+    String::from_utf8(bytes).context("Response body is not valid UTF-8")
+}
+
+/// Resolve a link against the page it was found on, dropping the fragment
+fn resolve(base: &URI<'static>, link: &str) -> Option<URI<'static>> {
+    let link = URIReference::try_from(link).ok()?;
+    let base: URI<'_> = base.clone();
+    let mut resolved = base.resolve(&link).into_owned();
+    resolved.set_fragment(None::<uriparse::Fragment>).ok()?;
+
+    Some(resolved)
+}
+
+fn is_internal(start: &URI<'_>, link: &URI<'_>) -> bool {
+    link.scheme() == start.scheme() && link.authority() == start.authority()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Response;
+    use crate::HandlerResponse;
+
+    #[test]
+    fn resolves_links() {
+        let base = URI::try_from("gemini://example.org/posts/index.gmi").unwrap().into_owned();
+
+        assert_eq!(resolve(&base, "hello.gmi#top").unwrap().to_string(), "gemini://example.org/posts/hello.gmi");
+        assert_eq!(resolve(&base, "../about").unwrap().to_string(), "gemini://example.org/about");
+        assert!(is_internal(&base, &resolve(&base, "/").unwrap()));
+        assert!(!is_internal(&base, &resolve(&base, "gemini://example.com/").unwrap()));
+        assert!(!is_internal(&base, &resolve(&base, "https://example.org/").unwrap()));
+    }
+
+    /// A page only served at exactly the path it is routed at
+    fn page(body: &'static str) -> Handler {
+        Arc::new(move |request: Request| Box::pin(async move {
+            if request.trailing_segments().is_empty() {
+                Ok(Response::success_gemini(body))
+            } else {
+                Ok(Response::not_found())
+            }
+        }) as HandlerResponse)
+    }
+
+    #[tokio::test]
+    async fn finds_broken_links() {
+        let mut routes = RoutingNode::<Handler>::default();
+        routes.add_route("/", page("=> /posts Posts\n=> /missing Missing\n=> gemini://example.com/ Elsewhere\n"));
+        routes.add_route("/posts", page("=> / Home\n=> /old Old post\n"));
+        routes.add_route("/old", Arc::new(|_: Request| {
+            Box::pin(async { Ok(Response::redirect_permanent_lossy("/gone")) }) as HandlerResponse
+        }));
+
+        let routes = Arc::new(routes);
+        let checker = |max_pages| LinkChecker::from_source(Source::Local {
+            middleware: Arc::from(Vec::new()),
+            routes: routes.clone(),
+            fallback: None,
+        }).set_max_pages(max_pages);
+
+        let report = checker(DEFAULT_MAX_PAGES).check().await.unwrap();
+
+        assert_eq!(report.checked, 5);
+        assert_eq!(report.broken.len(), 2);
+        assert_eq!(report.broken[0].link, "gemini://localhost/missing");
+        assert_eq!(report.broken[0].page, "gemini://localhost/");
+        assert_eq!(report.broken[1].link, "gemini://localhost/gone");
+        assert_eq!(report.broken[1].page, "gemini://localhost/old");
+        assert!(!report.truncated);
+
+        let report = checker(2).check().await.unwrap();
+        assert!(report.truncated);
+    }
+}
@@ -0,0 +1,313 @@
+//! Learning the real address of clients behind a load balancer
+//!
+//! When a server is run behind a TCP load balancer or gateway, every connection
+//! appears to come from the gateway.  Gateways supporting the
+//! [PROXY protocol](https://www.haproxy.org/download/2.3/doc/proxy-protocol.txt) send
+//! the address of the real client at the start of each connection, before the TLS
+//! handshake.
+//!
+//! Since anyone can send such a header, it is only honored for connections coming from
+//! addresses listed in [`TrustedProxies`].  Connections from these addresses must start
+//! with a PROXY protocol header (version 1 or 2), all other connections must not.
+//!
+//! The address received this way replaces the address of the gateway everywhere,
+//! including the access log and middleware.
+//!
+//! ```no_run
+//! # use twinstar::{Server, GEMINI_PORT, trusted_proxies::TrustedProxies};
+//! # async fn run() -> anyhow::Result<()> {
+//! Server::bind(("0.0.0.0", GEMINI_PORT))
+//!     .set_trusted_proxies(TrustedProxies::new().trust("10.0.0.0/8")?.trust("::1")?)
+//!     .serve()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+
+use anyhow::{Result, Context, anyhow, bail, ensure};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// The signature starting every version 2 header
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// The maximum length of a version 1 header, including the CRLF
+const V1_MAX_LEN: usize = 107;
+
+/// A range of IP addresses in CIDR notation, like `10.0.0.0/8` or `fd00::/8`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpRange {
+    /// Whether `ip` is part of this range
+    ///
+    /// IPv4 addresses mapped into IPv6, like `::ffff:10.0.0.1`, are treated as IPv4
+    /// addresses.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, canonical(ip)) {
+            (IpAddr::V4(range), IpAddr::V4(ip)) => {
+                prefix_matches(&range.octets(), &ip.octets(), self.prefix_len)
+            },
+            (IpAddr::V6(range), IpAddr::V6(ip)) => {
+                prefix_matches(&range.octets(), &ip.octets(), self.prefix_len)
+            },
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = anyhow::Error;
+
+    /// Parse a range in CIDR notation, or a single address
+    fn from_str(range: &str) -> Result<Self> {
+        let (addr, prefix_len) = match range.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (range, None),
+        };
+
+        let addr = canonical(addr.parse::<IpAddr>()
+            .with_context(|| format!("Invalid address in `{}`", range))?);
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len.parse::<u8>()
+                .with_context(|| format!("Invalid prefix length in `{}`", range))?,
+            None => max_len,
+        };
+
+        ensure!(prefix_len <= max_len, "Prefix length of `{}` exceeds {}", range, max_len);
+
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+impl fmt::Display for IpRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+/// The addresses of gateways allowed to tell the server who the real client is
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    ranges: Vec<IpRange>,
+}
+
+impl TrustedProxies {
+    /// Trust no one
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trust the addresses in `range`, either in CIDR notation or a single address
+    pub fn trust(mut self, range: &str) -> Result<Self> {
+        self.ranges.push(range.parse()?);
+        Ok(self)
+    }
+
+    /// Trust the addresses in `range`
+    pub fn trust_range(mut self, range: IpRange) -> Self {
+        self.ranges.push(range);
+        self
+    }
+
+    /// Whether connections from `ip` are expected to carry a PROXY protocol header
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.ranges.iter().any(|range| range.contains(ip))
+    }
+
+    /// Read the PROXY protocol header from a connection of a trusted gateway
+    ///
+    /// Returns the address of the real client, or the address of the gateway if the
+    /// header doesn't carry one, e.g. for health checks.
+    pub(crate) async fn read_header(
+        stream: &mut (impl AsyncRead + Unpin),
+        peer_addr: SocketAddr,
+    ) -> Result<SocketAddr> {
+        let mut start = [0; 8];
+        stream.read_exact(&mut start).await
+            .context("Failed to read PROXY protocol header")?;
+
+        let client = if start.starts_with(b"PROXY ") {
+            read_v1(stream, start).await?
+        } else if start == V2_SIGNATURE[..8] {
+            read_v2(stream).await?
+        } else {
+            bail!("Connection from trusted proxy {} lacks a PROXY protocol header", peer_addr);
+        };
+
+        Ok(client.unwrap_or(peer_addr))
+    }
+}
+
+/// Read the rest of a version 1 header, like `PROXY TCP4 10.0.0.1 10.0.0.2 4242 1965`
+async fn read_v1(stream: &mut (impl AsyncRead + Unpin), start: [u8; 8]) -> Result<Option<SocketAddr>> {
+    let mut line = start.to_vec();
+
+    while !line.ends_with(b"\r\n") {
+        ensure!(line.len() < V1_MAX_LEN, "PROXY protocol header too long");
+        line.push(stream.read_u8().await.context("Failed to read PROXY protocol header")?);
+    }
+
+    let line = std::str::from_utf8(&line[..line.len() - 2])
+        .context("PROXY protocol header is not valid UTF-8")?;
+    let fields = line.split(' ').collect::<Vec<_>>();
+
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4", source, _, source_port, _] | ["PROXY", "TCP6", source, _, source_port, _] => {
+            let ip = source.parse::<IpAddr>()
+                .context("Invalid source address in PROXY protocol header")?;
+            let port = source_port.parse::<u16>()
+                .context("Invalid source port in PROXY protocol header")?;
+
+            Ok(Some(SocketAddr::new(ip, port)))
+        },
+        _ => bail!("Malformed PROXY protocol header `{}`", line),
+    }
+}
+
+/// Read the rest of a version 2 header, after the first 8 bytes of the signature
+async fn read_v2(stream: &mut (impl AsyncRead + Unpin)) -> Result<Option<SocketAddr>> {
+    let mut rest = [0; 8];
+    stream.read_exact(&mut rest).await
+        .context("Failed to read PROXY protocol header")?;
+
+    ensure!(rest[..4] == V2_SIGNATURE[8..], "Malformed PROXY protocol signature");
+
+    let version_command = rest[4];
+    let family = rest[5];
+    let len = u16::from_be_bytes([rest[6], rest[7]]) as usize;
+
+    ensure!(version_command >> 4 == 2, "Unsupported PROXY protocol version");
+
+    let mut addresses = vec![0; len];
+    stream.read_exact(&mut addresses).await
+        .context("Failed to read PROXY protocol addresses")?;
+
+    // LOCAL connections are made by the proxy itself, e.g. for health checks
+    if version_command & 0x0f == 0 {
+        return Ok(None);
+    }
+
+    let too_short = || anyhow!("PROXY protocol addresses are truncated");
+
+    match family >> 4 {
+        // AF_INET
+        1 => {
+            let addresses = addresses.get(..12).ok_or_else(too_short)?;
+            let ip = Ipv4Addr::from(<[u8; 4]>::try_from(&addresses[..4]).expect("twinstar BUG"));
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        },
+        // AF_INET6
+        2 => {
+            let addresses = addresses.get(..36).ok_or_else(too_short)?;
+            let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&addresses[..16]).expect("twinstar BUG"));
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        },
+        // AF_UNSPEC or AF_UNIX, which carry no usable address
+        _ => Ok(None),
+    }
+}
+
+/// Treat IPv4 addresses mapped into IPv6 as IPv4 addresses
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => ip,
+        },
+        ip => ip,
+    }
+}
+
+fn prefix_matches(range: &[u8], ip: &[u8], prefix_len: u8) -> bool {
+    let full_bytes = prefix_len as usize / 8;
+    let rest_bits = prefix_len % 8;
+
+    if range[..full_bytes] != ip[..full_bytes] {
+        return false;
+    }
+
+    if rest_bits == 0 {
+        return true;
+    }
+
+    let mask = 0xff_u8 << (8 - rest_bits);
+    range[full_bytes] & mask == ip[full_bytes] & mask
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gateway() -> SocketAddr {
+        "10.0.0.1:50000".parse().unwrap()
+    }
+
+    #[test]
+    fn matches_ranges() {
+        let proxies = TrustedProxies::new()
+            .trust("10.0.0.0/8").unwrap()
+            .trust("192.168.1.128/25").unwrap()
+            .trust("fd00::/8").unwrap();
+
+        assert!(proxies.is_trusted("10.1.2.3".parse().unwrap()));
+        assert!(proxies.is_trusted("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(proxies.is_trusted("192.168.1.200".parse().unwrap()));
+        assert!(!proxies.is_trusted("192.168.1.100".parse().unwrap()));
+        assert!(proxies.is_trusted("fd12::1".parse().unwrap()));
+        assert!(!proxies.is_trusted("fe80::1".parse().unwrap()));
+
+        assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+        assert_eq!("::1".parse::<IpRange>().unwrap().to_string(), "::1/128");
+    }
+
+    #[tokio::test]
+    async fn reads_v1_headers() {
+        let mut stream = &b"PROXY TCP4 203.0.113.7 10.0.0.2 4242 1965\r\n\x16\x03"[..];
+        let client = TrustedProxies::read_header(&mut stream, gateway()).await.unwrap();
+
+        assert_eq!(client, "203.0.113.7:4242".parse().unwrap());
+        assert_eq!(stream, b"\x16\x03");
+
+        let mut stream = &b"PROXY UNKNOWN\r\n"[..];
+        assert_eq!(TrustedProxies::read_header(&mut stream, gateway()).await.unwrap(), gateway());
+
+        let mut stream = &b"\x16\x03\x01\x02\x00\x01\x00\x01\xfc\x03\x03"[..];
+        assert!(TrustedProxies::read_header(&mut stream, gateway()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn reads_v2_headers() {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x21, 0x21, 0, 36]);
+        header.extend_from_slice(&"2001:db8::7".parse::<Ipv6Addr>().unwrap().octets());
+        header.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        header.extend_from_slice(&[0x10, 0x92, 0x07, 0xad]);
+        header.push(0x16);
+
+        let mut stream = &header[..];
+        let client = TrustedProxies::read_header(&mut stream, gateway()).await.unwrap();
+
+        assert_eq!(client, "[2001:db8::7]:4242".parse().unwrap());
+        assert_eq!(stream, b"\x16");
+
+        let mut local = V2_SIGNATURE.to_vec();
+        local.extend_from_slice(&[0x20, 0x00, 0, 0]);
+        assert_eq!(TrustedProxies::read_header(&mut &local[..], gateway()).await.unwrap(), gateway());
+    }
+}
@@ -1,24 +1,59 @@
+//! The types requests, responses and documents are made of
+//!
+//! Without the `std` feature, only [`Document`] and [`Status`] are available.
+
+#[cfg(feature="std")]
 pub use ::mime::Mime;
-pub use rustls::Certificate;
+#[cfg(feature="std")]
 pub use uriparse::URIReference;
 
+#[cfg(feature="std")]
 mod meta;
+#[cfg(feature="std")]
 pub use self::meta::Meta;
 
+#[cfg(feature="std")]
 mod request;
+#[cfg(feature="std")]
 pub use request::Request;
 
+#[cfg(feature="std")]
+mod peer_certificate;
+#[cfg(feature="std")]
+pub use peer_certificate::PeerCertificate;
+
+#[cfg(feature="std")]
+mod tls_info;
+#[cfg(feature="std")]
+pub use tls_info::TlsInfo;
+
+#[cfg(feature="std")]
+mod extensions;
+#[cfg(feature="std")]
+pub use extensions::Extensions;
+
+#[cfg(feature="std")]
 mod response_header;
+#[cfg(feature="std")]
 pub use response_header::ResponseHeader;
 
 mod status;
 pub use status::{Status, StatusCategory};
 
+#[cfg(feature="std")]
 mod response;
-pub use response::Response;
+#[cfg(feature="std")]
+pub use response::{Response, HeaderFlush, IntoResponse};
 
+#[cfg(feature="std")]
 mod body;
+#[cfg(feature="std")]
 pub use body::Body;
 
+#[cfg(feature="mmap")]
+mod mmap;
+#[cfg(feature="mmap")]
+pub use mmap::MappedFile;
+
 pub mod document;
 pub use document::Document;
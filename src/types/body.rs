@@ -1,14 +1,78 @@
-use tokio::io::AsyncRead;
+use tokio::io::{AsyncRead, AsyncReadExt};
 #[cfg(feature="serve_dir")]
 use tokio::fs::File;
 
 use std::borrow::Borrow;
+use std::io::Cursor;
+
+use smallvec::SmallVec;
 
 use crate::types::Document;
+#[cfg(feature="mmap")]
+use crate::types::MappedFile;
+
+/// How many bytes a body can hold without allocating
+///
+/// This is kept small enough that inline bodies don't make [`Body`], and with it every
+/// [`Response`](crate::Response), any larger than a `Vec` would.
+pub const INLINE_BODY_LEN: usize = 16;
 
+#[non_exhaustive]
 pub enum Body {
     Bytes(Vec<u8>),
+    /// A short body stored inline, saving an allocation for tiny responses
+    ///
+    /// Bodies created from short strings, slices and documents are stored this way.
+    Inline(SmallVec<[u8; INLINE_BODY_LEN]>),
     Reader(Box<dyn AsyncRead + Send + Sync + Unpin>),
+    /// A file mapped into memory, see [`MappedFile`]
+    ///
+    /// This requires the `mmap` feature.
+    #[cfg(feature="mmap")]
+    Mmap(MappedFile),
+}
+
+impl Body {
+    /// Append `other` to this body
+    ///
+    /// Two bodies held in memory are concatenated right away, otherwise the result reads
+    /// this body to its end, followed by `other`.
+    pub fn chain(self, other: impl Into<Body>) -> Self {
+        let other = other.into();
+
+        if let Some(tail) = other.as_bytes() {
+            match self {
+                Self::Bytes(mut bytes) => {
+                    bytes.extend_from_slice(tail);
+                    return Self::Bytes(bytes);
+                },
+                Self::Inline(bytes) => return Self::Bytes([&bytes[..], tail].concat()),
+                _ => {},
+            }
+        }
+
+        Self::Reader(Box::new(self.into_reader().chain(other.into_reader())))
+    }
+
+    /// The contents of this body, if it is held in memory
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Self::Bytes(bytes) => Some(bytes),
+            Self::Inline(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// Turn this body into a reader, regardless of how it is held
+    pub fn into_reader(self) -> Box<dyn AsyncRead + Send + Sync + Unpin> {
+        match self {
+            Self::Bytes(bytes) => Box::new(Cursor::new(bytes)),
+            Self::Inline(bytes) => Box::new(Cursor::new(bytes)),
+            Self::Reader(reader) => reader,
+            #[cfg(feature="mmap")]
+            Self::Mmap(mapped) => Box::new(Cursor::new(mapped)),
+        }
+    }
 }
 
 impl<D: Borrow<Document>> From<D> for Body {
@@ -25,19 +89,34 @@ impl From<Vec<u8>> for Body {
 
 impl<'a> From<&'a [u8]> for Body {
     fn from(bytes: &[u8]) -> Self {
-        Self::Bytes(bytes.to_owned())
+        if bytes.len() <= INLINE_BODY_LEN {
+            Self::Inline(SmallVec::from_slice(bytes))
+        } else {
+            Self::Bytes(bytes.to_owned())
+        }
     }
 }
 
 impl From<String> for Body {
     fn from(text: String) -> Self {
-        Self::Bytes(text.into_bytes())
+        if text.len() <= INLINE_BODY_LEN {
+            Self::from(text.as_bytes())
+        } else {
+            Self::Bytes(text.into_bytes())
+        }
     }
 }
 
 impl<'a> From<&'a str> for Body {
     fn from(text: &str) -> Self {
-        Self::Bytes(text.to_owned().into_bytes())
+        Self::from(text.as_bytes())
+    }
+}
+
+#[cfg(feature="mmap")]
+impl From<MappedFile> for Body {
+    fn from(mapped: MappedFile) -> Self {
+        Self::Mmap(mapped)
     }
 }
 
@@ -47,3 +126,26 @@ impl From<File> for Body {
         Self::Reader(Box::new(file))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_small_bodies_inline() {
+        assert!(matches!(Body::from("hello"), Body::Inline(_)));
+        assert!(matches!(Body::from(String::from("hello")), Body::Inline(_)));
+        assert!(matches!(Body::from(Document::new().add_text("hello")), Body::Inline(_)));
+        assert!(matches!(Body::from("x".repeat(INLINE_BODY_LEN + 1)), Body::Bytes(_)));
+        assert!(matches!(Body::from("x".repeat(INLINE_BODY_LEN + 1).as_str()), Body::Bytes(_)));
+
+        let chained = Body::from("hello ").chain("world");
+        assert_eq!(chained.as_bytes(), Some(&b"hello world"[..]));
+        assert!(Body::from("hello").chain(Body::Reader(Box::new(Cursor::new(Vec::new())))).as_bytes().is_none());
+    }
+
+    #[test]
+    fn inline_bodies_stay_small() {
+        assert!(std::mem::size_of::<Body>() <= std::mem::size_of::<Vec<u8>>() + 8);
+    }
+}
@@ -27,7 +27,7 @@
 //!     #### Heading 3\n\
 //!     \n\
 //!     text\n\
-//!     => gemini://gemini.circumlunar.space/ Project Gemini\n\
+//!     => gemini://gemini.circumlunar.space Project Gemini\n\
 //!     * list item\n\
 //!     > quote\n\
 //!     ```\n\
@@ -36,11 +36,14 @@
 //! ");
 //! ```
 #![warn(missing_docs)]
-use std::convert::TryInto;
-use std::fmt;
+use alloc::borrow::ToOwned;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
 
+#[cfg(feature="std")]
 use crate::types::URIReference;
-use crate::util::Cowy;
 
 #[derive(Default)]
 /// Represents a Gemini document.
@@ -161,7 +164,7 @@ impl Document {
 
     /// Adds a link to the document.
     ///
-    /// `uri`s that fail to parse are substituted with `.`.
+    /// `uri`s that aren't valid URI references are substituted with `.`.
     ///
     /// Consecutive newlines in `label` will be replaced
     /// with a single whitespace.
@@ -173,18 +176,12 @@ impl Document {
     ///
     /// document.add_link("https://wikipedia.org", "Wiki\n\nWiki");
     ///
-    /// assert_eq!(document.to_string(), "=> https://wikipedia.org/ Wiki Wiki\n");
+    /// assert_eq!(document.to_string(), "=> https://wikipedia.org Wiki Wiki\n");
     /// ```
-    pub fn add_link<'a, U>(&mut self, uri: U, label: impl Cowy<str>) -> &mut Self
-    where
-        U: TryInto<URIReference<'a>>,
-    {
-        let uri = uri
-            .try_into()
-            .map(URIReference::into_owned)
-            .or_else(|_| ".".try_into()).expect("Northstar BUG");
+    pub fn add_link(&mut self, uri: impl IntoLinkUri, label: impl Cowy<str>) -> &mut Self {
+        let uri = uri.into_link_uri().unwrap_or_else(|| ".".to_owned());
         let label = LinkLabel::from_lossy(label);
-        let link = Link { uri: Box::new(uri), label: Some(label) };
+        let link = Link { uri, label: Some(label) };
         let link = Item::Link(link);
 
         self.add_item(link);
@@ -203,18 +200,12 @@ impl Document {
     ///
     /// document.add_link_without_label("https://wikipedia.org");
     ///
-    /// assert_eq!(document.to_string(), "=> https://wikipedia.org/\n");
+    /// assert_eq!(document.to_string(), "=> https://wikipedia.org\n");
     /// ```
-    pub fn add_link_without_label<'a, U>(&mut self, uri: U) -> &mut Self
-    where
-        U: TryInto<URIReference<'a>>,
-    {
-        let uri = uri
-            .try_into()
-            .map(URIReference::into_owned)
-            .or_else(|_| ".".try_into()).expect("Northstar BUG");
+    pub fn add_link_without_label(&mut self, uri: impl IntoLinkUri) -> &mut Self {
+        let uri = uri.into_link_uri().unwrap_or_else(|| ".".to_owned());
         let link = Link {
-            uri: Box::new(uri),
+            uri,
             label: None,
         };
         let link = Item::Link(link);
@@ -353,6 +344,266 @@ impl Document {
 
         self
     }
+
+    /// Parses a gemtext document.
+    ///
+    /// Parsing never fails.  Links with a URI that doesn't parse
+    /// are kept as plain text, and an unterminated preformatted block
+    /// extends to the end of the document.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let document = twinstar::Document::parse("# Posts\n=> /hello.gmi Hello\n");
+    ///
+    /// let links = document.links().collect::<Vec<_>>();
+    ///
+    /// assert_eq!(links, [("/hello.gmi", Some("Hello"))]);
+    /// assert_eq!(document.to_string(), "# Posts\n=> /hello.gmi Hello\n");
+    /// ```
+    ///
+    /// This requires the `document_parse` feature.
+    #[cfg(feature="document_parse")]
+    pub fn parse(gemtext: &str) -> Self {
+        let mut document = Self::new();
+        let mut preformatted: Option<Preformatted> = None;
+
+        for line in gemtext.lines() {
+            if let Some(block) = &mut preformatted {
+                if line.starts_with(PREFORMATTED_TOGGLE_START) {
+                    let block = preformatted.take().expect("twinstar BUG");
+                    document.add_item(Item::Preformatted(block));
+                } else {
+                    block.lines.push(PreformattedText(line.to_owned()));
+                }
+                continue;
+            }
+
+            if let Some(alt) = line.strip_prefix(PREFORMATTED_TOGGLE_START) {
+                preformatted = Some(Preformatted {
+                    alt: AltText(alt.to_owned()),
+                    lines: Vec::new(),
+                });
+                continue;
+            }
+
+            document.add_item(parse_line(line));
+        }
+
+        if let Some(block) = preformatted {
+            document.add_item(Item::Preformatted(block));
+        }
+
+        document
+    }
+
+    /// Returns the links of the document, along with their labels.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut document = twinstar::Document::new();
+    /// document.add_link("/hello.gmi", "Hello");
+    ///
+    /// let (uri, label) = document.links().next().unwrap();
+    ///
+    /// assert_eq!(uri, "/hello.gmi");
+    /// assert_eq!(label, Some("Hello"));
+    /// ```
+    pub fn links(&self) -> impl Iterator<Item = (&str, Option<&str>)> {
+        self.items.iter().filter_map(|item| match item {
+            Item::Link(link) => Some((link.uri.as_str(), link.label.as_ref().map(|label| label.0.as_str()))),
+            _ => None,
+        })
+    }
+
+    /// Returns the headings of the document, along with their levels.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use twinstar::document::HeadingLevel;
+    ///
+    /// let mut document = twinstar::Document::new();
+    /// document.add_heading(HeadingLevel::H2, "Posts");
+    /// document.add_text("text");
+    /// document.add_heading(HeadingLevel::H3, "2020");
+    /// let (level, title) = document.headings().next().unwrap();
+    ///
+    /// assert_eq!(level, HeadingLevel::H2);
+    /// assert_eq!(title, "Posts");
+    /// ```
+    pub fn headings(&self) -> impl Iterator<Item = (HeadingLevel, &str)> {
+        self.items.iter().filter_map(|item| match item {
+            Item::Heading(heading) => Some((heading.level, heading.text.0.as_str())),
+            _ => None,
+        })
+    }
+
+    /// Checks gemtext for lines which picky clients may render differently than intended.
+    ///
+    /// Parsing is lenient and keeps malformed lines as text, so this is the way to find
+    /// them.  It reports links without a valid URI, list items lacking
+    /// the space after `*`, and preformatted blocks which are never closed, which
+    /// usually means a toggle line is missing or one too many.
+    ///
+    /// Servers in [strict mode](crate::Builder::set_strict()) log these warnings for
+    /// every gemtext response.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let warnings = twinstar::Document::lint("=> \n*item\n```\ncode\n");
+    /// let lines = warnings.iter().map(|warning| warning.line).collect::<Vec<_>>();
+    ///
+    /// assert_eq!(lines, [1, 2, 3]);
+    /// assert!(twinstar::Document::lint("=> /hello.gmi Hello\n* item\n").is_empty());
+    /// ```
+    pub fn lint(gemtext: &str) -> Vec<LintWarning> {
+        let mut warnings = Vec::new();
+        let mut preformatted_since = None;
+        let mut warn = |line, message: String| warnings.push(LintWarning { line, message });
+
+        for (index, line) in gemtext.lines().enumerate() {
+            let number = index + 1;
+
+            if line.starts_with(PREFORMATTED_TOGGLE_START) {
+                preformatted_since = match preformatted_since {
+                    Some(_) => None,
+                    None => Some(number),
+                };
+                continue;
+            }
+
+            if preformatted_since.is_some() {
+                continue;
+            }
+
+            if let Some(link) = line.strip_prefix(LINK_START) {
+                let uri = link.split_whitespace().next().unwrap_or_default();
+
+                if uri.is_empty() {
+                    warn(number, "Link without a URI".to_owned());
+                } else if !is_uri_reference(uri) {
+                    warn(number, format!("Link to invalid URI `{}`", uri));
+                }
+            } else if line.starts_with('*') && !line.starts_with("* ") && line.len() > 1 {
+                warn(number, "List item without a space after `*`".to_owned());
+            }
+        }
+
+        if let Some(number) = preformatted_since {
+            warn(number, "Preformatted block is never closed".to_owned());
+        }
+
+        warnings
+    }
+}
+
+/// A line found by [`Document::lint`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintWarning {
+    /// The number of the line, starting at 1
+    pub line: usize,
+    /// What is wrong with the line
+    pub message: String,
+}
+
+impl fmt::Display for LintWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// A URI which can be linked to from a [`Document`].
+///
+/// This is implemented for strings, and for `URIReference`s with the `std` feature.
+pub trait IntoLinkUri {
+    /// Returns the URI as written in a link line, or `None` if it isn't a valid URI reference.
+    fn into_link_uri(self) -> Option<String>;
+}
+
+impl IntoLinkUri for &str {
+    fn into_link_uri(self) -> Option<String> {
+        if is_uri_reference(self) { Some(self.to_owned()) } else { None }
+    }
+}
+
+impl IntoLinkUri for String {
+    fn into_link_uri(self) -> Option<String> {
+        if is_uri_reference(&self) { Some(self) } else { None }
+    }
+}
+
+impl IntoLinkUri for &String {
+    fn into_link_uri(self) -> Option<String> {
+        self.as_str().into_link_uri()
+    }
+}
+
+#[cfg(feature="std")]
+impl IntoLinkUri for URIReference<'_> {
+    fn into_link_uri(self) -> Option<String> {
+        self.to_string().into_link_uri()
+    }
+}
+
+/// A convenience trait alias for `AsRef<T> + Into<T::Owned>`,
+/// most commonly used to accept `&str` or `String`:
+///
+/// `Cowy<str>` ⇔ `AsRef<str> + Into<String>`
+pub trait Cowy<T>
+where
+    Self: AsRef<T> + Into<T::Owned>,
+    T: ToOwned + ?Sized,
+{}
+
+impl<C, T> Cowy<T> for C
+where
+    C: AsRef<T> + Into<T::Owned>,
+    T: ToOwned + ?Sized,
+{}
+
+/// Parses a single line outside of preformatted blocks.
+#[cfg(feature="document_parse")]
+fn parse_line(line: &str) -> Item {
+    if let Some(link) = line.strip_prefix(LINK_START) {
+        let link = link.trim_start();
+        let (uri, label) = match link.find(char::is_whitespace) {
+            Some(end) => (&link[..end], link[end..].trim()),
+            None => (link, ""),
+        };
+
+        if !is_uri_reference(uri) {
+            return Item::Text(Text(line.to_owned()));
+        }
+
+        return Item::Link(Link {
+            uri: uri.to_owned(),
+            label: if label.is_empty() { None } else { Some(LinkLabel(label.to_owned())) },
+        });
+    }
+
+    let heading = [("###", HeadingLevel::H3), ("##", HeadingLevel::H2), (HEADING_START, HeadingLevel::H1)]
+        .iter()
+        .find_map(|(start, level)| line.strip_prefix(start).map(|text| (level, text)));
+
+    if let Some((level, text)) = heading {
+        return Item::Heading(Heading {
+            level: *level,
+            text: HeadingText(text.trim_start().to_owned()),
+        });
+    }
+
+    if let Some(text) = line.strip_prefix("* ") {
+        return Item::UnorderedListItem(UnorderedListItem(text.to_owned()));
+    }
+
+    if let Some(text) = line.strip_prefix(QUOTE_START) {
+        return Item::Quote(Quote(text.strip_prefix(' ').unwrap_or(text).to_owned()));
+    }
+
+    Item::Text(Text(line.to_owned()))
 }
 
 impl fmt::Display for Document {
@@ -418,7 +669,7 @@ impl Text {
 }
 
 struct Link {
-    uri: Box<URIReference<'static>>,
+    uri: String,
     label: Option<LinkLabel>,
 }
 
@@ -461,6 +712,7 @@ struct Heading {
 }
 
 /// The level of a heading.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum HeadingLevel {
     /// Heading level 1 (`#`)
     H1,
@@ -513,6 +765,29 @@ const SPECIAL_STARTS: &[&str] = &[
     QUOTE_START,
 ];
 
+/// Whether `uri` is a URI reference as defined by RFC 3986
+///
+/// Only the characters and the scheme are checked, which is enough to tell whether the
+/// link line can be parsed back.
+fn is_uri_reference(uri: &str) -> bool {
+    let bytes = uri.as_bytes();
+    let valid_bytes = bytes.iter().enumerate().all(|(index, &byte)| match byte {
+        b'%' => matches!(bytes.get(index + 1..index + 3), Some(hex) if hex.iter().all(u8::is_ascii_hexdigit)),
+        _ => byte.is_ascii_alphanumeric() || b"-._~:/?#[]@!$&'()*+,;=".contains(&byte),
+    });
+    let first_segment = uri.split(&['/', '?', '#'][..]).next().unwrap_or_default();
+    let valid_scheme = match first_segment.find(':') {
+        Some(end) => {
+            let scheme = &first_segment[..end];
+            scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+        }
+        None => true,
+    };
+
+    !uri.is_empty() && valid_bytes && valid_scheme
+}
+
 fn starts_with_any(s: &str, starts: &[&str]) -> bool {
     for start in starts {
         if s.starts_with(start) {
@@ -556,3 +831,72 @@ fn strip_newlines(text: impl Cowy<str>) -> String {
         .collect::<Vec<_>>()
         .join(" ")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature="document_parse")]
+    #[test]
+    fn parse_round_trips() {
+        let gemtext = "\
+# Title
+text
+=> gemini://example.org/ Example
+=>   /spaced   Spaced label
+=> %% broken
+* item
+> quote
+```alt
+# not a heading
+=> /not-a-link
+```
+";
+        let document = Document::parse(gemtext);
+
+        assert_eq!(document.links().count(), 2);
+        assert_eq!(document.headings().count(), 1);
+        assert_eq!(document.to_string(), gemtext.replace("=>   /spaced   Spaced", "=> /spaced Spaced"));
+
+        let unterminated = Document::parse("```\ncode");
+        assert_eq!(unterminated.to_string(), "```\ncode\n```\n");
+    }
+
+    #[test]
+    fn substitutes_invalid_link_uris() {
+        let mut document = Document::new();
+        document
+            .add_link("gemini://example.org/caf%C3%A9?q=1#top", "Valid")
+            .add_link_without_label(String::from("../posts/"))
+            .add_link("%%", "Broken escape")
+            .add_link("1st:page", "Bad scheme")
+            .add_link("with space", "Whitespace")
+            .add_link("", "Empty");
+
+        let uris = document.links().map(|(uri, _)| uri).collect::<Vec<_>>();
+
+        assert_eq!(uris, ["gemini://example.org/caf%C3%A9?q=1#top", "../posts/", ".", ".", ".", "."]);
+    }
+
+    #[cfg(feature="std")]
+    #[test]
+    fn links_to_uri_references() {
+        use std::convert::TryFrom;
+
+        let mut document = Document::new();
+        document.add_link(URIReference::try_from("gemini://example.org/").unwrap(), "Example");
+
+        assert_eq!(document.to_string(), "=> gemini://example.org/ Example\n");
+    }
+
+    #[test]
+    fn lints_malformed_lines() {
+        let gemtext = "# Title\n=> %% broken\n```\n=>\n*not an item\n```\n*\n```\n";
+        let warnings = Document::lint(gemtext).into_iter().map(|warning| warning.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(warnings, [
+            "line 2: Link to invalid URI `%%`",
+            "line 8: Preformatted block is never closed",
+        ]);
+    }
+}
@@ -0,0 +1,81 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+
+/// A map holding at most one value of each type
+///
+/// Middleware uses this to attach information to a [`Request`](crate::types::Request)
+/// for the handlers further down the line, without the request having to know about
+/// every kind of information in advance.  Values are keyed by their type, so defining
+/// a dedicated type for each value avoids collisions.
+///
+/// ```
+/// # use twinstar::types::Extensions;
+/// struct Visits(u32);
+///
+/// let mut extensions = Extensions::new();
+/// extensions.insert(Visits(3));
+///
+/// assert_eq!(extensions.get::<Visits>().map(|visits| visits.0), Some(3));
+/// assert!(extensions.get::<String>().is_none());
+/// ```
+#[derive(Default)]
+pub struct Extensions {
+    map: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl Extensions {
+    /// Create an empty map
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Insert a value, returning the previous value of the same type
+    pub fn insert<T: Any + Send + Sync>(&mut self, value: T) -> Option<T> {
+        self.map.insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|previous| previous.downcast().ok())
+            .map(|previous| *previous)
+    }
+
+    /// Get a reference to the value of type `T`
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.map.get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+
+    /// Get a mutable reference to the value of type `T`
+    pub fn get_mut<T: Any + Send + Sync>(&mut self) -> Option<&mut T> {
+        self.map.get_mut(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_mut())
+    }
+
+    /// Remove the value of type `T`, returning it
+    pub fn remove<T: Any + Send + Sync>(&mut self) -> Option<T> {
+        self.map.remove(&TypeId::of::<T>())
+            .and_then(|value| value.downcast().ok())
+            .map(|value| *value)
+    }
+
+    /// Whether a value of type `T` is present
+    pub fn contains<T: Any + Send + Sync>(&self) -> bool {
+        self.map.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Whether the map holds no values
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// The number of values in the map
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Extensions")
+            .field("len", &self.map.len())
+            .finish()
+    }
+}
@@ -0,0 +1,83 @@
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
+
+use memmap2::Mmap;
+
+/// A file mapped into memory, to be sent as a [`Body::Mmap`](crate::types::Body::Mmap)
+///
+/// Sending a mapped file copies it straight out of the OS page cache, instead of
+/// reading it into a buffer piece by piece first.  This pays off for large files which
+/// are requested often, and so likely stay cached.
+pub struct MappedFile {
+    mmap: Mmap,
+}
+
+impl MappedFile {
+    /// Open and map the file at `path`
+    ///
+    /// # Safety
+    ///
+    /// See [`map()`](Self::map()).
+    pub unsafe fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::map(&File::open(path)?)
+    }
+
+    /// Map the whole of `file`, which must be opened for reading
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified or truncated for as long as the `MappedFile`, or
+    /// any body or response it is sent in, is alive.  The mapped bytes change along with
+    /// the file, and on most platforms, accessing a truncated part of the mapping crashes
+    /// the process.  Only map files which are never modified in place, e.g. because new
+    /// versions are moved over them.
+    pub unsafe fn map(file: &File) -> io::Result<Self> {
+        Ok(Self { mmap: Mmap::map(file)? })
+    }
+}
+
+impl Deref for MappedFile {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.mmap
+    }
+}
+
+impl AsRef<[u8]> for MappedFile {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl fmt::Debug for MappedFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MappedFile")
+            .field("len", &self.mmap.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_files() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("mapped");
+        std::fs::write(&path, "mapped").unwrap();
+        // SAFETY: the file isn't modified while it is mapped
+        let mapped = unsafe { MappedFile::open(&path) }.unwrap();
+        assert_eq!(&*mapped, b"mapped");
+        drop(mapped);
+
+        std::fs::write(&path, "").unwrap();
+        // SAFETY: the file isn't modified while it is mapped
+        let empty = unsafe { MappedFile::open(&path) }.unwrap();
+        assert!(empty.is_empty());
+    }
+}
@@ -0,0 +1,97 @@
+#[cfg(feature="x509")]
+use std::time::SystemTime;
+
+/// A certificate presented by a client, DER encoded
+///
+/// This is what [`Request::certificate()`](crate::Request::certificate()) returns.
+/// Certificates can be compared and hashed, so they can be used as keys directly,
+/// although the [`fingerprint()`](Self::fingerprint()) is more convenient to store.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PeerCertificate {
+    der: Vec<u8>,
+}
+
+impl PeerCertificate {
+    /// Wrap a DER encoded certificate
+    pub const fn from_der(der: Vec<u8>) -> Self {
+        Self { der }
+    }
+
+    /// The DER encoded certificate
+    pub fn as_der(&self) -> &[u8] {
+        &self.der
+    }
+
+    /// Take the DER encoded certificate
+    #[allow(clippy::missing_const_for_fn)]
+    pub fn into_der(self) -> Vec<u8> {
+        self.der
+    }
+
+    /// The SHA-256 digest of the certificate, as lowercase hex
+    ///
+    /// The digest is taken over the DER encoded certificate, so it matches e.g.
+    /// `openssl x509 -noout -fingerprint -sha256` without the colons.  It stays the same
+    /// for as long as the client keeps using the certificate, so it can be used directly
+    /// to identify users.
+    pub fn fingerprint(&self) -> String {
+        let digest = ring::digest::digest(&ring::digest::SHA256, &self.der);
+        crate::util::hex(digest.as_ref())
+    }
+
+    /// Parse the commonly needed fields of the certificate
+    ///
+    /// See the [`x509`](crate::x509) module for details.
+    #[cfg(feature="x509")]
+    pub fn info(&self) -> anyhow::Result<crate::x509::CertificateInfo> {
+        crate::x509::CertificateInfo::parse(&self.der)
+    }
+
+    /// Check that the certificate is valid at `now`, returning the reason if it isn't
+    ///
+    /// Only the validity is read, so this doesn't fail for names which can't be decoded.
+    #[cfg(feature="x509")]
+    pub(crate) fn check_validity(&self, now: SystemTime) -> Result<(), &'static str> {
+        let (not_before, not_after) = crate::x509::validity(&self.der)
+            .map_err(|_| "Your certificate could not be read")?;
+
+        if now < not_before {
+            Err("Your certificate is not valid yet")
+        } else if now > not_after {
+            Err("Your certificate has expired")
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl AsRef<[u8]> for PeerCertificate {
+    fn as_ref(&self) -> &[u8] {
+        &self.der
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature="x509")]
+    use super::*;
+    use crate::testing::TestCertificate;
+
+    #[test]
+    fn checks_validity() {
+        let valid = TestCertificate::new("alice").generate().unwrap();
+        assert_eq!(valid.certificate().fingerprint().len(), 64);
+
+        #[cfg(feature="x509")]
+        {
+            let now = SystemTime::now();
+            let expired = TestCertificate::new("bob").expired().generate().unwrap();
+            let early = TestCertificate::new("carol").not_yet_valid().generate().unwrap();
+
+            assert_eq!(valid.certificate().check_validity(now), Ok(()));
+            assert_eq!(expired.certificate().check_validity(now), Err("Your certificate has expired"));
+            assert_eq!(early.certificate().check_validity(now), Err("Your certificate is not valid yet"));
+            assert!(PeerCertificate::from_der(b"junk".to_vec()).check_validity(now).is_err());
+        }
+    }
+}
@@ -1,14 +1,24 @@
+use std::any::Any;
+use std::convert::TryFrom;
 use std::ops;
+use std::net::SocketAddr;
+use std::sync::Arc;
 use anyhow::*;
 use percent_encoding::percent_decode_str;
 use uriparse::URIReference;
-use rustls::Certificate;
+use super::{Extensions, PeerCertificate, TlsInfo};
 
 pub struct Request {
     uri: URIReference<'static>,
     input: Option<String>,
-    certificate: Option<Certificate>,
+    input_bytes: Option<Vec<u8>>,
+    certificate: Option<PeerCertificate>,
     trailing_segments: Option<Vec<String>>,
+    remote_addr: Option<SocketAddr>,
+    local_addr: Option<SocketAddr>,
+    tls_info: Option<TlsInfo>,
+    state: Option<Arc<Extensions>>,
+    extensions: Extensions,
 }
 
 impl Request {
@@ -18,26 +28,26 @@ impl Request {
 
     pub fn with_certificate(
         mut uri: URIReference<'static>,
-        certificate: Option<Certificate>
+        certificate: Option<PeerCertificate>
     ) -> Result<Self> {
         uri.normalize();
 
-        let input = match uri.query() {
-            None => None,
-            Some(query) => {
-                let input = percent_decode_str(query.as_str())
-                    .decode_utf8()
-                    .context("Request URI query contains invalid UTF-8")?
-                    .into_owned();
-                Some(input)
-            }
-        };
+        let input_bytes = uri.query()
+            .map(|query| percent_decode_str(query.as_str()).collect::<Vec<u8>>());
+        let input = input_bytes.as_deref()
+            .map(|input| String::from_utf8_lossy(input).into_owned());
 
         Ok(Self {
             uri,
             input,
+            input_bytes,
             certificate,
             trailing_segments: None,
+            remote_addr: None,
+            local_addr: None,
+            tls_info: None,
+            state: None,
+            extensions: Extensions::new(),
         })
     }
 
@@ -79,11 +89,61 @@ impl Request {
             .collect::<Vec<String>>()
     }
 
+    /// The percent decoded query of the request, which is where clients send input
+    ///
+    /// Input which isn't valid UTF-8 has the invalid parts replaced with `�`, see
+    /// [`input_bytes()`](Self::input_bytes()) for the exact bytes.
     pub fn input(&self) -> Option<&str> {
         self.input.as_deref()
     }
 
-    pub fn set_cert(&mut self, cert: Option<Certificate>) {
+    /// The percent decoded query of the request as raw bytes
+    ///
+    /// Unlike [`input()`](Self::input()), this keeps input which isn't valid UTF-8 as
+    /// it is, for capsules accepting binary data or text in other encodings.
+    pub fn input_bytes(&self) -> Option<&[u8]> {
+        self.input_bytes.as_deref()
+    }
+
+    /// The `key=value` pairs of a form-style query, percent decoded
+    ///
+    /// For a request to `/search?q=gemini%20capsules&page=2`, this returns
+    /// `[("q", "gemini capsules"), ("page", "2")]`.  Pairs are separated by `&`, and a
+    /// pair without `=` has an empty value.  Unlike in HTML forms, `+` is kept as it is,
+    /// since Gemini clients encode spaces as `%20`.  Invalid UTF-8 is replaced, like in
+    /// [`path_segments()`](Self::path_segments()).
+    ///
+    /// Pairs are returned in order, including repeated keys.  Collect them into a
+    /// [`HashMap`](std::collections::HashMap) to look them up by key.
+    ///
+    /// This parses the query of the URI, and ignores input replaced with
+    /// [`set_input()`](Self::set_input()).
+    pub fn query_pairs(&self) -> Vec<(String, String)> {
+        let query = match self.uri.query() {
+            Some(query) => query.as_str(),
+            None => return Vec::new(),
+        };
+        let decode = |part: &str| percent_decode_str(part).decode_utf8_lossy().into_owned();
+
+        query.split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| match pair.split_once('=') {
+                Some((key, value)) => (decode(key), decode(value)),
+                None => (decode(pair), String::new()),
+            })
+            .collect()
+    }
+
+    /// Replace the input of the request, keeping the URI as it is
+    ///
+    /// This lets wrapping handlers pass on input which didn't arrive as the query of
+    /// this request, like [`ContinuedInput`](crate::util::ContinuedInput) does.
+    pub fn set_input(&mut self, input: Option<String>) {
+        self.input_bytes = input.as_ref().map(|input| input.clone().into_bytes());
+        self.input = input;
+    }
+
+    pub fn set_cert(&mut self, cert: Option<PeerCertificate>) {
         self.certificate = cert;
     }
 
@@ -91,10 +151,159 @@ impl Request {
         self.trailing_segments = Some(segments);
     }
 
+    /// Replace the path of the request URI, keeping the query
+    ///
+    /// The path must be percent encoded.  This lets middleware serve a request as if a
+    /// different path had been requested, as long as it does so before the request is
+    /// routed.
+    pub fn set_path(&mut self, path: &str) -> Result<()> {
+        let path = uriparse::Path::try_from(path)
+            .with_context(|| format!("Invalid path `{}`", path))?
+            .into_owned();
+
+        self.uri.set_path(path)
+            .context("Invalid path for request URI")?;
+        self.uri.normalize();
+
+        Ok(())
+    }
+
     #[allow(clippy::missing_const_for_fn)]
-    pub fn certificate(&self) -> Option<&Certificate> {
+    pub fn certificate(&self) -> Option<&PeerCertificate> {
         self.certificate.as_ref()
     }
+
+    /// The DER encoded client certificate
+    pub fn peer_cert_der(&self) -> Option<&[u8]> {
+        self.certificate.as_ref().map(PeerCertificate::as_der)
+    }
+
+    /// The SHA-256 digest of the client certificate, as lowercase hex
+    ///
+    /// See [`PeerCertificate::fingerprint()`].
+    pub fn certificate_fingerprint(&self) -> Option<String> {
+        self.certificate.as_ref().map(PeerCertificate::fingerprint)
+    }
+
+    /// The fields of the client certificate, like its common name and validity
+    ///
+    /// Fails if the client presented a certificate which couldn't be parsed.  See the
+    /// [`x509`](crate::x509) module for details.
+    #[cfg(feature="x509")]
+    pub fn certificate_info(&self) -> Result<Option<crate::x509::CertificateInfo>> {
+        self.certificate.as_ref()
+            .map(PeerCertificate::info)
+            .transpose()
+    }
+
+    /// Set the address of the client which sent the request
+    ///
+    /// The server sets this before any middleware or handler runs, so this is mostly
+    /// useful for requests constructed in tests.
+    pub fn set_remote_addr(&mut self, remote_addr: Option<SocketAddr>) {
+        self.remote_addr = remote_addr;
+    }
+
+    /// The address of the client which sent the request
+    ///
+    /// For connections from [trusted proxies](crate::Builder::set_trusted_proxies()),
+    /// this is the address of the client the proxy is relaying for.  This is `None` for
+    /// requests which weren't received by a server, like requests constructed in tests.
+    pub const fn remote_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr
+    }
+
+    /// Set the address of the server the request was received on
+    ///
+    /// The server sets this before any middleware or handler runs, so this is mostly
+    /// useful for requests constructed in tests.
+    pub fn set_local_addr(&mut self, local_addr: Option<SocketAddr>) {
+        self.local_addr = local_addr;
+    }
+
+    /// The address of the server the request was received on
+    ///
+    /// Unlike the address the server listens on, this is never unspecified, e.g.
+    /// `127.0.0.1:1965` for a client connecting to a server listening on `0.0.0.0:1965`.
+    /// This is `None` for requests which weren't received by a server.
+    pub const fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    /// Set what was negotiated during the TLS handshake
+    ///
+    /// The server sets this before any middleware or handler runs, so this is mostly
+    /// useful for requests constructed in tests.
+    pub fn set_tls_info(&mut self, tls_info: Option<TlsInfo>) {
+        self.tls_info = tls_info;
+    }
+
+    /// The TLS version, cipher suite and SNI hostname of the connection the request was
+    /// received on
+    ///
+    /// This is `None` for requests which weren't received by a server, like requests
+    /// constructed in tests.  Handlers can use it to log how clients connect, or to
+    /// hold a route to stricter standards than the rest of the server:
+    ///
+    /// ```
+    /// # #[cfg(feature="handler")] {
+    /// # use twinstar::{Request, Response, TlsVersion};
+    /// # use twinstar::handler::HandlerExt;
+    /// let handler = (|_: Request| Box::pin(async { Ok(Response::success_plain("Secret")) }) as _)
+    ///     .before(|request: &mut Request| match request.tls_info() {
+    ///         Some(info) if info.version >= TlsVersion::Tls13 => None,
+    ///         _ => Some(Response::bad_request_lossy("This page requires TLS 1.3")),
+    ///     });
+    /// # }
+    /// ```
+    pub const fn tls_info(&self) -> Option<&TlsInfo> {
+        self.tls_info.as_ref()
+    }
+
+    /// A copy of this request, leaving out the extensions, which can't be cloned
+    #[cfg_attr(not(any(feature="handler", feature="serve_dir")), allow(dead_code))]
+    pub(crate) fn clone_without_extensions(&self) -> Self {
+        Self {
+            uri: self.uri.clone(),
+            input: self.input.clone(),
+            input_bytes: self.input_bytes.clone(),
+            certificate: self.certificate.clone(),
+            trailing_segments: self.trailing_segments.clone(),
+            remote_addr: self.remote_addr,
+            local_addr: self.local_addr,
+            tls_info: self.tls_info.clone(),
+            state: self.state.clone(),
+            extensions: Extensions::new(),
+        }
+    }
+
+    /// The shared state of type `T`, see [`Builder::manage()`](crate::Builder::manage())
+    ///
+    /// Unlike [extensions](Self::extensions()), which belong to a single request, state
+    /// is shared by all requests the server receives.
+    pub fn state<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.state.as_ref().and_then(|state| state.get())
+    }
+
+    /// Set the shared state of the request
+    ///
+    /// The server sets this before any middleware or handler runs, so this is mostly
+    /// useful for requests constructed in tests.
+    pub fn set_state(&mut self, state: Arc<Extensions>) {
+        self.state = Some(state);
+    }
+
+    /// Values attached to this request by middleware
+    ///
+    /// See [`Extensions`] for details.
+    pub const fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    /// Mutable access to the values attached to this request
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
 }
 
 impl ops::Deref for Request {
@@ -104,3 +313,63 @@ impl ops::Deref for Request {
         &self.uri
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shares_state() {
+        struct Name(&'static str);
+
+        let uri = URIReference::try_from("gemini://localhost/").unwrap().into_owned();
+        let mut request = Request::from_uri(uri).unwrap();
+        assert!(request.state::<Name>().is_none());
+
+        let mut state = Extensions::new();
+        state.insert(Name("capsule"));
+        request.set_state(Arc::new(state));
+        request.extensions_mut().insert(Name("request"));
+
+        assert_eq!(request.state::<Name>().map(|name| name.0), Some("capsule"));
+        assert_eq!(request.clone_without_extensions().state::<Name>().map(|name| name.0), Some("capsule"));
+        assert_eq!(request.extensions().get::<Name>().map(|name| name.0), Some("request"));
+    }
+
+    #[test]
+    fn accepts_non_utf8_input() {
+        let uri = URIReference::try_from("gemini://localhost/?caf%E9").unwrap().into_owned();
+        let mut request = Request::from_uri(uri).unwrap();
+        assert_eq!(request.input(), Some("caf\u{fffd}"));
+        assert_eq!(request.input_bytes(), Some(&b"caf\xe9"[..]));
+
+        request.set_input(Some("café".to_owned()));
+        assert_eq!(request.input_bytes(), Some("café".as_bytes()));
+    }
+
+    #[test]
+    fn parses_query_pairs() {
+        let request = |uri: &str| Request::from_uri(URIReference::try_from(uri).unwrap().into_owned()).unwrap();
+        let pairs = |pairs: &[(&str, &str)]| pairs.iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(request("gemini://localhost/").query_pairs(), pairs(&[]));
+        assert_eq!(
+            request("gemini://localhost/?q=caf%C3%A9%20au+lait&flag&&page=2&q=").query_pairs(),
+            pairs(&[("q", "café au+lait"), ("flag", ""), ("page", "2"), ("q", "")]),
+        );
+    }
+
+    #[test]
+    fn fingerprints_certificates() {
+        let uri = URIReference::try_from("gemini://localhost/").unwrap().into_owned();
+        assert_eq!(Request::from_uri(uri.clone()).unwrap().certificate_fingerprint(), None);
+
+        let request = Request::with_certificate(uri, Some(PeerCertificate::from_der(b"abc".to_vec()))).unwrap();
+        assert_eq!(
+            request.certificate_fingerprint().unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+        );
+    }
+}
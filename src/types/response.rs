@@ -3,13 +3,67 @@ use std::borrow::Borrow;
 
 use anyhow::*;
 use uriparse::URIReference;
-use crate::types::{ResponseHeader, Body, Mime, Document};
+use crate::types::{ResponseHeader, Body, Meta, Mime, Document, Status};
 use crate::util::Cowy;
 use crate::GEMINI_MIME;
 
+/// A response to a request, made up of a header and an optional body
+///
+/// Every kind of response has its own constructor:
+///
+/// ```
+/// # use twinstar::{Response, Status, Document, GEMINI_MIME};
+/// # fn main() -> anyhow::Result<()> {
+/// let status = |response: Response| response.header().status;
+///
+/// assert_eq!(status(Response::input("Your name?")?), Status::INPUT);
+/// assert_eq!(status(Response::input_lossy("Your name?")), Status::INPUT);
+/// assert_eq!(status(Response::sensitive_input("Password?")?), Status::SENSITIVE_INPUT);
+/// assert_eq!(status(Response::sensitive_input_lossy("Password?")), Status::SENSITIVE_INPUT);
+/// assert_eq!(status(Response::success(&mime::TEXT_CSV, "a,b\n")), Status::SUCCESS);
+/// assert_eq!(status(Response::success_with_body(&GEMINI_MIME, "# Hi\n")), Status::SUCCESS);
+/// assert_eq!(status(Response::success_gemini(Document::new())), Status::SUCCESS);
+/// assert_eq!(status(Response::success_gemini_string(format!("# Hi {}\n", "alice"))), Status::SUCCESS);
+/// assert_eq!(status(Response::success_plain("hi")), Status::SUCCESS);
+/// assert_eq!(status(Response::redirect_temporary_lossy("/new")), Status::REDIRECT_TEMPORARY);
+/// assert_eq!(status(Response::redirect_permanent_lossy("/new")), Status::REDIRECT_PERMANENT);
+/// assert_eq!(status(Response::server_error("Oops")?), Status::PERMANENT_FAILURE);
+/// assert_eq!(status(Response::server_unavailable("Back soon")?), Status::SERVER_UNAVAILABLE);
+/// assert_eq!(status(Response::server_unavailable_lossy("Back soon")), Status::SERVER_UNAVAILABLE);
+/// assert_eq!(status(Response::slow_down(10)), Status::SLOW_DOWN);
+/// assert_eq!(status(Response::not_found()), Status::NOT_FOUND);
+/// assert_eq!(status(Response::gone()), Status::GONE);
+/// assert_eq!(status(Response::bad_request_lossy("No")), Status::BAD_REQUEST);
+/// assert_eq!(status(Response::client_certificate_required()), Status::CLIENT_CERTIFICATE_REQUIRED);
+/// assert_eq!(status(Response::certificate_not_authorized()), Status::CERTIFICATE_NOT_AUTHORIZED);
+/// assert_eq!(status(Response::certificate_not_valid_lossy("Expired")), Status::CERTIFICATE_NOT_VALID);
+/// assert_eq!(status(Document::new().into()), Status::SUCCESS);
+/// # Ok(())
+/// # }
+/// ```
 pub struct Response {
     header: ResponseHeader,
     body: Option<Body>,
+    header_flush: Option<HeaderFlush>,
+}
+
+/// When the response header is sent to the client
+///
+/// Unless a response chooses otherwise, the server's default is used, see
+/// [`Builder::set_header_flush()`](crate::Builder::set_header_flush()).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HeaderFlush {
+    /// Send the header on its own, as soon as it is ready
+    ///
+    /// Clients learn about the response before the body starts, which suits
+    /// interactive endpoints streaming their body slowly.  This is the default.
+    #[default]
+    Immediately,
+    /// Send the header together with the start of the body
+    ///
+    /// This saves a TLS record and usually a network packet per response, which suits
+    /// bulk endpoints whose body is available right away.
+    Coalesce,
 }
 
 impl Response {
@@ -17,6 +71,7 @@ impl Response {
         Self {
             header,
             body: None,
+            header_flush: None,
         }
     }
 
@@ -38,24 +93,60 @@ impl Response {
         Self::new(header)
     }
 
+    /// Ask for input which shouldn't be echoed, like a password
+    ///
+    /// The server keeps the answers out of the access log, see
+    /// [`Builder::set_log_sink()`](crate::Builder::set_log_sink()).
+    pub fn sensitive_input(prompt: impl Cowy<str>) -> Result<Self> {
+        let header = ResponseHeader::sensitive_input(prompt)?;
+        Ok(Self::new(header))
+    }
+
+    pub fn sensitive_input_lossy(prompt: impl Cowy<str>) -> Self {
+        let header = ResponseHeader::sensitive_input_lossy(prompt);
+        Self::new(header)
+    }
+
     pub fn redirect_temporary_lossy<'a>(location: impl TryInto<URIReference<'a>>) -> Self {
         let header = ResponseHeader::redirect_temporary_lossy(location);
         Self::new(header)
     }
 
+    pub fn redirect_permanent_lossy<'a>(location: impl TryInto<URIReference<'a>>) -> Self {
+        let header = ResponseHeader::redirect_permanent_lossy(location);
+        Self::new(header)
+    }
+
     /// Create a successful response with a given body and MIME
     pub fn success(mime: &Mime, body: impl Into<Body>) -> Self {
         Self {
             header: ResponseHeader::success(mime),
             body: Some(body.into()),
+            header_flush: None,
         }
     }
 
+    /// Create a successful response with a given body and MIME
+    ///
+    /// This is the same as [`success()`](Self::success()), under the name used by
+    /// earlier versions.
+    pub fn success_with_body(mime: &Mime, body: impl Into<Body>) -> Self {
+        Self::success(mime, body)
+    }
+
     /// Create a successful response with a `text/gemini` MIME
     pub fn success_gemini(body: impl Into<Body>) -> Self {
         Self::success(&GEMINI_MIME, body)
     }
 
+    /// Create a successful response with a `text/gemini` MIME from gemtext
+    ///
+    /// Unlike [`success_gemini()`](Self::success_gemini()), this only accepts text,
+    /// which helps type inference with e.g. `format!()` or `String::new()`.
+    pub fn success_gemini_string(gemtext: impl Into<String>) -> Self {
+        Self::success_gemini(gemtext.into())
+    }
+
     /// Create a successful response with a `text/plain` MIME
     pub fn success_plain(body: impl Into<Body>) -> Self {
         Self::success(&mime::TEXT_PLAIN, body)
@@ -66,11 +157,33 @@ impl Response {
         Ok(Self::new(header))
     }
 
+    pub fn server_unavailable(reason: impl Cowy<str>) -> Result<Self> {
+        let header = ResponseHeader::server_unavailable(reason)?;
+        Ok(Self::new(header))
+    }
+
+    pub fn server_unavailable_lossy(reason: impl Cowy<str>) -> Self {
+        let header = ResponseHeader::server_unavailable_lossy(reason);
+        Self::new(header)
+    }
+
+    /// Ask the client to wait `seconds` before making another request
+    pub fn slow_down(seconds: u64) -> Self {
+        let header = ResponseHeader::slow_down(seconds);
+        Self::new(header)
+    }
+
     pub fn not_found() -> Self {
         let header = ResponseHeader::not_found();
         Self::new(header)
     }
 
+    /// Tell the client that the resource is gone for good
+    pub fn gone() -> Self {
+        let header = ResponseHeader::gone();
+        Self::new(header)
+    }
+
     pub fn bad_request_lossy(reason: impl Cowy<str>) -> Self {
         let header = ResponseHeader::bad_request_lossy(reason);
         Self::new(header)
@@ -86,15 +199,45 @@ impl Response {
         Self::new(header)
     }
 
+    pub fn certificate_not_valid_lossy(reason: impl Cowy<str>) -> Self {
+        let header = ResponseHeader::certificate_not_valid_lossy(reason);
+        Self::new(header)
+    }
+
     pub fn with_body(mut self, body: impl Into<Body>) -> Self {
         self.body = Some(body.into());
         self
     }
 
+    /// Transform the body of this response, if it has one
+    pub fn map_body(mut self, f: impl FnOnce(Body) -> Body) -> Self {
+        self.body = self.body.map(f);
+        self
+    }
+
+    /// Choose when the header of this response is sent, overriding the server's default
+    pub fn with_header_flush(mut self, header_flush: HeaderFlush) -> Self {
+        self.header_flush = Some(header_flush);
+        self
+    }
+
+    /// When the header of this response is sent, if it overrides the server's default
+    pub const fn header_flush(&self) -> Option<HeaderFlush> {
+        self.header_flush
+    }
+
     pub const fn header(&self) -> &ResponseHeader {
         &self.header
     }
 
+    pub fn header_mut(&mut self) -> &mut ResponseHeader {
+        &mut self.header
+    }
+
+    pub const fn body(&self) -> Option<&Body> {
+        self.body.as_ref()
+    }
+
     pub fn take_body(&mut self) -> Option<Body> {
         self.body.take()
     }
@@ -105,3 +248,113 @@ impl<D: Borrow<Document>> From<D> for Response {
         Self::success_gemini(doc)
     }
 }
+
+/// Anything a handler can answer a request with
+///
+/// Handlers made with [`from_fn()`](crate::handler::from_fn()) may return any of
+/// these, which saves wrapping every page in a [`Response`]:
+///
+/// * a [`Response`], which is sent as it is
+/// * a [`Document`], a [`String`] or a `&'static str`, which is sent as `text/gemini`
+/// * a [`Status`] and a meta, like `(Status::GONE, "Moved to the new capsule")`, sent
+///   without a body.  Invalid meta is replaced like with
+///   [`Meta::new_lossy()`](Meta::new_lossy())
+/// * an [`Option`] of any of these, with `None` answered by `51 NOT FOUND`
+///
+/// ```no_run
+/// # #[cfg(feature="handler")] {
+/// # use twinstar::{Server, Request, Document, GEMINI_PORT};
+/// # use twinstar::handler::from_fn;
+/// async fn hello(request: Request) -> anyhow::Result<Option<Document>> {
+///     let name = match request.trailing_segments().first() {
+///         Some(name) => name.clone(),
+///         None => return Ok(None),
+///     };
+///
+///     let mut document = Document::new();
+///     document.add_text(format!("Hello {}!", name));
+///     Ok(Some(document))
+/// }
+///
+/// # async fn run() -> anyhow::Result<()> {
+/// Server::bind(("localhost", GEMINI_PORT))
+///     .add_route("/hello", from_fn(hello))
+///     .serve()
+///     .await?;
+/// # Ok(())
+/// # }
+/// # }
+/// ```
+pub trait IntoResponse {
+    /// Turn this into the response sent to the client
+    fn into_response(self) -> Response;
+}
+
+impl IntoResponse for Response {
+    fn into_response(self) -> Response {
+        self
+    }
+}
+
+impl IntoResponse for Document {
+    fn into_response(self) -> Response {
+        Response::success_gemini(self)
+    }
+}
+
+impl IntoResponse for String {
+    fn into_response(self) -> Response {
+        Response::success_gemini(self)
+    }
+}
+
+impl IntoResponse for &'static str {
+    fn into_response(self) -> Response {
+        Response::success_gemini(self)
+    }
+}
+
+impl IntoResponse for (Status, &str) {
+    fn into_response(self) -> Response {
+        let (status, meta) = self;
+        Response::new(ResponseHeader { status, meta: Meta::new_lossy(meta) })
+    }
+}
+
+impl IntoResponse for (Status, String) {
+    fn into_response(self) -> Response {
+        let (status, meta) = self;
+        Response::new(ResponseHeader { status, meta: Meta::new_lossy(meta) })
+    }
+}
+
+impl<T: IntoResponse> IntoResponse for Option<T> {
+    fn into_response(self) -> Response {
+        match self {
+            Some(response) => response.into_response(),
+            None => Response::not_found(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_into_responses() {
+        let mut document = Document::new();
+        document.add_text("Hi");
+        let mut response = document.into_response();
+        assert_eq!(response.header().meta.as_str(), "text/gemini");
+        assert_eq!(response.take_body().unwrap().as_bytes(), Some("Hi\n".as_bytes()));
+
+        let response = (Status::GONE, "Moved\naway").into_response();
+        assert_eq!(response.header().status, Status::GONE);
+        assert_eq!(response.header().meta.as_str(), "Moved");
+        assert!(response.body().is_none());
+
+        assert_eq!(None::<String>.into_response().header().status, Status::NOT_FOUND);
+        assert_eq!(Some("# Hi").into_response().header().status, Status::SUCCESS);
+    }
+}
@@ -1,10 +1,10 @@
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 
-use anyhow::{Result, Context};
+use anyhow::{Result, Context, ensure};
 use uriparse::URIReference;
 use crate::Mime;
 use crate::util::Cowy;
-use crate::types::{Status, Meta};
+use crate::types::{Status, StatusCategory, Meta};
 
 #[derive(Debug,Clone)]
 pub struct ResponseHeader {
@@ -27,6 +27,20 @@ impl ResponseHeader {
         }
     }
 
+    pub fn sensitive_input(prompt: impl Cowy<str>) -> Result<Self> {
+        Ok(Self {
+            status: Status::SENSITIVE_INPUT,
+            meta: Meta::new(prompt).context("Invalid input prompt")?,
+        })
+    }
+
+    pub fn sensitive_input_lossy(prompt: impl Cowy<str>) -> Self {
+        Self {
+            status: Status::SENSITIVE_INPUT,
+            meta: Meta::new_lossy(prompt),
+        }
+    }
+
     pub fn success(mime: &Mime) -> Self {
         Self {
             status: Status::SUCCESS,
@@ -46,6 +60,18 @@ impl ResponseHeader {
         }
     }
 
+    pub fn redirect_permanent_lossy<'a>(location: impl TryInto<URIReference<'a>>) -> Self {
+        let location = match location.try_into() {
+            Ok(location) => location,
+            Err(_) => return Self::bad_request_lossy("Invalid redirect location"),
+        };
+
+        Self {
+            status: Status::REDIRECT_PERMANENT,
+            meta: Meta::new_lossy(location.to_string()),
+        }
+    }
+
     pub fn server_error(reason: impl Cowy<str>) -> Result<Self> {
         Ok(Self {
             status: Status::PERMANENT_FAILURE,
@@ -60,6 +86,28 @@ impl ResponseHeader {
         }
     }
 
+    pub fn server_unavailable(reason: impl Cowy<str>) -> Result<Self> {
+        Ok(Self {
+            status: Status::SERVER_UNAVAILABLE,
+            meta: Meta::new(reason).context("Invalid server unavailable reason")?,
+        })
+    }
+
+    pub fn server_unavailable_lossy(reason: impl Cowy<str>) -> Self {
+        Self {
+            status: Status::SERVER_UNAVAILABLE,
+            meta: Meta::new_lossy(reason),
+        }
+    }
+
+    /// Ask the client to wait `seconds` before making another request
+    pub fn slow_down(seconds: u64) -> Self {
+        Self {
+            status: Status::SLOW_DOWN,
+            meta: Meta::new_lossy(seconds.to_string()),
+        }
+    }
+
     pub fn not_found() -> Self {
         Self {
             status: Status::NOT_FOUND,
@@ -67,6 +115,13 @@ impl ResponseHeader {
         }
     }
 
+    pub fn gone() -> Self {
+        Self {
+            status: Status::GONE,
+            meta: Meta::new_lossy("Gone"),
+        }
+    }
+
     pub fn bad_request_lossy(reason: impl Cowy<str>) -> Self {
         Self {
             status: Status::BAD_REQUEST,
@@ -88,6 +143,70 @@ impl ResponseHeader {
         }
     }
 
+    pub fn certificate_not_valid_lossy(reason: impl Cowy<str>) -> Self {
+        Self {
+            status: Status::CERTIFICATE_NOT_VALID,
+            meta: Meta::new_lossy(reason),
+        }
+    }
+
+    /// Create a header, making sure `meta` makes sense for `status`
+    ///
+    /// See [`validate()`](Self::validate()) for the rules checked.
+    ///
+    /// ```
+    /// # use twinstar::{ResponseHeader, Status};
+    /// assert!(ResponseHeader::new(Status::SUCCESS, "text/gemini; lang=en").is_ok());
+    /// assert!(ResponseHeader::new(Status::SUCCESS, "Here is your page").is_err());
+    /// ```
+    pub fn new(status: Status, meta: impl Cowy<str>) -> Result<Self> {
+        let header = Self {
+            status,
+            meta: Meta::new(meta)?,
+        };
+
+        header.validate()?;
+        Ok(header)
+    }
+
+    /// Check whether the meta makes sense for the status
+    ///
+    /// The fields of a header can be set to anything, so it's possible to create
+    /// headers clients can't make sense of, like a success response with a sentence
+    /// instead of a MIME type.  This checks that:
+    ///
+    /// * the prompt of input responses (`1x`) isn't empty
+    /// * the meta of success responses (`2x`) is a MIME type
+    /// * the meta of redirects (`3x`) is a URI reference
+    /// * the meta of `44 SLOW DOWN` is a number of seconds
+    ///
+    /// Servers in [strict mode](crate::Builder::set_strict()) validate every response
+    /// sent by a handler.
+    pub fn validate(&self) -> Result<()> {
+        let meta = self.meta.as_str();
+
+        match self.status.category() {
+            StatusCategory::Input => {
+                ensure!(!meta.trim().is_empty(), "Input prompt is empty");
+            },
+            StatusCategory::Success => {
+                self.meta.to_mime()
+                    .with_context(|| format!("Success meta `{}` is not a MIME type", meta))?;
+            },
+            StatusCategory::Redirect => {
+                URIReference::try_from(meta)
+                    .with_context(|| format!("Redirect meta `{}` is not a URI reference", meta))?;
+            },
+            _ if self.status == Status::SLOW_DOWN => {
+                meta.parse::<u64>()
+                    .with_context(|| format!("Slow down meta `{}` is not a number of seconds", meta))?;
+            },
+            _ => {},
+        }
+
+        Ok(())
+    }
+
     pub const fn status(&self) -> &Status {
         &self.status
     }
@@ -96,3 +215,29 @@ impl ResponseHeader {
         &self.meta
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(status: Status, meta: &str) -> ResponseHeader {
+        ResponseHeader {
+            status,
+            meta: Meta::new_lossy(meta),
+        }
+    }
+
+    #[test]
+    fn validates_meta() {
+        assert!(header(Status::INPUT, "Search for").validate().is_ok());
+        assert!(header(Status::SENSITIVE_INPUT, " ").validate().is_err());
+        assert!(header(Status::SUCCESS, "text/gemini; lang=en").validate().is_ok());
+        assert!(header(Status::SUCCESS, "Here you go").validate().is_err());
+        assert!(header(Status::REDIRECT_PERMANENT, "gemini://example.org/new").validate().is_ok());
+        assert!(header(Status::REDIRECT_TEMPORARY, "../up").validate().is_ok());
+        assert!(header(Status::REDIRECT_TEMPORARY, "not a uri").validate().is_err());
+        assert!(header(Status::SLOW_DOWN, "30").validate().is_ok());
+        assert!(header(Status::SLOW_DOWN, "Slow down").validate().is_err());
+        assert!(header(Status::NOT_FOUND, "").validate().is_ok());
+    }
+}
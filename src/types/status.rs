@@ -25,6 +25,15 @@ impl Status {
         self.0
     }
 
+    /// The status with the given code, if it is a two digit code of a known category
+    #[cfg_attr(not(any(feature="client", feature="multi_tenant")), allow(dead_code))]
+    pub(crate) fn from_code(code: u8) -> Option<Self> {
+        match code {
+            10..=69 => Some(Self(code)),
+            _ => None,
+        }
+    }
+
     pub fn is_success(&self) -> bool {
         self.category().is_success()
     }
@@ -46,6 +55,7 @@ impl Status {
 }
 
 #[derive(Copy,Clone,PartialEq,Eq)]
+#[non_exhaustive]
 pub enum StatusCategory {
     Input,
     Success,
@@ -0,0 +1,14 @@
+use crate::TlsVersion;
+
+/// What was negotiated during the TLS handshake of a connection
+///
+/// This is what [`Request::tls_info()`](crate::Request::tls_info()) returns.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TlsInfo {
+    /// The version of TLS in use
+    pub version: TlsVersion,
+    /// The name of the cipher suite in use, like `TLS13_AES_256_GCM_SHA384`
+    pub cipher_suite: String,
+    /// The hostname the client asked for using SNI, if it sent one
+    pub server_name: Option<String>,
+}
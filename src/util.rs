@@ -1,183 +1,134 @@
-#[cfg(feature="serve_dir")]
-use std::path::{Path, PathBuf};
-#[cfg(feature="serve_dir")]
-use mime::Mime;
-use anyhow::{Result, Context};
-#[cfg(feature="serve_dir")]
-use tokio::{
-    fs::{self, File},
-    io,
-};
-#[cfg(feature="serve_dir")]
-use crate::types::{Document, document::HeadingLevel::*};
+use anyhow::Result;
 use crate::types::Response;
+use std::fmt::Write;
 use std::panic::{catch_unwind, AssertUnwindSafe};
 use std::task::Poll;
+use std::time::{SystemTime, UNIX_EPOCH};
 use futures_core::future::Future;
 use tokio::time;
 
 #[cfg(feature="serve_dir")]
-pub async fn serve_file<P: AsRef<Path>>(path: P, mime: &Mime) -> Result<Response> {
-    let path = path.as_ref();
-
-    let file = match File::open(path).await {
-        Ok(file) => file,
-        Err(err) => match err.kind() {
-            std::io::ErrorKind::PermissionDenied => {
-                warn!("Asked to serve {}, but permission denied by OS", path.display());
-                return Ok(Response::not_found());
-            },
-            _ => return warn_unexpected(err, path, line!()),
-        }
-    };
+mod serve_dir;
+#[cfg(feature="serve_dir")]
+mod gallery;
+#[cfg(feature="serve_dir")]
+mod gemlog;
+#[cfg(feature="serve_dir")]
+pub use self::gemlog::{Gemlog, Post};
+#[cfg(feature="serve_dir")]
+mod search;
+#[cfg(feature="serve_dir")]
+pub use self::search::{Search, SearchIndex, SearchHit};
+#[cfg(feature="serve_dir")]
+pub use self::gallery::Gallery;
+#[cfg(feature="serve_dir")]
+mod hybrid_dir;
+#[cfg(feature="serve_dir")]
+pub use self::hybrid_dir::HybridDir;
+#[cfg(feature="serve_dir")]
+pub use self::serve_dir::{ServeDir, OversizePolicy, serve_dir, serve_file, guess_mime_from_path, guess_mime_from_contents};
 
-    Ok(Response::success(mime, file))
-}
+pub mod sniff;
 
-#[cfg(feature="serve_dir")]
-pub async fn serve_dir<D: AsRef<Path>, P: AsRef<Path>>(dir: D, virtual_path: &[P]) -> Result<Response> {
-    debug!("Dir: {}", dir.as_ref().display());
-    let dir = dir.as_ref();
-    let dir = match dir.canonicalize() {
-        Ok(dir) => dir,
-        Err(e) => {
-            match e.kind() {
-                std::io::ErrorKind::NotFound => {
-                    warn!("Path {} not found.  Check your configuration.", dir.display());
-                    return Response::server_error("Server incorrectly configured")
-                },
-                std::io::ErrorKind::PermissionDenied => {
-                    warn!("Permission denied for {}.  Check that the server has access.", dir.display());
-                    return Response::server_error("Server incorrectly configured")
-                },
-                _ => return warn_unexpected(e, dir, line!()),
-            }
-        },
-    };
-    let mut path = dir.to_path_buf();
-
-    for segment in virtual_path {
-        path.push(segment);
-    }
+mod deadline;
+pub use self::deadline::Deadline;
 
-    let path = match path.canonicalize() {
-        Ok(dir) => dir,
-        Err(e) => {
-            match e.kind() {
-                std::io::ErrorKind::NotFound => return Ok(Response::not_found()),
-                std::io::ErrorKind::PermissionDenied => {
-                    // Runs when asked to serve a file in a restricted dir
-                    // i.e. not /noaccess, but /noaccess/file
-                    warn!("Asked to serve {}, but permission denied by OS", path.display());
-                    return Ok(Response::not_found());
-                },
-                _ => return warn_unexpected(e, path.as_ref(), line!()),
-            }
-        },
-    };
-
-    if !path.starts_with(&dir) {
-        return Ok(Response::not_found());
-    }
+mod nonces;
+pub use self::nonces::{Nonces, DEFAULT_NONCE_TTL, DEFAULT_MAX_NONCES};
 
-    if !path.is_dir() {
-        let mime = guess_mime_from_path(&path);
-        return serve_file(path, &mime).await;
-    }
+mod input;
+pub use self::input::Prompt;
 
-    serve_dir_listing(path, virtual_path).await
-}
+mod path_normalization;
+pub use self::path_normalization::PathNormalizer;
+#[cfg(feature="unicode_normalization")]
+pub use self::path_normalization::nfc;
+pub(crate) use self::path_normalization::normalize_path;
 
-#[cfg(feature="serve_dir")]
-async fn serve_dir_listing<P: AsRef<Path>, B: AsRef<Path>>(path: P, virtual_path: &[B]) -> Result<Response> {
-    let mut dir = match fs::read_dir(path.as_ref()).await {
-        Ok(dir) => dir,
-        Err(err) => match err.kind() {
-            io::ErrorKind::NotFound => return Ok(Response::not_found()),
-            std::io::ErrorKind::PermissionDenied => {
-                warn!("Asked to serve {}, but permission denied by OS", path.as_ref().display());
-                return Ok(Response::not_found());
-            },
-            _ => return warn_unexpected(err, path.as_ref(), line!()),
-        }
-    };
+mod continued_input;
+pub use self::continued_input::{ContinuedInput, DEFAULT_CONTINUATION_TTL, DEFAULT_MAX_INPUT_LEN, DEFAULT_MAX_PENDING_INPUTS};
 
-    let breadcrumbs: PathBuf = virtual_path.iter().collect();
-    let mut document = Document::new();
+mod schedule;
+pub use self::schedule::Schedule;
 
-    document.add_heading(H1, format!("Index of /{}", breadcrumbs.display()));
-    document.add_blank_line();
+mod versions;
+pub use self::versions::Versions;
 
-    if virtual_path.get(0).map(<_>::as_ref) != Some(Path::new("")) {
-        document.add_link("..", "📁 ../");
-    }
+mod capsule_info;
+pub use self::capsule_info::{CapsuleInfo, SECURITY_TXT_PATH, DEFAULT_SECURITY_TXT_VALIDITY};
 
-    while let Some(entry) = dir.next_entry().await.context("Failed to list directory")? {
-        let file_name = entry.file_name();
-        let file_name = file_name.to_string_lossy();
-        let is_dir = entry.file_type().await
-            .with_context(|| format!("Failed to get file type of `{}`", entry.path().display()))?
-            .is_dir();
-        let trailing_slash = if is_dir { "/" } else { "" };
-        let uri = format!("./{}{}", file_name, trailing_slash);
-
-        document.add_link(uri.as_str(), format!("{icon} {name}{trailing_slash}",
-            icon = if is_dir { '📁' } else { '📄' },
-            name = file_name,
-            trailing_slash = trailing_slash
-        ));
-    }
+mod topic;
+pub use self::topic::{Topic, Subscription, DEFAULT_TOPIC_CAPACITY};
+
+#[cfg(feature="charset")]
+pub mod charset;
+
+pub use crate::types::document::Cowy;
 
-    Ok(document.into())
+/// Render strings as a JSON array
+#[cfg(feature="description")]
+pub(crate) fn json_list(items: impl Iterator<Item = String>) -> String {
+    let items = items
+        .map(|item| json_string(&item))
+        .collect::<Vec<_>>();
+
+    format!("[{}]", items.join(","))
 }
 
-#[cfg(feature="serve_dir")]
-pub fn guess_mime_from_path<P: AsRef<Path>>(path: P) -> Mime {
-    let path = path.as_ref();
-    let extension = path.extension().and_then(|s| s.to_str());
-    let extension = match extension {
-        Some(extension) => extension,
-        None => return mime::APPLICATION_OCTET_STREAM,
-    };
-
-    if let "gemini" | "gmi" = extension {
-        return crate::GEMINI_MIME.clone();
+/// Render bytes as lowercase hex, two digits per byte
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(hex, "{:02x}", byte);
     }
-
-    mime_guess::from_ext(extension).first_or_octet_stream()
+    hex
 }
 
-#[cfg(feature="serve_dir")]
-/// Print a warning to the log asking to file an issue and respond with "Unexpected Error"
-pub (crate) fn warn_unexpected(err: impl std::fmt::Debug, path: &Path, line: u32) -> Result<Response> {
-    warn!(
-        concat!(
-            "Unexpected error serving path {} at util.rs:{}, please report to ",
-            env!("CARGO_PKG_REPOSITORY"),
-            "/issues: {:?}",
-        ),
-        path.display(),
-        line,
-        err
-    );
-    Response::server_error("Unexpected error")
+/// Render a string as a JSON string literal, including the quotes
+#[cfg_attr(not(any(feature="description", feature="route_debug", feature="events")), allow(dead_code))]
+pub(crate) fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped.push('"');
+    escaped
 }
 
-/// A convenience trait alias for `AsRef<T> + Into<T::Owned>`,
-/// most commonly used to accept `&str` or `String`:
-///
-/// `Cowy<str>` ⇔ `AsRef<str> + Into<String>`
-pub trait Cowy<T>
-where
-    Self: AsRef<T> + Into<T::Owned>,
-    T: ToOwned + ?Sized,
-{}
-
-impl<C, T> Cowy<T> for C
-where
-    C: AsRef<T> + Into<T::Owned>,
-    T: ToOwned + ?Sized,
-{}
+/// Format a point in time as an RFC 3339 UTC timestamp, e.g. `2020-12-05T13:37:00Z`
+pub(crate) fn rfc3339(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let (days, secs_of_day) = (secs / 86400, secs % 86400);
+
+    // Convert days since the epoch to a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day,
+        secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60,
+    )
+}
 
 /// A utility for catching unwinds on Futures.
 ///
@@ -214,3 +165,22 @@ pub(crate) async fn opt_timeout<T>(duration: Option<time::Duration>, future: imp
         None => Ok(future.await),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn formats_timestamps() {
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(rfc3339(UNIX_EPOCH + Duration::from_secs(951_827_696)), "2000-02-29T12:34:56Z");
+        assert_eq!(rfc3339(UNIX_EPOCH + Duration::from_secs(1_607_126_400)), "2020-12-05T00:00:00Z");
+    }
+
+    #[test]
+    fn formats_hex() {
+        assert_eq!(hex(&[]), "");
+        assert_eq!(hex(&[0x00, 0x0f, 0xa5, 0xff]), "000fa5ff");
+    }
+}
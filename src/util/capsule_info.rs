@@ -0,0 +1,202 @@
+use std::time::{Duration, SystemTime};
+
+use crate::BoxedHandler;
+use crate::util::rfc3339;
+use crate::types::{Document, Request, Response, document::HeadingLevel::*};
+
+/// Where `security.txt` is served, see [RFC 9116](https://www.rfc-editor.org/rfc/rfc9116)
+pub const SECURITY_TXT_PATH: &str = "/.well-known/security.txt";
+
+/// How long a generated `security.txt` is valid without an explicit expiry
+///
+/// RFC 9116 recommends less than a year.  Since the file is generated for every
+/// request, it never actually goes stale.
+pub const DEFAULT_SECURITY_TXT_VALIDITY: Duration = Duration::from_secs(180 * 24 * 60 * 60);
+
+/// Who runs a capsule and how to reach them
+///
+/// Passing this to [`Builder::set_capsule_info()`](crate::Builder::set_capsule_info())
+/// serves a [`security.txt`](SECURITY_TXT_PATH) listing the contacts, and an
+/// [`about_handler()`](Self::about_handler()) can serve the same details as a page.
+/// Both are generated from the same fields for every host the server answers, so
+/// contact details can't drift apart between them.
+///
+/// ```no_run
+/// # use twinstar::{Server, GEMINI_PORT, util::CapsuleInfo};
+/// # async fn run() -> anyhow::Result<()> {
+/// let info = CapsuleInfo::new()
+///     .set_name("Alice's capsule")
+///     .set_description("Notes on gardening and Rust")
+///     .add_contact("mailto:alice@example.org")
+///     .add_contact("gemini://example.org/contact")
+///     .add_preferred_language("en");
+///
+/// Server::bind(("localhost", GEMINI_PORT))
+///     .add_route("/about", info.about_handler())
+///     .set_capsule_info(info)
+///     .serve()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CapsuleInfo {
+    name: Option<String>,
+    description: Option<String>,
+    contacts: Vec<String>,
+    policy: Option<String>,
+    preferred_languages: Vec<String>,
+    expires: Option<SystemTime>,
+}
+
+impl CapsuleInfo {
+    /// Create empty capsule info
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the name of the capsule, the title of the about page
+    pub fn set_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Set a description of the capsule for the about page
+    pub fn set_description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Add a way to contact the operator, as a URI like `mailto:alice@example.org`
+    ///
+    /// Contacts are listed in the order they were added, which should be the order of
+    /// preference.  Without any contact, no `security.txt` is served, since it requires
+    /// at least one.
+    pub fn add_contact(mut self, uri: impl Into<String>) -> Self {
+        self.contacts.push(uri.into());
+        self
+    }
+
+    /// Set the URI of the policy for reporting security issues
+    pub fn set_policy(mut self, uri: impl Into<String>) -> Self {
+        self.policy = Some(uri.into());
+        self
+    }
+
+    /// Add a language reports can be written in, as a language tag like `en`
+    pub fn add_preferred_language(mut self, language: impl Into<String>) -> Self {
+        self.preferred_languages.push(language.into());
+        self
+    }
+
+    /// Set when the `security.txt` should be considered stale
+    ///
+    /// Without an expiry, it expires [`DEFAULT_SECURITY_TXT_VALIDITY`] after being
+    /// requested.
+    pub fn set_expires(mut self, expires: SystemTime) -> Self {
+        self.expires = Some(expires);
+        self
+    }
+
+    /// The ways to contact the operator
+    pub fn contacts(&self) -> &[String] {
+        &self.contacts
+    }
+
+    /// The `security.txt` for `host`, generated at `now`
+    ///
+    /// The file names its canonical location on `host`, if given.
+    pub fn security_txt(&self, host: Option<&str>, now: SystemTime) -> String {
+        let mut txt = String::new();
+
+        for contact in &self.contacts {
+            txt += &format!("Contact: {}\n", contact);
+        }
+        let expires = self.expires.unwrap_or(now + DEFAULT_SECURITY_TXT_VALIDITY);
+        txt += &format!("Expires: {}\n", rfc3339(expires));
+        if !self.preferred_languages.is_empty() {
+            txt += &format!("Preferred-Languages: {}\n", self.preferred_languages.join(", "));
+        }
+        if let Some(policy) = &self.policy {
+            txt += &format!("Policy: {}\n", policy);
+        }
+        if let Some(host) = host {
+            txt += &format!("Canonical: gemini://{}{}\n", host, SECURITY_TXT_PATH);
+        }
+
+        txt
+    }
+
+    /// An about page with the name, description and contacts of the capsule
+    pub fn about_page(&self) -> Document {
+        let mut document = Document::new();
+        document.add_heading(H1, self.name.as_deref().unwrap_or("About"));
+
+        if let Some(description) = &self.description {
+            document.add_blank_line();
+            document.add_text(description);
+        }
+
+        if !self.contacts.is_empty() || self.policy.is_some() {
+            document.add_blank_line();
+            document.add_heading(H2, "Contact");
+            for contact in &self.contacts {
+                document.add_link(contact.as_str(), contact.as_str());
+            }
+            if let Some(policy) = &self.policy {
+                document.add_link(policy.as_str(), "Security policy");
+            }
+        }
+
+        document
+    }
+
+    /// A handler serving the [about page](Self::about_page())
+    pub fn about_handler(&self) -> BoxedHandler {
+        let page = self.about_page().to_string();
+
+        Box::new(move |_| {
+            let response = Response::success_gemini(page.clone());
+            Box::pin(async { Ok(response) })
+        })
+    }
+
+    /// Answer a request for [`SECURITY_TXT_PATH`]
+    pub(crate) fn respond_security_txt(&self, request: &Request) -> Response {
+        let host = request.uri().host().map(ToString::to_string);
+        let txt = self.security_txt(host.as_deref(), SystemTime::now());
+
+        Response::success(&mime::TEXT_PLAIN_UTF_8, txt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+    use super::*;
+
+    #[test]
+    fn generates_metadata() {
+        let info = CapsuleInfo::new()
+            .set_name("Capsule")
+            .add_contact("mailto:alice@example.org")
+            .add_contact("gemini://example.org/contact")
+            .set_policy("gemini://example.org/security")
+            .add_preferred_language("en")
+            .add_preferred_language("de");
+
+        let now = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        assert_eq!(info.security_txt(Some("example.org"), now), "\
+            Contact: mailto:alice@example.org\n\
+            Contact: gemini://example.org/contact\n\
+            Expires: 2021-03-12T12:26:40Z\n\
+            Preferred-Languages: en, de\n\
+            Policy: gemini://example.org/security\n\
+            Canonical: gemini://example.org/.well-known/security.txt\n\
+        ");
+
+        let page = info.about_page().to_string();
+        assert!(page.contains("=> mailto:alice@example.org mailto:alice@example.org\n"));
+        assert!(page.contains("=> gemini://example.org/security Security policy\n"));
+    }
+}
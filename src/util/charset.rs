@@ -0,0 +1,76 @@
+//! Transcoding legacy text files to UTF-8
+//!
+//! Gemini clients assume `text/*` responses without a `charset` parameter are UTF-8, so
+//! text written in a legacy encoding like Latin-1 is displayed garbled.  This module
+//! detects the encoding of such files and converts them to UTF-8 before they are sent.
+//!
+//! See [`ServeDir::set_text_transcoding()`](super::ServeDir::set_text_transcoding()).
+
+use std::borrow::Cow;
+
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_8};
+use mime::Mime;
+
+/// Files larger than this are served as-is rather than transcoded
+pub const MAX_TRANSCODE_LEN: u64 = 16 * 1024 * 1024;
+
+/// Decode `bytes` into a UTF-8 string, guessing the encoding if it isn't UTF-8 already
+///
+/// Returns the decoded text along with the encoding that was detected.  Byte sequences
+/// that are invalid in the detected encoding are replaced with U+FFFD.
+///
+/// ```
+/// # use twinstar::util::charset::to_utf8;
+/// let latin1 = b"Bienvenue \xe0 bord, voici le caf\xe9 du capitaine.";
+/// let (text, encoding) = to_utf8(latin1);
+///
+/// assert_eq!(text, "Bienvenue à bord, voici le café du capitaine.");
+/// assert_eq!(encoding.name(), "windows-1252");
+/// ```
+pub fn to_utf8(bytes: &[u8]) -> (Cow<'_, str>, &'static Encoding) {
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return (Cow::Borrowed(text), UTF_8);
+    }
+
+    let mut detector = EncodingDetector::new();
+    detector.feed(bytes, true);
+    let encoding = detector.guess(None, false);
+    let (text, _, _) = encoding.decode(bytes);
+
+    (text, encoding)
+}
+
+/// The MIME with the same type and parameters as `mime`, but with `charset=utf-8`
+pub(crate) fn with_utf8_charset(mime: &Mime) -> Mime {
+    let mut with_charset = mime.essence_str().to_owned();
+
+    for (name, value) in mime.params() {
+        if name != mime::CHARSET {
+            with_charset.push_str(&format!("; {}={}", name, value));
+        }
+    }
+
+    with_charset.push_str("; charset=utf-8");
+    with_charset.parse().expect("twinstar BUG")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utf8_is_borrowed() {
+        let (text, encoding) = to_utf8("café".as_bytes());
+
+        assert!(matches!(text, Cow::Borrowed("café")));
+        assert_eq!(encoding, UTF_8);
+    }
+
+    #[test]
+    fn charset_replaces_existing_charset() {
+        let mime: Mime = "text/gemini; charset=iso-8859-1; lang=fr".parse().unwrap();
+
+        assert_eq!(with_utf8_charset(&mime).to_string(), "text/gemini; lang=fr; charset=utf-8");
+    }
+}
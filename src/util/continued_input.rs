@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use ring::rand::{SecureRandom, SystemRandom};
+use uriparse::path::Segment;
+
+use crate::BoxedHandler;
+use crate::types::{Request, Response};
+use crate::HandlerResponse;
+use super::hex;
+
+/// How long unfinished input is kept by default
+pub const DEFAULT_CONTINUATION_TTL: Duration = Duration::from_secs(30 * 60);
+
+/// How long input stitched together from several submissions may be by default
+pub const DEFAULT_MAX_INPUT_LEN: usize = 64 * 1024;
+
+/// How many unfinished inputs are kept by default
+pub const DEFAULT_MAX_PENDING_INPUTS: usize = 1_000;
+
+/// Input which may be longer than fits into a single request
+///
+/// Request URIs are limited to 1024 bytes, which leaves room for less than 1000
+/// characters of input, and less for text which needs a lot of percent encoding.
+/// [`require()`](Self::require()) wraps a handler which needs more, letting the user
+/// submit the input in parts:
+///
+/// 1. The user is prompted for input, like with a [`Prompt`](super::Prompt).
+/// 2. A submission ending with the continuation marker, `\` by default, is stored under
+///    a random token, and the user is redirected to the route followed by the token,
+///    like `/compose/3f2a…`, which prompts for the next part.
+/// 3. The first submission not ending with the marker completes the input.  The handler
+///    is called with the completed request, whose [`input()`](Request::input()) is
+///    every part joined by newlines.
+///
+/// Every submission gets a new token, so reloading a page which already submitted a
+/// part answers `59 BAD REQUEST` instead of adding the part twice.  Since the token is
+/// a trailing segment, the wrapped handler should be the only handler for its route.
+///
+/// Unfinished input expires after a while, and only a limited number of them are kept,
+/// dropping the ones closest to expiring first.  They are kept in memory, so they don't
+/// survive restarts.  Clones share the same unfinished inputs.
+///
+/// ```no_run
+/// # use twinstar::{Server, Request, Response, GEMINI_PORT, util::ContinuedInput};
+/// # async fn run() -> anyhow::Result<()> {
+/// Server::bind(("localhost", GEMINI_PORT))
+///     .add_route("/compose", ContinuedInput::new("Your message").require(|request: Request| {
+///         let message = request.input().unwrap_or_default().to_owned();
+///         Box::pin(async move {
+///             Ok(Response::success_plain(format!("Received {} characters", message.chars().count())))
+///         }) as _
+///     }))
+///     .serve()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct ContinuedInput {
+    prompt: String,
+    marker: String,
+    /// The unfinished inputs by token, and when they expire
+    pending: Arc<Mutex<HashMap<String, (String, Instant)>>>,
+    rng: Arc<SystemRandom>,
+    ttl: Duration,
+    max_len: usize,
+    max_pending: usize,
+}
+
+impl ContinuedInput {
+    /// Prompt for input with `prompt`, sent with `10 INPUT`
+    pub fn new(prompt: impl Into<String>) -> Self {
+        Self {
+            prompt: prompt.into(),
+            marker: "\\".to_owned(),
+            pending: Arc::default(),
+            rng: Arc::new(SystemRandom::new()),
+            ttl: DEFAULT_CONTINUATION_TTL,
+            max_len: DEFAULT_MAX_INPUT_LEN,
+            max_pending: DEFAULT_MAX_PENDING_INPUTS,
+        }
+    }
+
+    /// Set what a submission ends with to continue the input, `\` by default
+    ///
+    /// The marker is removed from the submission.  An empty marker is ignored.
+    pub fn set_continuation_marker(mut self, marker: impl Into<String>) -> Self {
+        let marker = marker.into();
+        if !marker.is_empty() {
+            self.marker = marker;
+        }
+        self
+    }
+
+    /// Set how long unfinished input is kept
+    ///
+    /// The default is [`DEFAULT_CONTINUATION_TTL`].
+    pub fn set_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Set how many bytes the completed input may have at most
+    ///
+    /// Longer input is answered with `59 BAD REQUEST`.  The default is
+    /// [`DEFAULT_MAX_INPUT_LEN`].
+    pub fn set_max_len(mut self, max_len: usize) -> Self {
+        self.max_len = max_len;
+        self
+    }
+
+    /// Set how many unfinished inputs are kept at most
+    ///
+    /// The default is [`DEFAULT_MAX_PENDING_INPUTS`].
+    pub fn set_max_pending(mut self, max_pending: usize) -> Self {
+        self.max_pending = max_pending.max(1);
+        self
+    }
+
+    /// The response asking for the first part of the input
+    pub fn prompt(&self) -> Response {
+        Response::input_lossy(format!("{} (end with {} to write more)", self.prompt, self.marker))
+    }
+
+    /// The response asking for the next part of `input`
+    fn continuation_prompt(&self, input: &str) -> Response {
+        Response::input_lossy(format!(
+            "{} (continued after {} characters, end with {} to write more)",
+            self.prompt,
+            input.chars().count(),
+            self.marker,
+        ))
+    }
+
+    /// Store unfinished `input`, returning its token
+    fn store(&self, input: String) -> String {
+        let mut bytes = [0; 16];
+        self.rng.fill(&mut bytes).expect("Failed to generate token");
+        let token = hex(&bytes);
+
+        let now = Instant::now();
+        let mut pending = self.pending.lock().expect("twinstar BUG");
+        pending.retain(|_, (_, expires)| *expires > now);
+
+        while pending.len() >= self.max_pending {
+            let oldest = pending.iter()
+                .min_by_key(|(_, (_, expires))| *expires)
+                .map(|(token, _)| token.clone())
+                .expect("twinstar BUG");
+            pending.remove(&oldest);
+        }
+
+        pending.insert(token.clone(), (input, now + self.ttl));
+        token
+    }
+
+    /// The unfinished input stored under `token`, if it hasn't expired
+    fn get(&self, token: &str) -> Option<String> {
+        let pending = self.pending.lock().expect("twinstar BUG");
+
+        pending.get(token)
+            .filter(|(_, expires)| *expires > Instant::now())
+            .map(|(input, _)| input.clone())
+    }
+
+    /// Remove the unfinished input stored under `token`, if it hasn't expired
+    fn take(&self, token: &str) -> Option<String> {
+        let mut pending = self.pending.lock().expect("twinstar BUG");
+
+        match pending.remove(token) {
+            Some((input, expires)) if expires > Instant::now() => Some(input),
+            _ => None,
+        }
+    }
+
+    /// Complete the input of `request`, or answer it if the input isn't complete yet
+    fn continue_input(&self, request: &mut Request) -> Option<Response> {
+        let token = match request.trailing_segments().as_slice() {
+            [] => None,
+            [token] => Some(token.clone()),
+            _ => return Some(Response::not_found()),
+        };
+        let expired = || Response::bad_request_lossy("This input has expired or was already submitted, please start over");
+
+        let part = match request.input() {
+            Some(part) if !part.is_empty() => part,
+            _ => return match token {
+                None => Some(self.prompt()),
+                Some(token) => match self.get(&token) {
+                    Some(input) => Some(self.continuation_prompt(&input)),
+                    None => Some(expired()),
+                },
+            },
+        };
+
+        let mut input = match &token {
+            None => String::new(),
+            Some(token) => match self.take(token) {
+                Some(input) => input,
+                None => return Some(expired()),
+            },
+        };
+        let continued = part.ends_with(&self.marker);
+        let part = part.strip_suffix(&self.marker).unwrap_or(part);
+
+        if token.is_some() {
+            input.push('\n');
+        }
+        input.push_str(part);
+
+        if input.len() > self.max_len {
+            return Some(Response::bad_request_lossy(format!("Input is too long, at most {} bytes are allowed", self.max_len)));
+        }
+
+        if continued {
+            let token = self.store(input);
+            return Some(Response::redirect_temporary_lossy(token_path(request, &token).as_str()));
+        }
+
+        request.set_input(Some(input));
+        None
+    }
+
+    /// Only pass requests on to `handler` which complete the input, prompting for it
+    /// otherwise
+    pub fn require<H>(&self, handler: H) -> BoxedHandler
+    where
+        H: Fn(Request) -> HandlerResponse + Send + Sync + 'static,
+    {
+        let input = self.clone();
+
+        Box::new(move |mut request| {
+            match input.continue_input(&mut request) {
+                None => handler(request),
+                Some(response) => Box::pin(async { Ok(response) }),
+            }
+        })
+    }
+}
+
+/// The path of the route `request` was sent to, followed by `token`
+fn token_path(request: &Request, token: &str) -> String {
+    let mut segments = request.uri().path().segments().iter()
+        .map(Segment::as_str)
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>();
+    segments.truncate(segments.len().saturating_sub(request.trailing_segments().len()));
+    segments.push(token);
+
+    format!("/{}", segments.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use super::*;
+    use crate::types::Status;
+    use crate::uri::URIReference;
+
+    fn request(uri: &str) -> Request {
+        let mut request = Request::from_uri(URIReference::try_from(uri).unwrap().into_owned()).unwrap();
+        let trailing = request.path_segments().into_iter().skip(1).collect();
+        request.set_trailing(trailing);
+        request
+    }
+
+    #[tokio::test]
+    async fn stitches_input() {
+        let input = ContinuedInput::new("Message").set_max_len(20);
+        let handler = input.require(|request: Request| {
+            let input = request.input().unwrap_or_default().to_owned();
+            Box::pin(async move { Ok(Response::success_plain(input)) }) as HandlerResponse
+        });
+
+        let response = handler(request("gemini://localhost/compose")).await.unwrap();
+        assert_eq!(response.header().meta.as_str(), "Message (end with \\ to write more)");
+
+        let response = handler(request("gemini://localhost/compose?Hello%5C")).await.unwrap();
+        assert_eq!(response.header().status, Status::REDIRECT_TEMPORARY);
+        let first = response.header().meta.as_str().to_owned();
+
+        let response = handler(request(&format!("gemini://localhost{}", first))).await.unwrap();
+        assert_eq!(response.header().meta.as_str(), "Message (continued after 5 characters, end with \\ to write more)");
+
+        let response = handler(request(&format!("gemini://localhost{}?world%5C", first))).await.unwrap();
+        let second = response.header().meta.as_str().to_owned();
+        assert_ne!(first, second);
+
+        // Reloading doesn't submit the same part twice
+        let response = handler(request(&format!("gemini://localhost{}?world%5C", first))).await.unwrap();
+        assert_eq!(response.header().status, Status::BAD_REQUEST);
+
+        let mut response = handler(request(&format!("gemini://localhost{}?!", second))).await.unwrap();
+        assert_eq!(response.take_body().unwrap().as_bytes(), Some("Hello\nworld\n!".as_bytes()));
+
+        let response = handler(request("gemini://localhost/compose?This%20is%20far%20too%20long!")).await.unwrap();
+        assert_eq!(response.header().status, Status::BAD_REQUEST);
+    }
+}
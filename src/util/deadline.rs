@@ -0,0 +1,64 @@
+use std::time::{Duration, Instant};
+
+use crate::types::Request;
+
+/// The point in time by which a request should be answered
+///
+/// Handlers doing expensive work, like searching a large index, can use this to stop
+/// early or skip optional work once their time is running out.  A deadline is attached
+/// to every request passing through the [`Budget`](crate::middleware::Budget)
+/// middleware, and can be retrieved using [`Deadline::of()`].
+///
+/// ```
+/// # use std::time::Duration;
+/// # use twinstar::util::Deadline;
+/// let deadline = Deadline::after(Duration::from_secs(2));
+///
+/// assert!(!deadline.is_expired());
+/// assert!(deadline.remaining() <= Duration::from_secs(2));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deadline {
+    start: Instant,
+    budget: Duration,
+}
+
+impl Deadline {
+    /// A deadline `budget` from now
+    pub fn after(budget: Duration) -> Self {
+        Self {
+            start: Instant::now(),
+            budget,
+        }
+    }
+
+    /// The deadline attached to a request, if any
+    pub fn of(request: &Request) -> Option<Self> {
+        request.extensions().get::<Self>().copied()
+    }
+
+    /// The point in time of the deadline
+    pub fn instant(&self) -> Instant {
+        self.start + self.budget
+    }
+
+    /// The total time which was granted
+    pub const fn budget(&self) -> Duration {
+        self.budget
+    }
+
+    /// The time passed since the deadline was set
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// The time left until the deadline, or zero if it has passed
+    pub fn remaining(&self) -> Duration {
+        self.budget.checked_sub(self.elapsed()).unwrap_or_default()
+    }
+
+    /// Whether the deadline has passed
+    pub fn is_expired(&self) -> bool {
+        self.elapsed() >= self.budget
+    }
+}
@@ -0,0 +1,254 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Result, Context};
+use mime::Mime;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use tokio::fs;
+
+use crate::types::{Document, Request, Response, document::HeadingLevel::*};
+use crate::HandlerResponse;
+use super::serve_dir::{ServeDir, decoded_trailing_segments, guess_mime_from_path};
+
+/// Characters which need to be escaped in a relative link to a file
+const PATH_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ').add(b'"').add(b'#').add(b'%').add(b'<').add(b'>')
+    .add(b'?').add(b'`').add(b'{').add(b'}').add(b'/').add(b':');
+
+/// The extension of sidecar files containing captions
+const CAPTION_EXTENSION: &str = "txt";
+
+/// A handler presenting a directory of images and audio as a gemtext gallery
+///
+/// Directories are rendered as a document linking to every image, audio, and video
+/// file they contain, as well as to any subdirectories, which are rendered as galleries
+/// themselves.  Requests for the media files are answered with the raw file, like
+/// [`ServeDir`] would.
+///
+/// Each file can be captioned using a sidecar file with `.txt` appended to its name, so
+/// `cat.jpg` is captioned by `cat.jpg.txt`.  The first line of the caption is used as
+/// the label of the link, and any further lines are displayed below it.  Files without
+/// a caption are labelled with their file name.
+///
+/// ```no_run
+/// # use twinstar::{Server, GEMINI_PORT, util::Gallery};
+/// # async fn run() -> anyhow::Result<()> {
+/// Server::bind(("localhost", GEMINI_PORT))
+///     .add_route("/photos", Gallery::new("photos").set_title("Holiday photos").into_handler())
+///     .serve()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Gallery {
+    files: ServeDir,
+    title: Option<String>,
+}
+
+impl Gallery {
+    /// Present the media found in `root`
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            files: ServeDir::new(root),
+            title: None,
+        }
+    }
+
+    /// Set the heading of the gallery's index pages
+    ///
+    /// By default, the name of the directory being displayed is used.
+    pub fn set_title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    /// Present the media served by `files`
+    ///
+    /// This allows configuring how the media files themselves are served, for example
+    /// to limit their size using [`ServeDir::set_max_file_size()`].
+    pub fn with_files(files: ServeDir) -> Self {
+        Self {
+            files,
+            title: None,
+        }
+    }
+
+    /// Turn this into a handler which can be passed to
+    /// [`Builder::add_route()`](crate::Builder::add_route())
+    ///
+    /// The handler serves the path trailing the route it was mounted on.
+    pub fn into_handler(self) -> impl Fn(Request) -> HandlerResponse + Send + Sync {
+        let this = Arc::new(self);
+        move |request: Request| {
+            let this = this.clone();
+            Box::pin(async move {
+                this.serve(&decoded_trailing_segments(&request)).await
+            }) as HandlerResponse
+        }
+    }
+
+    /// Serve the gallery page or media file found at `virtual_path` within the root
+    pub async fn serve<P: AsRef<Path>>(&self, virtual_path: &[P]) -> Result<Response> {
+        let path = match self.files.resolve(virtual_path)? {
+            Ok(path) => path,
+            Err(response) => return Ok(response),
+        };
+
+        if !path.is_dir() {
+            return self.files.serve_file(path).await;
+        }
+
+        self.serve_index(&path, virtual_path).await
+    }
+
+    async fn serve_index<P: AsRef<Path>>(&self, path: &Path, virtual_path: &[P]) -> Result<Response> {
+        let mut dirs = Vec::new();
+        let mut media = Vec::new();
+        let mut entries = fs::read_dir(path).await
+            .with_context(|| format!("Failed to list `{}`", path.display()))?;
+
+        while let Some(entry) = entries.next_entry().await.context("Failed to list directory")? {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+
+            if file_name.starts_with('.') {
+                continue;
+            }
+
+            let is_dir = entry.file_type().await
+                .with_context(|| format!("Failed to get file type of `{}`", entry.path().display()))?
+                .is_dir();
+
+            if is_dir {
+                dirs.push(file_name);
+            } else if let Some(kind) = MediaKind::of(&guess_mime_from_path(&file_name)) {
+                media.push((file_name, kind));
+            }
+        }
+
+        dirs.sort();
+        media.sort();
+
+        let at_root = virtual_path.iter()
+            .all(|segment| segment.as_ref().as_os_str().is_empty());
+        let title = match &self.title {
+            Some(title) if at_root => title.clone(),
+            _ => path.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        };
+
+        let mut document = Document::new();
+        document.add_heading(H1, title);
+        document.add_blank_line();
+
+        if !at_root {
+            document.add_link("..", "📁 ../");
+        }
+
+        for dir in &dirs {
+            let uri = format!("./{}/", utf8_percent_encode(dir, PATH_SEGMENT));
+            document.add_link(uri.as_str(), format!("📁 {}/", dir));
+        }
+
+        if !dirs.is_empty() {
+            document.add_blank_line();
+        }
+
+        for (file_name, kind) in &media {
+            let uri = format!("./{}", utf8_percent_encode(file_name, PATH_SEGMENT));
+            let caption = read_caption(&path.join(file_name)).await;
+            let mut caption_lines = caption.as_deref().unwrap_or("").lines();
+            let label = caption_lines.next().unwrap_or(file_name);
+
+            document.add_link(uri.as_str(), format!("{} {}", kind.icon(), label));
+
+            let description = caption_lines.collect::<Vec<_>>().join("\n");
+            if !description.trim().is_empty() {
+                document.add_text(description.trim());
+                document.add_blank_line();
+            }
+        }
+
+        if media.is_empty() && dirs.is_empty() {
+            document.add_text("This gallery is empty.");
+        }
+
+        Ok(document.into())
+    }
+}
+
+/// Read the caption of a media file from its sidecar file, if there is one
+async fn read_caption(media_path: &Path) -> Option<String> {
+    let mut sidecar = media_path.as_os_str().to_owned();
+    sidecar.push(".");
+    sidecar.push(CAPTION_EXTENSION);
+
+    let caption = fs::read_to_string(PathBuf::from(sidecar)).await.ok()?;
+    let caption = caption.trim();
+
+    if caption.is_empty() {
+        None
+    } else {
+        Some(caption.to_owned())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum MediaKind {
+    Image,
+    Audio,
+    Video,
+}
+
+impl MediaKind {
+    fn of(mime: &Mime) -> Option<Self> {
+        match mime.type_() {
+            mime::IMAGE => Some(Self::Image),
+            mime::AUDIO => Some(Self::Audio),
+            mime::VIDEO => Some(Self::Video),
+            _ => None,
+        }
+    }
+
+    const fn icon(self) -> &'static str {
+        match self {
+            Self::Image => "🖼",
+            Self::Audio => "🎵",
+            Self::Video => "🎞",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn lists_captioned_media() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::create_dir_all(dir.join("older")).unwrap();
+        std::fs::write(dir.join("sea side.jpg"), b"\xff\xd8\xff").unwrap();
+        std::fs::write(dir.join("sea side.jpg.txt"), "The sea\nTaken at dawn\n").unwrap();
+        std::fs::write(dir.join("song.ogg"), b"OggS").unwrap();
+        std::fs::write(dir.join("notes.md"), "not media").unwrap();
+
+        let mut response = Gallery::new(dir).set_title("Photos").serve::<&str>(&[]).await.unwrap();
+        let body = match response.take_body() {
+            Some(crate::types::Body::Bytes(bytes)) => String::from_utf8(bytes).unwrap(),
+            _ => panic!("expected a document"),
+        };
+
+        assert_eq!(body, "\
+            # Photos\n\
+            \n\
+            => ./older/ 📁 older/\n\
+            \n\
+            => ./sea%20side.jpg 🖼 The sea\n\
+            Taken at dawn\n\
+            \n\
+            => ./song.ogg 🎵 song.ogg\n\
+        ");
+    }
+}
@@ -0,0 +1,405 @@
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Result, Context};
+use tokio::fs;
+
+use crate::types::{Document, Request, Response, document::HeadingLevel::*};
+use crate::{HandlerResponse, GEMINI_MIME};
+use super::serve_dir::{decoded_trailing_segments, serve_file};
+
+/// The MIME of the Atom feed
+const ATOM_MIME: &str = "application/atom+xml";
+
+/// The file name under which the Atom feed is served
+const FEED_NAME: &str = "atom.xml";
+
+/// The path segment under which the yearly archives are served
+const ARCHIVE_NAME: &str = "archive";
+
+/// A gemlog generated from a directory of dated posts
+///
+/// Posts are gemtext files named after the date they were published and a short slug,
+/// like `2020-12-24-merry-christmas.gmi`.  Their title is taken from the first level 1
+/// heading in the file, or from the slug if there is none.  Any other files in the
+/// directory are ignored.
+///
+/// Mounted on a route, the gemlog serves:
+///
+/// * The index, listing all posts newest first in the [gemlog subscription format],
+///   so the gemlog can be followed by feed readers without any extra work
+/// * `archive/`, listing the years posts were published in, and `archive/<year>`,
+///   listing the posts of a single year
+/// * `atom.xml`, an Atom feed of all posts
+/// * The posts themselves
+///
+/// The posts directory is scanned on every request, so new posts show up immediately.
+///
+/// ```no_run
+/// # use twinstar::{Server, GEMINI_PORT, util::Gemlog};
+/// # async fn run() -> anyhow::Result<()> {
+/// Server::bind(("localhost", GEMINI_PORT))
+///     .add_route("/gemlog", Gemlog::new("posts").set_title("My gemlog").into_handler())
+///     .serve()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [gemlog subscription format]: https://gemini.circumlunar.space/docs/companion/subscription.gmi
+#[derive(Debug, Clone)]
+pub struct Gemlog {
+    posts_dir: PathBuf,
+    title: String,
+    subtitle: Option<String>,
+}
+
+impl Gemlog {
+    /// Generate a gemlog from the posts found in `posts_dir`
+    pub fn new(posts_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            posts_dir: posts_dir.into(),
+            title: "Gemlog".to_owned(),
+            subtitle: None,
+        }
+    }
+
+    /// Set the title of the gemlog, used for the index heading and the feed
+    ///
+    /// The default is `Gemlog`.
+    pub fn set_title(mut self, title: impl Into<String>) -> Self {
+        self.title = title.into();
+        self
+    }
+
+    /// Set a subtitle, displayed below the title on the index and in the feed
+    pub fn set_subtitle(mut self, subtitle: impl Into<String>) -> Self {
+        self.subtitle = Some(subtitle.into());
+        self
+    }
+
+    /// Turn this into a handler which can be passed to
+    /// [`Builder::add_route()`](crate::Builder::add_route())
+    pub fn into_handler(self) -> impl Fn(Request) -> HandlerResponse + Send + Sync {
+        let this = Arc::new(self);
+        move |request: Request| {
+            let this = this.clone();
+            Box::pin(async move {
+                this.serve(&request).await
+            }) as HandlerResponse
+        }
+    }
+
+    /// Serve the page of the gemlog the request's trailing segments refer to
+    pub async fn serve(&self, request: &Request) -> Result<Response> {
+        let path = decoded_trailing_segments(request);
+        let path: Vec<&str> = path.iter()
+            .map(String::as_str)
+            .filter(|segment| !segment.is_empty())
+            .collect();
+
+        match path.as_slice() {
+            [] => self.serve_index().await,
+            [ARCHIVE_NAME] => self.serve_archive().await,
+            [ARCHIVE_NAME, year] => self.serve_year(year).await,
+            [FEED_NAME] => self.serve_feed(&feed_base_uri(request)).await,
+            [file_name] => match PostName::parse(file_name) {
+                Some(_) => serve_file(self.posts_dir.join(file_name), &GEMINI_MIME).await,
+                None => Ok(Response::not_found()),
+            },
+            _ => Ok(Response::not_found()),
+        }
+    }
+
+    /// All posts, newest first
+    pub async fn posts(&self) -> Result<Vec<Post>> {
+        let mut posts = Vec::new();
+        let mut entries = fs::read_dir(&self.posts_dir).await
+            .with_context(|| format!("Failed to list `{}`", self.posts_dir.display()))?;
+
+        while let Some(entry) = entries.next_entry().await.context("Failed to list directory")? {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let name = match PostName::parse(&file_name) {
+                Some(name) => name,
+                None => continue,
+            };
+            let title = read_title(&entry.path()).await
+                .unwrap_or_else(|| name.slug.replace('-', " "));
+
+            posts.push(Post {
+                date: name.date.to_owned(),
+                title,
+                file_name,
+            });
+        }
+
+        posts.sort_by(|a, b| b.date.cmp(&a.date).then_with(|| b.file_name.cmp(&a.file_name)));
+
+        Ok(posts)
+    }
+
+    async fn serve_index(&self) -> Result<Response> {
+        let posts = self.posts().await?;
+        let mut document = Document::new();
+
+        document.add_heading(H1, self.title.as_str());
+        if let Some(subtitle) = &self.subtitle {
+            document.add_heading(H2, subtitle.as_str());
+        }
+        document.add_blank_line();
+
+        for post in &posts {
+            document.add_link(post.file_name.as_str(), format!("{} - {}", post.date, post.title));
+        }
+
+        if posts.is_empty() {
+            document.add_text("Nothing has been posted yet.");
+        }
+
+        document
+            .add_blank_line()
+            .add_link(format!("{}/", ARCHIVE_NAME).as_str(), "Archive")
+            .add_link(FEED_NAME, "Atom feed");
+
+        Ok(document.into())
+    }
+
+    async fn serve_archive(&self) -> Result<Response> {
+        let posts = self.posts().await?;
+        let mut years: Vec<&str> = posts.iter().map(Post::year).collect();
+        years.dedup();
+
+        let mut document = Document::new();
+        document
+            .add_heading(H1, format!("{} archive", self.title))
+            .add_blank_line();
+
+        for year in years {
+            let count = posts.iter().filter(|post| post.year() == year).count();
+            let plural = if count == 1 { "" } else { "s" };
+            document.add_link(format!("./{}", year).as_str(), format!("{} ({} post{})", year, count, plural));
+        }
+
+        document
+            .add_blank_line()
+            .add_link("..", "Back to the index");
+
+        Ok(document.into())
+    }
+
+    async fn serve_year(&self, year: &str) -> Result<Response> {
+        let posts = self.posts().await?;
+        let posts: Vec<&Post> = posts.iter().filter(|post| post.year() == year).collect();
+
+        if posts.is_empty() {
+            return Ok(Response::not_found());
+        }
+
+        let mut document = Document::new();
+        document
+            .add_heading(H1, format!("{} in {}", self.title, year))
+            .add_blank_line();
+
+        for post in posts {
+            document.add_link(format!("../{}", post.file_name).as_str(), format!("{} - {}", post.date, post.title));
+        }
+
+        document
+            .add_blank_line()
+            .add_link("./", "Back to the archive");
+
+        Ok(document.into())
+    }
+
+    async fn serve_feed(&self, base_uri: &str) -> Result<Response> {
+        let posts = self.posts().await?;
+        let mime = ATOM_MIME.parse().expect("twinstar BUG");
+
+        Ok(Response::success(&mime, self.render_feed(&posts, base_uri)))
+    }
+
+    /// Render an Atom feed for `posts`, with links relative to `base_uri`
+    fn render_feed(&self, posts: &[Post], base_uri: &str) -> String {
+        let updated = posts.first()
+            .map(|post| post.date.as_str())
+            .unwrap_or("1970-01-01");
+        let mut feed = String::new();
+
+        // Writing to a String can't fail
+        let _ = write!(feed, concat!(
+                "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n",
+                "<feed xmlns=\"http://www.w3.org/2005/Atom\">\n",
+                "  <id>{base}</id>\n",
+                "  <title>{title}</title>\n",
+            ),
+            base = xml_escape(base_uri),
+            title = xml_escape(&self.title),
+        );
+
+        if let Some(subtitle) = &self.subtitle {
+            let _ = writeln!(feed, "  <subtitle>{}</subtitle>", xml_escape(subtitle));
+        }
+
+        let _ = write!(feed, concat!(
+                "  <updated>{updated}T00:00:00Z</updated>\n",
+                "  <link href=\"{base}\" rel=\"alternate\"/>\n",
+                "  <link href=\"{base}{feed}\" rel=\"self\"/>\n",
+            ),
+            updated = updated,
+            base = xml_escape(base_uri),
+            feed = FEED_NAME,
+        );
+
+        for post in posts {
+            let uri = xml_escape(&format!("{}{}", base_uri, post.file_name));
+            let _ = write!(feed, concat!(
+                    "  <entry>\n",
+                    "    <id>{uri}</id>\n",
+                    "    <title>{title}</title>\n",
+                    "    <updated>{date}T00:00:00Z</updated>\n",
+                    "    <link href=\"{uri}\" rel=\"alternate\"/>\n",
+                    "  </entry>\n",
+                ),
+                uri = uri,
+                title = xml_escape(&post.title),
+                date = post.date,
+            );
+        }
+
+        feed.push_str("</feed>\n");
+        feed
+    }
+}
+
+/// A single post of a [`Gemlog`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Post {
+    /// The date the post was published, formatted as `YYYY-MM-DD`
+    pub date: String,
+    /// The title of the post
+    pub title: String,
+    /// The file name of the post within the posts directory
+    pub file_name: String,
+}
+
+impl Post {
+    fn year(&self) -> &str {
+        &self.date[..4]
+    }
+}
+
+/// The components of a post's file name
+struct PostName<'a> {
+    date: &'a str,
+    slug: &'a str,
+}
+
+impl<'a> PostName<'a> {
+    /// Parse a file name like `2020-12-24-merry-christmas.gmi`
+    fn parse(file_name: &'a str) -> Option<Self> {
+        let stem = file_name.strip_suffix(".gmi")
+            .or_else(|| file_name.strip_suffix(".gemini"))?;
+        let date = stem.get(..10)?;
+        let slug = stem.get(10..)?.strip_prefix('-')?;
+
+        let is_date = date.char_indices().all(|(i, ch)| match i {
+            4 | 7 => ch == '-',
+            _ => ch.is_ascii_digit(),
+        });
+
+        if !is_date || slug.is_empty() {
+            return None;
+        }
+
+        Some(Self { date, slug })
+    }
+}
+
+/// Read the first level 1 heading of a gemtext file
+async fn read_title(path: &Path) -> Option<String> {
+    let text = fs::read_to_string(path).await.ok()?;
+    let mut preformatted = false;
+
+    for line in text.lines() {
+        if line.starts_with("```") {
+            preformatted = !preformatted;
+        } else if !preformatted && line.starts_with("# ") {
+            let title = line[2..].trim();
+            if !title.is_empty() {
+                return Some(title.to_owned());
+            }
+        }
+    }
+
+    None
+}
+
+/// The absolute URI of the gemlog's index, derived from a request for its feed
+fn feed_base_uri(request: &Request) -> String {
+    let uri = request.uri().to_string();
+    let uri = uri.split('?').next().unwrap_or_default();
+    let base = uri.strip_suffix(FEED_NAME).unwrap_or(uri);
+
+    if base.ends_with('/') {
+        base.to_owned()
+    } else {
+        format!("{}/", base)
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(ch),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_post_names() {
+        let name = PostName::parse("2020-12-24-merry-christmas.gmi").unwrap();
+        assert_eq!(name.date, "2020-12-24");
+        assert_eq!(name.slug, "merry-christmas");
+
+        assert!(PostName::parse("2020-12-24.gmi").is_none());
+        assert!(PostName::parse("2020-12-24-.gmi").is_none());
+        assert!(PostName::parse("2020-1-24-short-month.gmi").is_none());
+        assert!(PostName::parse("2020-12-24-not-gemtext.txt").is_none());
+        assert!(PostName::parse("index.gmi").is_none());
+    }
+
+    #[tokio::test]
+    async fn renders_feed_newest_first() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::write(dir.join("2020-01-02-first-post.gmi"), "# Hello & welcome\nText").unwrap();
+        std::fs::write(dir.join("2021-03-04-untitled.gmi"), "```\n# not a title\n```\n").unwrap();
+        std::fs::write(dir.join("index.gmi"), "# Not a post").unwrap();
+
+        let gemlog = Gemlog::new(dir).set_title("Test log");
+        let posts = gemlog.posts().await.unwrap();
+
+        assert_eq!(posts.len(), 2);
+        assert_eq!(posts[0].title, "untitled");
+        assert_eq!(posts[1].title, "Hello & welcome");
+
+        let feed = gemlog.render_feed(&posts, "gemini://example.org/log/");
+        assert!(feed.contains("<updated>2021-03-04T00:00:00Z</updated>\n  <link"));
+        assert!(feed.contains("<id>gemini://example.org/log/2020-01-02-first-post.gmi</id>"));
+        assert!(feed.contains("<title>Hello &amp; welcome</title>"));
+    }
+}
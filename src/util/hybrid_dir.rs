@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use crate::types::{Request, Response, Status};
+use crate::{Handler, HandlerResponse};
+use super::serve_dir::{ServeDir, decoded_trailing_segments};
+
+/// A directory of static files with a few generated pages mixed in
+///
+/// Most capsules are static files with some pages that need to be generated, like an
+/// index of recent posts or a page showing the current weather.  A `HybridDir` serves
+/// these pages from handlers, and everything else from a directory like [`ServeDir`]
+/// would, so both can live under the same route:
+///
+/// ```no_run
+/// # use twinstar::{Server, Request, Response, GEMINI_PORT, util::HybridDir};
+/// # async fn run() -> anyhow::Result<()> {
+/// let capsule = HybridDir::new("public")
+///     .add_page("/weather.gmi", |_: Request| Box::pin(async {
+///         Ok(Response::success_gemini("# Weather\nSunny, 21°C\n"))
+///     }) as _);
+///
+/// Server::bind(("localhost", GEMINI_PORT))
+///     .add_route("/", capsule.into_handler())
+///     .serve()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Pages are consulted first, and override any file at the same path.  A page answering
+/// `51 NOT FOUND` falls back to the file, so a page can also generate only some of the
+/// responses for its path.  Since the page consumes the request, the file is served for
+/// a copy of it without any [extensions](crate::types::Extensions).
+#[derive(Clone)]
+pub struct HybridDir {
+    files: ServeDir,
+    /// The generated pages, by their path relative to the route, without slashes at
+    /// either end
+    pages: HashMap<String, Handler>,
+}
+
+impl HybridDir {
+    /// Serve the files found in `root`
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self::with_files(ServeDir::new(root))
+    }
+
+    /// Serve the files served by `files`
+    ///
+    /// This allows configuring how the static files are served, for example to sniff
+    /// their MIME types using [`ServeDir::set_mime_sniffing()`].
+    pub fn with_files(files: ServeDir) -> Self {
+        Self {
+            files,
+            pages: HashMap::new(),
+        }
+    }
+
+    /// Generate the page at `path` using `handler`
+    ///
+    /// The path is relative to the route the `HybridDir` is added at, and isn't percent
+    /// encoded, e.g. `/notes/café.gmi`.  Unlike routes, pages only match their exact
+    /// path, so a page at `/` generates the index, without hiding any file.  Adding a
+    /// second page at the same path replaces the first one.
+    pub fn add_page<H>(mut self, path: &str, handler: H) -> Self
+    where
+        H: Fn(Request) -> HandlerResponse + Send + Sync + 'static,
+    {
+        self.pages.insert(page_key(path.split('/')), Arc::new(handler));
+        self
+    }
+
+    /// Turn this into a handler which can be passed to
+    /// [`Builder::add_route()`](crate::Builder::add_route())
+    ///
+    /// The handler serves the path trailing the route it was mounted on.
+    pub fn into_handler(self) -> impl Fn(Request) -> HandlerResponse + Send + Sync {
+        let this = Arc::new(self);
+        move |request: Request| {
+            let this = this.clone();
+            Box::pin(async move {
+                this.serve_request(request).await
+            }) as HandlerResponse
+        }
+    }
+
+    /// Serve the page or file at the path trailing the route of a request
+    pub async fn serve_request(&self, request: Request) -> Result<Response> {
+        let segments = decoded_trailing_segments(&request);
+        let page = match self.pages.get(&page_key(segments.iter().map(String::as_str))) {
+            Some(page) => page,
+            None => return self.files.serve_request(&request).await,
+        };
+
+        let fallback = request.clone_without_extensions();
+        let response = page(request).await?;
+
+        if response.header().status != Status::NOT_FOUND {
+            return Ok(response);
+        }
+
+        self.files.serve_request(&fallback).await
+    }
+}
+
+/// The key of a page in [`HybridDir::pages`], ignoring empty segments
+fn page_key<'a>(segments: impl Iterator<Item = &'a str>) -> String {
+    segments
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use super::*;
+    use crate::uri::URIReference;
+
+    fn request(path: &str) -> Request {
+        let uri = URIReference::try_from(format!("gemini://localhost{}", path).as_str()).unwrap().into_owned();
+        let mut request = Request::from_uri(uri).unwrap();
+        request.set_trailing(request.uri().path().segments().iter().map(|segment| segment.to_string()).collect());
+        request
+    }
+
+    #[tokio::test]
+    async fn serves_pages_over_files() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::write(dir.join("static.gmi"), "static").unwrap();
+        std::fs::write(dir.join("été.gmi"), "static").unwrap();
+        std::fs::write(dir.join("maybe.gmi"), "static").unwrap();
+
+        let handler = HybridDir::new(dir)
+            .add_page("/", |_| Box::pin(async { Ok(Response::success_gemini("index")) }) as HandlerResponse)
+            .add_page("été.gmi", |_| Box::pin(async { Ok(Response::success_gemini("generated")) }) as HandlerResponse)
+            .add_page("/maybe.gmi", |_| Box::pin(async { Ok(Response::not_found()) }) as HandlerResponse)
+            .into_handler();
+        let body = |mut response: Response| response.take_body().and_then(|body| body.as_bytes().map(<[u8]>::to_vec));
+
+        let index = handler(request("/")).await.unwrap();
+        let generated = handler(request("/%C3%A9t%C3%A9.gmi")).await.unwrap();
+        let fallback = handler(request("/maybe.gmi")).await.unwrap();
+        let file = handler(request("/static.gmi")).await.unwrap();
+        let missing = handler(request("/missing.gmi")).await.unwrap();
+
+        assert_eq!(body(index), Some(b"index".to_vec()));
+        assert_eq!(body(generated), Some(b"generated".to_vec()));
+        assert_eq!(fallback.header().status, Status::SUCCESS);
+        assert_eq!(file.header().status, Status::SUCCESS);
+        assert_eq!(missing.header().status, Status::NOT_FOUND);
+    }
+}
@@ -0,0 +1,117 @@
+use crate::BoxedHandler;
+use crate::types::{Request, Response};
+use crate::HandlerResponse;
+
+/// A prompt asking the user for input before a handler runs
+///
+/// Gemini clients submit input by requesting the same URI again, with the input as the
+/// query.  [`require()`](Self::require()) wraps a handler so it only sees requests
+/// carrying input, and answers all other requests with the prompt.
+///
+/// Prompts for passwords and other secrets should be marked as
+/// [sensitive](Self::sensitive()), so they are sent with `11 SENSITIVE INPUT`, asking
+/// clients not to echo what is typed.  The server then also keeps the answers out of its
+/// access log.
+///
+/// ```no_run
+/// # use twinstar::{Server, Request, Response, GEMINI_PORT, util::Prompt};
+/// # async fn run() -> anyhow::Result<()> {
+/// Server::bind(("localhost", GEMINI_PORT))
+///     .add_route("/unlock", Prompt::sensitive("Passphrase").require(|request: Request| {
+///         let unlocked = request.input() == Some("open sesame");
+///         Box::pin(async move {
+///             Ok(Response::success_plain(if unlocked { "Welcome!" } else { "Wrong passphrase" }))
+///         }) as _
+///     }))
+///     .serve()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Prompt {
+    prompt: String,
+    sensitive: bool,
+}
+
+impl Prompt {
+    /// A prompt for regular input, sent with `10 INPUT`
+    pub fn new(prompt: impl Into<String>) -> Self {
+        Self {
+            prompt: prompt.into(),
+            sensitive: false,
+        }
+    }
+
+    /// A prompt for sensitive input, sent with `11 SENSITIVE INPUT`
+    pub fn sensitive(prompt: impl Into<String>) -> Self {
+        Self::new(prompt).set_sensitive(true)
+    }
+
+    /// Set whether the input is sensitive, like a password
+    pub fn set_sensitive(mut self, sensitive: bool) -> Self {
+        self.sensitive = sensitive;
+        self
+    }
+
+    /// Whether the input is sensitive
+    pub const fn is_sensitive(&self) -> bool {
+        self.sensitive
+    }
+
+    /// The response asking for input
+    pub fn response(&self) -> Response {
+        if self.sensitive {
+            Response::sensitive_input_lossy(self.prompt.as_str())
+        } else {
+            Response::input_lossy(self.prompt.as_str())
+        }
+    }
+
+    /// Only pass requests on to `handler` which carry input, prompting for it otherwise
+    ///
+    /// An empty query counts as no input.
+    pub fn require<H>(&self, handler: H) -> BoxedHandler
+    where
+        H: Fn(Request) -> HandlerResponse + Send + Sync + 'static,
+    {
+        let prompt = self.clone();
+
+        Box::new(move |request| {
+            if request.input().is_some_and(|input| !input.is_empty()) {
+                return handler(request);
+            }
+
+            let response = prompt.response();
+            Box::pin(async { Ok(response) })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use super::*;
+    use crate::types::Status;
+    use crate::uri::URIReference;
+
+    fn request(uri: &str) -> Request {
+        Request::from_uri(URIReference::try_from(uri).unwrap().into_owned()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn prompts_for_input() {
+        let echo = |request: Request| {
+            let input = request.input().unwrap_or_default().to_owned();
+            Box::pin(async move { Ok(Response::success_plain(input)) }) as HandlerResponse
+        };
+        let regular = Prompt::new("Name").require(echo);
+        let sensitive = Prompt::sensitive("Password").require(echo);
+
+        assert_eq!(regular(request("gemini://localhost/")).await.unwrap().header().status, Status::INPUT);
+        assert_eq!(regular(request("gemini://localhost/?")).await.unwrap().header().status, Status::INPUT);
+        assert_eq!(sensitive(request("gemini://localhost/")).await.unwrap().header().status, Status::SENSITIVE_INPUT);
+        assert_eq!(sensitive(request("gemini://localhost/?hunter2")).await.unwrap().header().status, Status::SUCCESS);
+        assert_eq!(Prompt::sensitive("Password").response().header().meta.as_str(), "Password");
+    }
+}
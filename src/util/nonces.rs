@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::BoxedHandler;
+use crate::types::{Request, Response};
+use crate::HandlerResponse;
+use super::hex;
+
+/// How long nonces stay valid by default
+pub const DEFAULT_NONCE_TTL: Duration = Duration::from_secs(15 * 60);
+
+/// How many unused nonces are kept by default
+pub const DEFAULT_MAX_NONCES: usize = 10_000;
+
+/// Single-use tokens protecting actions which must not run twice
+///
+/// Gemini has no methods, so a link deleting a post looks just like any other link.
+/// Clients prefetching links, or users hitting reload, can easily trigger such an
+/// action twice, or by accident.  Handlers for these actions can be wrapped with
+/// [`require()`](Self::require()), which only lets requests through whose query is a
+/// nonce issued by [`link()`](Self::link()) and not used before.  Any other request is
+/// answered with `59 BAD REQUEST`.
+///
+/// Nonces expire after a while, and only a limited number of unused nonces are kept,
+/// dropping the ones closest to expiring first.  Nonces are kept in memory, so they
+/// don't survive restarts.  Clones share the same nonces, so pages can keep a clone
+/// for issuing them.
+///
+/// ```no_run
+/// # use twinstar::{Server, Request, Response, GEMINI_PORT, util::Nonces};
+/// # async fn run() -> anyhow::Result<()> {
+/// let nonces = Nonces::new();
+/// let page_nonces = nonces.clone();
+///
+/// Server::bind(("localhost", GEMINI_PORT))
+///     .add_route("/post", move |_: Request| {
+///         let delete = page_nonces.link("/post/delete");
+///         Box::pin(async move {
+///             Ok(Response::success_gemini(format!("=> {} Delete this post\n", delete)))
+///         }) as _
+///     })
+///     .add_route("/post/delete", nonces.require(|_: Request| {
+///         Box::pin(async { Ok(Response::success_plain("Deleted")) }) as _
+///     }))
+///     .serve()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Nonces {
+    /// The unused nonces, and when they expire
+    issued: Arc<Mutex<HashMap<String, Instant>>>,
+    rng: Arc<SystemRandom>,
+    ttl: Duration,
+    max_nonces: usize,
+}
+
+impl Nonces {
+    /// Create an empty nonce store
+    pub fn new() -> Self {
+        Self {
+            issued: Arc::default(),
+            rng: Arc::new(SystemRandom::new()),
+            ttl: DEFAULT_NONCE_TTL,
+            max_nonces: DEFAULT_MAX_NONCES,
+        }
+    }
+
+    /// Set how long issued nonces stay valid
+    ///
+    /// The default is [`DEFAULT_NONCE_TTL`].
+    pub fn set_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Set how many unused nonces are kept at most
+    ///
+    /// The default is [`DEFAULT_MAX_NONCES`].
+    pub fn set_max_nonces(mut self, max_nonces: usize) -> Self {
+        self.max_nonces = max_nonces.max(1);
+        self
+    }
+
+    /// Issue a new nonce
+    pub fn issue(&self) -> String {
+        let mut bytes = [0; 16];
+        self.rng.fill(&mut bytes).expect("Failed to generate nonce");
+        let nonce = hex(&bytes);
+
+        let now = Instant::now();
+        let mut issued = self.issued.lock().expect("twinstar BUG");
+        issued.retain(|_, expires| *expires > now);
+
+        while issued.len() >= self.max_nonces {
+            let oldest = issued.iter()
+                .min_by_key(|(_, expires)| **expires)
+                .map(|(nonce, _)| nonce.clone())
+                .expect("twinstar BUG");
+            issued.remove(&oldest);
+        }
+
+        issued.insert(nonce.clone(), now + self.ttl);
+        nonce
+    }
+
+    /// Issue a new nonce, and append it to `path` as the query
+    ///
+    /// The path must be given as it appears in the URI, and must not contain a query.
+    pub fn link(&self, path: &str) -> String {
+        format!("{}?{}", path, self.issue())
+    }
+
+    /// Use up `nonce`, returning whether it was issued, unused and unexpired
+    pub fn consume(&self, nonce: &str) -> bool {
+        let mut issued = self.issued.lock().expect("twinstar BUG");
+
+        match issued.remove(nonce) {
+            Some(expires) => expires > Instant::now(),
+            None => false,
+        }
+    }
+
+    /// Only pass requests on to `handler` whose query is a nonce which can be consumed
+    pub fn require<H>(&self, handler: H) -> BoxedHandler
+    where
+        H: Fn(Request) -> HandlerResponse + Send + Sync + 'static,
+    {
+        let nonces = self.clone();
+
+        Box::new(move |request| {
+            let valid = request.uri().query()
+                .map(|query| nonces.consume(query.as_str()))
+                .unwrap_or(false);
+
+            if !valid {
+                return Box::pin(async {
+                    Ok(Response::bad_request_lossy("This link was already used or has expired, please go back and reload the page"))
+                });
+            }
+
+            handler(request)
+        })
+    }
+}
+
+impl Default for Nonces {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use super::*;
+    use crate::types::Status;
+    use crate::uri::URIReference;
+
+    fn request(uri: &str) -> Request {
+        Request::from_uri(URIReference::try_from(uri).unwrap().into_owned()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn accepts_nonces_once() {
+        let nonces = Nonces::new().set_max_nonces(2);
+        let handler = nonces.require(|_: Request| Box::pin(async { Ok(Response::success_plain("done")) }) as HandlerResponse);
+
+        let link = nonces.link("/delete");
+        let uri = format!("gemini://localhost{}", link);
+        assert_eq!(handler(request(&uri)).await.unwrap().header().status, Status::SUCCESS);
+        assert_eq!(handler(request(&uri)).await.unwrap().header().status, Status::BAD_REQUEST);
+        assert_eq!(handler(request("gemini://localhost/delete")).await.unwrap().header().status, Status::BAD_REQUEST);
+
+        let first = nonces.issue();
+        let second = nonces.issue();
+        let third = nonces.issue();
+        assert!(!nonces.consume(&first));
+        assert!(nonces.consume(&second));
+        assert!(nonces.consume(&third));
+
+        let expired = Nonces::new().set_ttl(Duration::from_secs(0));
+        assert!(!expired.consume(&expired.issue()));
+    }
+}
@@ -0,0 +1,101 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use anyhow::Result;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use uriparse::path::Segment;
+
+use crate::types::Request;
+
+/// Characters which need to be escaped in a path segment
+const PATH_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ').add(b'"').add(b'#').add(b'%').add(b'<').add(b'>')
+    .add(b'?').add(b'`').add(b'{').add(b'}').add(b'/');
+
+/// Normalizes a percent decoded path segment, see
+/// [`Builder::set_path_normalizer()`](crate::Builder::set_path_normalizer())
+pub type PathNormalizer = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
+/// Normalize a string to Unicode Normalization Form C
+///
+/// Text typed on most systems is composed, so `é` is a single character, while macOS
+/// stores file names decomposed, as `e` followed by a combining accent.  Both look the
+/// same, but don't compare equal unless normalized.
+///
+/// This requires the `unicode_normalization` feature.
+///
+/// ```
+/// # use twinstar::util::nfc;
+/// assert_eq!(nfc("cafe\u{301}"), "caf\u{e9}");
+/// ```
+#[cfg(feature="unicode_normalization")]
+pub fn nfc(segment: &str) -> String {
+    use unicode_normalization::UnicodeNormalization;
+
+    segment.nfc().collect()
+}
+
+/// Normalize the percent decoded segments of the path of `request` with `normalizer`
+///
+/// Segments which the normalizer leaves as they are keep their original encoding, so a
+/// request is only changed if normalization changes any segment.
+pub(crate) fn normalize_path(request: &mut Request, normalizer: &(dyn Fn(&str) -> String + Send + Sync)) -> Result<()> {
+    let mut changed = false;
+
+    let segments = request.uri().path().segments().iter()
+        .map(Segment::as_str)
+        .map(|segment| {
+            let decoded = match percent_decode_str(segment).decode_utf8() {
+                Ok(decoded) => decoded,
+                Err(_) => return Cow::Borrowed(segment),
+            };
+
+            let normalized = normalizer(&decoded);
+            if normalized == decoded {
+                return Cow::Borrowed(segment);
+            }
+
+            changed = true;
+            Cow::Owned(utf8_percent_encode(&normalized, PATH_SEGMENT).to_string())
+        })
+        .collect::<Vec<_>>();
+
+    if !changed {
+        return Ok(());
+    }
+
+    let path = format!("/{}", segments.join("/"));
+    request.set_path(&path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use super::*;
+    use crate::types::URIReference;
+
+    fn request(uri: &str) -> Request {
+        Request::from_uri(URIReference::try_from(uri).unwrap().into_owned()).unwrap()
+    }
+
+    #[test]
+    fn normalizes_decoded_segments() {
+        let lowercase = |segment: &str| segment.to_lowercase();
+
+        let mut unchanged = request("gemini://localhost/a%20b/c?Query");
+        normalize_path(&mut unchanged, &lowercase).unwrap();
+        assert_eq!(unchanged.uri().to_string(), "gemini://localhost/a%20b/c?Query");
+
+        let mut changed = request("gemini://localhost/A%20B/%C3%89t%C3%A9/c?Query");
+        normalize_path(&mut changed, &lowercase).unwrap();
+        assert_eq!(changed.uri().to_string(), "gemini://localhost/a%20b/%C3%A9t%C3%A9/c?Query");
+    }
+
+    #[cfg(feature="unicode_normalization")]
+    #[test]
+    fn composes_segments() {
+        let mut decomposed = request("gemini://localhost/cafe%CC%81.gmi");
+        normalize_path(&mut decomposed, &nfc).unwrap();
+        assert_eq!(decomposed.path_segments(), ["caf\u{e9}.gmi"]);
+    }
+}
@@ -0,0 +1,106 @@
+use std::time::SystemTime;
+
+use crate::BoxedHandler;
+use crate::types::{Request, Response};
+use crate::HandlerResponse;
+
+/// A window of time in which content is available
+///
+/// [`require()`](Self::require()) wraps a handler, e.g. for an embargoed post or an
+/// event page, so it is only reachable within the window.  Before the window opens,
+/// requests are answered with `51 NOT FOUND`, as if the content didn't exist yet, and
+/// after it closes, with `52 GONE`.  Both ends of the window are optional.
+///
+/// ```no_run
+/// # #[cfg(feature="serve_dir")] {
+/// # use twinstar::{Server, GEMINI_PORT, util::{Schedule, ServeDir}};
+/// # use std::time::{Duration, UNIX_EPOCH};
+/// # async fn run() -> anyhow::Result<()> {
+/// let schedule = Schedule::new()
+///     .publish_at(UNIX_EPOCH + Duration::from_secs(1_767_225_600))
+///     .unpublish_at(UNIX_EPOCH + Duration::from_secs(1_769_904_000));
+///
+/// Server::bind(("localhost", GEMINI_PORT))
+///     .add_route("/event", schedule.require(ServeDir::new("event").into_handler()))
+///     .serve()
+///     .await?;
+/// # Ok(())
+/// # }
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Schedule {
+    publish_at: Option<SystemTime>,
+    unpublish_at: Option<SystemTime>,
+}
+
+impl Schedule {
+    /// A schedule under which content is always available
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make content available from `time` on
+    pub fn publish_at(mut self, time: SystemTime) -> Self {
+        self.publish_at = Some(time);
+        self
+    }
+
+    /// Make content unavailable from `time` on
+    pub fn unpublish_at(mut self, time: SystemTime) -> Self {
+        self.unpublish_at = Some(time);
+        self
+    }
+
+    /// Whether content is available at `time`
+    pub fn is_published_at(&self, time: SystemTime) -> bool {
+        self.response_at(time).is_none()
+    }
+
+    /// The response for requests at `time`, if content isn't available then
+    pub fn response_at(&self, time: SystemTime) -> Option<Response> {
+        if self.unpublish_at.is_some_and(|unpublish_at| time >= unpublish_at) {
+            Some(Response::gone())
+        } else if self.publish_at.is_some_and(|publish_at| time < publish_at) {
+            Some(Response::not_found())
+        } else {
+            None
+        }
+    }
+
+    /// Only pass requests on to `handler` while content is available
+    pub fn require<H>(&self, handler: H) -> BoxedHandler
+    where
+        H: Fn(Request) -> HandlerResponse + Send + Sync + 'static,
+    {
+        let schedule = *self;
+
+        Box::new(move |request| {
+            match schedule.response_at(SystemTime::now()) {
+                Some(response) => Box::pin(async { Ok(response) }),
+                None => handler(request),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::*;
+    use crate::types::Status;
+
+    #[test]
+    fn answers_outside_the_window() {
+        let now = SystemTime::now();
+        let hour = Duration::from_secs(60 * 60);
+        let status = |schedule: Schedule, time| schedule.response_at(time).map(|response| response.header().status);
+
+        let schedule = Schedule::new().publish_at(now).unpublish_at(now + hour);
+        assert_eq!(status(schedule, now - hour), Some(Status::NOT_FOUND));
+        assert_eq!(status(schedule, now), None);
+        assert_eq!(status(schedule, now + hour), Some(Status::GONE));
+        assert!(Schedule::new().is_published_at(now));
+        assert!(!Schedule::new().unpublish_at(now - hour).is_published_at(now));
+    }
+}
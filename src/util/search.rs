@@ -0,0 +1,417 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Result, Context};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use tokio::fs;
+
+use crate::types::{Document, Request, Response, document::HeadingLevel::*};
+use crate::HandlerResponse;
+
+/// Characters which need to be escaped in the query of a link
+const QUERY: &AsciiSet = &CONTROLS
+    .add(b' ').add(b'"').add(b'#').add(b'%').add(b'<').add(b'>').add(b'&').add(b'+');
+
+/// Tokens shorter than this are not indexed
+const MIN_TOKEN_LEN: usize = 2;
+
+/// An inverted index over a collection of gemtext documents
+///
+/// Documents are split into lowercase alphanumeric tokens, and a search returns every
+/// document containing all of the tokens in the query, ordered by how often they occur.
+///
+/// ```
+/// # use twinstar::util::SearchIndex;
+/// let mut index = SearchIndex::new();
+/// index.add_document("/cats.gmi", "# Cats\nCats are great pets.");
+/// index.add_document("/dogs.gmi", "# Dogs\nDogs are great pets, too.");
+///
+/// let hits = index.search("great cats");
+/// assert_eq!(hits.len(), 1);
+/// assert_eq!(hits[0].uri, "/cats.gmi");
+/// assert_eq!(hits[0].title, "Cats");
+/// ```
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    documents: Vec<IndexedDocument>,
+    postings: HashMap<String, Vec<(usize, usize)>>,
+}
+
+#[derive(Debug)]
+struct IndexedDocument {
+    uri: String,
+    title: String,
+    lines: Vec<String>,
+}
+
+/// A document matching a search
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchHit {
+    /// The URI the document was indexed under
+    pub uri: String,
+    /// The first heading of the document, or its URI if it has none
+    pub title: String,
+    /// The first line of the document containing one of the searched tokens
+    pub snippet: String,
+    /// How many times the searched tokens occur in the document
+    pub score: usize,
+}
+
+impl SearchIndex {
+    /// Create an empty index
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Index all gemtext files in `dir` and its subdirectories
+    ///
+    /// Each file is indexed under `uri_prefix` followed by its path relative to `dir`,
+    /// so with a prefix of `/docs/`, the file `dir/guide/intro.gmi` is indexed as
+    /// `/docs/guide/intro.gmi`.
+    pub async fn index_dir(&mut self, dir: impl AsRef<Path>, uri_prefix: &str) -> Result<&mut Self> {
+        let dir = dir.as_ref();
+        let mut unexplored: Vec<PathBuf> = vec![dir.to_path_buf()];
+
+        while let Some(current) = unexplored.pop() {
+            let mut entries = fs::read_dir(&current).await
+                .with_context(|| format!("Failed to list `{}`", current.display()))?;
+
+            while let Some(entry) = entries.next_entry().await.context("Failed to list directory")? {
+                let path = entry.path();
+                let file_type = entry.file_type().await
+                    .with_context(|| format!("Failed to get file type of `{}`", path.display()))?;
+
+                if file_type.is_dir() {
+                    unexplored.push(path);
+                    continue;
+                }
+
+                let is_gemtext = matches!(
+                    path.extension().and_then(|ext| ext.to_str()),
+                    Some("gmi") | Some("gemini")
+                );
+
+                if !is_gemtext {
+                    continue;
+                }
+
+                let text = fs::read_to_string(&path).await
+                    .with_context(|| format!("Failed to read `{}`", path.display()))?;
+                let relative = path.strip_prefix(dir).expect("twinstar BUG");
+                let relative: Vec<String> = relative.iter()
+                    .map(|segment| segment.to_string_lossy().into_owned())
+                    .collect();
+                let uri = format!("{}{}", uri_prefix, relative.join("/"));
+
+                self.add_document(uri, &text);
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// Add a single gemtext document to the index
+    pub fn add_document(&mut self, uri: impl Into<String>, gemtext: &str) -> &mut Self {
+        let uri = uri.into();
+        let id = self.documents.len();
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        let mut title = None;
+        let mut lines = Vec::new();
+
+        for line in gemtext.lines() {
+            let text = line_text(line);
+
+            if title.is_none() && line.starts_with('#') && !text.is_empty() {
+                title = Some(text.to_owned());
+            }
+
+            for token in tokenize(text) {
+                *counts.entry(token).or_default() += 1;
+            }
+
+            lines.push(text.to_owned());
+        }
+
+        for (token, count) in counts {
+            self.postings.entry(token).or_default().push((id, count));
+        }
+
+        self.documents.push(IndexedDocument {
+            title: title.unwrap_or_else(|| uri.clone()),
+            uri,
+            lines,
+        });
+
+        self
+    }
+
+    /// The number of documents in the index
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    /// Whether there are no documents in the index
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+
+    /// Find all documents containing every token of `query`, best matches first
+    pub fn search(&self, query: &str) -> Vec<SearchHit> {
+        let tokens: Vec<String> = tokenize(query).collect();
+
+        if tokens.is_empty() {
+            return Vec::new();
+        }
+
+        let mut scores: HashMap<usize, (usize, usize)> = HashMap::new();
+
+        for token in &tokens {
+            for &(id, count) in self.postings.get(token).into_iter().flatten() {
+                let (matched, score) = scores.entry(id).or_default();
+                *matched += 1;
+                *score += count;
+            }
+        }
+
+        let mut hits: Vec<SearchHit> = scores.into_iter()
+            .filter(|(_, (matched, _))| *matched == tokens.len())
+            .map(|(id, (_, score))| {
+                let document = &self.documents[id];
+                let snippet = document.lines.iter()
+                    .find(|line| tokenize(line).any(|token| tokens.contains(&token)))
+                    .cloned()
+                    .unwrap_or_default();
+
+                SearchHit {
+                    uri: document.uri.clone(),
+                    title: document.title.clone(),
+                    snippet,
+                    score,
+                }
+            })
+            .collect();
+
+        hits.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.uri.cmp(&b.uri)));
+        hits
+    }
+}
+
+/// A handler answering search queries against a [`SearchIndex`]
+///
+/// Requests without a query are answered with `10 INPUT`, prompting the user for one.
+/// Results are paginated, with later pages served on the route followed by the page
+/// number, e.g. `/search/2?query`.
+///
+/// ```no_run
+/// # use twinstar::{Server, GEMINI_PORT, util::{Search, SearchIndex}};
+/// # async fn run() -> anyhow::Result<()> {
+/// let mut index = SearchIndex::new();
+/// index.index_dir("public", "/").await?;
+///
+/// Server::bind(("localhost", GEMINI_PORT))
+///     .add_route("/search", Search::new(index).into_handler())
+///     .serve()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Search {
+    index: SearchIndex,
+    prompt: String,
+    page_size: usize,
+}
+
+impl Search {
+    /// Answer queries using `index`
+    pub fn new(index: SearchIndex) -> Self {
+        Self {
+            index,
+            prompt: "Search".to_owned(),
+            page_size: 10,
+        }
+    }
+
+    /// Set the prompt shown to users when asking for a query
+    ///
+    /// The default is `Search`.
+    pub fn set_prompt(mut self, prompt: impl Into<String>) -> Self {
+        self.prompt = prompt.into();
+        self
+    }
+
+    /// Set the number of results shown per page
+    ///
+    /// The default is 10.
+    pub fn set_page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size.max(1);
+        self
+    }
+
+    /// Turn this into a handler which can be passed to
+    /// [`Builder::add_route()`](crate::Builder::add_route())
+    pub fn into_handler(self) -> impl Fn(Request) -> HandlerResponse + Send + Sync {
+        let this = Arc::new(self);
+        move |request: Request| {
+            let response = this.serve(&request);
+            Box::pin(async move { Ok(response) }) as HandlerResponse
+        }
+    }
+
+    /// Answer a search request
+    pub fn serve(&self, request: &Request) -> Response {
+        let query = match request.input() {
+            Some(query) if !query.trim().is_empty() => query,
+            _ => return Response::input_lossy(self.prompt.as_str()),
+        };
+
+        let page = match request.trailing_segments().iter().find(|segment| !segment.is_empty()) {
+            None => 1,
+            Some(page) => match page.parse::<usize>() {
+                Ok(page) if page >= 1 => page,
+                _ => return Response::not_found(),
+            },
+        };
+
+        let hits = self.index.search(query);
+        let pages = hits.len().div_ceil(self.page_size);
+        let route = route_path(request);
+        let page_link = |page: usize| format!(
+            "{}/{}?{}",
+            route,
+            page,
+            utf8_percent_encode(query, QUERY),
+        );
+
+        let mut document = Document::new();
+        document
+            .add_heading(H1, format!("Results for \"{}\"", query))
+            .add_blank_line();
+
+        if hits.is_empty() {
+            document.add_text("Nothing was found.");
+        } else if page > pages {
+            return Response::not_found();
+        } else {
+            let plural = if hits.len() == 1 { "" } else { "s" };
+            document
+                .add_text(format!("{} result{}, page {} of {}", hits.len(), plural, page, pages))
+                .add_blank_line();
+
+            for hit in hits.iter().skip((page - 1) * self.page_size).take(self.page_size) {
+                document.add_link(hit.uri.as_str(), hit.title.as_str());
+                if !hit.snippet.is_empty() {
+                    document.add_quote(hit.snippet.as_str());
+                }
+                document.add_blank_line();
+            }
+
+            if page > 1 {
+                document.add_link(page_link(page - 1).as_str(), "Previous page");
+            }
+
+            if page < pages {
+                document.add_link(page_link(page + 1).as_str(), "Next page");
+            }
+        }
+
+        document
+            .add_blank_line()
+            .add_link(route.as_str(), "New search");
+
+        document.into()
+    }
+}
+
+/// The absolute path of the route a request was routed to, without a trailing slash
+fn route_path(request: &Request) -> String {
+    let segments: Vec<String> = request.uri().path().segments()
+        .iter()
+        .map(|segment| segment.as_str().to_owned())
+        .filter(|segment| !segment.is_empty())
+        .collect();
+    let trailing = request.trailing_segments().iter()
+        .filter(|segment| !segment.is_empty())
+        .count();
+    let route = &segments[..segments.len().saturating_sub(trailing)];
+
+    format!("/{}", route.join("/"))
+}
+
+/// The text of a gemtext line, without any line type markers
+fn line_text(line: &str) -> &str {
+    let line = line.trim_start_matches(&['#', '>', '*'][..]);
+
+    match line.strip_prefix("=>") {
+        Some(link) => {
+            let link = link.trim_start();
+            link.find(char::is_whitespace)
+                .map(|end| link[end..].trim())
+                .unwrap_or(link)
+        },
+        None => line.trim(),
+    }
+}
+
+fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|ch: char| !ch.is_alphanumeric())
+        .filter(|token| token.chars().count() >= MIN_TOKEN_LEN)
+        .map(str::to_lowercase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+    use crate::types::{Body, Status, URIReference};
+
+    fn request(uri: &str, trailing: &[&str]) -> Request {
+        let uri = URIReference::try_from(uri).unwrap().into_owned();
+        let mut request = Request::from_uri(uri).unwrap();
+        request.set_trailing(trailing.iter().map(|s| s.to_string()).collect());
+        request
+    }
+
+    fn index() -> SearchIndex {
+        let mut index = SearchIndex::new();
+        for i in 0..5 {
+            index.add_document(format!("/rust/{}.gmi", i), "# Rust\n=> /crab A crab named Ferris");
+        }
+        index.add_document("/go.gmi", "# Go\nA gopher");
+        index
+    }
+
+    #[test]
+    fn strips_line_markers() {
+        assert_eq!(line_text("## Heading"), "Heading");
+        assert_eq!(line_text("=> gemini://example.org Example site"), "Example site");
+        assert_eq!(line_text("=> gemini://example.org"), "gemini://example.org");
+        assert_eq!(line_text("> quoted"), "quoted");
+    }
+
+    #[test]
+    fn prompts_without_query() {
+        let search = Search::new(index());
+        let response = search.serve(&request("/search", &[]));
+
+        assert_eq!(response.header().status, Status::INPUT);
+    }
+
+    #[test]
+    fn paginates_results() {
+        let search = Search::new(index()).set_page_size(2);
+        let mut response = search.serve(&request("/find/2?ferris", &["2"]));
+        let body = match response.take_body() {
+            Some(Body::Bytes(bytes)) => String::from_utf8(bytes).unwrap(),
+            _ => panic!("expected a document"),
+        };
+
+        assert!(body.contains("5 results, page 2 of 3"));
+        assert!(body.contains("=> /rust/2.gmi Rust\n> A crab named Ferris\n"));
+        assert!(body.contains("=> /find/1?ferris Previous page"));
+        assert!(body.contains("=> /find/3?ferris Next page"));
+
+        let response = search.serve(&request("/find/4?ferris", &["4"]));
+        assert_eq!(response.header().status, Status::NOT_FOUND);
+    }
+}
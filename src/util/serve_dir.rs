@@ -0,0 +1,900 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use mime::Mime;
+use anyhow::{Result, Context};
+use percent_encoding::percent_decode_str;
+use tokio::{
+    fs::{self, File},
+    io::{self, AsyncReadExt, AsyncSeekExt},
+};
+use crate::types::{Body, Document, Request, Response, document::HeadingLevel::*};
+use crate::HandlerResponse;
+use super::{hex, sniff};
+
+/// A configurable handler for serving a directory of static files
+///
+/// For a quick way to serve a directory with default options, see [`serve_dir()`].
+///
+/// ```no_run
+/// # use twinstar::{Server, GEMINI_PORT, util::ServeDir};
+/// # async fn run() -> anyhow::Result<()> {
+/// Server::bind(("localhost", GEMINI_PORT))
+///     .add_route("/files", ServeDir::new("public").set_mime_sniffing(true).into_handler())
+///     .serve()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ServeDir {
+    root: PathBuf,
+    sniff_mime: bool,
+    max_file_size: Option<u64>,
+    oversize_policy: OversizePolicy,
+    range_queries: bool,
+    checksums: Option<ChecksumCache>,
+    #[cfg(feature="charset")]
+    transcode_text: bool,
+    #[cfg(feature="mmap")]
+    mmap_min_size: Option<u64>,
+    #[cfg(feature="unicode_normalization")]
+    unicode_normalization: bool,
+}
+
+/// The extension of checksum companions
+const CHECKSUM_EXTENSION: &str = "sha256";
+
+/// Checksums computed for companion requests, by file
+///
+/// Entries are keyed by the canonical path of the file, and only used as long as the
+/// file's modification time and size haven't changed.
+type ChecksumCache = Arc<Mutex<HashMap<PathBuf, (SystemTime, u64, String)>>>;
+
+impl ServeDir {
+    /// Serve the files found in `root`
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            sniff_mime: false,
+            max_file_size: None,
+            oversize_policy: OversizePolicy::BadRequest,
+            range_queries: false,
+            checksums: None,
+            #[cfg(feature="charset")]
+            transcode_text: false,
+            #[cfg(feature="mmap")]
+            mmap_min_size: None,
+            #[cfg(feature="unicode_normalization")]
+            unicode_normalization: false,
+        }
+    }
+
+    /// Guess the MIME of files without an extension by looking at their contents
+    ///
+    /// Without sniffing, files without an extension are always served as
+    /// `application/octet-stream`.  With sniffing enabled, the first few bytes of the
+    /// file are checked for common image and audio signatures, and for text that looks
+    /// like gemtext.  See [`sniff::sniff_mime()`] for details.
+    ///
+    /// This is disabled by default.
+    pub fn set_mime_sniffing(mut self, enabled: bool) -> Self {
+        self.sniff_mime = enabled;
+        self
+    }
+
+    /// Refuse to serve files larger than `max_file_size` bytes
+    ///
+    /// This prevents large files which accidentally ended up in a public directory from
+    /// being used to drain the server's bandwidth.  How the request is answered instead
+    /// is decided by the [`OversizePolicy`].
+    ///
+    /// By default, files of any size are served.
+    pub fn set_max_file_size(mut self, max_file_size: u64) -> Self {
+        self.max_file_size = Some(max_file_size);
+        self
+    }
+
+    /// Set how requests for files exceeding the maximum file size are answered
+    ///
+    /// The default is [`OversizePolicy::BadRequest`].
+    pub fn set_oversize_policy(mut self, policy: OversizePolicy) -> Self {
+        self.oversize_policy = policy;
+        self
+    }
+
+    /// Serve parts of files requested with an `offset` and `len` in the query
+    ///
+    /// Gemini has no way to request part of a file, so an interrupted download has to
+    /// start over.  With range queries enabled, cooperating clients can resume
+    /// downloads by requesting e.g. `/files/talk.webm?offset=1048576&len=65536`, which
+    /// is answered with at most `len` bytes of the file, starting at byte `offset`.
+    /// Either parameter can be left out, to start at the beginning or continue to the
+    /// end of the file.
+    ///
+    /// Parts are served with the MIME of the whole file, and are never transcoded.
+    /// Offsets beyond the end of the file, and queries with other parameters or
+    /// malformed numbers, are answered with `59 BAD REQUEST`.  Requests without a
+    /// query are served as usual.
+    ///
+    /// This is disabled by default.
+    pub fn set_range_queries(mut self, enabled: bool) -> Self {
+        self.range_queries = enabled;
+        self
+    }
+
+    /// Answer requests for `<file>.sha256` with the SHA-256 checksum of `<file>`
+    ///
+    /// Downloads over flaky connections can silently end up corrupted.  With checksums
+    /// enabled, every file gets a companion with `.sha256` appended to its name,
+    /// containing a line in the format of `sha256sum`, so downloaders can verify what
+    /// they received:
+    ///
+    /// ```text
+    /// 9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08  talk.webm
+    /// ```
+    ///
+    /// Checksums are computed when first requested, and cached until the file changes.
+    /// Actual files ending in `.sha256` take precedence over generated ones.
+    ///
+    /// This is disabled by default.
+    pub fn set_checksums(mut self, enabled: bool) -> Self {
+        self.checksums = if enabled { Some(ChecksumCache::default()) } else { None };
+        self
+    }
+
+    /// Convert `text/*` files in legacy encodings to UTF-8 before sending them
+    ///
+    /// Clients assume text without a `charset` parameter is UTF-8, and display files
+    /// written in e.g. Latin-1 garbled.  With transcoding enabled, text files which
+    /// aren't valid UTF-8 have their encoding detected and are converted to UTF-8, and
+    /// all text files are served with `charset=utf-8` in their MIME.
+    ///
+    /// Files larger than [`charset::MAX_TRANSCODE_LEN`](super::charset::MAX_TRANSCODE_LEN)
+    /// are served unchanged.
+    ///
+    /// This is disabled by default, and requires the `charset` feature.
+    #[cfg(feature="charset")]
+    pub fn set_text_transcoding(mut self, enabled: bool) -> Self {
+        self.transcode_text = enabled;
+        self
+    }
+
+    /// Map files of at least `min_size` bytes into memory instead of reading them
+    ///
+    /// Mapped files are sent straight from the OS page cache, which saves copying them
+    /// through a buffer for large, frequently requested files.  Parts requested by
+    /// [range queries](Self::set_range_queries()) and transcoded text are never mapped.
+    /// If a file can't be mapped, it is read as usual.
+    ///
+    /// This is disabled by default, and requires the `mmap` feature.
+    ///
+    /// # Safety
+    ///
+    /// Files served by this `ServeDir` must never be modified or truncated while they
+    /// are being served, see [`MappedFile::map()`](crate::types::MappedFile::map()).
+    #[cfg(feature="mmap")]
+    pub unsafe fn set_mmap_min_size(mut self, min_size: Option<u64>) -> Self {
+        self.mmap_min_size = min_size;
+        self
+    }
+
+    /// Find files whose names are normalized differently than the requested path
+    ///
+    /// File names written on macOS are usually decomposed, while links are usually
+    /// typed composed, so a file named `café.gmi` may not be found when requested as
+    /// `café.gmi`, even though both look the same.  With this enabled, a path which
+    /// doesn't exist as requested is looked up again, comparing each segment to the
+    /// directory entries after normalizing both with [`nfc()`](super::nfc()).
+    ///
+    /// This is disabled by default, and requires the `unicode_normalization` feature.
+    /// See [`Builder::set_path_normalizer()`](crate::Builder::set_path_normalizer()) for
+    /// normalizing paths before they are routed.
+    #[cfg(feature="unicode_normalization")]
+    pub fn set_unicode_normalization(mut self, enabled: bool) -> Self {
+        self.unicode_normalization = enabled;
+        self
+    }
+
+    /// Turn this into a handler which can be passed to
+    /// [`Builder::add_route()`](crate::Builder::add_route())
+    ///
+    /// The handler serves the path trailing the route it was mounted on.
+    pub fn into_handler(self) -> impl Fn(Request) -> HandlerResponse + Send + Sync {
+        let this = std::sync::Arc::new(self);
+        move |request: Request| {
+            let this = this.clone();
+            Box::pin(async move {
+                this.serve_request(&request).await
+            }) as HandlerResponse
+        }
+    }
+
+    /// Serve the path trailing the route of a request, honoring range queries if enabled
+    pub async fn serve_request(&self, request: &Request) -> Result<Response> {
+        let virtual_path = decoded_trailing_segments(request);
+
+        let range = match request.uri().query() {
+            Some(query) if self.range_queries => match ByteRange::parse(query.as_str()) {
+                Some(range) => range,
+                None => return Ok(Response::bad_request_lossy("Invalid range, expected offset=<bytes>&len=<bytes>")),
+            },
+            _ => return self.serve(&virtual_path).await,
+        };
+
+        let path = match self.resolve(&virtual_path)? {
+            Ok(path) => path,
+            Err(response) => return Ok(response),
+        };
+
+        if path.is_dir() {
+            return Ok(Response::bad_request_lossy("Ranges can only be requested for files"));
+        }
+
+        self.serve_file_range(path, Some(range)).await
+    }
+
+    /// Serve the file or directory listing found at `virtual_path` within the root
+    ///
+    /// Paths escaping the root directory are answered with `51 NOT FOUND`.
+    pub async fn serve<P: AsRef<Path>>(&self, virtual_path: &[P]) -> Result<Response> {
+        let path = match self.resolve(virtual_path)? {
+            Ok(path) => path,
+            Err(response) => return match self.serve_checksum(virtual_path).await? {
+                Some(checksum) => Ok(checksum),
+                None => Ok(response),
+            },
+        };
+
+        if !path.is_dir() {
+            return self.serve_file(path).await;
+        }
+
+        serve_dir_listing(path, virtual_path).await
+    }
+
+    /// Serve the checksum of the file `virtual_path` is a companion of, if it is one
+    async fn serve_checksum<P: AsRef<Path>>(&self, virtual_path: &[P]) -> Result<Option<Response>> {
+        let cache = match &self.checksums {
+            Some(cache) => cache,
+            None => return Ok(None),
+        };
+
+        let (last, parents) = match virtual_path.split_last() {
+            Some(split) => split,
+            None => return Ok(None),
+        };
+
+        let last = last.as_ref();
+        if last.extension() != Some(CHECKSUM_EXTENSION.as_ref()) {
+            return Ok(None);
+        }
+
+        let mut file_path = parents.iter().map(|segment| segment.as_ref().to_path_buf()).collect::<Vec<_>>();
+        file_path.push(last.with_extension(""));
+
+        let path = match self.resolve(&file_path)? {
+            Ok(path) if path.is_file() => path,
+            _ => return Ok(None),
+        };
+
+        let checksum = match cached_checksum(cache, &path).await? {
+            Ok(checksum) => checksum,
+            Err(response) => return Ok(Some(response)),
+        };
+
+        let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+
+        Ok(Some(Response::success_plain(format!("{}  {}\n", checksum, name))))
+    }
+
+    /// Find the file or directory `virtual_path` refers to
+    ///
+    /// If there is no such path within the root, this produces the response that should
+    /// be sent instead.
+    pub(super) fn resolve<P: AsRef<Path>>(&self, virtual_path: &[P]) -> Result<Result<PathBuf, Response>> {
+        debug!("Dir: {}", self.root.display());
+        let dir = self.root.as_path();
+        let dir = match dir.canonicalize() {
+            Ok(dir) => dir,
+            Err(e) => {
+                match e.kind() {
+                    std::io::ErrorKind::NotFound => {
+                        warn!("Path {} not found.  Check your configuration.", dir.display());
+                        return Response::server_error("Server incorrectly configured").map(Err)
+                    },
+                    std::io::ErrorKind::PermissionDenied => {
+                        warn!("Permission denied for {}.  Check that the server has access.", dir.display());
+                        return Response::server_error("Server incorrectly configured").map(Err)
+                    },
+                    _ => return warn_unexpected(e, dir, line!()).map(Err),
+                }
+            },
+        };
+        let mut path = dir.to_path_buf();
+
+        for segment in virtual_path {
+            path.push(segment);
+        }
+
+        #[cfg(feature="unicode_normalization")]
+        if self.unicode_normalization && !path.exists() {
+            if let Some(normalized) = find_normalized(&dir, virtual_path) {
+                path = normalized;
+            }
+        }
+
+        let path = match path.canonicalize() {
+            Ok(dir) => dir,
+            Err(e) => {
+                match e.kind() {
+                    std::io::ErrorKind::NotFound => return Ok(Err(Response::not_found())),
+                    std::io::ErrorKind::PermissionDenied => {
+                        // Runs when asked to serve a file in a restricted dir
+                        // i.e. not /noaccess, but /noaccess/file
+                        warn!("Asked to serve {}, but permission denied by OS", path.display());
+                        return Ok(Err(Response::not_found()));
+                    },
+                    _ => return warn_unexpected(e, path.as_ref(), line!()).map(Err),
+                }
+            },
+        };
+
+        if !path.starts_with(&dir) {
+            return Ok(Err(Response::not_found()));
+        }
+
+        Ok(Ok(path))
+    }
+
+    pub(super) async fn serve_file(&self, path: PathBuf) -> Result<Response> {
+        self.serve_file_range(path, None).await
+    }
+
+    async fn serve_file_range(&self, path: PathBuf, range: Option<ByteRange>) -> Result<Response> {
+        let mut file = match open_file(&path).await? {
+            Ok(file) => file,
+            Err(response) => return Ok(response),
+        };
+
+        if self.max_file_size.is_some() || range.is_some() {
+            let size = file.metadata().await
+                .with_context(|| format!("Failed to get metadata of `{}`", path.display()))?
+                .len();
+
+            match self.max_file_size {
+                Some(max_file_size) if size > max_file_size => {
+                    debug!("Refusing to serve {}, it is {} bytes large", path.display(), size);
+                    return Ok(self.oversize_policy.response(&path, size, max_file_size));
+                },
+                _ => {},
+            }
+
+            if let Some(range) = range {
+                if range.offset > size {
+                    return Ok(Response::bad_request_lossy(format!("Offset beyond the end of the file, which is {} bytes long", size)));
+                }
+            }
+        }
+
+        let mime = if self.sniff_mime && path.extension().is_none() {
+            let mut head = Vec::with_capacity(sniff::SNIFF_LEN);
+            (&mut file).take(sniff::SNIFF_LEN as u64).read_to_end(&mut head).await
+                .with_context(|| format!("Failed to read `{}`", path.display()))?;
+            file.seek(std::io::SeekFrom::Start(0)).await
+                .with_context(|| format!("Failed to seek `{}`", path.display()))?;
+
+            guess_mime_from_contents(&path, &head)
+        } else {
+            guess_mime_from_path(&path)
+        };
+
+        if let Some(range) = range {
+            file.seek(std::io::SeekFrom::Start(range.offset)).await
+                .with_context(|| format!("Failed to seek `{}`", path.display()))?;
+            let part = file.take(range.len.unwrap_or(u64::MAX));
+
+            return Ok(Response::success(&mime, Body::Reader(Box::new(part))));
+        }
+
+        #[cfg(feature="charset")]
+        {
+            if self.transcode_text && mime.type_() == mime::TEXT {
+                return serve_text_as_utf8(file, &path, &mime).await;
+            }
+        }
+
+        #[cfg(feature="mmap")]
+        {
+            if let Some(min_size) = self.mmap_min_size {
+                return Ok(Response::success(&mime, map_large_file(file, &path, min_size).await?));
+            }
+        }
+
+        Ok(Response::success(&mime, file))
+    }
+}
+
+/// Map `file` into memory if it is at least `min_size` bytes large
+#[cfg(feature="mmap")]
+async fn map_large_file(file: File, path: &Path, min_size: u64) -> Result<Body> {
+    use crate::types::MappedFile;
+
+    let size = file.metadata().await
+        .with_context(|| format!("Failed to get metadata of `{}`", path.display()))?
+        .len();
+
+    if size < min_size {
+        return Ok(file.into());
+    }
+
+    let file = file.into_std().await;
+    // SAFETY: mapping was enabled through `set_mmap_min_size()`, whose caller promised
+    // that served files aren't modified
+    match unsafe { MappedFile::map(&file) } {
+        Ok(mapped) => Ok(Body::Mmap(mapped)),
+        Err(err) => {
+            debug!("Failed to map {}, reading it instead: {}", path.display(), err);
+            Ok(File::from_std(file).into())
+        },
+    }
+}
+
+#[cfg(feature="charset")]
+async fn serve_text_as_utf8(mut file: File, path: &Path, mime: &Mime) -> Result<Response> {
+    use super::charset;
+
+    let len = file.metadata().await
+        .with_context(|| format!("Failed to get metadata of `{}`", path.display()))?
+        .len();
+
+    if len > charset::MAX_TRANSCODE_LEN {
+        debug!("Not transcoding {}, it is too large", path.display());
+        return Ok(Response::success(mime, file));
+    }
+
+    let mut bytes = Vec::with_capacity(len as usize);
+    file.read_to_end(&mut bytes).await
+        .with_context(|| format!("Failed to read `{}`", path.display()))?;
+
+    let (text, encoding) = charset::to_utf8(&bytes);
+    if encoding != encoding_rs::UTF_8 {
+        debug!("Transcoding {} from {} to UTF-8", path.display(), encoding.name());
+    }
+
+    let mime = charset::with_utf8_charset(mime);
+
+    Ok(Response::success(&mime, text.into_owned()))
+}
+
+/// The checksum of a file, from the cache if it hasn't changed since it was computed
+async fn cached_checksum(cache: &ChecksumCache, path: &Path) -> Result<Result<String, Response>> {
+    let metadata = fs::metadata(path).await
+        .with_context(|| format!("Failed to get metadata of `{}`", path.display()))?;
+    let modified = metadata.modified()
+        .with_context(|| format!("Failed to get modification time of `{}`", path.display()))?;
+    let len = metadata.len();
+
+    if let Some((cached_modified, cached_len, checksum)) = cache.lock().unwrap().get(path) {
+        if *cached_modified == modified && *cached_len == len {
+            return Ok(Ok(checksum.clone()));
+        }
+    }
+
+    let mut file = match open_file(path).await? {
+        Ok(file) => file,
+        Err(response) => return Ok(Err(response)),
+    };
+
+    debug!("Computing checksum of {}", path.display());
+    let mut context = ring::digest::Context::new(&ring::digest::SHA256);
+    let mut buffer = vec![0; 64 * 1024];
+
+    loop {
+        let read = file.read(&mut buffer).await
+            .with_context(|| format!("Failed to read `{}`", path.display()))?;
+        if read == 0 {
+            break;
+        }
+        context.update(&buffer[..read]);
+    }
+
+    let checksum = hex(context.finish().as_ref());
+
+    cache.lock().unwrap().insert(path.to_owned(), (modified, len, checksum.clone()));
+
+    Ok(Ok(checksum))
+}
+
+/// The part of a file requested using a range query, like `offset=1024&len=512`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ByteRange {
+    offset: u64,
+    len: Option<u64>,
+}
+
+impl ByteRange {
+    /// Parse a query, or return `None` if it isn't a valid range query
+    fn parse(query: &str) -> Option<Self> {
+        let mut range = Self { offset: 0, len: None };
+
+        for pair in query.split('&') {
+            let (key, value) = pair.split_once('=')?;
+            let value = value.parse::<u64>().ok()?;
+
+            match key {
+                "offset" => range.offset = value,
+                "len" => range.len = Some(value),
+                _ => return None,
+            }
+        }
+
+        Some(range)
+    }
+}
+
+/// Find `virtual_path` within `dir`, comparing segments which don't exist as they are
+/// by their NFC normalized form
+#[cfg(feature="unicode_normalization")]
+fn find_normalized<P: AsRef<Path>>(dir: &Path, virtual_path: &[P]) -> Option<PathBuf> {
+    let mut path = dir.to_path_buf();
+
+    for segment in virtual_path {
+        let segment = segment.as_ref();
+        if path.join(segment).exists() {
+            path.push(segment);
+            continue;
+        }
+
+        let wanted = super::nfc(segment.to_str()?);
+        let entry = std::fs::read_dir(&path).ok()?
+            .filter_map(|entry| entry.ok())
+            .find(|entry| entry.file_name().to_str().map(super::nfc).as_deref() == Some(wanted.as_str()))?;
+        path.push(entry.file_name());
+    }
+
+    Some(path)
+}
+
+/// The trailing segments of a request, percent decoded
+pub(super) fn decoded_trailing_segments(request: &Request) -> Vec<String> {
+    request.trailing_segments()
+        .iter()
+        .map(|segment| percent_decode_str(segment).decode_utf8_lossy().into_owned())
+        .collect()
+}
+
+/// How to answer requests for files exceeding [`ServeDir::set_max_file_size()`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OversizePolicy {
+    /// Answer with `59 BAD REQUEST`
+    BadRequest,
+    /// Answer with a short gemtext document explaining that the file is too large
+    Explain,
+}
+
+impl OversizePolicy {
+    fn response(self, path: &Path, size: u64, max_file_size: u64) -> Response {
+        match self {
+            Self::BadRequest => Response::bad_request_lossy("File too large"),
+            Self::Explain => {
+                let name = path.file_name()
+                    .map(|name| name.to_string_lossy())
+                    .unwrap_or_default();
+                let mut document = Document::new();
+
+                document
+                    .add_heading(H1, "File too large")
+                    .add_blank_line()
+                    .add_text(format!(
+                        "The file \"{}\" is {}, but this capsule only serves files up to {}.",
+                        name,
+                        format_size(size),
+                        format_size(max_file_size),
+                    ))
+                    .add_blank_line()
+                    .add_link("./", "Back");
+
+                document.into()
+            },
+        }
+    }
+}
+
+/// Format a size in bytes for humans, e.g. `1.5 MiB`
+fn format_size(size: u64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB"];
+
+    if size < 1024 {
+        return format!("{} bytes", size);
+    }
+
+    let mut size = size as f64 / 1024.;
+    let mut unit = UNITS[0];
+
+    for next_unit in &UNITS[1..] {
+        if size < 1024. {
+            break;
+        }
+        size /= 1024.;
+        unit = next_unit;
+    }
+
+    format!("{:.1} {}", size, unit)
+}
+
+/// Serve a single file with the given MIME
+///
+/// Files the server isn't permitted to read are answered with `51 NOT FOUND`.
+pub async fn serve_file<P: AsRef<Path>>(path: P, mime: &Mime) -> Result<Response> {
+    let file = match open_file(path.as_ref()).await? {
+        Ok(file) => file,
+        Err(response) => return Ok(response),
+    };
+
+    Ok(Response::success(mime, file))
+}
+
+/// Open a file, or produce the response that should be sent if that isn't possible
+async fn open_file(path: &Path) -> Result<Result<File, Response>> {
+    match File::open(path).await {
+        Ok(file) => Ok(Ok(file)),
+        Err(err) => match err.kind() {
+            std::io::ErrorKind::PermissionDenied => {
+                warn!("Asked to serve {}, but permission denied by OS", path.display());
+                Ok(Err(Response::not_found()))
+            },
+            _ => warn_unexpected(err, path, line!()).map(Err),
+        }
+    }
+}
+
+/// Serve a directory with the default [`ServeDir`] options
+pub async fn serve_dir<D: AsRef<Path>, P: AsRef<Path>>(dir: D, virtual_path: &[P]) -> Result<Response> {
+    ServeDir::new(dir.as_ref()).serve(virtual_path).await
+}
+
+async fn serve_dir_listing<P: AsRef<Path>, B: AsRef<Path>>(path: P, virtual_path: &[B]) -> Result<Response> {
+    let mut dir = match fs::read_dir(path.as_ref()).await {
+        Ok(dir) => dir,
+        Err(err) => match err.kind() {
+            io::ErrorKind::NotFound => return Ok(Response::not_found()),
+            std::io::ErrorKind::PermissionDenied => {
+                warn!("Asked to serve {}, but permission denied by OS", path.as_ref().display());
+                return Ok(Response::not_found());
+            },
+            _ => return warn_unexpected(err, path.as_ref(), line!()),
+        }
+    };
+
+    let breadcrumbs: PathBuf = virtual_path.iter().collect();
+    let mut document = Document::new();
+
+    document.add_heading(H1, format!("Index of /{}", breadcrumbs.display()));
+    document.add_blank_line();
+
+    if virtual_path.get(0).map(<_>::as_ref) != Some(Path::new("")) {
+        document.add_link("..", "📁 ../");
+    }
+
+    while let Some(entry) = dir.next_entry().await.context("Failed to list directory")? {
+        let file_name = entry.file_name();
+        let file_name = file_name.to_string_lossy();
+        let is_dir = entry.file_type().await
+            .with_context(|| format!("Failed to get file type of `{}`", entry.path().display()))?
+            .is_dir();
+        let trailing_slash = if is_dir { "/" } else { "" };
+        let uri = format!("./{}{}", file_name, trailing_slash);
+
+        document.add_link(uri.as_str(), format!("{icon} {name}{trailing_slash}",
+            icon = if is_dir { '📁' } else { '📄' },
+            name = file_name,
+            trailing_slash = trailing_slash
+        ));
+    }
+
+    Ok(document.into())
+}
+
+pub fn guess_mime_from_path<P: AsRef<Path>>(path: P) -> Mime {
+    let path = path.as_ref();
+    let extension = path.extension().and_then(|s| s.to_str());
+    let extension = match extension {
+        Some(extension) => extension,
+        None => return mime::APPLICATION_OCTET_STREAM,
+    };
+
+    if let "gemini" | "gmi" = extension {
+        return crate::GEMINI_MIME.clone();
+    }
+
+    mime_guess::from_ext(extension).first_or_octet_stream()
+}
+
+/// Guess the MIME of a file from its path, falling back to its contents
+///
+/// Files with an extension are handled like in [`guess_mime_from_path()`].  For files
+/// without one, `contents` (usually the first [`sniff::SNIFF_LEN`] bytes of the file) is
+/// passed to [`sniff::sniff_mime()`], and `application/octet-stream` is only used if
+/// that doesn't recognize it either.
+pub fn guess_mime_from_contents<P: AsRef<Path>>(path: P, contents: &[u8]) -> Mime {
+    let path = path.as_ref();
+
+    if path.extension().is_some() {
+        return guess_mime_from_path(path);
+    }
+
+    sniff::sniff_mime(contents).unwrap_or(mime::APPLICATION_OCTET_STREAM)
+}
+
+/// Print a warning to the log asking to file an issue and respond with "Unexpected Error"
+pub (crate) fn warn_unexpected(err: impl std::fmt::Debug, path: &Path, line: u32) -> Result<Response> {
+    warn!(
+        concat!(
+            "Unexpected error serving path {} at util/serve_dir.rs:{}, please report to ",
+            env!("CARGO_PKG_REPOSITORY"),
+            "/issues: {:?}",
+        ),
+        path.display(),
+        line,
+        err
+    );
+    Response::server_error("Unexpected error")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Status;
+
+    async fn read_body(mut response: Response) -> Vec<u8> {
+        let mut body = Vec::new();
+        if let Some(reader) = response.take_body() {
+            reader.into_reader().read_to_end(&mut body).await.unwrap();
+        }
+        body
+    }
+
+    #[test]
+    fn format_size_picks_units() {
+        assert_eq!(format_size(0), "0 bytes");
+        assert_eq!(format_size(1023), "1023 bytes");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+
+    #[tokio::test]
+    async fn refuses_oversized_files() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::write(dir.join("small.txt"), "small").unwrap();
+        std::fs::write(dir.join("large.txt"), "large".repeat(10)).unwrap();
+
+        let serve_dir = ServeDir::new(dir).set_max_file_size(10);
+        let small = serve_dir.serve(&["small.txt"]).await.unwrap();
+        let large = serve_dir.serve(&["large.txt"]).await.unwrap();
+        let explained = serve_dir.clone()
+            .set_oversize_policy(OversizePolicy::Explain)
+            .serve(&["large.txt"]).await.unwrap();
+
+        assert_eq!(small.header().status, Status::SUCCESS);
+        assert_eq!(large.header().status, Status::BAD_REQUEST);
+        assert_eq!(explained.header().status, Status::SUCCESS);
+        assert_eq!(explained.header().meta.as_str(), "text/gemini");
+    }
+
+    #[tokio::test]
+    async fn serves_checksums() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::create_dir(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("sub/test.txt"), "test").unwrap();
+        std::fs::write(dir.join("own.sha256"), "handwritten").unwrap();
+
+        let serve_dir = ServeDir::new(dir).set_checksums(true);
+        let body = |response| async { String::from_utf8(read_body(response).await).unwrap() };
+
+        let checksum = serve_dir.serve(&["sub", "test.txt.sha256"]).await.unwrap();
+        let cached = serve_dir.serve(&["sub", "test.txt.sha256"]).await.unwrap();
+        let own = serve_dir.serve(&["own.sha256"]).await.unwrap();
+        let missing = serve_dir.serve(&["missing.txt.sha256"]).await.unwrap();
+        let disabled = ServeDir::new(dir).serve(&["sub", "test.txt.sha256"]).await.unwrap();
+
+        assert_eq!(checksum.header().meta.as_str(), "text/plain");
+        assert_eq!(
+            body(checksum).await,
+            "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08  test.txt\n",
+        );
+        assert_eq!(body(cached).await.len(), 64 + 2 + 8 + 1);
+        assert_eq!(body(own).await, "handwritten");
+        assert_eq!(missing.header().status, Status::NOT_FOUND);
+        assert_eq!(disabled.header().status, Status::NOT_FOUND);
+    }
+
+    #[test]
+    fn parses_range_queries() {
+        assert_eq!(ByteRange::parse("offset=10&len=5"), Some(ByteRange { offset: 10, len: Some(5) }));
+        assert_eq!(ByteRange::parse("len=5"), Some(ByteRange { offset: 0, len: Some(5) }));
+        assert_eq!(ByteRange::parse("offset=10"), Some(ByteRange { offset: 10, len: None }));
+        assert_eq!(ByteRange::parse("offset=-1"), None);
+        assert_eq!(ByteRange::parse("offset=1&page=2"), None);
+        assert_eq!(ByteRange::parse("search terms"), None);
+    }
+
+    #[tokio::test]
+    async fn serves_ranges() {
+        use std::convert::TryFrom;
+        use crate::uri::URIReference;
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::write(dir.join("data.bin"), b"0123456789").unwrap();
+
+        let serve_dir = ServeDir::new(dir).set_range_queries(true);
+        let serve = |query: &str| {
+            let uri = format!("gemini://localhost/data.bin{}", query);
+            let mut request = Request::from_uri(URIReference::try_from(uri.as_str()).unwrap().into_owned()).unwrap();
+            request.set_trailing(vec!["data.bin".to_owned()]);
+            let serve_dir = serve_dir.clone();
+            async move {
+                let response = serve_dir.serve_request(&request).await.unwrap();
+                (response.header().clone(), read_body(response).await)
+            }
+        };
+
+        let (whole, whole_body) = serve("").await;
+        let (part, part_body) = serve("?offset=3&len=4").await;
+        let (_, rest_body) = serve("?offset=8").await;
+        let (beyond, _) = serve("?offset=11").await;
+        let (invalid, _) = serve("?offset=three").await;
+
+        assert_eq!(whole_body, b"0123456789");
+        assert_eq!(part.status, Status::SUCCESS);
+        assert_eq!(part.meta.as_str(), whole.meta.as_str());
+        assert_eq!(part_body, b"3456");
+        assert_eq!(rest_body, b"89");
+        assert_eq!(beyond.status, Status::BAD_REQUEST);
+        assert_eq!(invalid.status, Status::BAD_REQUEST);
+    }
+
+    #[cfg(feature="unicode_normalization")]
+    #[tokio::test]
+    async fn finds_differently_normalized_files() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::create_dir(dir.join("re\u{301}sume\u{301}")).unwrap();
+        std::fs::write(dir.join("re\u{301}sume\u{301}").join("cafe\u{301}.txt"), "coffee").unwrap();
+
+        let composed = ["r\u{e9}sum\u{e9}", "caf\u{e9}.txt"];
+        let strict = ServeDir::new(dir);
+        let normalizing = ServeDir::new(dir).set_unicode_normalization(true);
+
+        let strict = strict.serve(&composed).await.unwrap();
+        let normalized = normalizing.serve(&composed).await.unwrap();
+
+        assert_eq!(strict.header().status, Status::NOT_FOUND);
+        assert_eq!(normalized.header().status, Status::SUCCESS);
+        assert_eq!(normalized.header().meta.as_str(), "text/plain");
+    }
+
+    #[cfg(feature="mmap")]
+    #[tokio::test]
+    async fn maps_large_files() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::write(dir.join("small.txt"), "small").unwrap();
+        std::fs::write(dir.join("large.txt"), "large".repeat(10)).unwrap();
+
+        // SAFETY: the files aren't modified while they are served
+        let serve_dir = unsafe { ServeDir::new(dir).set_mmap_min_size(Some(10)) };
+        let mut small = serve_dir.serve(&["small.txt"]).await.unwrap();
+        let mut large = serve_dir.serve(&["large.txt"]).await.unwrap();
+
+        assert!(matches!(small.take_body(), Some(Body::Reader(_))));
+        match large.take_body() {
+            Some(Body::Mmap(mapped)) => assert_eq!(&*mapped, "large".repeat(10).as_bytes()),
+            _ => panic!("Large file wasn't mapped"),
+        }
+    }
+}
@@ -0,0 +1,126 @@
+//! Guessing the MIME of a file from its contents
+//!
+//! This is used as a fallback for files without an extension, see
+//! [`ServeDir::set_mime_sniffing()`](super::ServeDir::set_mime_sniffing()).  Only a small
+//! number of very common formats are recognized, and anything else is left for the
+//! caller to decide.
+
+use mime::Mime;
+
+/// The number of bytes [`sniff_mime()`] needs to see to make its best guess
+pub const SNIFF_LEN: usize = 512;
+
+/// Magic byte signatures, and the MIME they correspond to
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"OggS", "audio/ogg"),
+    (b"fLaC", "audio/flac"),
+    (b"ID3", "audio/mpeg"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+];
+
+/// Line starts which only appear in gemtext, not in plain text
+const GEMTEXT_STARTS: &[&str] = &["=> ", "=>\t", "```", "# ", "## ", "### "];
+
+/// Guess the MIME of a file from its first few bytes
+///
+/// `head` should be the first [`SNIFF_LEN`] bytes of the file, or the whole file if it
+/// is shorter.
+///
+/// The following is recognized:
+///
+/// * PNG, JPEG, GIF, and WebP images
+/// * Ogg, FLAC, MP3, and WAV audio
+/// * PDF documents, and zip and gzip archives
+/// * UTF-8 text, which is reported as `text/gemini` if it contains any link,
+///   heading, or preformatting lines, and as `text/plain` otherwise
+///
+/// ```
+/// # use twinstar::util::sniff::sniff_mime;
+/// assert_eq!(sniff_mime(b"\x89PNG\r\n\x1a\n...").unwrap(), "image/png");
+/// assert_eq!(sniff_mime(b"# Hello\n=> /about About\n").unwrap(), "text/gemini");
+/// assert_eq!(sniff_mime(b"Hello").unwrap(), "text/plain");
+/// assert_eq!(sniff_mime(b"\x00\x01\x02"), None);
+/// ```
+pub fn sniff_mime(head: &[u8]) -> Option<Mime> {
+    let head = &head[..head.len().min(SNIFF_LEN)];
+
+    for (signature, mime) in SIGNATURES {
+        if head.starts_with(signature) {
+            return Some(mime.parse().expect("twinstar BUG"));
+        }
+    }
+
+    if head.len() >= 12 && &head[..4] == b"RIFF" {
+        match &head[8..12] {
+            b"WEBP" => return Some("image/webp".parse().expect("twinstar BUG")),
+            b"WAVE" => return Some("audio/wav".parse().expect("twinstar BUG")),
+            _ => {},
+        }
+    }
+
+    let text = as_text(head)?;
+
+    if text.lines().any(|line| GEMTEXT_STARTS.iter().any(|start| line.starts_with(start))) {
+        Some(crate::GEMINI_MIME.clone())
+    } else {
+        Some(mime::TEXT_PLAIN)
+    }
+}
+
+/// Interpret `head` as UTF-8 text, if it looks like text
+///
+/// Since `head` may be cut off in the middle of a multi-byte sequence, an incomplete
+/// sequence at the very end is tolerated.
+fn as_text(head: &[u8]) -> Option<&str> {
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        Err(err) if err.error_len().is_none() => {
+            std::str::from_utf8(&head[..err.valid_up_to()]).expect("twinstar BUG")
+        },
+        Err(_) => return None,
+    };
+
+    let is_binary = text.chars()
+        .any(|ch| ch.is_control() && !matches!(ch, '\n' | '\r' | '\t' | '\x0c'));
+
+    if is_binary {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniffs_riff_containers() {
+        assert_eq!(sniff_mime(b"RIFF\0\0\0\0WEBPVP8 ").unwrap(), "image/webp");
+        assert_eq!(sniff_mime(b"RIFF\0\0\0\0WAVEfmt ").unwrap(), "audio/wav");
+    }
+
+    #[test]
+    fn tolerates_truncated_utf8() {
+        let mut head = "x".repeat(SNIFF_LEN - 1).into_bytes();
+        head.extend_from_slice("🦀".as_bytes());
+
+        assert_eq!(sniff_mime(&head).unwrap(), mime::TEXT_PLAIN);
+    }
+
+    #[test]
+    fn rejects_invalid_utf8() {
+        assert_eq!(sniff_mime(b"abc\xff\xfe"), None);
+    }
+
+    #[test]
+    fn plain_text_mentioning_gemtext_markers_inline() {
+        assert_eq!(sniff_mime(b"a => b\nc # d\n").unwrap(), mime::TEXT_PLAIN);
+    }
+}
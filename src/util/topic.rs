@@ -0,0 +1,170 @@
+use tokio::io::{self, AsyncWriteExt};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::types::Body;
+
+/// How many messages a topic buffers by default
+pub const DEFAULT_TOPIC_CAPACITY: usize = 64;
+
+/// How many rendered bytes a streamed body holds while the client is reading
+const STREAM_BUFFER: usize = 8 * 1024;
+
+/// Delivers the same messages to many live responses, like a chat room
+///
+/// Every [`publish()`](Self::publish()) reaches all current subscribers, and responses
+/// can follow a topic by answering with a body from [`stream()`](Self::stream()),
+/// which renders each message as it is published, and stays open until the topic goes
+/// away or the client disconnects.  Clones publish to the same subscribers.
+///
+/// Only the last few messages are buffered for each subscriber.  A subscriber falling
+/// further behind, e.g. because its client reads slowly, skips the oldest messages it
+/// hasn't received yet, rather than holding up publishers or the other subscribers.
+///
+/// ```no_run
+/// # use twinstar::{Server, Request, Response, GEMINI_PORT, util::Topic};
+/// # async fn run() -> anyhow::Result<()> {
+/// let topic = Topic::<String>::new();
+/// let chat = topic.clone();
+///
+/// Server::bind(("localhost", GEMINI_PORT))
+///     .add_route("/chat", move |_: Request| {
+///         let body = chat.stream(|message: String| format!("* {}\n", message));
+///         Box::pin(async move { Ok(Response::success_gemini(body)) }) as _
+///     })
+///     .add_route("/say", move |request: Request| {
+///         if let Some(message) = request.input() {
+///             topic.publish(message.to_owned());
+///         }
+///         Box::pin(async { Ok(Response::success_plain("Sent")) }) as _
+///     })
+///     .serve()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Topic<T> {
+    sender: broadcast::Sender<T>,
+}
+
+impl<T: Clone + Send + 'static> Topic<T> {
+    /// Create a topic buffering [`DEFAULT_TOPIC_CAPACITY`] messages
+    pub fn new() -> Self {
+        Self::with_capacity(DEFAULT_TOPIC_CAPACITY)
+    }
+
+    /// Create a topic buffering `capacity` messages for each subscriber
+    pub fn with_capacity(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity.max(1));
+        Self { sender }
+    }
+
+    /// Send `message` to all subscribers, returning how many there are
+    pub fn publish(&self, message: T) -> usize {
+        self.sender.send(message).unwrap_or(0)
+    }
+
+    /// Receive all messages published from now on
+    pub fn subscribe(&self) -> Subscription<T> {
+        Subscription {
+            receiver: self.sender.subscribe(),
+            missed: 0,
+        }
+    }
+
+    /// How many subscribers there currently are
+    pub fn subscriber_count(&self) -> usize {
+        self.sender.receiver_count()
+    }
+
+    /// A body rendering every message published from now on with `render`
+    ///
+    /// The body ends once all clones of the topic are dropped.  Rendering happens on a
+    /// task of its own, so this has to be called from within the server's runtime.
+    pub fn stream<F>(&self, mut render: F) -> Body
+    where
+        F: FnMut(T) -> String + Send + 'static,
+    {
+        let mut subscription = self.subscribe();
+        let (mut writer, reader) = io::duplex(STREAM_BUFFER);
+
+        tokio::spawn(async move {
+            while let Some(message) = subscription.recv().await {
+                if writer.write_all(render(message).as_bytes()).await.is_err() {
+                    // The client went away
+                    break;
+                }
+            }
+
+            if subscription.missed() > 0 {
+                debug!("Stream subscriber missed {} messages", subscription.missed());
+            }
+        });
+
+        Body::Reader(Box::new(reader))
+    }
+}
+
+impl<T: Clone + Send + 'static> Default for Topic<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The messages of a [`Topic`], as received by one subscriber
+#[derive(Debug)]
+pub struct Subscription<T> {
+    receiver: broadcast::Receiver<T>,
+    missed: u64,
+}
+
+impl<T: Clone> Subscription<T> {
+    /// Wait for the next message, or `None` once all clones of the topic are dropped
+    ///
+    /// Messages skipped because this subscriber fell behind are counted in
+    /// [`missed()`](Self::missed()).
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(message) => return Some(message),
+                Err(RecvError::Lagged(skipped)) => self.missed += skipped,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    /// How many messages were skipped so far because this subscriber fell behind
+    pub const fn missed(&self) -> u64 {
+        self.missed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn broadcasts_to_subscribers() {
+        let topic = Topic::with_capacity(2);
+        let mut slow = topic.subscribe();
+        let body = topic.stream(|message: u32| format!("{}\n", message));
+        assert_eq!(topic.subscriber_count(), 2);
+
+        for message in 1..=4 {
+            assert_eq!(topic.publish(message), 2);
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        assert_eq!(slow.recv().await, Some(3));
+        assert_eq!(slow.missed(), 2);
+
+        drop(topic);
+        assert_eq!(slow.recv().await, Some(4));
+        assert_eq!(slow.recv().await, None);
+
+        let mut streamed = String::new();
+        body.into_reader().read_to_string(&mut streamed).await.unwrap();
+        assert_eq!(streamed, "1\n2\n3\n4\n");
+    }
+}
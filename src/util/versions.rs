@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::BoxedHandler;
+use crate::types::{Request, Response};
+use crate::HandlerResponse;
+use super::hex;
+
+/// What the version segment of a versioned link starts with
+const VERSION_PREFIX: &str = "v-";
+
+/// Links which change whenever the content they point to changes
+///
+/// Gemini has no conditional requests, so clients caching responses can't ask whether
+/// a page changed since they last saw it.  Versioned links carry the version of the
+/// content as their last path segment instead, e.g. `/feed/v-3f2a9c1e0b7d4a66`, so a
+/// changed page is reachable under a new URL, which clients can compare against what
+/// they have cached.
+///
+/// Handlers wrapped with [`serve()`](Self::serve()) answer requests for the current
+/// version as if the segment wasn't there, and requests for an older version with
+/// `31 REDIRECT - PERMANENT` to the current one, so old links keep working.  Requests
+/// without a version are served as usual.  Clones share the same versions, so pages
+/// can keep a clone for generating links.
+///
+/// ```no_run
+/// # use twinstar::{Server, Request, Response, GEMINI_PORT, util::Versions};
+/// # async fn run() -> anyhow::Result<()> {
+/// let feed = "# My feed\n=> /posts/hello Hello\n";
+/// let versions = Versions::new();
+/// versions.set_content("/feed", feed.as_bytes());
+/// let index_versions = versions.clone();
+///
+/// Server::bind(("localhost", GEMINI_PORT))
+///     .add_route("/", move |_: Request| {
+///         let index = format!("=> {} Feed\n", index_versions.link("/feed"));
+///         Box::pin(async move { Ok(Response::success_gemini(index)) }) as _
+///     })
+///     .add_route("/feed", versions.serve("/feed", move |_: Request| {
+///         Box::pin(async move { Ok(Response::success_gemini(feed)) }) as _
+///     }))
+///     .serve()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Versions {
+    /// The current version of each path
+    current: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl Versions {
+    /// Create an empty set of versions
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the current version of the content at `path`
+    ///
+    /// The version must be a valid path segment.
+    pub fn set(&self, path: &str, version: impl Into<String>) {
+        self.current.lock().expect("twinstar BUG").insert(path.to_owned(), version.into());
+    }
+
+    /// Set the current version of the content at `path` from the content itself,
+    /// returning the version
+    ///
+    /// The version is derived from the SHA-256 digest of `content`.
+    pub fn set_content(&self, path: &str, content: &[u8]) -> String {
+        let digest = ring::digest::digest(&ring::digest::SHA256, content);
+        let version = hex(&digest.as_ref()[..8]);
+
+        self.set(path, version.clone());
+        version
+    }
+
+    /// The current version of the content at `path`, if set
+    pub fn version(&self, path: &str) -> Option<String> {
+        self.current.lock().expect("twinstar BUG").get(path).cloned()
+    }
+
+    /// A link to the current version of the content at `path`
+    ///
+    /// Without a version set, this is just `path`.
+    pub fn link(&self, path: &str) -> String {
+        match self.version(path) {
+            Some(version) => format!("{}/{}{}", path.trim_end_matches('/'), VERSION_PREFIX, version),
+            None => path.to_owned(),
+        }
+    }
+
+    /// Serve versioned links to `path` with `handler`
+    ///
+    /// The returned handler has to be added as the route for `path`.
+    pub fn serve<H>(&self, path: &str, handler: H) -> BoxedHandler
+    where
+        H: Fn(Request) -> HandlerResponse + Send + Sync + 'static,
+    {
+        let versions = self.clone();
+        let path = path.to_owned();
+
+        Box::new(move |mut request| {
+            let requested = match request.trailing_segments().last() {
+                Some(segment) if segment.starts_with(VERSION_PREFIX) => segment[VERSION_PREFIX.len()..].to_owned(),
+                _ => return handler(request),
+            };
+
+            if versions.version(&path).is_some_and(|current| current != requested) {
+                let location = versions.link(&path);
+                return Box::pin(async move { Ok(Response::redirect_permanent_lossy(location.as_str())) });
+            }
+
+            // Serve the current version as if the segment wasn't there
+            let mut trailing = request.trailing_segments().clone();
+            trailing.pop();
+            let segments = request.uri().path().segments().iter()
+                .map(|segment| segment.as_str().to_owned())
+                .collect::<Vec<_>>();
+            let unversioned = format!("/{}", segments[..segments.len().saturating_sub(1)].join("/"));
+
+            if let Err(err) = request.set_path(&unversioned) {
+                return Box::pin(async move { Err(err) });
+            }
+            request.set_trailing(trailing);
+
+            handler(request)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use super::*;
+    use crate::types::Status;
+    use crate::uri::URIReference;
+
+    fn request(uri: &str, trailing: &[&str]) -> Request {
+        let mut request = Request::from_uri(URIReference::try_from(uri).unwrap().into_owned()).unwrap();
+        request.set_trailing(trailing.iter().map(|segment| segment.to_string()).collect());
+        request
+    }
+
+    #[tokio::test]
+    async fn redirects_old_versions() {
+        let versions = Versions::new();
+        let version = versions.set_content("/feed", b"# Feed");
+        assert_eq!(versions.link("/feed"), format!("/feed/v-{}", version));
+        assert_eq!(versions.link("/other"), "/other");
+
+        let handler = versions.serve("/feed", |request: Request| {
+            let path = request.uri().path().to_string();
+            let trailing = request.trailing_segments().len();
+            Box::pin(async move { Ok(Response::success_plain(format!("{} {}", path, trailing))) }) as HandlerResponse
+        });
+
+        let current = format!("gemini://localhost/feed/v-{}", version);
+        let mut response = handler(request(&current, &[&format!("v-{}", version)])).await.unwrap();
+        assert_eq!(response.take_body().unwrap().as_bytes(), Some(&b"/feed 0"[..]));
+
+        let response = handler(request("gemini://localhost/feed/v-old", &["v-old"])).await.unwrap();
+        assert_eq!(response.header().status, Status::REDIRECT_PERMANENT);
+        assert_eq!(response.header().meta.as_str(), versions.link("/feed"));
+
+        let response = handler(request("gemini://localhost/feed", &[])).await.unwrap();
+        assert_eq!(response.header().status, Status::SUCCESS);
+    }
+}
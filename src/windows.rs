@@ -0,0 +1,163 @@
+//! Running a server as a Windows service
+//!
+//! Services are started by the service control manager, which expects the process to
+//! register itself and report its status, and which asks the service to stop or to
+//! reload its configuration.  [`WindowsService`] takes care of this, and translates
+//! stop requests into a [`Shutdown`] of the server.
+//!
+//! ```no_run
+//! # use twinstar::{Server, GEMINI_PORT, logging::EventLogSink, windows::WindowsService};
+//! fn main() -> anyhow::Result<()> {
+//!     WindowsService::new("capsule")
+//!         .set_reload_handler(|| log::info!("Reloading"))
+//!         .run(|shutdown| {
+//!             let runtime = tokio::runtime::Runtime::new()?;
+//!
+//!             runtime.block_on(async {
+//!                 Server::bind(("0.0.0.0", GEMINI_PORT))
+//!                     .set_log_sink(EventLogSink::new("capsule")?)
+//!                     .serve_until(shutdown.wait())
+//!                     .await
+//!             })
+//!         })
+//! }
+//! ```
+//!
+//! This requires the `windows-service` feature.
+
+use std::ffi::OsString;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{Result, Context};
+use lazy_static::lazy_static;
+use winsvc::define_windows_service;
+use winsvc::service::{
+    ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus,
+    ServiceType,
+};
+use winsvc::service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle};
+use winsvc::service_dispatcher;
+
+use crate::Shutdown;
+
+/// How long the service control manager should wait for the server to stop
+const STOP_WAIT_HINT: Duration = Duration::from_secs(10);
+
+type ServeFn = Box<dyn FnOnce(Shutdown) -> Result<()> + Send>;
+type ReloadFn = Box<dyn FnMut() + Send>;
+
+lazy_static! {
+    /// The service to run once the service control manager calls `service_main`
+    static ref SERVICE: Mutex<Option<(WindowsService, ServeFn)>> = Mutex::new(None);
+}
+
+define_windows_service!(ffi_service_main, service_main);
+
+/// A server running under the Windows service control manager
+pub struct WindowsService {
+    name: String,
+    on_reload: Option<ReloadFn>,
+}
+
+impl WindowsService {
+    /// Run as the service called `name`
+    ///
+    /// The name needs to match the name the service was installed under.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            on_reload: None,
+        }
+    }
+
+    /// Set a function to call when the service is asked to reload its configuration
+    ///
+    /// By default, reload requests are ignored.
+    pub fn set_reload_handler(mut self, on_reload: impl FnMut() + Send + 'static) -> Self {
+        self.on_reload = Some(Box::new(on_reload));
+        self
+    }
+
+    /// Hand control over to the service control manager
+    ///
+    /// Once the service is started, `serve` is called on a thread of the service control
+    /// manager with a [`Shutdown`] which is triggered when the service is asked to
+    /// stop.  This blocks until the service has stopped.
+    ///
+    /// This fails if the process wasn't started as a service.
+    pub fn run(self, serve: impl FnOnce(Shutdown) -> Result<()> + Send + 'static) -> Result<()> {
+        let name = self.name.clone();
+
+        *SERVICE.lock().expect("twinstar BUG") = Some((self, Box::new(serve)));
+
+        service_dispatcher::start(&name, ffi_service_main)
+            .context("Failed to start service dispatcher")
+    }
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    let service = SERVICE.lock().expect("twinstar BUG").take();
+
+    if let Some((service, serve)) = service {
+        if let Err(err) = run_service(service, serve) {
+            error!("Service failed: {:?}", err);
+        }
+    }
+}
+
+fn run_service(service: WindowsService, serve: ServeFn) -> Result<()> {
+    let shutdown = Shutdown::new();
+    let stop = shutdown.clone();
+    let mut on_reload = service.on_reload;
+
+    let status_handle = service_control_handler::register(&service.name, move |control| {
+        match control {
+            ServiceControl::Stop | ServiceControl::Shutdown | ServiceControl::Preshutdown => {
+                info!("Service was asked to stop");
+                stop.trigger();
+                ServiceControlHandlerResult::NoError
+            },
+            ServiceControl::ParamChange => {
+                info!("Service was asked to reload");
+                if let Some(on_reload) = &mut on_reload {
+                    on_reload();
+                }
+                ServiceControlHandlerResult::NoError
+            },
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        }
+    }).context("Failed to register service control handler")?;
+
+    set_status(&status_handle, ServiceState::Running, ServiceExitCode::Win32(0))?;
+
+    let result = serve(shutdown);
+    let exit_code = match &result {
+        Ok(()) => ServiceExitCode::Win32(0),
+        Err(_) => ServiceExitCode::ServiceSpecific(1),
+    };
+
+    set_status(&status_handle, ServiceState::Stopped, exit_code)?;
+
+    result
+}
+
+fn set_status(handle: &ServiceStatusHandle, state: ServiceState, exit_code: ServiceExitCode) -> Result<()> {
+    let controls_accepted = match state {
+        ServiceState::Running => ServiceControlAccept::STOP
+            | ServiceControlAccept::SHUTDOWN
+            | ServiceControlAccept::PARAM_CHANGE,
+        _ => ServiceControlAccept::empty(),
+    };
+
+    handle.set_service_status(ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state: state,
+        controls_accepted,
+        exit_code,
+        checkpoint: 0,
+        wait_hint: STOP_WAIT_HINT,
+        process_id: None,
+    }).context("Failed to report service status")
+}
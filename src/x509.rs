@@ -0,0 +1,246 @@
+//! Reading the fields of client certificates
+//!
+//! Certificate based authentication usually needs more than the raw certificate, like
+//! the name a user chose when creating it, or when it expires.  [`CertificateInfo`]
+//! holds the commonly needed fields of a certificate, parsed from its DER encoding, so
+//! handlers don't need a DER parser of their own:
+//!
+//! ```no_run
+//! # use twinstar::{Request, Response};
+//! # #[cfg(feature="x509")]
+//! # fn handle(request: Request) -> anyhow::Result<Response> {
+//! Ok(match request.certificate_info()? {
+//!     Some(info) => Response::success_plain(format!(
+//!         "Hello {}!",
+//!         info.common_name.as_deref().unwrap_or("stranger"),
+//!     )),
+//!     None => Response::client_certificate_required(),
+//! })
+//! # }
+//! ```
+//!
+//! Only the fields are read, the certificate is not verified in any way.  Certificates
+//! are parsed using [x509-parser](https://docs.rs/x509-parser).
+
+use std::convert::TryFrom;
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Result, Context, anyhow, bail, ensure};
+use x509_parser::prelude::{ASN1Time, GeneralName, X509Certificate, X509Name};
+use x509_parser::der_parser::asn1_rs::{Any, Tag};
+
+use crate::util::hex;
+
+/// The commonly needed fields of a certificate
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct CertificateInfo {
+    /// The common name (CN) of the subject, if it has one
+    pub common_name: Option<String>,
+    /// The common name (CN) of the issuer, if it has one
+    ///
+    /// Self-signed certificates, as usually used by Gemini clients, have the subject as
+    /// their issuer.
+    pub issuer_common_name: Option<String>,
+    /// The DNS names, email addresses, URIs and IP addresses the certificate is for
+    pub subject_alt_names: Vec<SubjectAltName>,
+    /// When the certificate starts being valid
+    pub not_before: SystemTime,
+    /// When the certificate stops being valid
+    pub not_after: SystemTime,
+    /// The serial number, as lowercase hex
+    pub serial_number: String,
+}
+
+/// An entry of the subject alternative name extension
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SubjectAltName {
+    /// A DNS name, like `example.org`
+    Dns(String),
+    /// An email address
+    Email(String),
+    /// A URI, like `gemini://example.org/~alice/`
+    Uri(String),
+    /// An IPv4 or IPv6 address
+    Ip(IpAddr),
+}
+
+impl CertificateInfo {
+    /// Parse a DER encoded X.509 certificate
+    pub fn parse(der: &[u8]) -> Result<Self> {
+        let certificate = parse_certificate(der)?;
+        Self::from_certificate(&certificate).context("Failed to parse certificate")
+    }
+
+    fn from_certificate(certificate: &X509Certificate<'_>) -> Result<Self> {
+        let subject_alt_names = match certificate.subject_alternative_name()? {
+            Some(extension) => subject_alt_names(&extension.value.general_names)?,
+            None => Vec::new(),
+        };
+
+        Ok(Self {
+            common_name: common_name(certificate.subject())?,
+            issuer_common_name: common_name(certificate.issuer())?,
+            subject_alt_names,
+            not_before: system_time(certificate.validity().not_before),
+            not_after: system_time(certificate.validity().not_after),
+            serial_number: hex(without_leading_zeros(certificate.raw_serial())),
+        })
+    }
+
+    /// Whether the certificate is valid at `time`
+    pub fn is_valid_at(&self, time: SystemTime) -> bool {
+        self.not_before <= time && time <= self.not_after
+    }
+}
+
+/// Read only when a DER encoded certificate starts and stops being valid
+///
+/// Unlike [`CertificateInfo::parse()`], this doesn't decode any names, so it works for
+/// certificates whose names can't be read.
+pub(crate) fn validity(der: &[u8]) -> Result<(SystemTime, SystemTime)> {
+    let validity = parse_certificate(der)?.validity().clone();
+    Ok((system_time(validity.not_before), system_time(validity.not_after)))
+}
+
+fn parse_certificate(der: &[u8]) -> Result<X509Certificate<'_>> {
+    let (_, certificate) = x509_parser::parse_x509_certificate(der)
+        .map_err(|err| anyhow!("Failed to parse certificate: {}", err))?;
+    Ok(certificate)
+}
+
+fn without_leading_zeros(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|&byte| byte != 0).unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+fn system_time(time: ASN1Time) -> SystemTime {
+    let seconds = time.timestamp();
+    match u64::try_from(seconds) {
+        Ok(seconds) => UNIX_EPOCH + Duration::from_secs(seconds),
+        Err(_) => UNIX_EPOCH - Duration::from_secs(seconds.unsigned_abs()),
+    }
+}
+
+/// Find the common name among the attributes of a name
+fn common_name(name: &X509Name<'_>) -> Result<Option<String>> {
+    name.iter_common_name()
+        .next()
+        .map(|attribute| decode_string(attribute.attr_value()).context("Failed to read common name"))
+        .transpose()
+}
+
+/// Decode any of the string types found in names
+fn decode_string(value: &Any<'_>) -> Result<String> {
+    let bytes = value.data;
+
+    match value.tag() {
+        Tag::Utf8String | Tag::PrintableString | Tag::Ia5String | Tag::NumericString | Tag::VisibleString => {
+            String::from_utf8(bytes.to_vec()).context("String is not valid UTF-8")
+        },
+        // Hardly anyone implements T.61, and in practice these hold Latin-1
+        Tag::T61String => Ok(bytes.iter().copied().map(char::from).collect()),
+        Tag::BmpString => {
+            let units = bytes.chunks_exact(2);
+            ensure!(units.remainder().is_empty(), "Truncated BMPString");
+            let units = units.map(|unit| u16::from_be_bytes([unit[0], unit[1]]));
+            char::decode_utf16(units).collect::<Result<_, _>>().context("Invalid BMPString")
+        },
+        Tag::UniversalString => {
+            let units = bytes.chunks_exact(4);
+            ensure!(units.remainder().is_empty(), "Truncated UniversalString");
+            units
+                .map(|unit| char::from_u32(u32::from_be_bytes([unit[0], unit[1], unit[2], unit[3]])))
+                .collect::<Option<_>>()
+                .ok_or_else(|| anyhow!("Invalid UniversalString"))
+        },
+        tag => bail!("Unsupported string type {}", tag),
+    }
+}
+
+fn subject_alt_names(names: &[GeneralName<'_>]) -> Result<Vec<SubjectAltName>> {
+    let mut subject_alt_names = Vec::new();
+
+    for name in names {
+        subject_alt_names.push(match *name {
+            GeneralName::DNSName(name) => SubjectAltName::Dns(name.to_owned()),
+            GeneralName::RFC822Name(name) => SubjectAltName::Email(name.to_owned()),
+            GeneralName::URI(name) => SubjectAltName::Uri(name.to_owned()),
+            GeneralName::IPAddress(address) => match address.len() {
+                4 => SubjectAltName::Ip(IpAddr::from(<[u8; 4]>::try_from(address)?)),
+                16 => SubjectAltName::Ip(IpAddr::from(<[u8; 16]>::try_from(address)?)),
+                _ => bail!("Invalid IP address in alternative name"),
+            },
+            // Other kinds of names are rarely used
+            _ => continue,
+        });
+    }
+
+    Ok(subject_alt_names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x509_parser::prelude::FromDer;
+    use crate::testing::TestCertificate;
+
+    #[test]
+    fn parses_certificates() {
+        let not_before = UNIX_EPOCH + Duration::from_secs(951_827_696);
+        let not_after = UNIX_EPOCH + Duration::from_secs(2_556_143_999);
+        let generated = TestCertificate::new("alice")
+            .add_dns_name("alice.example.org")
+            .set_not_before(not_before)
+            .set_not_after(not_after)
+            .generate()
+            .unwrap();
+
+        let info = CertificateInfo::parse(generated.certificate().as_der()).unwrap();
+        assert_eq!(info.common_name.as_deref(), Some("alice"));
+        assert_eq!(info.issuer_common_name.as_deref(), Some("alice"));
+        assert_eq!(info.not_before, not_before);
+        assert_eq!(info.not_after, not_after);
+        assert_eq!(info.serial_number.len(), 32);
+        assert_eq!(info.subject_alt_names, [SubjectAltName::Dns("alice.example.org".to_owned())]);
+        assert!(info.is_valid_at(not_before) && !info.is_valid_at(not_after + Duration::from_secs(1)));
+        assert_eq!(validity(generated.certificate().as_der()).unwrap(), (not_before, not_after));
+
+        assert_eq!(subject_alt_names(&[
+            GeneralName::DNSName("a.b"),
+            GeneralName::IPAddress(&[192, 0, 2, 1]),
+            GeneralName::RegisteredID(x509_parser::oid_registry::OID_X509_COMMON_NAME),
+        ]).unwrap(), vec![
+            SubjectAltName::Dns("a.b".to_owned()),
+            SubjectAltName::Ip(IpAddr::from([192, 0, 2, 1])),
+        ]);
+
+        assert!(CertificateInfo::parse(b"\x30\x05abc").is_err());
+    }
+
+    #[test]
+    fn decodes_legacy_strings() {
+        // A name with a single common name attribute, encoded with `tag`
+        let name = |tag: u8, value: &[u8]| {
+            let attribute = [&[0x06, 0x03, 0x55, 0x04, 0x03, tag, value.len() as u8][..], value].concat();
+            let set = [&[0x31, attribute.len() as u8 + 2, 0x30, attribute.len() as u8][..], &attribute].concat();
+            [&[0x30, set.len() as u8][..], &set].concat()
+        };
+        let common_name = |der: &[u8]| common_name(&X509Name::from_der(der).unwrap().1);
+
+        assert_eq!(common_name(&name(0x0c, "Zoë".as_bytes())).unwrap().as_deref(), Some("Zoë"));
+        assert_eq!(common_name(&name(0x14, b"Zo\xeb")).unwrap().as_deref(), Some("Zoë"));
+        assert_eq!(common_name(&name(0x1e, &[0, b'Z', 0, b'o', 0, 0xeb])).unwrap().as_deref(), Some("Zoë"));
+        assert_eq!(common_name(&name(0x1c, &[0, 0, 0, b'Z', 0, 0, 0, b'o', 0, 0, 0, 0xeb])).unwrap().as_deref(), Some("Zoë"));
+        assert!(common_name(&name(0x1e, &[0, b'Z', 0])).is_err());
+        assert_eq!(common_name(&[0x30, 0x00]).unwrap(), None);
+    }
+
+    #[test]
+    fn reads_times_before_1970() {
+        let time = ASN1Time::from_timestamp(-86_400).unwrap();
+        assert_eq!(system_time(time), UNIX_EPOCH - Duration::from_secs(86_400));
+    }
+}